@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use hyper::Method;
+use log::error;
+use prometheus::{Encoder, TextEncoder};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio::time::{timeout, Duration, MissedTickBehavior};
+
+use crate::aggregator::{self, Agent};
+use crate::config::{Defaults, Endpoint, ProbeType};
+use crate::http::{self, Verify};
+use crate::{config, duration, BoxError};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Pushes the metrics of this instance to an aggregator, which exposes them along with those of every other site.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentConfig {
+    // Where the aggregator listens, such as `https://monitor.example.com:9898`.
+    pub url: String,
+    // The `site` label the aggregator gives the metrics.
+    pub site: String,
+    #[serde(default = "default_interval", with = "duration")]
+    pub interval: Duration,
+    pub token_env: Option<String>,
+    pub token_file: Option<String>,
+    pub mesh: Option<Mesh>,
+}
+
+// Probes every other agent with a mesh from this one, which together give the latency and loss between every pair of
+// sites in both directions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mesh {
+    // Where the other agents reach this one, passed on to them by the aggregator.
+    pub address: String,
+    #[serde(default = "default_refresh_interval", with = "duration")]
+    pub refresh_interval: Duration,
+    // Settings of the endpoints of the other agents, which are pinged unless `probe` says otherwise.
+    #[serde(default)]
+    pub defaults: Defaults,
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(15)
+}
+
+fn default_refresh_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl AgentConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        http::parse_url(&self.url).map_err(|err| err.to_string())?;
+        aggregator::check_site(&self.site)?;
+        if self.token_env.is_some() && self.token_file.is_some() {
+            return Err("set only one of token_env or token_file".to_string());
+        }
+        if self.token()?.is_none() {
+            return Err("set token_env or token_file, the aggregator always needs a token".to_string());
+        }
+        if self.interval.is_zero() {
+            return Err("interval must be greater than 0".to_string());
+        }
+        if let Some(mesh) = &self.mesh {
+            if mesh.address.is_empty() || mesh.address.contains(char::is_whitespace) {
+                return Err(format!("mesh: invalid address `{}`", mesh.address));
+            }
+            if mesh.refresh_interval.is_zero() {
+                return Err("mesh: refresh_interval must be greater than 0".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.url.trim_end_matches('/'), path)
+    }
+
+    fn token(&self) -> Result<Option<String>, String> {
+        config::secret("token", &self.token_env, &self.token_file)
+    }
+}
+
+// Pushes the current metrics each `interval` until `stop` is set, and once more then so the last results get there.
+pub async fn run(config: AgentConfig, mut stop: watch::Receiver<bool>) {
+    let mut ticker = tokio::time::interval(config.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        let stopping = tokio::select! {
+            _ = ticker.tick() => false,
+            _ = stop.changed() => true,
+        };
+
+        let mut body = Vec::new();
+        if let Err(err) = TextEncoder::new().encode(&prometheus::gather(), &mut body) {
+            error!("Failed to encode metrics for {}: {}", config.url, err);
+        } else if let Err(err) = push(&config, body).await {
+            error!("Failed to push to the aggregator at {}: {}", config.url, err);
+        }
+        if stopping {
+            break;
+        }
+    }
+}
+
+async fn push(config: &AgentConfig, body: Vec<u8>) -> Result<(), BoxError> {
+    let mut headers = BTreeMap::new();
+    let token = config.token()?.unwrap_or_default();
+    headers.insert("Authorization".to_string(), format!("Bearer {}", token));
+    let mut url = config.url(&format!("/api/agents/{}", config.site));
+    if let Some(mesh) = &config.mesh {
+        url.push_str(&format!("?mesh_address={}", http::percent_encode(&mesh.address)));
+    }
+    http::upload(Method::PUT, &url, TextEncoder::new().format_type(), &headers, body).await
+}
+
+// The other agents of the mesh, as endpoints with the `source` and `destination` labels of the sites they are probed
+// between. Agents that stopped pushing are left out by the aggregator.
+pub(crate) async fn peers(config: &AgentConfig) -> Result<Vec<Endpoint>, String> {
+    let mesh = match &config.mesh {
+        Some(mesh) => mesh,
+        None => return Ok(Vec::new()),
+    };
+    let url = config.url("/api/agents");
+    let mut headers = BTreeMap::new();
+    headers.insert(
+        "Authorization".to_string(),
+        format!("Bearer {}", config.token()?.unwrap_or_default()),
+    );
+    let response = timeout(REQUEST_TIMEOUT, http::fetch(&url, &headers, &Verify::Roots))
+        .await
+        .map_err(|_| format!("requesting {} timed out", url))?
+        .map_err(|err| format!("requesting {}: {}", url, err))?;
+    if !response.status().is_success() {
+        return Err(format!("requesting {}: unexpected status {}", url, response.status()));
+    }
+    let agents: Vec<Agent> =
+        serde_json::from_slice(response.body()).map_err(|err| format!("requesting {}: {}", url, err))?;
+
+    let mut endpoints = Vec::new();
+    for agent in agents.into_iter().filter(|agent| agent.site != config.site) {
+        let address = match agent.mesh_address {
+            Some(address) => address,
+            None => continue,
+        };
+        let mut endpoint = Endpoint::new(format!("mesh {}", agent.site), address);
+        endpoint
+            .labels
+            .insert("source".to_string(), config.site.clone());
+        endpoint
+            .labels
+            .insert("destination".to_string(), agent.site);
+        endpoint.inherit(&mesh.defaults);
+        endpoint.probe.get_or_insert(ProbeType::Icmp);
+        endpoints.push(endpoint);
+    }
+    Ok(endpoints)
+}
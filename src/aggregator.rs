@@ -0,0 +1,279 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+use crate::metrics::MetricsConfig;
+use crate::{config, duration, status};
+
+// Takes the metrics agents push and exposes them on `/metrics` along with this instance's own, each with the `site`
+// label of the agent that pushed it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AggregatorConfig {
+    pub token_env: Option<String>,
+    pub token_file: Option<String>,
+    // The metrics of an agent that stopped pushing are dropped after this long, rather than shown as they were last.
+    #[serde(default = "default_stale_after", with = "duration")]
+    pub stale_after: Duration,
+}
+
+fn default_stale_after() -> Duration {
+    Duration::from_secs(300)
+}
+
+impl AggregatorConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.token_env.is_some() && self.token_file.is_some() {
+            return Err("set only one of token_env or token_file".to_string());
+        }
+        match self.token()? {
+            None => return Err("set token_env or token_file, agents always need a token to push".to_string()),
+            Some(token) if token.is_empty() => return Err("the token is empty".to_string()),
+            Some(_) => {}
+        }
+        if self.stale_after.is_zero() {
+            return Err("stale_after must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn token(&self) -> Result<Option<String>, String> {
+        config::secret("token", &self.token_env, &self.token_file)
+    }
+}
+
+// Site names go into the path agents push to, so they are kept to characters that need no encoding there.
+pub(crate) fn check_site(site: &str) -> Result<(), String> {
+    if site.is_empty()
+        || !site
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        return Err(format!(
+            "invalid site `{}`, it may only contain letters, digits, `.`, `_` and `-`",
+            site
+        ));
+    }
+    Ok(())
+}
+
+// An agent as the aggregator lists it to the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Agent {
+    pub(crate) site: String,
+    pub(crate) mesh_address: Option<String>,
+    // When it last pushed, in seconds since the epoch.
+    pub(crate) last_push: f64,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PushQuery {
+    pub(crate) mesh_address: Option<String>,
+}
+
+struct Site {
+    metrics: String,
+    mesh_address: Option<String>,
+    received: Instant,
+    // When it was received, in seconds since the epoch.
+    timestamp: f64,
+}
+
+// What the server needs for the aggregator: the token agents have to push with, and the metrics of every site.
+#[derive(Clone)]
+pub struct Aggregator {
+    pub(crate) token: String,
+    stale_after: Duration,
+    // The name of `agent_last_push_timestamp_seconds` and the const labels written before `site`, after the metrics
+    // section.
+    last_push_name: String,
+    const_labels: String,
+    sites: Arc<Mutex<BTreeMap<String, Site>>>,
+}
+
+impl Aggregator {
+    pub(crate) fn new(config: &AggregatorConfig, metrics: &MetricsConfig) -> Result<Aggregator, String> {
+        let const_labels = metrics
+            .const_labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\",", name, escape(value)))
+            .collect();
+        Ok(Aggregator {
+            token: config.token()?.unwrap_or_default(),
+            stale_after: config.stale_after,
+            last_push_name: metrics.name("agent_last_push_timestamp_seconds"),
+            const_labels,
+            sites: Arc::default(),
+        })
+    }
+
+    // Replaces everything the site pushed before.
+    pub(crate) fn push(&self, site: &str, metrics: String, mesh_address: Option<String>) -> Result<(), String> {
+        check_site(site)?;
+        let site_metrics = Site {
+            metrics,
+            mesh_address,
+            received: Instant::now(),
+            timestamp: status::unix_time(),
+        };
+        self.sites
+            .lock()
+            .unwrap()
+            .insert(site.to_string(), site_metrics);
+        Ok(())
+    }
+
+    // The agents that pushed within `stale_after`.
+    pub(crate) fn agents(&self) -> Vec<Agent> {
+        let mut sites = self.sites.lock().unwrap();
+        sites.retain(|_, site| site.received.elapsed() < self.stale_after);
+        sites
+            .iter()
+            .map(|(name, site)| Agent {
+                site: name.clone(),
+                mesh_address: site.mesh_address.clone(),
+                last_push: site.timestamp,
+            })
+            .collect()
+    }
+
+    // This instance's metrics in the text format, with those of every site that pushed within `stale_after` merged
+    // into them. Each metric has to be written as one family, so the samples of every site are gathered under its
+    // name.
+    pub(crate) fn merge(&self, local: &str) -> String {
+        let mut families = Families::default();
+        families.add(local, None);
+
+        let mut sites = self.sites.lock().unwrap();
+        sites.retain(|_, site| site.received.elapsed() < self.stale_after);
+        let mut last_push = format!(
+            "# HELP {name} When each agent last pushed its metrics, in seconds since the epoch\n# TYPE {name} gauge\n",
+            name = self.last_push_name
+        );
+        for (name, site) in sites.iter() {
+            families.add(&site.metrics, Some(name));
+            last_push.push_str(&format!(
+                "{}{{{}site=\"{}\"}} {}\n",
+                self.last_push_name, self.const_labels, name, site.timestamp
+            ));
+        }
+        if !sites.is_empty() {
+            families.add(&last_push, None);
+        }
+        families.render()
+    }
+}
+
+// Metric families in the order they were first seen, with their HELP and TYPE lines and their samples.
+#[derive(Default)]
+struct Families {
+    index: HashMap<String, usize>,
+    families: Vec<Family>,
+}
+
+#[derive(Default)]
+struct Family {
+    help: Option<String>,
+    kind: Option<String>,
+    samples: Vec<String>,
+}
+
+impl Families {
+    // Adds the families of an exposition in the text format, where the samples of each follow its HELP and TYPE
+    // lines, with `site` as a label of every sample.
+    fn add(&mut self, text: &str, site: Option<&str>) {
+        let mut current: Option<usize> = None;
+        for line in text.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                let mut parts = comment.splitn(3, ' ');
+                let (keyword, name) = (parts.next(), parts.next());
+                if let (Some(keyword @ ("HELP" | "TYPE")), Some(name)) = (keyword, name) {
+                    let family = self.family(name);
+                    let field = match keyword {
+                        "HELP" => &mut self.families[family].help,
+                        _ => &mut self.families[family].kind,
+                    };
+                    field.get_or_insert_with(|| line.to_string());
+                    current = Some(family);
+                }
+                continue;
+            }
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // Samples without a TYPE line before them are a family of their own.
+            let family = match current {
+                Some(family) => family,
+                None => self.family(sample_name(line)),
+            };
+            let sample = match site {
+                Some(site) => with_site(line, site),
+                None => line.to_string(),
+            };
+            self.families[family].samples.push(sample);
+        }
+    }
+
+    fn family(&mut self, name: &str) -> usize {
+        if let Some(index) = self.index.get(name) {
+            return *index;
+        }
+        self.families.push(Family::default());
+        self.index.insert(name.to_string(), self.families.len() - 1);
+        self.families.len() - 1
+    }
+
+    fn render(&self) -> String {
+        let mut text = String::new();
+        for family in &self.families {
+            for line in family
+                .help
+                .iter()
+                .chain(&family.kind)
+                .chain(&family.samples)
+            {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        text
+    }
+}
+
+pub(crate) fn sample_name(sample: &str) -> &str {
+    let end = sample.find(['{', ' ']).unwrap_or(sample.len());
+    &sample[..end]
+}
+
+// Label values as the text format writes them.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// The sample with a `site` label, unless it already has one from the agent's own labels.
+fn with_site(sample: &str, site: &str) -> String {
+    let name = sample_name(sample);
+    let rest = &sample[name.len()..];
+    match rest.strip_prefix('{') {
+        Some(labels) => {
+            let end = labels.find('}').unwrap_or(labels.len());
+            let has_site = labels[..end]
+                .split(',')
+                .any(|label| label.trim_start().starts_with("site="));
+            if has_site {
+                sample.to_string()
+            } else if labels.starts_with('}') {
+                format!("{}{{site=\"{}\"{}", name, site, labels)
+            } else {
+                format!("{}{{site=\"{}\",{}", name, site, labels)
+            }
+        }
+        None => format!("{}{{site=\"{}\"}}{}", name, site, rest),
+    }
+}
@@ -0,0 +1,541 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, oneshot, watch};
+use tokio::task::JoinSet;
+
+use crate::duration;
+use crate::smtp::{self, Email, SmtpConfig};
+use crate::status::{self, ProbeEvent};
+
+// Written to render the same in Slack and Discord.
+const RECENT_LEN: usize = 10;
+
+const FIRING_MESSAGE: &str =
+    ":red_circle: [{alert}] {endpoint} ({address}{at_location}) is failing: {summary}. Last error: {error}";
+const RESOLVED_MESSAGE: &str =
+    ":white_check_mark: [{alert}] {endpoint} ({address}{at_location}) recovered after {duration}";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    pub name: String,
+    // The names of the endpoints the rule applies to, or every endpoint when both this and `groups` are empty. The
+    // endpoints of the groups are added to it when the config is parsed.
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    pub consecutive_failures: Option<u32>,
+    #[serde(default, with = "crate::duration::option")]
+    pub latency_above: Option<Duration>,
+    #[serde(default = "default_quantile")]
+    pub quantile: f64,
+    #[serde(default = "default_window", with = "crate::duration")]
+    pub window: Duration,
+    pub webhook: Option<String>,
+    pub slack: Option<String>,
+    pub discord: Option<String>,
+    #[serde(default)]
+    pub email: Vec<String>,
+    // Overrides the chat message, with placeholders such as `{endpoint}` filled in from the notification.
+    pub message: Option<String>,
+}
+
+// Everything the evaluator needs from the config, swapped out as a whole on reload.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AlertConfig {
+    pub rules: Vec<AlertRule>,
+    pub smtp: Option<SmtpConfig>,
+    // The keys of the endpoints that are probed, so the state of removed ones can be dropped.
+    pub endpoints: HashSet<String>,
+}
+
+fn default_quantile() -> f64 {
+    0.95
+}
+
+fn default_window() -> Duration {
+    Duration::from_secs(300)
+}
+
+impl AlertRule {
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.consecutive_failures, self.latency_above) {
+            (Some(0), None) => return Err("consecutive_failures must be at least 1".to_string()),
+            (Some(_), None) | (None, Some(_)) => {}
+            _ => return Err("set exactly one of consecutive_failures or latency_above".to_string()),
+        }
+        if !(self.quantile > 0.0 && self.quantile <= 1.0) {
+            return Err(format!("quantile {} is not between 0 and 1", self.quantile));
+        }
+        for url in [self.webhook.as_ref(), self.slack.as_ref(), self.discord.as_ref()]
+            .iter()
+            .flatten()
+        {
+            crate::http::parse_url(url).map_err(|err| err.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    fn applies_to(&self, endpoint: &str) -> bool {
+        (self.endpoints.is_empty() && self.groups.is_empty()) || self.endpoints.iter().any(|name| name == endpoint)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub alert: String,
+    pub status: AlertStatus,
+    pub endpoint: String,
+    pub address: String,
+    pub location: Option<String>,
+    pub probe_type: &'static str,
+    pub ip_version: Option<&'static str>,
+    pub summary: String,
+    pub error: Option<String>,
+    pub started_at: f64,
+    pub timestamp: f64,
+    pub duration_seconds: f64,
+}
+
+impl Notification {
+    // The endpoint name together with the IP version it was probed over, if any.
+    fn target(&self) -> String {
+        status::key(&self.endpoint, self.ip_version)
+    }
+
+    fn message(&self, template: Option<&str>) -> String {
+        let template = template.unwrap_or(match self.status {
+            AlertStatus::Firing => FIRING_MESSAGE,
+            AlertStatus::Resolved => RESOLVED_MESSAGE,
+        });
+        let location = self.location.as_deref().unwrap_or_default();
+        let at_location = if location.is_empty() {
+            String::new()
+        } else {
+            format!(" in {}", location)
+        };
+
+        [
+            ("{alert}", self.alert.as_str()),
+            ("{status}", self.status.as_str()),
+            ("{endpoint}", self.endpoint.as_str()),
+            ("{address}", self.address.as_str()),
+            ("{location}", location),
+            ("{at_location}", at_location.as_str()),
+            ("{probe_type}", self.probe_type),
+            ("{ip_version}", self.ip_version.unwrap_or_default()),
+            ("{summary}", self.summary.as_str()),
+            ("{error}", self.error.as_deref().unwrap_or("none")),
+            ("{duration}", format_elapsed(self.duration_seconds).as_str()),
+        ]
+        .iter()
+        .fold(template.to_string(), |message, (placeholder, value)| {
+            message.replace(placeholder, value)
+        })
+    }
+}
+
+impl AlertStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AlertStatus::Firing => "firing",
+            AlertStatus::Resolved => "resolved",
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    failures: u32,
+    failing_since: Option<f64>,
+    latencies: VecDeque<(f64, f64)>,
+    firing_since: Option<f64>,
+}
+
+// Evaluates every rule against each probe result as it is published on the status board.
+pub async fn run(
+    mut events: broadcast::Receiver<ProbeEvent>,
+    mut config: watch::Receiver<AlertConfig>,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let mut evaluator = Evaluator::new(config.borrow().clone());
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => evaluator.handle(event),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Alert evaluation fell behind and skipped {} probe results", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            changed = config.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                evaluator.update(config.borrow().clone());
+            }
+            Some(_) = evaluator.notifications.join_next() => {}
+            _ = &mut stop => break,
+        }
+    }
+
+    // Results published by the last probes are still evaluated, and notifications already on their way get to finish.
+    while let Ok(event) = events.try_recv() {
+        evaluator.handle(event);
+    }
+    while evaluator.notifications.join_next().await.is_some() {}
+}
+
+struct Evaluator {
+    config: AlertConfig,
+    states: HashMap<(String, String), State>,
+    recent: HashMap<String, VecDeque<ProbeEvent>>,
+    notifications: JoinSet<()>,
+}
+
+impl Evaluator {
+    fn new(config: AlertConfig) -> Self {
+        Evaluator {
+            config,
+            states: HashMap::new(),
+            recent: HashMap::new(),
+            notifications: JoinSet::new(),
+        }
+    }
+
+    fn handle(&mut self, event: ProbeEvent) {
+        if event.maintenance {
+            return;
+        }
+        let history = self.recent.entry(event.key()).or_default();
+        if history.len() == RECENT_LEN {
+            history.pop_front();
+        }
+        history.push_back(event.clone());
+
+        for rule in self
+            .config
+            .rules
+            .iter()
+            .filter(|rule| rule.applies_to(&event.name))
+        {
+            let state = self
+                .states
+                .entry((rule.name.clone(), event.key()))
+                .or_default();
+            if let Some(notification) = evaluate(rule, state, &event) {
+                notify(
+                    &mut self.notifications,
+                    rule,
+                    notification,
+                    self.config.smtp.as_ref(),
+                    history,
+                );
+            }
+        }
+    }
+
+    // Rules that changed start over, so a stricter threshold does not inherit old state. Endpoints that are no longer
+    // probed are forgotten.
+    fn update(&mut self, config: AlertConfig) {
+        let current = &self.config;
+        self.states.retain(|(name, key), _| {
+            let old = current.rules.iter().find(|rule| rule.name == *name);
+            let new = config.rules.iter().find(|rule| rule.name == *name);
+            old.is_some() && old == new && config.endpoints.contains(key)
+        });
+        self.recent.retain(|key, _| config.endpoints.contains(key));
+        self.config = config;
+    }
+}
+
+fn evaluate(rule: &AlertRule, state: &mut State, event: &ProbeEvent) -> Option<Notification> {
+    // A firing alert only resolves once the endpoint is up again, which takes `recovery_threshold` successful probes.
+    let down = state.firing_since.is_some() && !event.up;
+    let summary = if let Some(threshold) = rule.consecutive_failures {
+        state.failures = if event.error.is_none() { 0 } else { state.failures + 1 };
+        state.failing_since = match state.failures {
+            0 => None,
+            1 => Some(event.timestamp),
+            _ => state.failing_since,
+        };
+        (state.failures >= threshold || down).then(|| format!("{} consecutive failures", state.failures))
+    } else {
+        let threshold = rule.latency_above?.as_secs_f64();
+        if let Some(rtt) = event.rtt_seconds {
+            state.latencies.push_back((event.timestamp, rtt));
+        }
+        let oldest = event.timestamp - rule.window.as_secs_f64();
+        while state
+            .latencies
+            .front()
+            .is_some_and(|(timestamp, _)| *timestamp < oldest)
+        {
+            state.latencies.pop_front();
+        }
+
+        quantile(&state.latencies, rule.quantile)
+            .filter(|latency| *latency > threshold)
+            .map(|latency| {
+                format!(
+                    "p{} latency of {:.1}ms is above {:.1}ms over the last {}",
+                    (rule.quantile * 1000.0).round() / 10.0,
+                    latency * 1000.0,
+                    threshold * 1000.0,
+                    duration::format(&rule.window)
+                )
+            })
+            .or_else(|| down.then(|| "the endpoint is down".to_string()))
+    };
+
+    // Failure alerts date back to the first failed probe, since that is when the outage began.
+    let (status, started_at) = match (summary.is_some(), state.firing_since) {
+        (true, None) => {
+            let started_at = state.failing_since.unwrap_or(event.timestamp);
+            state.firing_since = Some(started_at);
+            (AlertStatus::Firing, started_at)
+        }
+        (false, Some(started_at)) => {
+            state.firing_since = None;
+            (AlertStatus::Resolved, started_at)
+        }
+        _ => return None,
+    };
+
+    Some(Notification {
+        alert: rule.name.clone(),
+        status,
+        endpoint: event.name.clone(),
+        address: event.address.clone(),
+        location: event.location.clone(),
+        probe_type: event.probe_type,
+        ip_version: event.ip_version,
+        summary: summary.unwrap_or_else(|| "recovered".to_string()),
+        error: event.error.clone(),
+        started_at,
+        timestamp: event.timestamp,
+        duration_seconds: event.timestamp - started_at,
+    })
+}
+
+fn quantile(latencies: &VecDeque<(f64, f64)>, quantile: f64) -> Option<f64> {
+    let mut values: Vec<f64> = latencies.iter().map(|(_, latency)| *latency).collect();
+    values.sort_by(f64::total_cmp);
+
+    let index = ((quantile * values.len() as f64).ceil() as usize).max(1) - 1;
+    values.get(index).copied()
+}
+
+fn format_elapsed(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+fn email(notification: &Notification, to: &[String], recent: &VecDeque<ProbeEvent>) -> Email {
+    let mut body = format!(
+        "Alert: {}\nStatus: {}\nEndpoint: {} ({})\nLocation: {}\nProbe: {}\nSummary: {}\nLast error: {}\n",
+        notification.alert,
+        notification.status.as_str(),
+        notification.target(),
+        notification.address,
+        notification.location.as_deref().unwrap_or("-"),
+        notification.probe_type,
+        notification.summary,
+        notification.error.as_deref().unwrap_or("none"),
+    );
+    body.push_str(&format!(
+        "Since: {} ({})\n\nRecent probes:\n",
+        smtp::format_timestamp(notification.started_at),
+        format_elapsed(notification.duration_seconds)
+    ));
+    for event in recent.iter().rev() {
+        let result = match (&event.rtt_seconds, &event.error) {
+            (Some(rtt), _) => format!("up    {:.1}ms", rtt * 1000.0),
+            (None, Some(error)) => format!("down  {}", error),
+            (None, None) => "up".to_string(),
+        };
+        body.push_str(&format!("  {}  {}\n", smtp::format_timestamp(event.timestamp), result));
+    }
+
+    Email {
+        to: to.to_vec(),
+        subject: format!(
+            "[rust-net-stab] {} is {} for {}",
+            notification.alert,
+            notification.status.as_str(),
+            notification.target()
+        ),
+        body,
+    }
+}
+
+fn notify(
+    notifications: &mut JoinSet<()>,
+    rule: &AlertRule,
+    notification: Notification,
+    smtp: Option<&SmtpConfig>,
+    recent: &VecDeque<ProbeEvent>,
+) {
+    warn!(
+        "Alert `{}` is {} for {}: {}",
+        notification.alert,
+        notification.status.as_str(),
+        notification.target(),
+        notification.summary
+    );
+
+    let message = notification.message(rule.message.as_deref());
+    let targets = [
+        (&rule.webhook, serde_json::to_value(&notification)),
+        (&rule.slack, Ok(serde_json::json!({ "text": message }))),
+        (&rule.discord, Ok(serde_json::json!({ "content": message }))),
+    ];
+    if let (Some(smtp), false) = (smtp, rule.email.is_empty()) {
+        let smtp = smtp.clone();
+        let email = email(&notification, &rule.email, recent);
+        let alert = notification.alert.clone();
+        notifications.spawn(async move {
+            match smtp::send(&smtp, &email).await {
+                Ok(()) => info!("Mailed alert `{}` to {}", alert, email.to.join(", ")),
+                Err(err) => error!("Failed to mail alert `{}` to {}: {}", alert, email.to.join(", "), err),
+            }
+        });
+    }
+
+    for (url, body) in targets {
+        let url = match url {
+            Some(url) => url.clone(),
+            None => continue,
+        };
+        let body = match body {
+            Ok(body) => body.to_string().into_bytes(),
+            Err(err) => {
+                error!("Failed to encode alert `{}`: {}", notification.alert, err);
+                continue;
+            }
+        };
+
+        let alert = notification.alert.clone();
+        notifications.spawn(async move {
+            match crate::http::post(&url, "application/json", &BTreeMap::new(), body).await {
+                Ok(()) => info!("Sent alert `{}` to {}", alert, url),
+                Err(err) => error!("Failed to send alert `{}` to {}: {}", alert, url, err),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(yaml: &str) -> AlertRule {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn event(name: &str, timestamp: f64, up: bool, rtt_seconds: Option<f64>) -> ProbeEvent {
+        ProbeEvent {
+            name: name.to_string(),
+            address: "192.0.2.1".to_string(),
+            location: None,
+            probe_type: "icmp",
+            ip_version: None,
+            up,
+            rtt_seconds,
+            loss_ratio: 0.0,
+            error: rtt_seconds.is_none().then(|| "timed out".to_string()),
+            reason: None,
+            maintenance: false,
+            timestamp,
+            trace_id: None,
+        }
+    }
+
+    #[test]
+    fn resolves_consecutive_failures_once_up_again() {
+        let rule = rule("name: down\nconsecutive_failures: 2\n");
+        let mut state = State::default();
+        assert!(evaluate(&rule, &mut state, &event("gw", 1.0, true, None)).is_none());
+        let firing = evaluate(&rule, &mut state, &event("gw", 2.0, false, None)).unwrap();
+        assert_eq!((firing.status, firing.started_at), (AlertStatus::Firing, 1.0));
+        assert_eq!(firing.summary, "2 consecutive failures");
+
+        // Still down until enough probes succeeded for the endpoint to recover.
+        assert!(evaluate(&rule, &mut state, &event("gw", 3.0, false, Some(0.01))).is_none());
+        let resolved = evaluate(&rule, &mut state, &event("gw", 4.0, true, Some(0.01))).unwrap();
+        assert_eq!(resolved.status, AlertStatus::Resolved);
+        assert_eq!(resolved.duration_seconds, 3.0);
+    }
+
+    #[test]
+    fn keeps_latency_alerts_firing_while_down() {
+        let rule = rule("name: slow\nlatency_above: 100ms\nquantile: 0.5\nwindow: 60s\n");
+        let mut state = State::default();
+        let firing = evaluate(&rule, &mut state, &event("gw", 1.0, true, Some(0.2))).unwrap();
+        assert_eq!(firing.status, AlertStatus::Firing);
+        assert_eq!(
+            firing.summary,
+            "p50 latency of 200.0ms is above 100.0ms over the last 60s"
+        );
+
+        assert!(evaluate(&rule, &mut state, &event("gw", 2.0, false, None)).is_none());
+        // The window has no latencies left, which does not make the endpoint fast.
+        assert!(evaluate(&rule, &mut state, &event("gw", 100.0, false, None)).is_none());
+        let resolved = evaluate(&rule, &mut state, &event("gw", 101.0, true, Some(0.01))).unwrap();
+        assert_eq!(resolved.status, AlertStatus::Resolved);
+    }
+
+    #[test]
+    fn picks_the_quantile_of_the_window() {
+        let latencies: VecDeque<(f64, f64)> = [3.0, 1.0, 4.0, 2.0]
+            .iter()
+            .map(|latency| (0.0, *latency))
+            .collect();
+        assert_eq!(quantile(&latencies, 0.0), Some(1.0));
+        assert_eq!(quantile(&latencies, 0.5), Some(2.0));
+        assert_eq!(quantile(&latencies, 0.95), Some(4.0));
+        assert_eq!(quantile(&latencies, 1.0), Some(4.0));
+        assert_eq!(quantile(&VecDeque::new(), 0.5), None);
+    }
+
+    #[test]
+    fn forgets_removed_endpoints() {
+        let config = AlertConfig {
+            rules: vec![rule("name: down\nconsecutive_failures: 2\n")],
+            smtp: None,
+            endpoints: ["gw", "old"].iter().map(|key| key.to_string()).collect(),
+        };
+        let mut evaluator = Evaluator::new(config.clone());
+        evaluator.handle(event("gw", 1.0, true, Some(0.01)));
+        evaluator.handle(event("old", 1.0, true, Some(0.01)));
+        assert_eq!(evaluator.states.len(), 2);
+
+        evaluator.update(AlertConfig {
+            endpoints: ["gw"].iter().map(|key| key.to_string()).collect(),
+            ..config
+        });
+        assert!(evaluator
+            .states
+            .contains_key(&("down".to_string(), "gw".to_string())));
+        assert_eq!(evaluator.states.len(), 1);
+        assert_eq!(evaluator.recent.keys().collect::<Vec<_>>(), ["gw"]);
+    }
+}
@@ -0,0 +1,418 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use crate::source::Source;
+
+// A host that answered an ARP request, with the hardware address it answered with.
+pub struct Neighbor {
+    pub ip: Ipv4Addr,
+    pub mac: [u8; 6],
+    pub rtt: Duration,
+}
+
+// Asks for the hardware address of a neighbor on the local network, over ARP for IPv4 and NDP for IPv6, and returns
+// how long the answer took. Devices that drop ICMP still have to answer these to be reachable at all.
+#[cfg(target_os = "linux")]
+pub async fn request(ip: IpAddr, timeout: Duration, source: Source) -> io::Result<Duration> {
+    tokio::task::spawn_blocking(move || linux::request(ip, timeout, &source))
+        .await
+        .map_err(io::Error::other)?
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn request(_ip: IpAddr, _timeout: Duration, _source: Source) -> io::Result<Duration> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the arp probe is only supported on Linux",
+    ))
+}
+
+// Asks for the hardware address of every one of the addresses, which have to be on a single directly connected network,
+// and returns the neighbors that answered within `timeout` of being asked.
+#[cfg(target_os = "linux")]
+pub async fn sweep(ips: Vec<Ipv4Addr>, timeout: Duration) -> io::Result<Vec<Neighbor>> {
+    tokio::task::spawn_blocking(move || linux::sweep(&ips, timeout))
+        .await
+        .map_err(io::Error::other)?
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn sweep(_ips: Vec<Ipv4Addr>, _timeout: Duration) -> io::Result<Vec<Neighbor>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "ARP sweeps are only supported on Linux",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::HashMap;
+    use std::convert::TryInto;
+    use std::fs;
+    use std::io;
+    use std::mem;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+    use std::ptr;
+    use std::time::{Duration, Instant};
+
+    use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+    use super::Neighbor;
+    use crate::icmp;
+    use crate::source::Source;
+
+    const AF_PACKET: u16 = 17;
+    const ETH_P_ARP: u16 = 0x0806;
+    const ETH_P_IP: u16 = 0x0800;
+    const ARP_ETHERNET: u16 = 1;
+    const ARP_REQUEST: u16 = 1;
+    const ARP_REPLY: u16 = 2;
+    const NEIGHBOR_SOLICITATION: u8 = 135;
+    const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+    const SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+    // Neighbor discovery messages from anywhere but the link itself are dropped by the receiver.
+    const NDP_HOP_LIMIT: u32 = 255;
+    const BROADCAST: [u8; 6] = [0xff; 6];
+    const RTF_REJECT: u32 = 0x0200;
+    // Between the requests of a sweep, so that a large network is not flooded with broadcasts.
+    const SWEEP_PACING: Duration = Duration::from_millis(1);
+
+    // `struct sockaddr_ll` of <linux/if_packet.h>.
+    #[repr(C)]
+    struct LinkAddress {
+        family: u16,
+        protocol: u16,
+        index: i32,
+        hardware_type: u16,
+        packet_type: u8,
+        length: u8,
+        address: [u8; 8],
+    }
+
+    // The interface a neighbor is reached on, and the addresses requests for it are sent from.
+    struct Link {
+        name: String,
+        index: u32,
+        mac: [u8; 6],
+        local: IpAddr,
+    }
+
+    pub(super) fn request(ip: IpAddr, timeout: Duration, source: &Source) -> io::Result<Duration> {
+        let link = link(ip, source)?;
+        match (ip, link.local) {
+            (IpAddr::V4(ip), IpAddr::V4(local)) => arp(ip, local, &link, timeout),
+            (IpAddr::V6(ip), IpAddr::V6(local)) => ndp(ip, local, &link, timeout),
+            (_, local) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("source_ip {} cannot reach {}", local, ip),
+            )),
+        }
+    }
+
+    fn arp(ip: Ipv4Addr, local: Ipv4Addr, link: &Link, timeout: Duration) -> io::Result<Duration> {
+        let socket = arp_socket(link)?;
+        let start = Instant::now();
+        socket.send_to(&arp_request(ip, local, link), &link_address(link.index, BROADCAST)?)?;
+        wait(&socket, start + timeout, |packet| {
+            arp_reply(packet).is_some_and(|(sender, _)| sender == ip)
+        })?;
+        Ok(start.elapsed())
+    }
+
+    pub(super) fn sweep(ips: &[Ipv4Addr], timeout: Duration) -> io::Result<Vec<Neighbor>> {
+        let first = match ips.first() {
+            Some(ip) => IpAddr::V4(*ip),
+            None => return Ok(Vec::new()),
+        };
+        let link = link(first, &Source::default())?;
+        let local = match link.local {
+            IpAddr::V4(local) => local,
+            local => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} cannot reach {}", local, first),
+                ))
+            }
+        };
+        let socket = arp_socket(&link)?;
+        // Room for the replies of a busy network, which are read in between sending.
+        socket.set_recv_buffer_size(1 << 20)?;
+        let broadcast = link_address(link.index, BROADCAST)?;
+
+        let mut asked = HashMap::new();
+        let mut neighbors = Vec::new();
+        let mut buffer = [0u8; 1500];
+        let mut receive = |asked: &mut HashMap<Ipv4Addr, Instant>, until: Instant| -> io::Result<()> {
+            loop {
+                let remaining = until.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Ok(());
+                }
+                socket.set_read_timeout(Some(remaining))?;
+                let len = match icmp::recv_from(&socket, &mut buffer) {
+                    Ok((len, _)) => len,
+                    Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                    Err(err) => return Err(err),
+                };
+                if let Some((ip, mac)) = arp_reply(&buffer[..len]) {
+                    if let Some(sent) = asked.remove(&ip) {
+                        neighbors.push(Neighbor {
+                            ip,
+                            mac,
+                            rtt: sent.elapsed(),
+                        });
+                    }
+                }
+            }
+        };
+
+        for ip in ips {
+            socket.send_to(&arp_request(*ip, local, &link), &broadcast)?;
+            asked.insert(*ip, Instant::now());
+            receive(&mut asked, Instant::now() + SWEEP_PACING)?;
+        }
+        receive(&mut asked, Instant::now() + timeout)?;
+        Ok(neighbors)
+    }
+
+    // A datagram packet socket leaves the Ethernet header to the kernel.
+    fn arp_socket(link: &Link) -> io::Result<Socket> {
+        let protocol = Protocol::from(i32::from(ETH_P_ARP.to_be()));
+        let socket = Socket::new(Domain::PACKET, Type::DGRAM, Some(protocol))?;
+        socket.bind(&link_address(link.index, [0; 6])?)?;
+        Ok(socket)
+    }
+
+    fn arp_request(ip: Ipv4Addr, local: Ipv4Addr, link: &Link) -> Vec<u8> {
+        let mut request = Vec::with_capacity(28);
+        request.extend_from_slice(&ARP_ETHERNET.to_be_bytes());
+        request.extend_from_slice(&ETH_P_IP.to_be_bytes());
+        request.extend_from_slice(&[6, 4]);
+        request.extend_from_slice(&ARP_REQUEST.to_be_bytes());
+        request.extend_from_slice(&link.mac);
+        request.extend_from_slice(&local.octets());
+        request.extend_from_slice(&[0; 6]);
+        request.extend_from_slice(&ip.octets());
+        request
+    }
+
+    // The address and hardware address of the sender of an ARP reply.
+    fn arp_reply(packet: &[u8]) -> Option<(Ipv4Addr, [u8; 6])> {
+        if packet.len() < 28 || packet[6..8] != ARP_REPLY.to_be_bytes() {
+            return None;
+        }
+        let ip: [u8; 4] = packet[14..18].try_into().ok()?;
+        Some((Ipv4Addr::from(ip), packet[8..14].try_into().ok()?))
+    }
+
+    fn ndp(ip: Ipv6Addr, local: Ipv6Addr, link: &Link, timeout: Duration) -> io::Result<Duration> {
+        let socket = Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?;
+        socket.bind_device(Some(link.name.as_bytes()))?;
+        socket.bind(&SocketAddr::V6(SocketAddrV6::new(local, 0, 0, scope(local, link))).into())?;
+        socket.set_multicast_if_v6(link.index)?;
+        socket.set_multicast_hops_v6(NDP_HOP_LIMIT)?;
+        socket.set_unicast_hops_v6(NDP_HOP_LIMIT)?;
+
+        // The kernel fills in the checksum, as for echo requests.
+        let mut solicitation = vec![NEIGHBOR_SOLICITATION, 0, 0, 0, 0, 0, 0, 0];
+        solicitation.extend_from_slice(&ip.octets());
+        solicitation.extend_from_slice(&[SOURCE_LINK_LAYER_ADDRESS, 1]);
+        solicitation.extend_from_slice(&link.mac);
+
+        // The solicited-node multicast group of the address, which only its owner and few others listen to.
+        let octets = ip.octets();
+        let group = Ipv6Addr::new(
+            0xff02,
+            0,
+            0,
+            0,
+            0,
+            1,
+            0xff00 | u16::from(octets[13]),
+            u16::from_be_bytes([octets[14], octets[15]]),
+        );
+        let group = SocketAddr::V6(SocketAddrV6::new(group, 0, 0, link.index));
+
+        let start = Instant::now();
+        socket.send_to(&solicitation, &group.into())?;
+        wait(&socket, start + timeout, |packet| {
+            packet.len() >= 24 && packet[0] == NEIGHBOR_ADVERTISEMENT && packet[8..24] == ip.octets()
+        })?;
+        Ok(start.elapsed())
+    }
+
+    fn wait(socket: &Socket, deadline: Instant, answers: impl Fn(&[u8]) -> bool) -> io::Result<()> {
+        let mut buffer = [0u8; 1500];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "neighbor did not answer"));
+            }
+            socket.set_read_timeout(Some(remaining))?;
+
+            match icmp::recv_from(socket, &mut buffer) {
+                Ok((len, _)) if answers(&buffer[..len]) => return Ok(()),
+                Ok(_) => {}
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn link(ip: IpAddr, source: &Source) -> io::Result<Link> {
+        let name = match &source.interface {
+            Some(interface) => interface.clone(),
+            None => on_link_interface(ip)?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NetworkUnreachable,
+                    format!(
+                        "{} is not on a directly connected network, set source_interface if it is",
+                        ip
+                    ),
+                )
+            })?,
+        };
+        let read = |file: &str| {
+            let path = format!("/sys/class/net/{}/{}", name, file);
+            fs::read_to_string(&path)
+                .map(|value| value.trim().to_string())
+                .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path, err)))
+        };
+        let invalid = |what: &str, value: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid {} `{}` of interface {}", what, value, name),
+            )
+        };
+
+        let index = read("ifindex")?;
+        let index = index.parse().map_err(|_| invalid("index", &index))?;
+        let address = read("address")?;
+        let parts: Vec<u8> = address
+            .split(':')
+            .filter_map(|part| u8::from_str_radix(part, 16).ok())
+            .collect();
+        let mac: [u8; 6] = parts
+            .as_slice()
+            .try_into()
+            .map_err(|_| invalid("hardware address", &address))?;
+
+        let mut link = Link {
+            name,
+            index,
+            mac,
+            local: ip,
+        };
+        link.local = match source.ip {
+            Some(local) => local,
+            None => local_address(ip, &link)?,
+        };
+        Ok(link)
+    }
+
+    // The address the system would send from, found by connecting a UDP socket, which sends nothing.
+    fn local_address(ip: IpAddr, link: &Link) -> io::Result<IpAddr> {
+        let (domain, target) = match ip {
+            IpAddr::V4(ip) => (Domain::IPV4, SocketAddr::new(ip.into(), 9)),
+            IpAddr::V6(ip) => (Domain::IPV6, SocketAddr::V6(SocketAddrV6::new(ip, 9, 0, link.index))),
+        };
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.bind_device(Some(link.name.as_bytes()))?;
+        socket.connect(&target.into())?;
+        socket
+            .local_addr()?
+            .as_socket()
+            .map(|local| local.ip())
+            .ok_or_else(|| io::Error::other("no local address"))
+    }
+
+    fn scope(local: Ipv6Addr, link: &Link) -> u32 {
+        if local.segments()[0] & 0xffc0 == 0xfe80 {
+            link.index
+        } else {
+            0
+        }
+    }
+
+    fn link_address(index: u32, mac: [u8; 6]) -> io::Result<SockAddr> {
+        let mut address = [0; 8];
+        address[..6].copy_from_slice(&mac);
+        let address = LinkAddress {
+            family: AF_PACKET,
+            protocol: ETH_P_ARP.to_be(),
+            index: index as i32,
+            hardware_type: 0,
+            packet_type: 0,
+            length: 6,
+            address,
+        };
+        // SAFETY: the storage is large and aligned enough for any socket address, and the length is that of the one
+        // written to it.
+        let ((), address) = unsafe {
+            SockAddr::init(|storage, length| {
+                ptr::write(storage as *mut LinkAddress, address);
+                *length = mem::size_of::<LinkAddress>() as _;
+                Ok(())
+            })
+        }?;
+        Ok(address)
+    }
+
+    // The interface of the most specific route to the address that does not go through a gateway.
+    fn on_link_interface(ip: IpAddr) -> io::Result<Option<String>> {
+        let mut best: Option<(u32, String)> = None;
+        let mut consider = |prefix: u32, interface: &str| {
+            if interface != "lo" && best.as_ref().is_none_or(|(best, _)| prefix > *best) {
+                best = Some((prefix, interface.to_string()));
+            }
+        };
+
+        match ip {
+            // Iface, Destination, Gateway, Flags, RefCnt, Use, Metric, Mask, with addresses as hexadecimal numbers in
+            // host byte order.
+            IpAddr::V4(ip) => {
+                let target = u32::from_ne_bytes(ip.octets());
+                for line in fs::read_to_string("/proc/net/route")?.lines().skip(1) {
+                    let fields: Vec<&str> = line.split_whitespace().collect();
+                    let number = |index: usize| {
+                        fields
+                            .get(index)
+                            .and_then(|field| u32::from_str_radix(field, 16).ok())
+                    };
+                    if let (Some(destination), Some(0), Some(flags), Some(mask)) =
+                        (number(1), number(2), number(3), number(7))
+                    {
+                        if flags & RTF_REJECT == 0 && target & mask == destination {
+                            consider(mask.count_ones(), fields[0]);
+                        }
+                    }
+                }
+            }
+            // Destination, prefix length, source, source prefix length, next hop, metric, reference count, use,
+            // flags and device, with addresses as hexadecimal bytes.
+            IpAddr::V6(ip) => {
+                let target = u128::from_be_bytes(ip.octets());
+                for line in fs::read_to_string("/proc/net/ipv6_route")?.lines() {
+                    let fields: Vec<&str> = line.split_whitespace().collect();
+                    if fields.len() < 10 {
+                        continue;
+                    }
+                    let parsed = (
+                        u128::from_str_radix(fields[0], 16),
+                        u32::from_str_radix(fields[1], 16),
+                        u128::from_str_radix(fields[4], 16),
+                        u32::from_str_radix(fields[8], 16),
+                    );
+                    if let (Ok(destination), Ok(prefix @ 0..=128), Ok(0), Ok(flags)) = parsed {
+                        let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+                        if flags & RTF_REJECT == 0 && target & mask == destination {
+                            consider(prefix, fields[9]);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(best.map(|(_, interface)| interface))
+    }
+}
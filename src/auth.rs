@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{config, http};
+
+// Credentials every request to the HTTP server has to carry, either basic auth or a bearer token, or either of them
+// when both are set. The targets API and agents pushing to an aggregator check their own tokens instead.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    pub username: Option<String>,
+    pub password_env: Option<String>,
+    pub password_file: Option<String>,
+    pub token_env: Option<String>,
+    pub token_file: Option<String>,
+}
+
+impl AuthConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.password_env.is_some() && self.password_file.is_some() {
+            return Err("set only one of password_env or password_file".to_string());
+        }
+        if self.token_env.is_some() && self.token_file.is_some() {
+            return Err("set only one of token_env or token_file".to_string());
+        }
+        let password = config::secret("password", &self.password_env, &self.password_file)?;
+        match (&self.username, &password) {
+            (Some(_), None) => return Err("a username is set but no password".to_string()),
+            (None, Some(_)) => return Err("a password is set but no username".to_string()),
+            (_, Some(password)) if password.is_empty() => return Err("the password is empty".to_string()),
+            _ => {}
+        }
+        let token = config::secret("token", &self.token_env, &self.token_file)?;
+        if token.as_deref() == Some("") {
+            return Err("the token is empty".to_string());
+        }
+        if password.is_none() && token.is_none() {
+            return Err("set a username and password, a token, or both".to_string());
+        }
+        Ok(())
+    }
+}
+
+// The `Authorization` headers that are let through, read once when the server starts.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    accepted: Vec<String>,
+    basic: bool,
+}
+
+impl Credentials {
+    pub(crate) fn new(config: &AuthConfig) -> Result<Credentials, String> {
+        let mut accepted = Vec::new();
+        if let (Some(username), Some(password)) = (
+            &config.username,
+            config::secret("password", &config.password_env, &config.password_file)?,
+        ) {
+            accepted.push(http::basic_auth(username, &password));
+        }
+        if let Some(token) = config::secret("token", &config.token_env, &config.token_file)? {
+            accepted.push(format!("Bearer {}", token));
+        }
+        let basic = config.username.is_some();
+        Ok(Credentials { accepted, basic })
+    }
+
+    // Whether basic auth is accepted, which browsers can be asked for.
+    pub(crate) fn basic(&self) -> bool {
+        self.basic
+    }
+
+    pub(crate) fn allow(&self, authorization: Option<&str>) -> bool {
+        authorization.is_some_and(|authorization| {
+            self.accepted
+                .iter()
+                .any(|accepted| same(authorization.as_bytes(), accepted.as_bytes()))
+        })
+    }
+}
+
+// Compares every byte rather than stopping at the first difference, so the time it takes says nothing about the token.
+pub(crate) fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(yaml: &str) -> AuthConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn allows_only_the_configured_credentials() {
+        std::env::set_var("NETSTAB_TEST_AUTH_PASSWORD", "secret");
+        std::env::set_var("NETSTAB_TEST_AUTH_TOKEN", "t0ken");
+        let config =
+            auth("username: admin\npassword_env: NETSTAB_TEST_AUTH_PASSWORD\ntoken_env: NETSTAB_TEST_AUTH_TOKEN\n");
+        assert_eq!(config.validate(), Ok(()));
+        let credentials = Credentials::new(&config).unwrap();
+        assert!(credentials.basic());
+        assert!(credentials.allow(Some(&http::basic_auth("admin", "secret"))));
+        assert!(credentials.allow(Some("Bearer t0ken")));
+        assert!(!credentials.allow(Some(&http::basic_auth("admin", "guess"))));
+        assert!(!credentials.allow(Some("Bearer t0ke")));
+        assert!(!credentials.allow(Some("Bearer t0ken ")));
+        assert!(!credentials.allow(Some("")));
+        assert!(!credentials.allow(None));
+    }
+
+    #[test]
+    fn compares_whole_values() {
+        assert!(same(b"token", b"token"));
+        assert!(same(b"", b""));
+        assert!(!same(b"token", b"tokem"));
+        assert!(!same(b"token", b"tok"));
+        assert!(!same(b"", b"token"));
+    }
+
+    #[test]
+    fn rejects_empty_secrets() {
+        std::env::set_var("NETSTAB_TEST_AUTH_EMPTY", "");
+        assert_eq!(
+            auth("token_env: NETSTAB_TEST_AUTH_EMPTY\n").validate(),
+            Err("the token is empty".to_string())
+        );
+        assert_eq!(
+            auth("username: admin\npassword_env: NETSTAB_TEST_AUTH_EMPTY\n").validate(),
+            Err("the password is empty".to_string())
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use std::process;
+
+use rust_net_stab::cli::{Cli, Command};
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    // The dashboard takes over the terminal, log lines would only garble it.
+    if !cli.tui {
+        simple_logger::init_with_level(cli.log_level).unwrap();
+    }
+
+    // Whether the command succeeded, `check` and `validate` fail without an error when they find problems.
+    let result = match &cli.command {
+        Command::Run => rust_net_stab::run(cli).await.map(|()| true),
+        Command::Check { json } => rust_net_stab::check::run(&cli.config, *json).await,
+        Command::Report(options) => rust_net_stab::report::print(&cli.config, options)
+            .await
+            .map(|()| true),
+        Command::Discover(options) => rust_net_stab::sweep::run(&cli.config, options)
+            .await
+            .map(|()| true),
+        Command::Validate => rust_net_stab::validate::run(&cli.config),
+        Command::Init { force } => rust_net_stab::init::run(&cli.config, *force).map(|()| true),
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::net::Ipv6Addr;
+use std::net::{IpAddr, Ipv4Addr};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::config::{self, Defaults, Endpoint, ProbeType};
+use crate::duration;
+
+// Written by systemd-resolved, which points /etc/resolv.conf at its local stub instead.
+const RESOLVED_UPSTREAMS: &str = "/run/systemd/resolve/resolv.conf";
+const RESOLVED_STUB: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 53));
+
+// The default gateways and DNS resolvers of the host itself, which are probed without being listed, and followed as
+// DHCP changes them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuiltinTargets {
+    // Pinged, one endpoint for each default route.
+    #[serde(default)]
+    pub gateway: bool,
+    // Asked for `query` with the dns probe, one endpoint for each nameserver.
+    #[serde(default)]
+    pub dns: bool,
+    #[serde(default = "default_query")]
+    pub query: String,
+    #[serde(default = "default_refresh_interval", with = "duration")]
+    pub refresh_interval: Duration,
+    #[serde(default)]
+    pub defaults: Defaults,
+}
+
+fn default_query() -> String {
+    "example.com".to_string()
+}
+
+fn default_refresh_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl BuiltinTargets {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.gateway && !self.dns {
+            return Err("set gateway, dns or both".to_string());
+        }
+        if self.dns && cfg!(windows) {
+            return Err("dns is not supported on Windows, which has no /etc/resolv.conf".to_string());
+        }
+        if !config::valid_hostname(&self.query) {
+            return Err(format!("`{}` is not a valid DNS name", self.query));
+        }
+        if self.refresh_interval.is_zero() {
+            return Err("refresh_interval must be longer than 0s".to_string());
+        }
+        Ok(())
+    }
+}
+
+// A gateway, along with the interface it is on, which link-local gateways can only be reached through.
+#[derive(Debug, PartialEq)]
+struct Gateway {
+    ip: IpAddr,
+    interface: Option<String>,
+}
+
+pub(crate) async fn lookup(config: &BuiltinTargets) -> Result<Vec<Endpoint>, String> {
+    let mut endpoints = Vec::new();
+    if config.gateway {
+        for gateway in gateways().await? {
+            let link_local = is_link_local(gateway.ip);
+            let interface = gateway.interface.filter(|_| link_local);
+            let name = match &interface {
+                Some(interface) => format!("gateway {}%{}", gateway.ip, interface),
+                None => format!("gateway {}", gateway.ip),
+            };
+            let mut endpoint = Endpoint::new(name, gateway.ip.to_string());
+            endpoint.probe = Some(ProbeType::Icmp);
+            endpoint.source_interface = interface;
+            endpoint.inherit(&config.defaults);
+            endpoints.push(endpoint);
+        }
+    }
+    if config.dns {
+        for ip in nameservers()? {
+            let mut endpoint = Endpoint::new(format!("dns {}", ip), config.query.clone());
+            endpoint.probe = Some(ProbeType::Dns);
+            endpoint.resolver = Some(ip.to_string());
+            endpoint.inherit(&config.defaults);
+            endpoints.push(endpoint);
+        }
+    }
+    Ok(endpoints)
+}
+
+// The gateways of the default routes in the kernel's routing tables.
+#[cfg(target_os = "linux")]
+async fn gateways() -> Result<Vec<Gateway>, String> {
+    const RTF_UP: u32 = 0x1;
+    const RTF_GATEWAY: u32 = 0x2;
+    let read = |path: &str| fs::read_to_string(path).map_err(|err| format!("failed to read {}: {}", path, err));
+    let mut gateways = Vec::new();
+    let mut add = |gateway: Gateway| {
+        if !gateways.contains(&gateway) {
+            gateways.push(gateway);
+        }
+    };
+
+    // Interface, destination, gateway, flags, and then the rest, with the addresses as little-endian hexadecimal.
+    for line in read("/proc/net/route")?.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let hex = |index: usize| {
+            fields
+                .get(index)
+                .and_then(|field| u32::from_str_radix(field, 16).ok())
+        };
+        match (hex(1), hex(2), hex(3), hex(7)) {
+            (Some(0), Some(gateway), Some(flags), Some(0))
+                if flags & (RTF_UP | RTF_GATEWAY) == RTF_UP | RTF_GATEWAY =>
+            {
+                add(Gateway {
+                    ip: IpAddr::V4(Ipv4Addr::from(gateway.to_le_bytes())),
+                    interface: Some(fields[0].to_string()),
+                })
+            }
+            _ => {}
+        }
+    }
+    // Destination, its prefix length, source, its prefix length, next hop, metric, three counters and flags, then the
+    // interface. Hosts without IPv6 have no such file.
+    if let Ok(routes) = fs::read_to_string("/proc/net/ipv6_route") {
+        for line in routes.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 || fields[1] != "00" {
+                continue;
+            }
+            let address = |index: usize| u128::from_str_radix(fields[index], 16).ok();
+            let flags = u32::from_str_radix(fields[8], 16).unwrap_or_default();
+            match (address(0), address(4)) {
+                (Some(0), Some(next_hop))
+                    if next_hop != 0 && flags & (RTF_UP | RTF_GATEWAY) == RTF_UP | RTF_GATEWAY =>
+                {
+                    add(Gateway {
+                        ip: IpAddr::V6(Ipv6Addr::from(next_hop)),
+                        interface: Some(fields[9].to_string()),
+                    })
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(gateways)
+}
+
+// The default routes `netstat -rn` lists, as `default` followed by the gateway on macOS and the BSDs, and as a
+// destination and mask of 0.0.0.0 followed by it on Windows. Link-local gateways are left out, since they can only be
+// reached by binding to their interface.
+#[cfg(not(target_os = "linux"))]
+async fn gateways() -> Result<Vec<Gateway>, String> {
+    let output = tokio::process::Command::new("netstat")
+        .arg("-rn")
+        .output()
+        .await
+        .map_err(|err| format!("failed to run netstat: {}", err))?;
+    let mut gateways = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let gateway = match fields.as_slice() {
+            ["default", gateway, ..] => gateway,
+            ["0.0.0.0", "0.0.0.0", gateway, ..] => gateway,
+            _ => continue,
+        };
+        let gateway = match gateway.parse::<IpAddr>() {
+            Ok(ip) if !is_link_local(ip) => Gateway { ip, interface: None },
+            _ => continue,
+        };
+        if !gateways.contains(&gateway) {
+            gateways.push(gateway);
+        }
+    }
+    Ok(gateways)
+}
+
+// The nameservers of /etc/resolv.conf, or the ones systemd-resolved forwards to when it only lists the local stub.
+fn nameservers() -> Result<Vec<IpAddr>, String> {
+    let read = |path: &str| -> Result<Vec<IpAddr>, String> {
+        let resolv = fs::read_to_string(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+        let mut nameservers = Vec::new();
+        for line in resolv.lines() {
+            // Link-local nameservers come with a zone, which the dns probe cannot send to.
+            let ip = line
+                .trim()
+                .strip_prefix("nameserver")
+                .and_then(|nameserver| nameserver.trim().parse::<IpAddr>().ok());
+            if let Some(ip) = ip.filter(|ip| !nameservers.contains(ip)) {
+                nameservers.push(ip);
+            }
+        }
+        Ok(nameservers)
+    };
+
+    let nameservers = read("/etc/resolv.conf")?;
+    if nameservers == [RESOLVED_STUB] {
+        if let Ok(upstreams) = read(RESOLVED_UPSTREAMS) {
+            return Ok(upstreams);
+        }
+    }
+    Ok(nameservers)
+}
+
+fn is_link_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::config::{self, Endpoint};
+use crate::discovery::Discovery;
+use crate::metrics::Metrics;
+use crate::probe::{self, failure_reason};
+use crate::scheduler::RateLimit;
+use crate::BoxError;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub address: String,
+    pub probe_type: &'static str,
+    pub ip_version: Option<&'static str>,
+    pub success: bool,
+    pub rtt_seconds: Option<f64>,
+    pub reason: Option<&'static str>,
+    pub error: Option<String>,
+}
+
+// Probes every endpoint of the config once, at most `max_concurrency` at a time and `max_probe_rate` a second,
+// returning the results in the order of the config.
+pub async fn check(config: &config::Config) -> Result<Vec<CheckResult>, BoxError> {
+    let metrics = Metrics::register(config)?;
+    let limit = Arc::new(Semaphore::new(config.max_concurrency.unwrap_or(Semaphore::MAX_PERMITS)));
+    let rate_limit = config
+        .max_probe_rate
+        .map(|rate| Arc::new(RateLimit::new(rate)));
+
+    let mut tasks = JoinSet::new();
+    let targets: Vec<Endpoint> = config
+        .endpoints
+        .iter()
+        .flat_map(Endpoint::targets)
+        .collect();
+    for (index, endpoint) in targets.into_iter().enumerate() {
+        let settings = endpoint.settings(config);
+        let probe = probe::build(&endpoint, &settings, &metrics);
+        let limit = limit.clone();
+        let rate_limit = rate_limit.clone();
+        tasks.spawn(async move {
+            let _permit = limit.acquire().await;
+            if let Some(rate_limit) = rate_limit {
+                rate_limit.wait().await;
+            }
+            let output = probe::attempt(&*probe, &settings, || {}).await;
+
+            let result = CheckResult {
+                name: endpoint.name.clone(),
+                address: endpoint.address.clone(),
+                probe_type: endpoint.probe_type().as_str(),
+                ip_version: endpoint.ip_version.map(|ip_version| ip_version.as_str()),
+                success: output.is_ok(),
+                rtt_seconds: output.as_ref().ok().map(|rtt| rtt.as_secs_f64()),
+                reason: output.as_ref().err().map(failure_reason),
+                error: output.as_ref().err().map(|err| err.to_string()),
+            };
+            (index, result)
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        results.push(result?);
+    }
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+// Lines up the results in columns, with the error of failed probes at the end of their line.
+pub fn table(results: &[CheckResult]) -> String {
+    let rows: Vec<[String; 5]> = results
+        .iter()
+        .map(|result| {
+            [
+                crate::status::key(&result.name, result.ip_version),
+                result.address.clone(),
+                result.probe_type.to_string(),
+                if result.success { "ok" } else { "FAIL" }.to_string(),
+                result
+                    .rtt_seconds
+                    .map(|rtt| format!("{:.2} ms", rtt * 1000.0))
+                    .unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+
+    let header = ["ENDPOINT", "ADDRESS", "PROBE", "RESULT", "RTT"].map(String::from);
+    let mut widths = header.clone().map(|column| column.len());
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    let mut line = |row: &[String; 5], error: Option<&str>| {
+        let mut line = String::new();
+        for (column, width) in row.iter().zip(widths) {
+            line.push_str(&format!("{:<width$}  ", column, width = width));
+        }
+        line.push_str(error.unwrap_or_default());
+        table.push_str(line.trim_end());
+        table.push('\n');
+    };
+    line(&header, Some("ERROR"));
+    for (row, result) in rows.iter().zip(results) {
+        line(row, result.error.as_deref());
+    }
+    table
+}
+
+// The `check` command. Returns whether every probe succeeded, so the binary can exit accordingly.
+pub async fn run(config_path: &str, json: bool) -> Result<bool, BoxError> {
+    let config = config::load(config_path)?;
+    let config = Discovery::start(&config).await.merge(&config);
+    let results = check(&config).await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        print!("{}", table(&results));
+    }
+    Ok(results.iter().all(|result| result.success))
+}
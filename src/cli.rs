@@ -0,0 +1,289 @@
+use std::net::SocketAddr;
+
+use clap::{Args, Parser, Subcommand};
+use log::Level;
+
+use crate::report::{self, ReportOptions};
+use crate::sweep::SweepOptions;
+
+#[derive(Debug)]
+pub struct Cli {
+    pub command: Command,
+    pub config: String,
+    pub listen: Option<SocketAddr>,
+    pub log_level: Level,
+    pub tui: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run,
+    Check { json: bool },
+    Report(ReportOptions),
+    Discover(SweepOptions),
+    Validate,
+    Init { force: bool },
+}
+
+// The arguments as clap parses them, turned into a `Cli` once they are known to fit together.
+#[derive(Debug, Parser)]
+#[command(
+    name = "rust-net-stab",
+    version,
+    about = "Monitors the latency and availability of network endpoints"
+)]
+struct Arguments {
+    #[command(subcommand)]
+    command: Option<Subcommands>,
+    #[arg(
+        short,
+        long,
+        global = true,
+        env = "NET_STAB_CONFIG",
+        default_value = "config.yaml",
+        value_name = "PATH",
+        help = "Config file to load"
+    )]
+    config: String,
+    #[arg(
+        short,
+        long,
+        global = true,
+        env = "NET_STAB_LISTEN",
+        value_name = "ADDR",
+        value_parser = parse_listen,
+        help = "Address to serve metrics on, overriding `listen` in the config"
+    )]
+    listen: Option<SocketAddr>,
+    #[arg(
+        long,
+        global = true,
+        env = "NET_STAB_LOG_LEVEL",
+        default_value = "info",
+        value_name = "LEVEL",
+        value_parser = parse_log_level,
+        help = "One of error, warn, info, debug or trace"
+    )]
+    log_level: Level,
+    // Belongs to `run`, which is also what runs without a command.
+    #[arg(
+        long,
+        global = true,
+        help = "Show a live dashboard in the terminal instead of logging"
+    )]
+    tui: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Subcommands {
+    #[command(about = "Probe the endpoints until stopped (the default)")]
+    Run,
+    #[command(about = "Probe every endpoint once, print the results and exit with 1 if any failed")]
+    Check {
+        #[arg(long, help = "Print the results as JSON instead of a table")]
+        json: bool,
+    },
+    #[command(about = "Print the availability, outages and latency of every endpoint from the history store")]
+    Report(ReportArguments),
+    #[command(about = "Sweep a network for hosts with ARP and ping, and optionally add them to the config")]
+    Discover {
+        #[arg(
+            long,
+            value_name = "CIDR",
+            help = "Network to sweep, such as 192.168.1.0/24 [default: every directly connected network]"
+        )]
+        subnet: Option<String>,
+        #[arg(long, help = "Add the hosts that are not probed yet to the endpoints of the config")]
+        add: bool,
+        #[arg(long, help = "Print the results as JSON instead of a table")]
+        json: bool,
+    },
+    #[command(about = "Check the config for mistakes without probing anything")]
+    Validate,
+    #[command(about = "Write a commented example config to the config path")]
+    Init {
+        #[arg(long, help = "Overwrite the config file if it already exists")]
+        force: bool,
+    },
+}
+
+#[derive(Debug, Args)]
+struct ReportArguments {
+    #[arg(
+        long,
+        value_name = "TIME",
+        help = "Start of the report, a Unix timestamp, a date or a duration ago [default: 30d]"
+    )]
+    from: Option<String>,
+    #[arg(
+        long,
+        value_name = "TIME",
+        help = "End of the report, in the same forms [default: now]"
+    )]
+    to: Option<String>,
+    #[arg(long, value_name = "NAME", help = "Only report on this endpoint")]
+    endpoint: Option<String>,
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "json",
+        value_parser = report::Format::parse,
+        help = "One of json, csv or html"
+    )]
+    format: report::Format,
+}
+
+impl Cli {
+    // Exits with the usage text when the arguments are invalid, like most command line tools do.
+    pub fn parse() -> Cli {
+        match Cli::try_parse_from(std::env::args_os()) {
+            Ok(cli) => cli,
+            Err(err) => err.exit(),
+        }
+    }
+
+    // Like `parse`, with the program name as the first argument, but returning the error.
+    pub fn try_parse_from<I, T>(args: I) -> Result<Cli, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let arguments = Arguments::try_parse_from(args)?;
+        let command = match arguments.command {
+            None | Some(Subcommands::Run) => Command::Run,
+            Some(_) if arguments.tui => {
+                return Err(clap::Error::raw(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    "--tui is only valid for the run command\n",
+                ))
+            }
+            Some(Subcommands::Check { json }) => Command::Check { json },
+            Some(Subcommands::Report(report)) => Command::Report(ReportOptions {
+                from: report.from,
+                to: report.to,
+                endpoint: report.endpoint,
+                format: report.format,
+            }),
+            Some(Subcommands::Discover { subnet, add, json }) => Command::Discover(SweepOptions { subnet, add, json }),
+            Some(Subcommands::Validate) => Command::Validate,
+            Some(Subcommands::Init { force }) => Command::Init { force },
+        };
+
+        Ok(Cli {
+            command,
+            config: arguments.config,
+            listen: arguments.listen,
+            log_level: arguments.log_level,
+            tui: arguments.tui,
+        })
+    }
+}
+
+fn parse_log_level(level: &str) -> Result<Level, String> {
+    level
+        .parse()
+        .map_err(|_| format!("invalid log level `{}`", level))
+}
+
+// Accepts `host:port`, `[v6]:port`, or `:port` to listen on all IPv4 interfaces.
+pub fn parse_listen(listen: &str) -> Result<SocketAddr, String> {
+    let invalid = || format!("invalid listen address `{}`, expected host:port", listen);
+
+    match listen.strip_prefix(':') {
+        Some(port) => port
+            .parse::<u16>()
+            .map(|port| SocketAddr::from(([0, 0, 0, 0], port)))
+            .map_err(|_| invalid()),
+        None => listen.parse().map_err(|_| invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("rust-net-stab").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn runs_without_a_command() {
+        let cli = parse(&[]).unwrap();
+        assert_eq!(cli.command, Command::Run);
+        assert_eq!(cli.config, "config.yaml");
+        assert_eq!(cli.log_level, Level::Info);
+        assert_eq!(cli.listen, None);
+        assert!(!cli.tui);
+    }
+
+    #[test]
+    fn parses_global_flags_around_the_command() {
+        let cli = parse(&[
+            "-c",
+            "/etc/net-stab.yaml",
+            "check",
+            "--json",
+            "--listen",
+            ":9100",
+            "--log-level",
+            "debug",
+        ])
+        .unwrap();
+        assert_eq!(cli.command, Command::Check { json: true });
+        assert_eq!(cli.config, "/etc/net-stab.yaml");
+        assert_eq!(cli.listen, Some("0.0.0.0:9100".parse().unwrap()));
+        assert_eq!(cli.log_level, Level::Debug);
+    }
+
+    #[test]
+    fn parses_command_flags() {
+        let cli = parse(&["report", "--from", "7d", "--endpoint", "Google DNS", "--format", "csv"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Report(ReportOptions {
+                from: Some("7d".into()),
+                to: None,
+                endpoint: Some("Google DNS".into()),
+                format: report::Format::Csv,
+            })
+        );
+
+        let cli = parse(&["discover", "--subnet", "10.0.0.0/24", "--add"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Discover(SweepOptions {
+                subnet: Some("10.0.0.0/24".into()),
+                add: true,
+                json: false
+            })
+        );
+
+        assert_eq!(
+            parse(&["init", "--force"]).unwrap().command,
+            Command::Init { force: true }
+        );
+        assert!(parse(&["run", "--tui"]).unwrap().tui);
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert!(parse(&["check", "--tui"])
+            .unwrap_err()
+            .to_string()
+            .contains("only valid for the run command"));
+        assert!(parse(&["validate", "--json"]).is_err());
+        assert!(parse(&["--listen", "localhost"]).is_err());
+        assert!(parse(&["--log-level", "loud"]).is_err());
+        assert!(parse(&["report", "--format", "xml"]).is_err());
+        assert!(parse(&["sweep"]).is_err());
+    }
+
+    #[test]
+    fn parses_listen_addresses() {
+        assert_eq!(parse_listen(":9898"), Ok("0.0.0.0:9898".parse().unwrap()));
+        assert_eq!(parse_listen("127.0.0.1:80"), Ok("127.0.0.1:80".parse().unwrap()));
+        assert_eq!(parse_listen("[::1]:9100"), Ok("[::1]:9100".parse().unwrap()));
+        assert!(parse_listen("::1").is_err());
+        assert!(parse_listen(":99999").is_err());
+    }
+}
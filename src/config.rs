@@ -0,0 +1,1555 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::net::IpAddr;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::alerts::AlertRule;
+use crate::cron::Cron;
+use crate::outputs::Outputs;
+use crate::status::{self, EndpointStatus};
+use crate::timezone::Timezone;
+use crate::{cli, dns, dscp, duration, expand, http, icmp, snmp, websocket, BoxError};
+
+pub use crate::agent::{AgentConfig, Mesh};
+pub use crate::aggregator::AggregatorConfig;
+pub use crate::auth::AuthConfig;
+pub use crate::builtin_targets::BuiltinTargets;
+pub use crate::consul_sd::ConsulSdConfig;
+pub use crate::discovery::DiscoveryConfig;
+pub use crate::dns::RecordType;
+pub use crate::dns_sd::DnsSdConfig;
+pub use crate::dscp::Dscp;
+pub use crate::file_output::{FileConfig, Format as FileFormat};
+pub use crate::file_sd::FileSdConfig;
+pub use crate::history::HistoryConfig;
+pub use crate::http::StatusPattern;
+pub use crate::http_sd::HttpSdConfig;
+pub use crate::influxdb::InfluxDbConfig;
+pub use crate::kubernetes_sd::{KubernetesSdConfig, Role as KubernetesRole};
+pub use crate::maintenance::{MaintenanceMode, MaintenanceWindow};
+pub use crate::metrics::MetricsConfig;
+pub use crate::otlp::OtlpConfig;
+pub use crate::presets::Preset;
+pub use crate::pushgateway::PushgatewayConfig;
+pub use crate::remote_write::RemoteWriteConfig;
+pub use crate::server::{ServerConfig, TlsConfig};
+pub use crate::sip::SipTransport;
+pub use crate::smtp::{Security, SmtpConfig};
+pub use crate::snmp::SnmpVersion;
+pub use crate::statsd::{GraphiteConfig, StatsdConfig};
+pub use crate::targets::ApiConfig;
+pub use crate::throughput::{Direction, Throughput};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_interval", with = "duration")]
+    pub interval: Duration,
+    #[serde(default = "default_timeout", with = "duration")]
+    pub timeout: Duration,
+    #[serde(default = "default_count")]
+    pub count: u32,
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default = "default_retry_backoff", with = "duration")]
+    pub retry_backoff: Duration,
+    #[serde(default = "default_loss_window")]
+    pub loss_window: usize,
+    #[serde(default = "default_threshold")]
+    pub failure_threshold: u32,
+    #[serde(default = "default_threshold")]
+    pub recovery_threshold: u32,
+    #[serde(default = "default_latency_buckets")]
+    pub latency_buckets: Vec<f64>,
+    #[serde(default = "default_resolve_interval", with = "duration")]
+    pub resolve_interval: Duration,
+    pub max_concurrency: Option<usize>,
+    // Probes a second across every endpoint.
+    pub max_probe_rate: Option<f64>,
+    #[serde(default)]
+    pub stagger: bool,
+    #[serde(default)]
+    pub interval_jitter: f64,
+    pub listen: Option<String>,
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default = "default_shutdown_timeout", with = "duration")]
+    pub shutdown_timeout: Duration,
+    #[serde(default)]
+    pub defaults: Defaults,
+    #[serde(default)]
+    pub endpoints: Vec<Endpoint>,
+    // Moved into `endpoints` when the config is parsed, leaving the groups without any.
+    #[serde(default)]
+    pub groups: Vec<Group>,
+    // Each becomes a group named after it when the config is parsed.
+    #[serde(default)]
+    pub presets: Vec<Preset>,
+    #[serde(default)]
+    pub alerts: Vec<AlertRule>,
+    pub smtp: Option<SmtpConfig>,
+    #[serde(default)]
+    pub outputs: Outputs,
+    pub history: Option<HistoryConfig>,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    pub builtin_targets: Option<BuiltinTargets>,
+    pub api: Option<ApiConfig>,
+    pub agent: Option<AgentConfig>,
+    pub aggregator: Option<AggregatorConfig>,
+    pub auth: Option<AuthConfig>,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Endpoint {
+    pub name: String,
+    pub address: String,
+    pub location: Option<String>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    // Unset when the endpoint inherits the probe type, see `probe_type`.
+    #[serde(default, alias = "type")]
+    pub probe: Option<ProbeType>,
+    pub ip_version: Option<IpVersion>,
+    pub source_ip: Option<IpAddr>,
+    pub source_interface: Option<String>,
+    // The DSCP marking of the probe's packets. With several, the endpoint is probed once with each of them.
+    #[serde(default, with = "dscp::list", skip_serializing_if = "Vec::is_empty")]
+    pub dscp: Vec<Dscp>,
+    // The ICMP payload of echo requests in bytes, as with `ping -s`, and whether they must not be fragmented.
+    pub size: Option<u16>,
+    #[serde(default)]
+    pub dont_fragment: bool,
+    pub port: Option<u16>,
+    #[serde(default = "http::default_expected_status")]
+    pub expected_status: Vec<StatusPattern>,
+    pub resolver: Option<String>,
+    #[serde(default)]
+    pub record_type: RecordType,
+    pub expected_answer: Option<String>,
+    pub server_name: Option<String>,
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    pub max_hops: Option<u8>,
+    // The largest packet the pmtu probe tries, IP header included, 1500 bytes unless set.
+    pub max_mtu: Option<u16>,
+    // What the udp probe sends, as text or as hexadecimal bytes for binary protocols.
+    pub payload: Option<String>,
+    pub payload_hex: Option<String>,
+    // The ntp probe fails when the server's clock is further off than this either way.
+    #[serde(default, with = "duration::option")]
+    pub max_offset: Option<Duration>,
+    // The smtp probe upgrades the connection with STARTTLS, verified like the tls probe.
+    #[serde(default)]
+    pub starttls: bool,
+    // The grpc probe asks for the health of this service rather than of the server as a whole. It and the mqtt probe
+    // connect over TLS with `tls`.
+    pub service: Option<String>,
+    #[serde(default)]
+    pub tls: bool,
+    // The websocket probe sends a ping frame once connected and waits for the pong.
+    #[serde(default)]
+    pub ping: bool,
+    // What the mqtt probe logs in with, and the user the snmp probe sends over v3. The password is never part of the
+    // config itself, it is read from the environment or a file instead.
+    pub username: Option<String>,
+    pub password_env: Option<String>,
+    pub password_file: Option<String>,
+    // What the snmp probe fetches, sysUpTime.0 unless set. v2c, the default, sends `community`, `public` unless set.
+    pub oid: Option<String>,
+    pub community: Option<String>,
+    pub snmp_version: Option<SnmpVersion>,
+    // The sip probe sends over UDP unless set to tcp.
+    pub transport: Option<SipTransport>,
+    // Exports an estimated MOS from the latency, jitter and loss, for endpoints that carry calls.
+    #[serde(default)]
+    pub voice: bool,
+    #[serde(default, with = "duration::option")]
+    pub interval: Option<Duration>,
+    #[serde(default, with = "duration::option")]
+    pub timeout: Option<Duration>,
+    pub count: Option<u32>,
+    // Failed requests are tried again up to this many times, waiting `retry_backoff` before the first retry and
+    // twice as long before every one after it.
+    pub retries: Option<u32>,
+    #[serde(default, with = "duration::option")]
+    pub retry_backoff: Option<Duration>,
+    pub loss_window: Option<usize>,
+    pub failure_threshold: Option<u32>,
+    pub recovery_threshold: Option<u32>,
+    pub latency_buckets: Option<Vec<f64>>,
+    // A cron expression for when to probe, instead of every `interval`.
+    pub schedule: Option<String>,
+    // The timezone of `schedule`, UTC unless set.
+    pub timezone: Option<String>,
+    #[serde(default, with = "duration::option")]
+    pub resolve_interval: Option<Duration>,
+    pub maintenance: Option<Vec<MaintenanceWindow>>,
+    pub adaptive: Option<Adaptive>,
+    pub throughput: Option<Throughput>,
+    // The group the endpoint was listed in.
+    #[serde(skip)]
+    pub group: Option<String>,
+    // The name of the entry the endpoint was expanded from, when its address was a CIDR block or a range.
+    #[serde(skip)]
+    pub template: Option<String>,
+}
+
+// Settings every endpoint inherits unless it sets them itself. Labels are merged, with those of the endpoint winning.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    pub location: Option<String>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(alias = "type")]
+    pub probe: Option<ProbeType>,
+    pub ip_version: Option<IpVersion>,
+    pub port: Option<u16>,
+    pub resolver: Option<String>,
+    #[serde(default, with = "duration::option")]
+    pub interval: Option<Duration>,
+    #[serde(default, with = "duration::option")]
+    pub timeout: Option<Duration>,
+    pub count: Option<u32>,
+    pub retries: Option<u32>,
+    #[serde(default, with = "duration::option")]
+    pub retry_backoff: Option<Duration>,
+    pub loss_window: Option<usize>,
+    pub failure_threshold: Option<u32>,
+    pub recovery_threshold: Option<u32>,
+    pub latency_buckets: Option<Vec<f64>>,
+    pub schedule: Option<String>,
+    pub timezone: Option<String>,
+    #[serde(default, with = "duration::option")]
+    pub resolve_interval: Option<Duration>,
+    pub maintenance: Option<Vec<MaintenanceWindow>>,
+    pub adaptive: Option<Adaptive>,
+}
+
+// Endpoints that share settings, which they inherit before the defaults, and a `group` label. Takes the same settings
+// as `Defaults`, they are not flattened into it since that would let unknown keys through.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Group {
+    pub name: String,
+    // The endpoints of the preset are added to those of the group.
+    pub preset: Option<Preset>,
+    pub location: Option<String>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(alias = "type")]
+    pub probe: Option<ProbeType>,
+    pub ip_version: Option<IpVersion>,
+    pub port: Option<u16>,
+    pub resolver: Option<String>,
+    #[serde(default, with = "duration::option")]
+    pub interval: Option<Duration>,
+    #[serde(default, with = "duration::option")]
+    pub timeout: Option<Duration>,
+    pub count: Option<u32>,
+    pub retries: Option<u32>,
+    #[serde(default, with = "duration::option")]
+    pub retry_backoff: Option<Duration>,
+    pub loss_window: Option<usize>,
+    pub failure_threshold: Option<u32>,
+    pub recovery_threshold: Option<u32>,
+    pub latency_buckets: Option<Vec<f64>>,
+    pub schedule: Option<String>,
+    pub timezone: Option<String>,
+    #[serde(default, with = "duration::option")]
+    pub resolve_interval: Option<Duration>,
+    pub maintenance: Option<Vec<MaintenanceWindow>>,
+    pub adaptive: Option<Adaptive>,
+    #[serde(default)]
+    pub endpoints: Vec<Endpoint>,
+}
+
+impl Group {
+    // The settings the endpoints of the group inherit, with the group name as the `group` label.
+    fn settings(&self) -> Defaults {
+        let mut labels = self.labels.clone();
+        labels
+            .entry("group".to_string())
+            .or_insert_with(|| self.name.clone());
+        Defaults {
+            location: self.location.clone(),
+            labels,
+            probe: self.probe,
+            ip_version: self.ip_version,
+            port: self.port,
+            resolver: self.resolver.clone(),
+            interval: self.interval,
+            timeout: self.timeout,
+            count: self.count,
+            retries: self.retries,
+            retry_backoff: self.retry_backoff,
+            loss_window: self.loss_window,
+            failure_threshold: self.failure_threshold,
+            recovery_threshold: self.recovery_threshold,
+            latency_buckets: self.latency_buckets.clone(),
+            resolve_interval: self.resolve_interval,
+            schedule: self.schedule.clone(),
+            timezone: self.timezone.clone(),
+            maintenance: self.maintenance.clone(),
+            adaptive: self.adaptive.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeType {
+    #[default]
+    Icmp,
+    Tcp,
+    Http,
+    Dns,
+    Tls,
+    Traceroute,
+    Arp,
+    Udp,
+    Ntp,
+    Ssh,
+    Smtp,
+    Grpc,
+    Quic,
+    Websocket,
+    Mqtt,
+    Redis,
+    Postgres,
+    Mysql,
+    Snmp,
+    Sip,
+    Pmtu,
+}
+
+impl ProbeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProbeType::Icmp => "icmp",
+            ProbeType::Tcp => "tcp",
+            ProbeType::Http => "http",
+            ProbeType::Dns => "dns",
+            ProbeType::Tls => "tls",
+            ProbeType::Traceroute => "traceroute",
+            ProbeType::Arp => "arp",
+            ProbeType::Udp => "udp",
+            ProbeType::Ntp => "ntp",
+            ProbeType::Ssh => "ssh",
+            ProbeType::Smtp => "smtp",
+            ProbeType::Grpc => "grpc",
+            ProbeType::Quic => "quic",
+            ProbeType::Websocket => "websocket",
+            ProbeType::Mqtt => "mqtt",
+            ProbeType::Redis => "redis",
+            ProbeType::Postgres => "postgres",
+            ProbeType::Mysql => "mysql",
+            ProbeType::Snmp => "snmp",
+            ProbeType::Sip => "sip",
+            ProbeType::Pmtu => "pmtu",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    V4,
+    V6,
+    Both,
+}
+
+impl IpVersion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IpVersion::V4 => "v4",
+            IpVersion::V6 => "v6",
+            IpVersion::Both => "both",
+        }
+    }
+
+    pub fn matches(&self, ip: IpAddr) -> bool {
+        match self {
+            IpVersion::V4 => ip.is_ipv4(),
+            IpVersion::V6 => ip.is_ipv6(),
+            IpVersion::Both => true,
+        }
+    }
+}
+
+// Probes a healthy endpoint every `interval`, but halves the interval, down to `min_interval`, after every probe that
+// failed or took longer than `latency_above`, and doubles it back after every one that did not.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Adaptive {
+    #[serde(with = "duration")]
+    pub min_interval: Duration,
+    #[serde(default, with = "duration::option")]
+    pub latency_above: Option<Duration>,
+}
+
+pub const DEFAULT_LISTEN: ([u8; 4], u16) = ([127, 0, 0, 1], 9898);
+
+pub(crate) const LABELS: &[&str] = &["name", "address", "location", "probe_type", "ip_version", "dscp"];
+pub(crate) const RESERVED_LABELS: &[&str] = &[
+    "le",
+    "rcode",
+    "reason",
+    "hop",
+    "hop_address",
+    "resolved_address",
+    "banner",
+    "serving_status",
+    "value",
+];
+// The backoff doubles with every retry, so a few more add up to a long time.
+const MAX_RETRIES: u32 = 10;
+
+// Per-endpoint settings with the config-wide defaults filled in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub count: u32,
+    pub retries: u32,
+    pub retry_backoff: Duration,
+    pub loss_window: usize,
+    pub failure_threshold: u32,
+    pub recovery_threshold: u32,
+    pub latency_buckets: Vec<f64>,
+    pub resolve_interval: Duration,
+    pub stagger: bool,
+    pub interval_jitter: f64,
+    pub schedule: Option<String>,
+    pub timezone: Option<String>,
+    pub adaptive: Option<Adaptive>,
+}
+
+impl Endpoint {
+    // An endpoint with every other setting left to the defaults, as found by discovery.
+    pub fn new(name: String, address: String) -> Endpoint {
+        Endpoint {
+            name,
+            address,
+            location: None,
+            labels: BTreeMap::new(),
+            probe: None,
+            ip_version: None,
+            source_ip: None,
+            source_interface: None,
+            dscp: Vec::new(),
+            size: None,
+            dont_fragment: false,
+            port: None,
+            expected_status: http::default_expected_status(),
+            resolver: None,
+            record_type: RecordType::default(),
+            expected_answer: None,
+            server_name: None,
+            insecure_skip_verify: false,
+            max_hops: None,
+            max_mtu: None,
+            payload: None,
+            payload_hex: None,
+            max_offset: None,
+            starttls: false,
+            service: None,
+            tls: false,
+            ping: false,
+            username: None,
+            password_env: None,
+            password_file: None,
+            oid: None,
+            community: None,
+            snmp_version: None,
+            transport: None,
+            voice: false,
+            interval: None,
+            timeout: None,
+            count: None,
+            retries: None,
+            retry_backoff: None,
+            loss_window: None,
+            failure_threshold: None,
+            recovery_threshold: None,
+            latency_buckets: None,
+            resolve_interval: None,
+            schedule: None,
+            timezone: None,
+            maintenance: None,
+            adaptive: None,
+            throughput: None,
+            group: None,
+            template: None,
+        }
+    }
+
+    pub fn probe_type(&self) -> ProbeType {
+        self.probe.unwrap_or_default()
+    }
+
+    pub(crate) fn password(&self) -> Result<Option<String>, String> {
+        secret("password", &self.password_env, &self.password_file)
+    }
+
+    // The datagram the udp probe sends, empty unless `payload` or `payload_hex` is set.
+    pub(crate) fn payload(&self) -> Result<Vec<u8>, String> {
+        match (&self.payload, &self.payload_hex) {
+            (Some(_), Some(_)) => Err("set only one of payload or payload_hex".to_string()),
+            (Some(payload), None) => Ok(payload.as_bytes().to_vec()),
+            (None, Some(hex)) => {
+                let digits: Vec<u8> = hex
+                    .bytes()
+                    .filter(|byte| !byte.is_ascii_whitespace())
+                    .collect();
+                digits
+                    .chunks(2)
+                    .map(|pair| {
+                        std::str::from_utf8(pair)
+                            .ok()
+                            .filter(|pair| pair.len() == 2)
+                            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    })
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| format!("payload_hex `{}` is not a whole number of hexadecimal bytes", hex))
+            }
+            (None, None) => Ok(Vec::new()),
+        }
+    }
+
+    // One endpoint for each DSCP marking when there are several, and for each address when the address is a CIDR block
+    // or a range. Endpoints whose address is something else, or invalid, are kept as they are.
+    fn expand(self) -> Vec<Endpoint> {
+        if self.dscp.len() > 1 {
+            return self
+                .dscp
+                .iter()
+                .flat_map(|dscp| {
+                    Endpoint {
+                        name: dscp::name(&self.name, *dscp),
+                        dscp: vec![*dscp],
+                        template: Some(self.name.clone()),
+                        ..self.clone()
+                    }
+                    .expand()
+                })
+                .collect();
+        }
+        if matches!(
+            self.probe_type(),
+            ProbeType::Http | ProbeType::Dns | ProbeType::Websocket
+        ) {
+            return vec![self];
+        }
+        match expand::addresses(&self.address) {
+            Some(Ok(addresses)) => addresses
+                .into_iter()
+                .enumerate()
+                .map(|(index, ip)| Endpoint {
+                    name: expand::name(&self.name, ip, index + 1),
+                    address: ip.to_string(),
+                    template: Some(self.name.clone()),
+                    ..self.clone()
+                })
+                .collect(),
+            _ => vec![self],
+        }
+    }
+
+    pub(crate) fn inherit(&mut self, defaults: &Defaults) {
+        for (name, value) in &defaults.labels {
+            self.labels
+                .entry(name.clone())
+                .or_insert_with(|| value.clone());
+        }
+        fill(&mut self.location, &defaults.location);
+        fill(&mut self.probe, &defaults.probe);
+        // ip_version does not apply to the dns probe, so dns endpoints do not inherit it.
+        if self.probe_type() != ProbeType::Dns {
+            fill(&mut self.ip_version, &defaults.ip_version);
+        }
+        fill(&mut self.port, &defaults.port);
+        fill(&mut self.resolver, &defaults.resolver);
+        fill(&mut self.interval, &defaults.interval);
+        fill(&mut self.timeout, &defaults.timeout);
+        fill(&mut self.count, &defaults.count);
+        fill(&mut self.retries, &defaults.retries);
+        fill(&mut self.retry_backoff, &defaults.retry_backoff);
+        fill(&mut self.loss_window, &defaults.loss_window);
+        fill(&mut self.failure_threshold, &defaults.failure_threshold);
+        fill(&mut self.recovery_threshold, &defaults.recovery_threshold);
+        fill(&mut self.latency_buckets, &defaults.latency_buckets);
+        fill(&mut self.resolve_interval, &defaults.resolve_interval);
+        fill(&mut self.schedule, &defaults.schedule);
+        fill(&mut self.timezone, &defaults.timezone);
+        fill(&mut self.maintenance, &defaults.maintenance);
+        fill(&mut self.adaptive, &defaults.adaptive);
+    }
+
+    // The endpoints that are actually probed, one for each IP version when probing over both.
+    pub fn targets(&self) -> Vec<Endpoint> {
+        match self.ip_version {
+            Some(IpVersion::Both) => [IpVersion::V4, IpVersion::V6]
+                .iter()
+                .map(|ip_version| Endpoint {
+                    ip_version: Some(*ip_version),
+                    ..self.clone()
+                })
+                .collect(),
+            _ => vec![self.clone()],
+        }
+    }
+
+    // Tells the targets of an endpoint apart.
+    pub fn key(&self) -> String {
+        status::key(&self.name, self.ip_version.map(|ip_version| ip_version.as_str()))
+    }
+
+    pub(crate) fn status(&self) -> EndpointStatus {
+        EndpointStatus {
+            name: self.name.clone(),
+            address: self.address.clone(),
+            location: self.location.clone(),
+            probe_type: self.probe_type().as_str(),
+            ip_version: self.ip_version.map(|ip_version| ip_version.as_str()),
+            labels: self.labels.clone(),
+            up: None,
+            last_rtt_seconds: None,
+            loss_ratio: 0.0,
+            last_error: None,
+            last_probe: None,
+            last_change: None,
+            maintenance: false,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn settings(&self, config: &Config) -> Settings {
+        Settings {
+            interval: self.interval.unwrap_or(config.interval),
+            timeout: self.timeout.unwrap_or(config.timeout),
+            count: self.count.unwrap_or(config.count).max(1),
+            retries: self.retries.unwrap_or(config.retries),
+            retry_backoff: self.retry_backoff.unwrap_or(config.retry_backoff),
+            loss_window: self.loss_window.unwrap_or(config.loss_window).max(1),
+            failure_threshold: self
+                .failure_threshold
+                .unwrap_or(config.failure_threshold)
+                .max(1),
+            recovery_threshold: self
+                .recovery_threshold
+                .unwrap_or(config.recovery_threshold)
+                .max(1),
+            latency_buckets: self
+                .latency_buckets
+                .clone()
+                .unwrap_or_else(|| config.latency_buckets.clone()),
+            resolve_interval: self.resolve_interval.unwrap_or(config.resolve_interval),
+            stagger: config.stagger,
+            interval_jitter: config.interval_jitter,
+            schedule: self.schedule.clone(),
+            timezone: self.timezone.clone(),
+            adaptive: self.adaptive.clone(),
+        }
+    }
+}
+
+fn validate_schedule(endpoint: &Endpoint) -> Result<(), String> {
+    let timezone = match &endpoint.timezone {
+        Some(timezone) => Timezone::parse(timezone).map_err(|err| format!("timezone: {}", err))?,
+        None => Timezone::Fixed(0),
+    };
+    match &endpoint.schedule {
+        Some(schedule) => {
+            let cron = Cron::parse(schedule).map_err(|err| format!("schedule: {}", err))?;
+            if cron.next(status::unix_time() as i64, &timezone).is_none() {
+                return Err(format!("schedule: `{}` never matches", schedule));
+            }
+            Ok(())
+        }
+        None if endpoint.timezone.is_some() => Err("timezone only applies to schedule".to_string()),
+        None => Ok(()),
+    }
+}
+
+fn fill<T: Clone>(setting: &mut Option<T>, default: &Option<T>) {
+    if setting.is_none() {
+        setting.clone_from(default);
+    }
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+fn default_retry_backoff() -> Duration {
+    Duration::from_millis(100)
+}
+
+fn default_loss_window() -> usize {
+    20
+}
+
+fn default_shutdown_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_threshold() -> u32 {
+    1
+}
+
+fn default_resolve_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_latency_buckets() -> Vec<f64> {
+    prometheus::DEFAULT_BUCKETS.to_vec()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl Format {
+    // Told by the extension of the file, anything other than `.toml` and `.json` is YAML.
+    pub fn of(path: &str) -> Format {
+        match Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("toml") => Format::Toml,
+            Some("json") => Format::Json,
+            _ => Format::Yaml,
+        }
+    }
+}
+
+// A config file that cannot be deserialized, with the position of the mistake when it is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{} at line {} column {}", self.message, line, column),
+            _ => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl ParseError {
+    // serde_yaml and serde_json end their messages with the position, which is kept apart here.
+    fn at(message: String, line: usize, column: usize) -> ParseError {
+        let position = format!(" at line {} column {}", line, column);
+        ParseError {
+            message: message
+                .strip_suffix(&position)
+                .map(String::from)
+                .unwrap_or(message),
+            line: Some(line),
+            column: Some(column),
+        }
+    }
+}
+
+pub fn load(path: &str) -> Result<Config, BoxError> {
+    let config = parse(&fs::read_to_string(path)?, Format::of(path))?;
+    match validate(&config).into_iter().next() {
+        Some(problem) => Err(problem.message.into()),
+        None => Ok(config),
+    }
+}
+
+pub fn parse(source: &str, format: Format) -> Result<Config, ParseError> {
+    let mut config: Config = match format {
+        Format::Yaml => {
+            let mut tree: serde_yaml::Value = serde_yaml::from_str(source).map_err(yaml_error)?;
+            let substituted = substitute_yaml(&mut tree, "")?.then(|| serde_yaml::from_value(tree));
+            read(substituted, || serde_yaml::from_str(source).map_err(yaml_error))
+        }
+        Format::Json => {
+            let json_error = |err: serde_json::Error| ParseError::at(err.to_string(), err.line(), err.column());
+            let mut tree: serde_json::Value = serde_json::from_str(source).map_err(json_error)?;
+            let substituted = substitute_json(&mut tree, "")?.then(|| serde_json::from_value(tree));
+            read(substituted, || serde_json::from_str(source).map_err(json_error))
+        }
+        Format::Toml => parse_toml(source),
+    }?;
+
+    for preset in mem::take(&mut config.presets) {
+        config.groups.push(Group {
+            name: preset.as_str().to_string(),
+            preset: Some(preset),
+            ..Group::default()
+        });
+    }
+    for group in &mut config.groups {
+        let settings = group.settings();
+        let preset = group.preset.iter().flat_map(Preset::endpoints);
+        for mut endpoint in preset.chain(mem::take(&mut group.endpoints)) {
+            endpoint.inherit(&settings);
+            endpoint.group = Some(group.name.clone());
+            config.endpoints.push(endpoint);
+        }
+    }
+    for endpoint in &mut config.endpoints {
+        endpoint.inherit(&config.defaults);
+    }
+    config.endpoints = mem::take(&mut config.endpoints)
+        .into_iter()
+        .flat_map(Endpoint::expand)
+        .collect();
+    for rule in &mut config.alerts {
+        let groups = &rule.groups;
+        let members: Vec<String> = config
+            .endpoints
+            .iter()
+            .filter(|endpoint| {
+                endpoint
+                    .group
+                    .as_ref()
+                    .is_some_and(|group| groups.contains(group))
+            })
+            .map(|endpoint| endpoint.name.clone())
+            .collect();
+        rule.endpoints.extend(members);
+    }
+    Ok(config)
+}
+
+fn yaml_error(err: serde_yaml::Error) -> ParseError {
+    match err.location() {
+        Some(location) => ParseError::at(err.to_string(), location.line(), location.column()),
+        None => ParseError {
+            message: err.to_string(),
+            line: None,
+            column: None,
+        },
+    }
+}
+
+// Deserializes the config from the tree its references were substituted in, or from the text when it has none. Type
+// errors only have a position when read from the text, so the text gives the error too when it has the same one.
+fn read<E: fmt::Display>(
+    substituted: Option<Result<Config, E>>,
+    text: impl Fn() -> Result<Config, ParseError>,
+) -> Result<Config, ParseError> {
+    match substituted {
+        None => text(),
+        Some(Ok(config)) => Ok(config),
+        Some(Err(err)) => Err(text().err().unwrap_or(ParseError {
+            message: err.to_string(),
+            line: None,
+            column: None,
+        })),
+    }
+}
+
+// Replaces `${NAME}` with the value of the environment variable, or with `default` in `${NAME:-default}` when it is
+// not set, and `$${` with a literal `${`. Returns `None` for a string without `${`.
+fn substitute(text: &str) -> Result<Option<String>, String> {
+    if !text.contains("${") {
+        return Ok(None);
+    }
+    let mut substituted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            substituted.push_str(&rest[..start - 1]);
+            substituted.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        substituted.push_str(&rest[..start]);
+        let reference = &rest[start + 2..];
+        let end = reference
+            .find('}')
+            .ok_or_else(|| "`${` is not closed by `}`".to_string())?;
+        let (name, default) = match reference[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&reference[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!(
+                "`${{{}}}` is not a valid environment variable reference",
+                &reference[..end]
+            ));
+        }
+        match std::env::var(name)
+            .ok()
+            .or_else(|| default.map(String::from))
+        {
+            Some(value) => substituted.push_str(&value),
+            None => return Err(format!("environment variable `{}` is not set", name)),
+        }
+        rest = &reference[end + 1..];
+    }
+    substituted.push_str(rest);
+    Ok(Some(substituted))
+}
+
+// Substitutes the references in a string of the tree, naming the key it is under when that fails.
+fn substitute_at(text: &mut String, path: &str) -> Result<bool, ParseError> {
+    match substitute(text) {
+        Ok(Some(substituted)) => {
+            *text = substituted;
+            Ok(true)
+        }
+        Ok(None) => Ok(false),
+        Err(message) => Err(ParseError {
+            message: format!("{}: {}", path, message),
+            line: None,
+            column: None,
+        }),
+    }
+}
+
+fn key(path: &str, key: &str) -> String {
+    match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    }
+}
+
+// Substitutes the references in every string value of the tree, and says whether there were any. Keys are left alone.
+fn substitute_yaml(tree: &mut serde_yaml::Value, path: &str) -> Result<bool, ParseError> {
+    let mut substituted = false;
+    match tree {
+        serde_yaml::Value::String(text) => substituted = substitute_at(text, path)?,
+        serde_yaml::Value::Sequence(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                substituted |= substitute_yaml(item, &format!("{}[{}]", path, index))?;
+            }
+        }
+        serde_yaml::Value::Mapping(entries) => {
+            for (name, value) in entries.iter_mut() {
+                let name = name.as_str().map(String::from).unwrap_or_default();
+                substituted |= substitute_yaml(value, &key(path, &name))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(substituted)
+}
+
+fn substitute_json(tree: &mut serde_json::Value, path: &str) -> Result<bool, ParseError> {
+    let mut substituted = false;
+    match tree {
+        serde_json::Value::String(text) => substituted = substitute_at(text, path)?,
+        serde_json::Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                substituted |= substitute_json(item, &format!("{}[{}]", path, index))?;
+            }
+        }
+        serde_json::Value::Object(entries) => {
+            for (name, value) in entries.iter_mut() {
+                substituted |= substitute_json(value, &key(path, name))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(substituted)
+}
+
+#[cfg(feature = "toml")]
+fn substitute_toml(tree: &mut toml::Value, path: &str) -> Result<bool, ParseError> {
+    let mut substituted = false;
+    match tree {
+        toml::Value::String(text) => substituted = substitute_at(text, path)?,
+        toml::Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                substituted |= substitute_toml(item, &format!("{}[{}]", path, index))?;
+            }
+        }
+        toml::Value::Table(entries) => {
+            for (name, value) in entries.iter_mut() {
+                substituted |= substitute_toml(value, &key(path, name))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(substituted)
+}
+
+#[cfg(feature = "toml")]
+fn parse_toml(source: &str) -> Result<Config, ParseError> {
+    let toml_error = |err: toml::de::Error| {
+        let (line, column) = match err.span() {
+            Some(span) => {
+                let before = &source[..span.start];
+                let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+                (
+                    Some(before.matches('\n').count() + 1),
+                    Some(span.start - line_start + 1),
+                )
+            }
+            None => (None, None),
+        };
+        ParseError {
+            message: err.message().to_string(),
+            line,
+            column,
+        }
+    };
+    let mut tree = toml::Value::Table(toml::from_str(source).map_err(toml_error)?);
+    let substituted = substitute_toml(&mut tree, "")?.then(|| tree.try_into());
+    read(substituted, || toml::from_str(source).map_err(toml_error))
+}
+
+#[cfg(not(feature = "toml"))]
+fn parse_toml(_source: &str) -> Result<Config, ParseError> {
+    Err(ParseError {
+        message: "TOML configs need rust-net-stab to be built with the `toml` feature".to_string(),
+        line: None,
+        column: None,
+    })
+}
+
+// A reason the config cannot be run, along with the top-level key it is under and the position of the endpoint or
+// alert it is about, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub section: &'static str,
+    pub index: Option<usize>,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(section: &'static str, index: Option<usize>, message: String) -> Problem {
+        Problem {
+            section,
+            index,
+            message,
+        }
+    }
+}
+
+// Checks everything that deserializing the config does not, returning every problem rather than just the first.
+pub fn validate(config: &Config) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    if let Some(listen) = &config.listen {
+        if let Err(err) = cli::parse_listen(listen) {
+            problems.push(Problem::new("listen", None, err));
+        }
+    }
+    if let Some(Err(err)) = config.tls.as_ref().map(TlsConfig::validate) {
+        problems.push(Problem::new("tls", None, format!("tls: {}", err)));
+    }
+    if config.max_concurrency == Some(0) {
+        problems.push(Problem::new(
+            "max_concurrency",
+            None,
+            "max_concurrency must be at least 1".to_string(),
+        ));
+    }
+    if config
+        .max_probe_rate
+        .is_some_and(|rate| !(rate > 0.0 && rate.is_finite()))
+    {
+        problems.push(Problem::new(
+            "max_probe_rate",
+            None,
+            "max_probe_rate must be more than 0".to_string(),
+        ));
+    }
+    if !(0.0..1.0).contains(&config.interval_jitter) {
+        problems.push(Problem::new(
+            "interval_jitter",
+            None,
+            "interval_jitter must be at least 0 and less than 1".to_string(),
+        ));
+    }
+
+    let mut groups = HashSet::new();
+    for (index, group) in config.groups.iter().enumerate() {
+        if !groups.insert(group.name.as_str()) {
+            problems.push(Problem::new(
+                "groups",
+                Some(index),
+                format!("group name `{}` is used more than once", group.name),
+            ));
+        }
+    }
+
+    let mut names = HashSet::new();
+    let mut entries = 0;
+    let mut previous = None;
+    for endpoint in &config.endpoints {
+        // The endpoints expanded from the same entry only differ in their address, so only the first is checked
+        // beyond its name.
+        let expanded = endpoint.template.is_some() && endpoint.template == previous;
+        previous.clone_from(&endpoint.template);
+        // Endpoints of groups are pointed out by their group.
+        let (section, entry) = match &endpoint.group {
+            Some(name) => ("groups", config.groups.iter().position(|group| &group.name == name)),
+            None => {
+                entries += !expanded as usize;
+                ("endpoints", Some(entries - 1))
+            }
+        };
+        if !names.insert(endpoint.name.as_str()) {
+            problems.push(Problem::new(
+                section,
+                entry,
+                format!("endpoint name `{}` is used more than once", endpoint.name),
+            ));
+            continue;
+        }
+        if expanded {
+            continue;
+        }
+
+        let invalid = endpoint_problems(endpoint, config);
+        problems.extend(
+            invalid
+                .into_iter()
+                .map(|message| Problem::new(section, entry, format!("endpoint `{}`: {}", endpoint.name, message))),
+        );
+    }
+
+    let mut alerts = HashSet::new();
+    for (index, rule) in config.alerts.iter().enumerate() {
+        let entry = Some(index);
+        if !alerts.insert(rule.name.as_str()) {
+            problems.push(Problem::new(
+                "alerts",
+                entry,
+                format!("alert name `{}` is used more than once", rule.name),
+            ));
+            continue;
+        }
+        if let Err(err) = rule.validate() {
+            problems.push(Problem::new("alerts", entry, format!("alert `{}`: {}", rule.name, err)));
+        }
+        for group in rule
+            .groups
+            .iter()
+            .filter(|name| !groups.contains(name.as_str()))
+        {
+            problems.push(Problem::new(
+                "alerts",
+                entry,
+                format!("alert `{}` refers to unknown group `{}`", rule.name, group),
+            ));
+        }
+        for endpoint in rule
+            .endpoints
+            .iter()
+            .filter(|name| !names.contains(name.as_str()))
+        {
+            problems.push(Problem::new(
+                "alerts",
+                entry,
+                format!("alert `{}` refers to unknown endpoint `{}`", rule.name, endpoint),
+            ));
+        }
+        if !rule.email.is_empty() && config.smtp.is_none() {
+            problems.push(Problem::new(
+                "alerts",
+                entry,
+                format!("alert `{}` sends email but there is no smtp section", rule.name),
+            ));
+        }
+    }
+    if let Some(Err(err)) = config.smtp.as_ref().map(SmtpConfig::validate) {
+        problems.push(Problem::new("smtp", None, format!("smtp: {}", err)));
+    }
+    if let Err(err) = config.outputs.validate() {
+        problems.push(Problem::new("outputs", None, format!("outputs: {}", err)));
+    }
+    if let Err(err) = config.discovery.validate() {
+        problems.push(Problem::new("discovery", None, format!("discovery: {}", err)));
+    }
+    if let Some(Err(err)) = config
+        .builtin_targets
+        .as_ref()
+        .map(BuiltinTargets::validate)
+    {
+        problems.push(Problem::new(
+            "builtin_targets",
+            None,
+            format!("builtin_targets: {}", err),
+        ));
+    }
+    if let Some(Err(err)) = config.history.as_ref().map(HistoryConfig::validate) {
+        problems.push(Problem::new("history", None, format!("history: {}", err)));
+    }
+    if let Some(Err(err)) = config.api.as_ref().map(ApiConfig::validate) {
+        problems.push(Problem::new("api", None, format!("api: {}", err)));
+    }
+    if let Some(Err(err)) = config.agent.as_ref().map(AgentConfig::validate) {
+        problems.push(Problem::new("agent", None, format!("agent: {}", err)));
+    }
+    if let Some(Err(err)) = config.aggregator.as_ref().map(AggregatorConfig::validate) {
+        problems.push(Problem::new("aggregator", None, format!("aggregator: {}", err)));
+    }
+    if let Some(Err(err)) = config.auth.as_ref().map(AuthConfig::validate) {
+        problems.push(Problem::new("auth", None, format!("auth: {}", err)));
+    }
+    if let Err(err) = config.metrics.validate() {
+        problems.push(Problem::new("metrics", None, format!("metrics: {}", err)));
+    }
+
+    problems
+}
+
+// What is wrong with a single endpoint, also used for the endpoints found by discovery.
+pub(crate) fn endpoint_problems(endpoint: &Endpoint, config: &Config) -> Vec<String> {
+    let mut invalid = Vec::new();
+    for name in endpoint.labels.keys() {
+        if !valid_label_name(name) || LABELS.contains(&name.as_str()) || RESERVED_LABELS.contains(&name.as_str()) {
+            invalid.push(format!("`{}` cannot be used as a label name", name));
+        } else if config.metrics.const_labels.contains_key(name) {
+            invalid.push(format!("`{}` is already a const label of the metrics section", name));
+        }
+    }
+    let settings = endpoint.settings(config);
+    if settings.interval.is_zero() {
+        invalid.push("interval must be longer than 0s".to_string());
+    }
+    if settings.timeout.is_zero() {
+        invalid.push("timeout must be longer than 0s".to_string());
+    }
+    if let Some(adaptive) = &settings.adaptive {
+        if endpoint.schedule.is_some() {
+            invalid.push("adaptive does not apply to endpoints probed on a schedule".to_string());
+        } else if adaptive.min_interval.is_zero() || adaptive.min_interval >= settings.interval {
+            invalid.push("adaptive: min_interval must be longer than 0s and shorter than interval".to_string());
+        }
+        if adaptive
+            .latency_above
+            .is_some_and(|latency| latency.is_zero())
+        {
+            invalid.push("adaptive: latency_above must be longer than 0s".to_string());
+        }
+    }
+    if let Some(Err(err)) = endpoint.throughput.as_ref().map(Throughput::validate) {
+        invalid.push(format!("throughput: {}", err));
+    }
+    if settings.retries > MAX_RETRIES {
+        invalid.push(format!("retries must be at most {}", MAX_RETRIES));
+    }
+    match endpoint.probe_type() {
+        ProbeType::Http => {
+            if let Err(err) = http::parse_url(&endpoint.address) {
+                invalid.push(err.to_string());
+            } else if !cfg!(feature = "tls") && endpoint.address.starts_with("https:") {
+                invalid.push("https urls need rust-net-stab to be built with the `tls` feature".to_string());
+            }
+        }
+        ProbeType::Websocket => {
+            if let Err(err) = websocket::parse_url(&endpoint.address) {
+                invalid.push(err.to_string());
+            } else if !cfg!(feature = "tls") && endpoint.address.starts_with("wss:") {
+                invalid.push("wss urls need rust-net-stab to be built with the `tls` feature".to_string());
+            }
+        }
+        ProbeType::Quic if endpoint.address.contains("://") => match http::parse_url(&endpoint.address) {
+            Ok(url) if url.scheme_str() == Some("https") => {}
+            _ => invalid.push(format!("`{}` is not an https url", endpoint.address)),
+        },
+        ProbeType::Dns => match endpoint.resolver.as_deref() {
+            Some(resolver) => match dns::parse_server(resolver) {
+                Ok(dns::Server::Udp(_)) => {}
+                Ok(_) if !cfg!(feature = "tls") => invalid.push(format!(
+                    "resolver `{}` needs rust-net-stab to be built with the `tls` feature",
+                    resolver
+                )),
+                Ok(_) => {}
+                Err(err) => invalid.push(err),
+            },
+            None => invalid.push("the dns probe needs a resolver".to_string()),
+        },
+        _ => {
+            if let Some(Err(err)) = expand::addresses(&endpoint.address) {
+                invalid.push(err);
+            } else if endpoint.address.parse::<IpAddr>().is_err() && !valid_hostname(&endpoint.address) {
+                invalid.push(format!("`{}` is not an IP address or hostname", endpoint.address));
+            }
+        }
+    }
+    if matches!(endpoint.probe_type(), ProbeType::Tcp | ProbeType::Udp | ProbeType::Grpc) && endpoint.port.is_none() {
+        invalid.push(format!("the {} probe needs a port", endpoint.probe_type().as_str()));
+    }
+    if endpoint.payload.is_some() || endpoint.payload_hex.is_some() {
+        if endpoint.probe_type() != ProbeType::Udp {
+            invalid.push("payload only applies to the udp probe".to_string());
+        } else if let Err(err) = endpoint.payload() {
+            invalid.push(err);
+        }
+    }
+    if endpoint.port == Some(0) {
+        invalid.push("port must be between 1 and 65535".to_string());
+    }
+    if let Err(err) = validate_schedule(endpoint) {
+        invalid.push(err);
+    }
+    for (index, window) in endpoint.maintenance.iter().flatten().enumerate() {
+        if let Err(err) = window.validate() {
+            invalid.push(format!("maintenance[{}]: {}", index, err));
+        }
+    }
+    if let Some(max_offset) = endpoint.max_offset {
+        if endpoint.probe_type() != ProbeType::Ntp {
+            invalid.push("max_offset only applies to the ntp probe".to_string());
+        } else if max_offset.is_zero() {
+            invalid.push("max_offset must be longer than 0s".to_string());
+        }
+    }
+    if endpoint.starttls {
+        if endpoint.probe_type() != ProbeType::Smtp {
+            invalid.push("starttls only applies to the smtp probe".to_string());
+        } else if !cfg!(feature = "tls") {
+            invalid.push("starttls needs rust-net-stab to be built with the `tls` feature".to_string());
+        }
+    }
+    if endpoint.service.is_some() && endpoint.probe_type() != ProbeType::Grpc {
+        invalid.push("service only applies to the grpc probe".to_string());
+    }
+    if endpoint.tls {
+        if !matches!(endpoint.probe_type(), ProbeType::Grpc | ProbeType::Mqtt) {
+            invalid.push("tls only applies to the grpc and mqtt probes".to_string());
+        } else if !cfg!(feature = "tls") {
+            invalid.push("tls needs rust-net-stab to be built with the `tls` feature".to_string());
+        }
+    }
+    let snmp_v3 = endpoint.probe_type() == ProbeType::Snmp && endpoint.snmp_version == Some(SnmpVersion::V3);
+    if endpoint.username.is_some() || endpoint.password_env.is_some() || endpoint.password_file.is_some() {
+        if endpoint.probe_type() != ProbeType::Mqtt && !snmp_v3 {
+            invalid.push("username and password only apply to the mqtt probe and the snmp probe over v3".to_string());
+        } else if endpoint.password_env.is_some() && endpoint.password_file.is_some() {
+            invalid.push("set only one of password_env or password_file".to_string());
+        } else if endpoint.username.is_none() {
+            invalid.push("a password is set but no username".to_string());
+        } else {
+            match endpoint.password() {
+                // RFC 3414 turns shorter passwords away.
+                Ok(Some(password)) if snmp_v3 && password.len() < 8 => {
+                    invalid.push("the snmp password must be at least 8 characters".to_string())
+                }
+                Ok(_) => {}
+                Err(err) => invalid.push(err),
+            }
+        }
+    } else if snmp_v3 {
+        invalid.push("snmp_version v3 needs a username".to_string());
+    }
+    if endpoint.oid.is_some() || endpoint.community.is_some() || endpoint.snmp_version.is_some() {
+        if endpoint.probe_type() != ProbeType::Snmp {
+            invalid.push("oid, community and snmp_version only apply to the snmp probe".to_string());
+        } else if endpoint.community.is_some() && snmp_v3 {
+            invalid.push("community only applies to snmp_version v2c".to_string());
+        } else if let Err(err) = snmp::parse_oid(endpoint.oid.as_deref().unwrap_or(snmp::DEFAULT_OID)) {
+            invalid.push(err);
+        }
+    }
+    if endpoint.transport.is_some() && endpoint.probe_type() != ProbeType::Sip {
+        invalid.push("transport only applies to the sip probe".to_string());
+    }
+    if endpoint.ping && endpoint.probe_type() != ProbeType::Websocket {
+        invalid.push("ping only applies to the websocket probe".to_string());
+    }
+    if endpoint.probe_type() == ProbeType::Arp && !cfg!(target_os = "linux") {
+        invalid.push("the arp probe is only supported on Linux".to_string());
+    }
+    if !endpoint.dscp.is_empty() {
+        if endpoint.probe_type() == ProbeType::Arp {
+            invalid.push("dscp does not apply to the arp probe".to_string());
+        } else if !cfg!(unix) {
+            invalid.push("dscp is not supported on Windows".to_string());
+        }
+    }
+    if endpoint.size.is_some() || endpoint.dont_fragment {
+        if endpoint.probe_type() != ProbeType::Icmp {
+            invalid.push("size and dont_fragment only apply to the icmp probe".to_string());
+        } else if endpoint
+            .size
+            .is_some_and(|size| usize::from(size) > icmp::MAX_PAYLOAD)
+        {
+            invalid.push(format!("size must be at most {}", icmp::MAX_PAYLOAD));
+        } else if endpoint.dont_fragment && !cfg!(target_os = "linux") {
+            invalid.push("dont_fragment is only supported on Linux".to_string());
+        }
+    }
+    if endpoint.probe_type() == ProbeType::Pmtu && !cfg!(target_os = "linux") {
+        invalid.push("the pmtu probe is only supported on Linux".to_string());
+    }
+    if endpoint.probe_type() == ProbeType::Quic && !cfg!(feature = "quic") {
+        invalid.push("the quic probe needs rust-net-stab to be built with the `quic` feature".to_string());
+    }
+    if endpoint.probe_type() == ProbeType::Tls && !cfg!(feature = "tls") {
+        invalid.push("the tls probe needs rust-net-stab to be built with the `tls` feature".to_string());
+    }
+    if let Some(ip_version) = endpoint.ip_version {
+        if endpoint.probe_type() == ProbeType::Dns {
+            invalid.push("ip_version does not apply to the dns probe".to_string());
+        } else if let Ok(ip) = endpoint.address.parse::<IpAddr>() {
+            if ip_version == IpVersion::Both || !ip_version.matches(ip) {
+                invalid.push(format!(
+                    "ip_version `{}` needs a hostname rather than {}",
+                    ip_version.as_str(),
+                    ip
+                ));
+            }
+        }
+    }
+    if let Some(source_ip) = endpoint.source_ip {
+        let target = endpoint.address.parse::<IpAddr>().ok();
+        let mismatched = match endpoint.ip_version {
+            Some(IpVersion::Both) => true,
+            Some(ip_version) => !ip_version.matches(source_ip),
+            None => target.is_some_and(|target| target.is_ipv4() != source_ip.is_ipv4()),
+        };
+        if mismatched {
+            invalid.push(format!(
+                "source_ip {} does not match the IP version of the target",
+                source_ip
+            ));
+        }
+    }
+    if endpoint.max_hops == Some(0) {
+        invalid.push("max_hops must be at least 1".to_string());
+    }
+    if let Some(max_mtu) = endpoint.max_mtu {
+        if endpoint.probe_type() != ProbeType::Pmtu {
+            invalid.push("max_mtu only applies to the pmtu probe".to_string());
+        } else if max_mtu < 68 {
+            invalid.push("max_mtu must be at least 68".to_string());
+        }
+    }
+    invalid
+}
+
+// Secrets such as passwords are never part of the config itself, they are read from the environment or a file named
+// by `<field>_env` or `<field>_file` instead.
+pub(crate) fn secret(field: &str, env: &Option<String>, file: &Option<String>) -> Result<Option<String>, String> {
+    if let Some(name) = env {
+        return std::env::var(name)
+            .map(Some)
+            .map_err(|_| format!("environment variable `{}` is not set", name));
+    }
+    if let Some(path) = file {
+        return fs::read_to_string(path)
+            .map(|secret| Some(secret.trim_end_matches(['\r', '\n']).to_string()))
+            .map_err(|err| format!("failed to read {}_file `{}`: {}", field, path, err));
+    }
+
+    Ok(None)
+}
+
+pub(crate) fn valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+}
+
+pub(crate) fn valid_hostname(name: &str) -> bool {
+    name.len() <= 253
+        && name.trim_end_matches('.').split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitutes_within_strings() {
+        std::env::set_var("NETSTAB_TEST_SECRET", "a\"b\\c # d: e\nf'g");
+        std::env::set_var("NETSTAB_TEST_PORT", "8080");
+        let config = parse(
+            "listen: \"0.0.0.0:${NETSTAB_TEST_PORT}\"\nendpoints:\n  - name: ${NETSTAB_TEST_SECRET} # \
+             ${NETSTAB_TEST_UNSET}\n    address: ${NETSTAB_TEST_UNSET:-10.0.0.1}\n    labels:\n      ${NETSTAB_TEST_PORT}: \
+             'Bob''s ${NETSTAB_TEST_PORT}'\n# ${NETSTAB_TEST_UNSET}\n  - name: $${NOT_A_REFERENCE}\n    address: \
+             10.0.0.2\n",
+            Format::Yaml,
+        )
+        .unwrap();
+        assert_eq!(config.listen.as_deref(), Some("0.0.0.0:8080"));
+        assert_eq!(config.endpoints[0].name, "a\"b\\c # d: e\nf'g");
+        assert_eq!(config.endpoints[0].address, "10.0.0.1");
+        assert_eq!(config.endpoints[0].labels["${NETSTAB_TEST_PORT}"], "Bob's 8080");
+        assert_eq!(config.endpoints[1].name, "${NOT_A_REFERENCE}");
+    }
+
+    #[test]
+    fn substitutes_json_and_toml() {
+        std::env::set_var("NETSTAB_TEST_QUOTES", "x\", \"injected\": \"y");
+        let config = parse(
+            "{\"endpoints\": [{\"name\": \"${NETSTAB_TEST_QUOTES}\", \"address\": \"10.0.0.1\"}]}",
+            Format::Json,
+        )
+        .unwrap();
+        assert_eq!(config.endpoints[0].name, "x\", \"injected\": \"y");
+
+        #[cfg(feature = "toml")]
+        {
+            let config = parse(
+                "# ${NETSTAB_TEST_UNSET}\n[[endpoints]]\nname = '${NETSTAB_TEST_QUOTES}'\naddress = \"10.0.0.1\"\n",
+                Format::Toml,
+            )
+            .unwrap();
+            assert_eq!(config.endpoints[0].name, "x\", \"injected\": \"y");
+        }
+    }
+
+    #[test]
+    fn reports_references_that_cannot_be_substituted() {
+        let error = parse(
+            "endpoints:\n  - name: a\n    address: ${NETSTAB_TEST_UNSET}\n",
+            Format::Yaml,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.message,
+            "endpoints[0].address: environment variable `NETSTAB_TEST_UNSET` is not set"
+        );
+        assert!(parse("listen: ${NETSTAB_TEST", Format::Yaml).is_err());
+        assert!(parse("listen: ${NOT-VALID}", Format::Yaml).is_err());
+
+        // Only strings are substituted, and type errors keep their position in a config with references.
+        std::env::set_var("NETSTAB_TEST_COUNT", "3");
+        let error = parse(
+            "listen: ${NETSTAB_TEST_COUNT}\ncount: ${NETSTAB_TEST_COUNT}\n",
+            Format::Yaml,
+        )
+        .unwrap_err();
+        assert_eq!((error.line, error.column), (Some(2), Some(8)));
+    }
+}
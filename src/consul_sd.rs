@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::{timeout, Duration};
+
+use crate::config::{self, Defaults, Endpoint};
+use crate::duration;
+use crate::http::{self, Verify};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConsulSdConfig {
+    #[serde(default = "default_address")]
+    pub address: String,
+    pub token_env: Option<String>,
+    pub token_file: Option<String>,
+    pub datacenter: Option<String>,
+    // Every service in the catalog when empty.
+    #[serde(default)]
+    pub services: Vec<String>,
+    // Only instances with all of these tags are probed.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default = "default_refresh_interval", with = "duration")]
+    pub refresh_interval: Duration,
+    #[serde(default)]
+    pub defaults: Defaults,
+}
+
+fn default_address() -> String {
+    "http://localhost:8500".to_string()
+}
+
+fn default_refresh_interval() -> Duration {
+    Duration::from_secs(30)
+}
+
+impl ConsulSdConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        http::parse_url(&self.address).map_err(|err| err.to_string())?;
+        if self.token_env.is_some() && self.token_file.is_some() {
+            return Err("set only one of token_env or token_file".to_string());
+        }
+        self.token()?;
+        if self.refresh_interval.is_zero() {
+            return Err("refresh_interval must be longer than 0s".to_string());
+        }
+        Ok(())
+    }
+
+    fn token(&self) -> Result<Option<String>, String> {
+        config::secret("token", &self.token_env, &self.token_file)
+    }
+
+    fn url(&self, path: &str) -> String {
+        let datacenter = self
+            .datacenter
+            .as_ref()
+            .map(|datacenter| format!("?dc={}", http::percent_encode(datacenter)))
+            .unwrap_or_default();
+        format!("{}{}{}", self.address.trim_end_matches('/'), path, datacenter)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Instance {
+    node: String,
+    address: String,
+    datacenter: Option<String>,
+    #[serde(rename = "ServiceID")]
+    service_id: String,
+    service_name: String,
+    #[serde(default)]
+    service_address: String,
+    #[serde(default)]
+    service_port: u16,
+    #[serde(default)]
+    service_tags: Vec<String>,
+}
+
+// Lists the instances of the services, and returns the first failure so that the endpoints found before can be kept.
+pub(crate) async fn lookup(config: &ConsulSdConfig) -> Result<Vec<Endpoint>, String> {
+    let mut headers = BTreeMap::new();
+    if let Some(token) = config.token()? {
+        headers.insert("X-Consul-Token".to_string(), token);
+    }
+
+    let services = if config.services.is_empty() {
+        let catalog: BTreeMap<String, Vec<String>> = get(&config.url("/v1/catalog/services"), &headers).await?;
+        catalog.into_keys().collect()
+    } else {
+        config.services.clone()
+    };
+
+    let mut endpoints = Vec::new();
+    for service in services {
+        let path = format!("/v1/catalog/service/{}", http::percent_encode(&service));
+        let instances: Vec<Instance> = get(&config.url(&path), &headers).await?;
+        endpoints.extend(
+            instances
+                .into_iter()
+                .filter(|instance| {
+                    config
+                        .tags
+                        .iter()
+                        .all(|tag| instance.service_tags.contains(tag))
+                })
+                .map(|instance| endpoint(config, instance)),
+        );
+    }
+    Ok(endpoints)
+}
+
+async fn get<T: for<'de> Deserialize<'de>>(url: &str, headers: &BTreeMap<String, String>) -> Result<T, String> {
+    let response = timeout(REQUEST_TIMEOUT, http::fetch(url, headers, &Verify::Roots))
+        .await
+        .map_err(|_| format!("requesting {} timed out", url))?
+        .map_err(|err| format!("requesting {}: {}", url, err))?;
+    if !response.status().is_success() {
+        return Err(format!("requesting {}: unexpected status {}", url, response.status()));
+    }
+    serde_json::from_slice(response.body()).map_err(|err| format!("requesting {}: {}", url, err))
+}
+
+// Instances are probed at the address of the service, or of the node when the service has none, and named after both
+// as service IDs are only unique on their node.
+fn endpoint(config: &ConsulSdConfig, instance: Instance) -> Endpoint {
+    let address = if instance.service_address.is_empty() {
+        instance.address
+    } else {
+        instance.service_address
+    };
+    let mut endpoint = Endpoint::new(format!("{}/{}", instance.node, instance.service_id), address);
+    endpoint.port = Some(instance.service_port).filter(|port| *port != 0);
+    endpoint
+        .labels
+        .insert("service".to_string(), instance.service_name);
+    endpoint.labels.insert("node".to_string(), instance.node);
+    if let Some(datacenter) = instance.datacenter {
+        endpoint.labels.insert("datacenter".to_string(), datacenter);
+    }
+    if !instance.service_tags.is_empty() {
+        endpoint
+            .labels
+            .insert("tags".to_string(), instance.service_tags.join(","));
+    }
+    endpoint.inherit(&config.defaults);
+    endpoint
+}
@@ -0,0 +1,255 @@
+use crate::status;
+use crate::timezone::Timezone;
+
+// How far ahead and back times are looked for, long enough for the 29th of February.
+const SEARCH_DAYS: i64 = 8 * 366;
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+// A cron expression of minute, hour, day of the month, month and day of the week, such as `*/5 9-17 * * MON-FRI`.
+// Like cron, a day matches when either of the day fields does unless one of them starts with `*`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub(crate) fn parse(expression: &str) -> Result<Cron, String> {
+        let expression = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "`{}` is not a cron expression, expected minute, hour, day of month, month and day of week",
+                expression
+            ));
+        }
+
+        let weekdays = field(fields[4], 0, 7, &WEEKDAYS).map_err(|err| format!("day of week: {}", err))?;
+        Ok(Cron {
+            minutes: field(fields[0], 0, 59, &[]).map_err(|err| format!("minute: {}", err))?,
+            hours: field(fields[1], 0, 23, &[]).map_err(|err| format!("hour: {}", err))?,
+            days: field(fields[2], 1, 31, &[]).map_err(|err| format!("day of month: {}", err))?,
+            months: field(fields[3], 1, 12, &MONTHS).map_err(|err| format!("month: {}", err))?,
+            // Sunday is both 0 and 7.
+            weekdays: (weekdays | weekdays >> 7) & 0x7f,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+
+    // The first time after `after` the expression matches, in Unix time.
+    pub(crate) fn next(&self, after: i64, timezone: &Timezone) -> Option<i64> {
+        let today = timezone.local(after).div_euclid(86400);
+        (today..=today + SEARCH_DAYS)
+            .filter(|day| self.matches(*day))
+            .find_map(|day| {
+                self.times(day, timezone)
+                    .into_iter()
+                    .find(|time| *time > after)
+            })
+    }
+
+    // The last time at or before `at` the expression matched, as long as it was after `since`.
+    pub(crate) fn previous(&self, at: i64, since: i64, timezone: &Timezone) -> Option<i64> {
+        let today = timezone.local(at).div_euclid(86400);
+        let first = timezone
+            .local(since)
+            .div_euclid(86400)
+            .max(today - SEARCH_DAYS);
+        (first..=today)
+            .rev()
+            .filter(|day| self.matches(*day))
+            .find_map(|day| {
+                self.times(day, timezone)
+                    .into_iter()
+                    .rev()
+                    .find(|time| *time <= at)
+            })
+            .filter(|time| *time > since)
+    }
+
+    fn matches(&self, day: i64) -> bool {
+        let (_, month, day_of_month, ..) = status::civil((day * 86400) as f64);
+        let weekday = (day + 4).rem_euclid(7);
+        let by_day = self.days & 1 << day_of_month != 0;
+        let by_weekday = self.weekdays & 1 << weekday != 0;
+        let matches_day = match (self.any_day, self.any_weekday) {
+            (true, _) => by_weekday,
+            (_, true) => by_day,
+            _ => by_day || by_weekday,
+        };
+        matches_day && self.months & 1 << month != 0
+    }
+
+    // The times of the day the expression matches, in Unix time and in order.
+    fn times(&self, day: i64, timezone: &Timezone) -> Vec<i64> {
+        let mut times: Vec<i64> = bits(self.hours)
+            .flat_map(|hour| bits(self.minutes).map(move |minute| hour * 3600 + minute * 60))
+            .map(|time| timezone.utc(day * 86400 + time))
+            .collect();
+        times.sort_unstable();
+        times.dedup();
+        times
+    }
+}
+
+fn bits(set: u64) -> impl Iterator<Item = i64> {
+    (0..64).filter(move |bit| set & 1 << bit != 0)
+}
+
+// A comma separated list of `*`, values and ranges, each optionally with a `/step`.
+fn field(value: &str, min: u64, max: u64, names: &[&str]) -> Result<u64, String> {
+    let number = |value: &str| -> Result<u64, String> {
+        let number = match names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(value))
+        {
+            Some(index) => index as u64 + min,
+            None => value
+                .parse()
+                .map_err(|_| format!("`{}` is not a number", value))?,
+        };
+        if number < min || number > max {
+            return Err(format!("{} is not between {} and {}", number, min, max));
+        }
+        Ok(number)
+    };
+
+    let mut set = 0;
+    for item in value.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => {
+                let step: u64 = step
+                    .parse()
+                    .map_err(|_| format!("`{}` is not a valid step", step))?;
+                if step == 0 {
+                    return Err("the step must not be 0".to_string());
+                }
+                (range, Some(step))
+            }
+            None => (item, None),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // A single value with a step runs to the end, as in `5/15`.
+            None if step.is_some() => (number(range)?, max),
+            None => {
+                let value = number(range)?;
+                (value, value)
+            }
+        };
+        if start > end {
+            return Err(format!("`{}` ends before it starts", range));
+        }
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UTC: Timezone = Timezone::Fixed(0);
+    // Monday, 2024-01-15 10:00 UTC.
+    const MONDAY: i64 = 1705312800;
+
+    fn next(expression: &str, after: i64) -> Option<i64> {
+        Cron::parse(expression).unwrap().next(after, &UTC)
+    }
+
+    #[test]
+    fn parses_fields() {
+        let cron = Cron::parse("*/15 9-17/4 1,15 JAN-mar SUN").unwrap();
+        assert_eq!(cron.minutes, 1 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(cron.hours, 1 << 9 | 1 << 13 | 1 << 17);
+        assert_eq!(cron.days, 1 << 1 | 1 << 15);
+        assert_eq!(cron.months, 0b1110);
+        assert_eq!(cron.weekdays, 1);
+        assert!(!cron.any_day && !cron.any_weekday);
+
+        assert_eq!(Cron::parse("0 0 * * 7").unwrap().weekdays, 1);
+        assert_eq!(Cron::parse("5/20 * * * *").unwrap().minutes, 1 << 5 | 1 << 25 | 1 << 45);
+        assert_eq!(Cron::parse("@weekly"), Cron::parse("0 0 * * 0"));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        let error = |expression| Cron::parse(expression).unwrap_err();
+        assert!(error("* * * *").contains("expected minute, hour"));
+        assert_eq!(error("60 * * * *"), "minute: 60 is not between 0 and 59");
+        assert_eq!(error("* * 0 * *"), "day of month: 0 is not between 1 and 31");
+        assert_eq!(error("* * * * FUN"), "day of week: `FUN` is not a number");
+        assert_eq!(error("*/0 * * * *"), "minute: the step must not be 0");
+        assert_eq!(error("* 17-9 * * *"), "hour: `17-9` ends before it starts");
+    }
+
+    #[test]
+    fn finds_the_next_time() {
+        assert_eq!(next("*/5 * * * *", MONDAY), Some(MONDAY + 300));
+        assert_eq!(next("0 * * * *", MONDAY - 1), Some(MONDAY));
+        assert_eq!(next("@daily", MONDAY), Some(MONDAY + 14 * 3600));
+        // From Friday evening to Monday morning.
+        assert_eq!(next("*/5 9-17 * * MON-FRI", 1705686900), Some(1705914000));
+        // Either day field matches, so the Monday after Saturday the 1st of June comes before the 1st of July.
+        assert_eq!(next("0 0 1 * MON", 1717200000), Some(1717372800));
+        // The next 29th of February is four years away.
+        assert_eq!(next("0 0 29 2 *", 1709251200), Some(1835395200));
+        assert_eq!(next("0 0 30 2 *", MONDAY), None);
+    }
+
+    #[test]
+    fn finds_the_previous_time() {
+        let cron = Cron::parse("@monthly").unwrap();
+        // 2024-06-15 back to the 1st.
+        assert_eq!(cron.previous(1718409600, 0, &UTC), Some(1717200000));
+        assert_eq!(cron.previous(1717200000, 0, &UTC), Some(1717200000));
+        assert_eq!(cron.previous(1718409600, 1717200000, &UTC), None);
+        assert_eq!(cron.previous(1718409600, 1717200000 - 1, &UTC), Some(1717200000));
+    }
+
+    #[test]
+    fn follows_daylight_saving_time() {
+        // Berlin in 2024, from +01:00 to +02:00 on the 31st of March and back on the 27th of October.
+        let (summer, winter) = (1711846800, 1729990800);
+        let berlin = Timezone::Zone {
+            transitions: vec![(summer, 7200), (winter, 3600)],
+            initial: 3600,
+            rule: None,
+        };
+        let cron = Cron::parse("30 2 * * *").unwrap();
+        assert_eq!(cron.next(summer - 86400, &berlin), Some(summer - 86400 + 1800));
+        // 02:30 is skipped, so it runs at what would have been 02:30 before the change.
+        assert_eq!(cron.next(summer - 3600, &berlin), Some(summer + 1800));
+        // 02:30 happens twice and it runs only the first time.
+        assert_eq!(cron.next(winter - 3600, &berlin), Some(winter - 1800));
+        assert_eq!(cron.next(winter - 1800, &berlin), Some(winter + 86400 + 1800));
+        assert_eq!(cron.previous(winter + 3600, 0, &berlin), Some(winter - 1800));
+
+        assert_eq!(
+            Cron::parse("0 9 * * *")
+                .unwrap()
+                .next(MONDAY, &Timezone::Fixed(-5 * 3600)),
+            Some(MONDAY + 4 * 3600)
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::source::Source;
+use crate::BoxError;
+
+// The user the handshakes introduce themselves as, which never logs in.
+const USER: &str = "rust-net-stab";
+// Longer greetings and error messages than this are not read.
+const MAX_MESSAGE: usize = 8192;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Database {
+    Redis,
+    Postgres,
+    Mysql,
+}
+
+impl Database {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Database::Redis => 6379,
+            Database::Postgres => 5432,
+            Database::Mysql => 3306,
+        }
+    }
+}
+
+// Completes the protocol's greeting without credentials, which only shows that the server is there and answering.
+// Returns the time from connecting up to the server's answer.
+pub async fn handshake(database: Database, server: SocketAddr, source: &Source) -> Result<Duration, BoxError> {
+    match database {
+        Database::Redis => redis(server, source).await,
+        Database::Postgres => postgres(server, source).await,
+        Database::Mysql => mysql(server, source).await,
+    }
+}
+
+// Sends PING. A server that wants the client to authenticate first has still answered.
+async fn redis(server: SocketAddr, source: &Source) -> Result<Duration, BoxError> {
+    let start = Instant::now();
+    let mut stream = BufReader::new(source.connect(server).await?);
+    stream.get_mut().write_all(b"PING\r\n").await?;
+
+    let mut reply = String::new();
+    if stream.read_line(&mut reply).await? == 0 {
+        return Err("redis closed the connection without answering".into());
+    }
+    let elapsed = start.elapsed();
+    let reply = reply.trim_end();
+    if reply == "+PONG" || reply.starts_with("-NOAUTH") {
+        Ok(elapsed)
+    } else {
+        Err(format!("redis answered `{}`", reply).into())
+    }
+}
+
+// Sends a startup message and succeeds once the server asks for authentication, which is never given.
+async fn postgres(server: SocketAddr, source: &Source) -> Result<Duration, BoxError> {
+    let start = Instant::now();
+    let mut stream = source.connect(server).await?;
+
+    let mut body = 196608u32.to_be_bytes().to_vec();
+    for (name, value) in [("user", USER), ("database", USER), ("application_name", USER)].iter() {
+        body.extend_from_slice(name.as_bytes());
+        body.push(0);
+        body.extend_from_slice(value.as_bytes());
+        body.push(0);
+    }
+    body.push(0);
+    let mut message = ((body.len() + 4) as u32).to_be_bytes().to_vec();
+    message.extend_from_slice(&body);
+    stream.write_all(&message).await?;
+
+    let kind = stream
+        .read_u8()
+        .await
+        .map_err(|_| "postgres closed the connection without answering")?;
+    let elapsed = start.elapsed();
+    let len = stream.read_u32().await? as usize;
+    // Read whole, so closing does not reset the connection while the server waits for a password.
+    let mut body = vec![0; len.saturating_sub(4).min(MAX_MESSAGE)];
+    stream.read_exact(&mut body).await?;
+    match kind {
+        b'R' => Ok(elapsed),
+        b'E' => {
+            // Fields are a type byte and a string each, of which the code and the message are wanted.
+            let field = |kind: u8| {
+                body.split(|byte| *byte == 0)
+                    .find(|field| field.first() == Some(&kind))
+                    .map(|field| String::from_utf8_lossy(&field[1..]).into_owned())
+                    .unwrap_or_default()
+            };
+            Err(format!("postgres answered {}: {}", field(b'C'), field(b'M')).into())
+        }
+        _ => Err("postgres did not answer with an authentication request".into()),
+    }
+}
+
+// Reads the greeting the server sends first, then logs in without a password so the server sees a failed login rather
+// than an aborted handshake, since too many of those from a host make MySQL block it.
+async fn mysql(server: SocketAddr, source: &Source) -> Result<Duration, BoxError> {
+    let start = Instant::now();
+    let mut stream = source.connect(server).await?;
+
+    let greeting = read_packet(&mut stream)
+        .await
+        .map_err(|_| "mysql closed the connection without a greeting")?;
+    let elapsed = start.elapsed();
+    match greeting.first() {
+        Some(10) => {}
+        Some(0xff) if greeting.len() >= 3 => {
+            let code = u16::from_le_bytes([greeting[1], greeting[2]]);
+            // The SQL state follows a `#` since protocol 4.1, though not in errors sent before the handshake.
+            let message = match greeting.get(3) {
+                Some(b'#') => greeting.get(9..).unwrap_or_default(),
+                _ => &greeting[3..],
+            };
+            return Err(format!("mysql answered {}: {}", code, String::from_utf8_lossy(message)).into());
+        }
+        _ => return Err("mysql did not send a protocol 10 greeting".into()),
+    }
+
+    // CLIENT_LONG_PASSWORD, CLIENT_PROTOCOL_41, CLIENT_SECURE_CONNECTION and CLIENT_PLUGIN_AUTH.
+    let capabilities: u32 = 0x0000_0001 | 0x0000_0200 | 0x0000_8000 | 0x0008_0000;
+    let mut response = capabilities.to_le_bytes().to_vec();
+    response.extend_from_slice(&(MAX_MESSAGE as u32).to_le_bytes());
+    // utf8mb4, then 23 reserved bytes.
+    response.push(45);
+    response.extend_from_slice(&[0; 23]);
+    response.extend_from_slice(USER.as_bytes());
+    response.push(0);
+    // An empty auth response.
+    response.push(0);
+    response.extend_from_slice(b"mysql_native_password\0");
+
+    let mut packet = (response.len() as u32).to_le_bytes()[..3].to_vec();
+    packet.push(1);
+    packet.extend_from_slice(&response);
+    if stream.write_all(&packet).await.is_ok() {
+        let _ = read_packet(&mut stream).await;
+    }
+    Ok(elapsed)
+}
+
+// A packet is its length in three bytes, a sequence number and the payload.
+async fn read_packet(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+
+    let mut payload = vec![0; len.min(MAX_MESSAGE)];
+    stream.read_exact(&mut payload).await?;
+    Ok(payload)
+}
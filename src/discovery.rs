@@ -0,0 +1,277 @@
+use std::collections::{BTreeMap, HashSet};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+use crate::agent::{self, AgentConfig};
+use crate::builtin_targets::{self, BuiltinTargets};
+use crate::config::{self, Config, Endpoint};
+use crate::consul_sd::{self, ConsulSdConfig};
+use crate::dns_sd::{self, DnsSdConfig};
+use crate::file_sd::{self, FileSdConfig};
+use crate::http_sd::{HttpSd, HttpSdConfig};
+use crate::kubernetes_sd::{self, KubernetesSdConfig};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    #[serde(default)]
+    pub file: Vec<FileSdConfig>,
+    #[serde(default)]
+    pub dns: Vec<DnsSdConfig>,
+    #[serde(default)]
+    pub kubernetes: Vec<KubernetesSdConfig>,
+    #[serde(default)]
+    pub consul: Vec<ConsulSdConfig>,
+    #[serde(default)]
+    pub http: Vec<HttpSdConfig>,
+}
+
+impl DiscoveryConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (index, file) in self.file.iter().enumerate() {
+            file.validate()
+                .map_err(|err| format!("file[{}]: {}", index, err))?;
+        }
+        for (index, dns) in self.dns.iter().enumerate() {
+            dns.validate()
+                .map_err(|err| format!("dns[{}]: {}", index, err))?;
+        }
+        for (index, kubernetes) in self.kubernetes.iter().enumerate() {
+            kubernetes
+                .validate()
+                .map_err(|err| format!("kubernetes[{}]: {}", index, err))?;
+        }
+        for (index, consul) in self.consul.iter().enumerate() {
+            consul
+                .validate()
+                .map_err(|err| format!("consul[{}]: {}", index, err))?;
+        }
+        for (index, http) in self.http.iter().enumerate() {
+            http.validate()
+                .map_err(|err| format!("http[{}]: {}", index, err))?;
+        }
+        Ok(())
+    }
+}
+
+// The endpoints a source found, which replace everything it found before.
+pub(crate) struct Update {
+    pub(crate) source: String,
+    pub(crate) endpoints: Vec<Endpoint>,
+}
+
+// Runs the discovery sources of the config and keeps what each of them found last.
+pub(crate) struct Discovery {
+    config: DiscoveryConfig,
+    builtin_targets: Option<BuiltinTargets>,
+    agent: Option<AgentConfig>,
+    // Kept so that `updated` waits forever rather than returning when there are no sources.
+    _sender: mpsc::Sender<Update>,
+    updates: mpsc::Receiver<Update>,
+    tasks: Vec<JoinHandle<()>>,
+    found: BTreeMap<String, Vec<Endpoint>>,
+}
+
+impl Discovery {
+    // Every source is read once right away, so that the labels of what they find are known when the metrics are
+    // registered.
+    pub(crate) async fn start(full: &Config) -> Discovery {
+        let config = &full.discovery;
+        let (sender, updates) = mpsc::channel(16);
+        let mut tasks = Vec::new();
+        let mut found = BTreeMap::new();
+
+        for (index, file) in config.file.iter().enumerate() {
+            let source = format!("file[{}]", index);
+            let files = file_sd::Files::read(file);
+            found.insert(source.clone(), files.endpoints());
+            tasks.push(tokio::spawn(file_sd::run(file.clone(), files, source, sender.clone())));
+        }
+        let dns = config
+            .dns
+            .iter()
+            .enumerate()
+            .map(|(index, dns)| (format!("dns[{}]", index), Lookup::Dns(dns.clone())));
+        let kubernetes = config
+            .kubernetes
+            .iter()
+            .enumerate()
+            .map(|(index, kubernetes)| (format!("kubernetes[{}]", index), Lookup::Kubernetes(kubernetes.clone())));
+        let consul = config
+            .consul
+            .iter()
+            .enumerate()
+            .map(|(index, consul)| (format!("consul[{}]", index), Lookup::Consul(consul.clone())));
+        let http = config.http.iter().enumerate().map(|(index, http)| {
+            (
+                format!("http[{}]", index),
+                Lookup::Http(http.clone(), HttpSd::default()),
+            )
+        });
+        let builtin = full
+            .builtin_targets
+            .iter()
+            .map(|builtin| ("builtin_targets".to_string(), Lookup::Builtin(builtin.clone())));
+        let mesh = full
+            .agent
+            .iter()
+            .filter(|agent| agent.mesh.is_some())
+            .map(|agent| ("agent mesh".to_string(), Lookup::Mesh(agent.clone())));
+        for (source, mut lookup) in dns
+            .chain(kubernetes)
+            .chain(consul)
+            .chain(http)
+            .chain(builtin)
+            .chain(mesh)
+        {
+            let endpoints = lookup.run().await.unwrap_or_else(|err| {
+                warn!("Failed to discover endpoints from {}: {}", source, err);
+                Vec::new()
+            });
+            found.insert(source.clone(), endpoints.clone());
+            tasks.push(tokio::spawn(poll(lookup, endpoints, source, sender.clone())));
+        }
+
+        Discovery {
+            config: config.clone(),
+            builtin_targets: full.builtin_targets.clone(),
+            agent: full.agent.clone(),
+            _sender: sender,
+            updates,
+            tasks,
+            found,
+        }
+    }
+
+    // Starts over with the new sources when they changed, forgetting everything the old ones found.
+    pub(crate) async fn reconfigure(&mut self, config: &Config) {
+        if config.discovery != self.config
+            || config.builtin_targets != self.builtin_targets
+            || config.agent != self.agent
+        {
+            *self = Discovery::start(config).await;
+        }
+    }
+
+    // Waits for a source to find something new.
+    pub(crate) async fn updated(&mut self) {
+        if let Some(update) = self.updates.recv().await {
+            self.found.insert(update.source, update.endpoints);
+        }
+    }
+
+    // The config with the endpoints that were found added to it. Endpoints whose name is already taken, or that are
+    // invalid, are left out.
+    pub(crate) fn merge(&self, config: &Config) -> Config {
+        let mut merged = config.clone();
+        let mut names: HashSet<String> = config
+            .endpoints
+            .iter()
+            .map(|endpoint| endpoint.name.clone())
+            .collect();
+
+        for (source, endpoints) in &self.found {
+            for endpoint in endpoints {
+                let mut endpoint = endpoint.clone();
+                endpoint.inherit(&config.defaults);
+                if !names.insert(endpoint.name.clone()) {
+                    warn!(
+                        "Ignoring endpoint `{}` from {}, the name is already used",
+                        endpoint.name, source
+                    );
+                    continue;
+                }
+                let problems = config::endpoint_problems(&endpoint, config);
+                if !problems.is_empty() {
+                    warn!(
+                        "Ignoring endpoint `{}` from {}: {}",
+                        endpoint.name,
+                        source,
+                        problems.join("; ")
+                    );
+                    continue;
+                }
+                merged.endpoints.push(endpoint);
+            }
+        }
+        merged
+    }
+}
+
+// A source that is looked up again every refresh interval.
+enum Lookup {
+    Dns(DnsSdConfig),
+    Kubernetes(KubernetesSdConfig),
+    Consul(ConsulSdConfig),
+    Http(HttpSdConfig, HttpSd),
+    Builtin(BuiltinTargets),
+    Mesh(AgentConfig),
+}
+
+impl Lookup {
+    fn refresh_interval(&self) -> Duration {
+        match self {
+            Lookup::Dns(config) => config.refresh_interval,
+            Lookup::Kubernetes(config) => config.refresh_interval,
+            Lookup::Consul(config) => config.refresh_interval,
+            Lookup::Http(config, _) => config.refresh_interval,
+            Lookup::Builtin(config) => config.refresh_interval,
+            Lookup::Mesh(config) => config
+                .mesh
+                .as_ref()
+                .map_or(Duration::from_secs(60), |mesh| mesh.refresh_interval),
+        }
+    }
+
+    async fn run(&mut self) -> Result<Vec<Endpoint>, String> {
+        match self {
+            Lookup::Dns(config) => dns_sd::lookup(config).await,
+            Lookup::Kubernetes(config) => kubernetes_sd::lookup(config).await,
+            Lookup::Consul(config) => consul_sd::lookup(config).await,
+            Lookup::Http(config, state) => state.lookup(config).await,
+            Lookup::Builtin(config) => builtin_targets::lookup(config).await,
+            Lookup::Mesh(config) => agent::peers(config).await,
+        }
+    }
+}
+
+// Sends the endpoints whenever a lookup finds something different. A lookup that fails keeps the endpoints found
+// before.
+async fn poll(mut lookup: Lookup, mut endpoints: Vec<Endpoint>, source: String, updates: mpsc::Sender<Update>) {
+    loop {
+        tokio::time::sleep(lookup.refresh_interval()).await;
+
+        let found = match lookup.run().await {
+            Ok(found) => found,
+            Err(err) => {
+                warn!("Keeping the endpoints from {}: {}", source, err);
+                continue;
+            }
+        };
+        if found == endpoints {
+            continue;
+        }
+        endpoints = found;
+
+        info!("Discovered {} endpoints from {}", endpoints.len(), source);
+        let update = Update {
+            source: source.clone(),
+            endpoints: endpoints.clone(),
+        };
+        if updates.send(update).await.is_err() {
+            return;
+        }
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
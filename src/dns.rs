@@ -0,0 +1,489 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use hyper::Uri;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::http::{self, Verify};
+use crate::source::Source;
+use crate::BoxError;
+
+pub(crate) const DNS_PORT: u16 = 53;
+const DOT_PORT: u16 = 853;
+const CLASS_IN: u16 = 1;
+const TRUNCATED: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RecordType {
+    #[default]
+    A,
+    Aaaa,
+    Cname,
+    Mx,
+    Ns,
+    Ptr,
+    Soa,
+    Srv,
+    Txt,
+}
+
+impl RecordType {
+    fn code(&self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Ns => 2,
+            RecordType::Cname => 5,
+            RecordType::Soa => 6,
+            RecordType::Ptr => 12,
+            RecordType::Mx => 15,
+            RecordType::Txt => 16,
+            RecordType::Aaaa => 28,
+            RecordType::Srv => 33,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub rcode: u8,
+    pub answers: Vec<String>,
+}
+
+impl Response {
+    pub fn rcode_name(&self) -> String {
+        match self.rcode {
+            0 => "NOERROR".to_string(),
+            1 => "FORMERR".to_string(),
+            2 => "SERVFAIL".to_string(),
+            3 => "NXDOMAIN".to_string(),
+            4 => "NOTIMP".to_string(),
+            5 => "REFUSED".to_string(),
+            rcode => format!("RCODE{}", rcode),
+        }
+    }
+
+    // Names are compared without regard to case or a trailing dot.
+    pub fn matches(&self, expected: &str) -> bool {
+        let normalize = |value: &str| value.trim_end_matches('.').to_ascii_lowercase();
+        self.answers
+            .iter()
+            .any(|answer| normalize(answer) == normalize(expected))
+    }
+}
+
+pub fn parse_resolver(resolver: &str) -> Result<SocketAddr, String> {
+    resolver
+        .parse::<SocketAddr>()
+        .or_else(|_| {
+            resolver
+                .parse::<IpAddr>()
+                .map(|ip| SocketAddr::new(ip, DNS_PORT))
+        })
+        .map_err(|_| {
+            format!(
+                "invalid resolver `{}`, expected an IP address with an optional port",
+                resolver
+            )
+        })
+}
+
+// Where the dns probe sends its query: plain DNS, DNS over TLS for `tls://` resolvers, or DNS over HTTPS for `https`
+// urls.
+pub enum Server {
+    Udp(SocketAddr),
+    Tls(SocketAddr),
+    Https(Uri),
+}
+
+pub fn parse_server(resolver: &str) -> Result<Server, String> {
+    if let Some(address) = resolver.strip_prefix("tls://") {
+        return address
+            .parse::<SocketAddr>()
+            .or_else(|_| {
+                address
+                    .parse::<IpAddr>()
+                    .map(|ip| SocketAddr::new(ip, DOT_PORT))
+            })
+            .map(Server::Tls)
+            .map_err(|_| {
+                format!(
+                    "invalid resolver `{}`, expected tls:// and an IP address with an optional port",
+                    resolver
+                )
+            });
+    }
+    if resolver.contains("://") {
+        return match http::parse_url(resolver) {
+            Ok(url) if url.scheme_str() == Some("https") => Ok(Server::Https(url)),
+            _ => Err(format!("invalid resolver `{}`, expected an https url", resolver)),
+        };
+    }
+    parse_resolver(resolver).map(Server::Udp)
+}
+
+pub async fn query(
+    resolver: SocketAddr,
+    name: &str,
+    record_type: RecordType,
+    source: &Source,
+) -> Result<Response, BoxError> {
+    let id: u16 = rand::random();
+    let request = encode_query(id, name, record_type)?;
+
+    let socket = source.udp(resolver).await?;
+    socket.connect(resolver).await?;
+    socket.send(&request).await?;
+
+    let mut buffer = [0u8; 4096];
+    loop {
+        let len = socket.recv(&mut buffer).await?;
+        let message = &buffer[..len];
+        if message.len() < 12 || u16::from_be_bytes([message[0], message[1]]) != id {
+            continue;
+        }
+
+        if message[2] & TRUNCATED != 0 {
+            return query_tcp(resolver, &request, source).await;
+        }
+        return decode_response(message);
+    }
+}
+
+// The certificate is verified against `server_name` unless `verify` is off.
+#[cfg(feature = "tls")]
+pub async fn query_tls(
+    resolver: SocketAddr,
+    server_name: &str,
+    verify: bool,
+    name: &str,
+    record_type: RecordType,
+    source: &Source,
+) -> Result<Response, BoxError> {
+    let request = encode_query(rand::random(), name, record_type)?;
+    let stream = source.connect(resolver).await?;
+    let stream = crate::tls::connect(stream, server_name, verify).await?;
+
+    exchange(stream, &request).await
+}
+
+#[cfg(not(feature = "tls"))]
+pub async fn query_tls(
+    _resolver: SocketAddr,
+    _server_name: &str,
+    _verify: bool,
+    _name: &str,
+    _record_type: RecordType,
+    _source: &Source,
+) -> Result<Response, BoxError> {
+    Err("tls:// resolvers need rust-net-stab to be built with the `tls` feature".into())
+}
+
+// Posts the query to the url at `ip` as described in RFC 8484, with an ID of 0 so the answer can be cached.
+pub async fn query_https(
+    url: &Uri,
+    ip: IpAddr,
+    verify: &Verify,
+    name: &str,
+    record_type: RecordType,
+    source: &Source,
+) -> Result<Response, BoxError> {
+    let request = encode_query(0, name, record_type)?;
+    let response = http::exchange(url, "application/dns-message", request, ip, source, verify).await?;
+    if !response.status().is_success() {
+        return Err(format!("resolver answered with status {}", response.status()).into());
+    }
+
+    decode_response(response.body())
+}
+
+async fn query_tcp(resolver: SocketAddr, request: &[u8], source: &Source) -> Result<Response, BoxError> {
+    exchange(source.connect(resolver).await?, request).await
+}
+
+// Sends the query over a stream, where messages are prefixed with their length.
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, request: &[u8]) -> Result<Response, BoxError> {
+    let mut framed = (request.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(request);
+    stream.write_all(&framed).await?;
+
+    let len = stream.read_u16().await?;
+    let mut message = vec![0; len as usize];
+    stream.read_exact(&mut message).await?;
+
+    decode_response(&message)
+}
+
+pub fn encode_query(id: u16, name: &str, record_type: RecordType) -> Result<Vec<u8>, BoxError> {
+    // Standard query with recursion desired, a single question and no other records.
+    let mut message = id.to_be_bytes().to_vec();
+    message.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|label| !label.is_empty())
+    {
+        if label.len() > 63 {
+            return Err(format!("label `{}` in `{}` is longer than 63 bytes", label, name).into());
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&record_type.code().to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    Ok(message)
+}
+
+pub fn decode_response(message: &[u8]) -> Result<Response, BoxError> {
+    let header = message.get(..12).ok_or("truncated DNS header")?;
+    let rcode = header[3] & 0x0f;
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answer_count = u16::from_be_bytes([header[6], header[7]]);
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = read_name(message, offset)?.1 + 4;
+    }
+
+    let mut answers = Vec::new();
+    for _ in 0..answer_count {
+        let (_, next) = read_name(message, offset)?;
+        let fixed = message.get(next..next + 10).ok_or("truncated DNS record")?;
+        let kind = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let start = next + 10;
+        let data = message
+            .get(start..start + len)
+            .ok_or("truncated DNS record data")?;
+
+        if let Some(answer) = format_record(message, kind, start, data)? {
+            answers.push(answer);
+        }
+        offset = start + len;
+    }
+
+    Ok(Response { rcode, answers })
+}
+
+fn format_record(message: &[u8], kind: u16, start: usize, data: &[u8]) -> Result<Option<String>, BoxError> {
+    let answer = match kind {
+        1 if data.len() == 4 => Ipv4Addr::new(data[0], data[1], data[2], data[3]).to_string(),
+        28 if data.len() == 16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(data);
+            Ipv6Addr::from(octets).to_string()
+        }
+        2 | 5 | 12 => read_name(message, start)?.0,
+        6 => {
+            let (primary, next) = read_name(message, start)?;
+            let (mailbox, _) = read_name(message, next)?;
+            format!("{} {}", primary, mailbox)
+        }
+        15 if data.len() > 2 => {
+            let preference = u16::from_be_bytes([data[0], data[1]]);
+            format!("{} {}", preference, read_name(message, start + 2)?.0)
+        }
+        16 => {
+            let mut text = String::new();
+            let mut rest = data;
+            while let Some((&len, tail)) = rest.split_first() {
+                let chunk = tail.get(..len as usize).unwrap_or(tail);
+                text.push_str(&String::from_utf8_lossy(chunk));
+                rest = &tail[chunk.len()..];
+            }
+            text
+        }
+        33 if data.len() > 6 => {
+            let priority = u16::from_be_bytes([data[0], data[1]]);
+            let weight = u16::from_be_bytes([data[2], data[3]]);
+            let port = u16::from_be_bytes([data[4], data[5]]);
+            format!("{} {} {} {}", priority, weight, port, read_name(message, start + 6)?.0)
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(answer))
+}
+
+// Returns the decoded name and the offset just past it, following compression pointers.
+fn read_name(message: &[u8], mut offset: usize) -> Result<(String, usize), BoxError> {
+    let mut labels = Vec::new();
+    let mut end = None;
+
+    for _ in 0..128 {
+        let len = *message.get(offset).ok_or("truncated DNS name")? as usize;
+        match len {
+            0 => {
+                let name = if labels.is_empty() {
+                    ".".to_string()
+                } else {
+                    labels.join(".")
+                };
+                return Ok((name, end.unwrap_or(offset + 1)));
+            }
+            len if len & 0xc0 == 0xc0 => {
+                let low = *message.get(offset + 1).ok_or("truncated DNS name")? as usize;
+                end.get_or_insert(offset + 2);
+                offset = ((len & 0x3f) << 8) | low;
+            }
+            len => {
+                let label = message
+                    .get(offset + 1..offset + 1 + len)
+                    .ok_or("truncated DNS name")?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + len;
+            }
+        }
+    }
+
+    Err("DNS name has too many labels or a compression loop".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A response to `query` with the answers, whose names point back at the name of the question.
+    fn response(query: &[u8], rcode: u8, answers: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut message = query.to_vec();
+        message[2] |= 0x80;
+        message[3] = 0x80 | rcode;
+        message[6..8].copy_from_slice(&(answers.len() as u16).to_be_bytes());
+        for (kind, data) in answers {
+            message.extend_from_slice(&[0xc0, 12]);
+            message.extend_from_slice(&kind.to_be_bytes());
+            message.extend_from_slice(&[0, 1, 0, 0, 0x0e, 0x10]);
+            message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            message.extend_from_slice(data);
+        }
+        message
+    }
+
+    #[test]
+    fn encodes_queries() {
+        assert_eq!(
+            encode_query(0x1234, "example.com.", RecordType::A).unwrap(),
+            b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07example\x03com\x00\x00\x01\x00\x01"
+        );
+        assert_eq!(
+            encode_query(1, "", RecordType::Ns).unwrap(),
+            b"\x00\x01\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00\x02\x00\x01"
+        );
+        assert!(encode_query(1, "_sip._tcp.example.com", RecordType::Srv)
+            .unwrap()
+            .ends_with(b"\x04_sip\x04_tcp\x07example\x03com\x00\x00\x21\x00\x01"));
+
+        let long = format!("{}.com", "a".repeat(64));
+        assert!(encode_query(1, &long, RecordType::A)
+            .unwrap_err()
+            .to_string()
+            .contains("longer than 63 bytes"));
+    }
+
+    #[test]
+    fn decodes_answers() {
+        let query = encode_query(7, "example.com", RecordType::A).unwrap();
+        let name = b"\x04mail\xc0\x0c".to_vec();
+        let answers = [
+            (1, vec![93, 184, 216, 34]),
+            (28, vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+            (5, name.clone()),
+            (15, [&[0, 10][..], &name].concat()),
+            (16, b"\x05hello\x06 world".to_vec()),
+            (33, [&[0, 1, 0, 5, 0x13, 0xc4][..], &name].concat()),
+            (
+                6,
+                [&b"\x02ns\xc0\x0c"[..], b"\x0ahostmaster\xc0\x0c", &[0; 20]].concat(),
+            ),
+            // Records of other types are skipped.
+            (99, vec![1, 2, 3]),
+        ];
+
+        let response = decode_response(&response(&query, 0, &answers)).unwrap();
+        assert_eq!(response.rcode_name(), "NOERROR");
+        assert_eq!(
+            response.answers,
+            [
+                "93.184.216.34",
+                "2001:db8::1",
+                "mail.example.com",
+                "10 mail.example.com",
+                "hello world",
+                "1 5 5060 mail.example.com",
+                "ns.example.com hostmaster.example.com",
+            ]
+        );
+        assert!(response.matches("MAIL.example.com."));
+        assert!(!response.matches("example.com"));
+    }
+
+    #[test]
+    fn decodes_errors() {
+        let query = encode_query(7, "missing.example", RecordType::A).unwrap();
+        let response = decode_response(&response(&query, 3, &[])).unwrap();
+        assert_eq!(response.rcode_name(), "NXDOMAIN");
+        assert!(response.answers.is_empty());
+        assert_eq!(
+            decode_response(&self::response(&query, 9, &[]))
+                .unwrap()
+                .rcode_name(),
+            "RCODE9"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_responses() {
+        let error = |message: &[u8]| decode_response(message).unwrap_err().to_string();
+        assert_eq!(error(&[0; 11]), "truncated DNS header");
+
+        let query = encode_query(7, "example.com", RecordType::A).unwrap();
+        let complete = response(&query, 0, &[(1, vec![93, 184, 216, 34])]);
+        assert_eq!(error(&complete[..complete.len() - 2]), "truncated DNS record data");
+        assert_eq!(error(&complete[..query.len() + 6]), "truncated DNS record");
+        assert_eq!(error(&query[..20]), "truncated DNS name");
+
+        // A name that points at itself.
+        let mut looped = query[..12].to_vec();
+        looped.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+        assert_eq!(error(&looped), "DNS name has too many labels or a compression loop");
+    }
+
+    #[test]
+    fn parses_servers() {
+        assert_eq!(parse_resolver("1.1.1.1"), Ok("1.1.1.1:53".parse().unwrap()));
+        assert_eq!(parse_resolver("[::1]:5353"), Ok("[::1]:5353".parse().unwrap()));
+        assert!(parse_resolver("dns.google").is_err());
+
+        assert!(matches!(parse_server("9.9.9.9"), Ok(Server::Udp(address)) if address.port() == 53));
+        assert!(matches!(parse_server("tls://9.9.9.9"), Ok(Server::Tls(address)) if address.port() == 853));
+        assert!(matches!(parse_server("tls://9.9.9.9:8853"), Ok(Server::Tls(address)) if address.port() == 8853));
+        assert!(
+            matches!(parse_server("https://dns.google/dns-query"), Ok(Server::Https(url)) if url.path() == "/dns-query")
+        );
+        assert!(parse_server("http://dns.google/dns-query").is_err());
+        assert!(parse_server("tls://dns.google").is_err());
+    }
+
+    #[tokio::test]
+    async fn exchanges_messages_over_streams() {
+        let query = encode_query(7, "example.com", RecordType::A).unwrap();
+        let answer = response(&query, 0, &[(1, vec![10, 0, 0, 1])]);
+        let (client, mut server) = tokio::io::duplex(1024);
+
+        let expected = query.clone();
+        let server = tokio::spawn(async move {
+            let len = server.read_u16().await.unwrap();
+            let mut request = vec![0; len as usize];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, expected);
+            server.write_u16(answer.len() as u16).await.unwrap();
+            server.write_all(&answer).await.unwrap();
+        });
+
+        assert_eq!(exchange(client, &query).await.unwrap().answers, ["10.0.0.1"]);
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::{timeout, Duration};
+
+use crate::config::{self, Defaults, Endpoint, RecordType};
+use crate::dns;
+use crate::duration;
+use crate::source::Source;
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DnsSdConfig {
+    pub names: Vec<String>,
+    // SRV, A or AAAA.
+    #[serde(default = "default_record_type")]
+    pub record_type: RecordType,
+    // The port of the endpoints found by A and AAAA lookups, which SRV records come with.
+    pub port: Option<u16>,
+    // Queried instead of the first nameserver in /etc/resolv.conf.
+    pub resolver: Option<String>,
+    #[serde(default = "default_refresh_interval", with = "duration")]
+    pub refresh_interval: Duration,
+    #[serde(default)]
+    pub defaults: Defaults,
+}
+
+fn default_record_type() -> RecordType {
+    RecordType::Srv
+}
+
+fn default_refresh_interval() -> Duration {
+    Duration::from_secs(30)
+}
+
+impl DnsSdConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.names.is_empty() {
+            return Err("names must not be empty".to_string());
+        }
+        if let Some(name) = self.names.iter().find(|name| !config::valid_hostname(name)) {
+            return Err(format!("`{}` is not a valid DNS name", name));
+        }
+        if !matches!(self.record_type, RecordType::Srv | RecordType::A | RecordType::Aaaa) {
+            return Err("record_type must be SRV, A or AAAA".to_string());
+        }
+        if let Some(resolver) = &self.resolver {
+            dns::parse_resolver(resolver)?;
+        }
+        if self.refresh_interval.is_zero() {
+            return Err("refresh_interval must be longer than 0s".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Looks up every name, or returns the first failure so that the endpoints found before can be kept.
+pub(crate) async fn lookup(config: &DnsSdConfig) -> Result<Vec<Endpoint>, String> {
+    let resolver = match &config.resolver {
+        Some(resolver) => dns::parse_resolver(resolver)?,
+        None => system_resolver()?,
+    };
+
+    let mut endpoints = Vec::new();
+    for name in &config.names {
+        let response = timeout(
+            QUERY_TIMEOUT,
+            dns::query(resolver, name, config.record_type, &Source::default()),
+        )
+        .await
+        .map_err(|_| format!("looking up {} timed out", name))?
+        .map_err(|err| format!("looking up {}: {}", name, err))?;
+        // A name that does not exist has no endpoints, rather than being an error.
+        if response.rcode != 0 && response.rcode != 3 {
+            return Err(format!("looking up {}: {}", name, response.rcode_name()));
+        }
+
+        for answer in &response.answers {
+            if let Some(endpoint) = endpoint(config, answer) {
+                if !endpoints.contains(&endpoint) {
+                    endpoints.push(endpoint);
+                }
+            }
+        }
+    }
+    Ok(endpoints)
+}
+
+// SRV answers are formatted as `priority weight port target`, A and AAAA answers are the address.
+fn endpoint(config: &DnsSdConfig, answer: &str) -> Option<Endpoint> {
+    let (address, port) = match config.record_type {
+        RecordType::Srv => {
+            let mut fields = answer.split_whitespace().skip(2);
+            let port = fields.next()?.parse().ok()?;
+            let target = fields.next()?.trim_end_matches('.');
+            // A target of `.` means the service is not available.
+            if target.is_empty() {
+                return None;
+            }
+            (target.to_string(), Some(port))
+        }
+        _ => (answer.parse::<IpAddr>().ok()?.to_string(), config.port),
+    };
+
+    let name = match port {
+        Some(port) if address.contains(':') => format!("[{}]:{}", address, port),
+        Some(port) => format!("{}:{}", address, port),
+        None => address.clone(),
+    };
+    let mut endpoint = Endpoint::new(name, address);
+    endpoint.port = port;
+    endpoint.inherit(&config.defaults);
+    Some(endpoint)
+}
+
+fn system_resolver() -> Result<SocketAddr, String> {
+    let resolv = fs::read_to_string("/etc/resolv.conf")
+        .map_err(|err| format!("failed to read /etc/resolv.conf, set a resolver: {}", err))?;
+    resolv
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .find_map(|nameserver| nameserver.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, dns::DNS_PORT))
+        .ok_or_else(|| "/etc/resolv.conf has no nameserver, set a resolver".to_string())
+}
@@ -0,0 +1,142 @@
+use std::fmt;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+// A DSCP marking, either a number from 0 to 63 or one of the names of the standard per-hop behaviors such as `ef`,
+// `af41` or `cs5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Dscp(u8);
+
+// The name of each marking where it has one, and the number otherwise.
+const NAMES: [&str; 64] = [
+    "be", "le", "2", "3", "4", "5", "6", "7", "cs1", "9", "af11", "11", "af12", "13", "af13", "15", "cs2", "17",
+    "af21", "19", "af22", "21", "af23", "23", "cs3", "25", "af31", "27", "af32", "29", "af33", "31", "cs4", "33",
+    "af41", "35", "af42", "37", "af43", "39", "cs5", "41", "42", "43", "va", "45", "ef", "47", "cs6", "49", "50", "51",
+    "52", "53", "54", "55", "cs7", "57", "58", "59", "60", "61", "62", "63",
+];
+
+impl Dscp {
+    // The value of the IPv4 type of service and IPv6 traffic class fields, of which DSCP is the upper six bits and
+    // the ECN bits stay clear.
+    pub fn tos(&self) -> u8 {
+        self.0 << 2
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        NAMES[usize::from(self.0)]
+    }
+}
+
+impl std::str::FromStr for Dscp {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid dscp `{}`, expected a number from 0 to 63 or a name like `ef`, `af41` or `cs5`",
+                value
+            )
+        };
+
+        let lower = value.to_ascii_lowercase();
+        let dscp = match (lower.as_str(), lower.parse::<u8>()) {
+            ("default" | "cs0", _) => 0,
+            (_, Ok(dscp @ 0..=63)) => dscp,
+            (name, _) => match NAMES
+                .iter()
+                .position(|known| *known == name && name.starts_with(char::is_alphabetic))
+            {
+                Some(dscp) => dscp as u8,
+                None => return Err(invalid()),
+            },
+        };
+        Ok(Dscp(dscp))
+    }
+}
+
+impl fmt::Display for Dscp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Dscp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Dscp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(dscp) => dscp.to_string().parse().map_err(de::Error::custom),
+            Raw::Text(text) => text.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+// The name of one of the endpoints an entry with several markings expands to. `{dscp}` in the name of the entry is
+// filled in, and names without it get the marking appended so that they stay unique.
+pub(crate) fn name(template: &str, dscp: Dscp) -> String {
+    if template.contains("{dscp}") {
+        template.replace("{dscp}", dscp.as_str())
+    } else {
+        format!("{} {}", template, dscp)
+    }
+}
+
+// Fields holding a single marking or a list of them, given either way in the config.
+pub mod list {
+    use std::fmt;
+
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::{Deserializer, Serialize, Serializer};
+
+    use super::Dscp;
+
+    pub fn serialize<S: Serializer>(markings: &[Dscp], serializer: S) -> Result<S::Ok, S::Error> {
+        markings.serialize(serializer)
+    }
+
+    // A visitor rather than an untagged enum, so an invalid marking is reported as such.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Dscp>, D::Error> {
+        struct Markings;
+
+        impl<'de> Visitor<'de> for Markings {
+            type Value = Vec<Dscp>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a DSCP marking or a list of them")
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+                self.visit_str(&value.to_string())
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+                self.visit_str(&value.to_string())
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                value.parse().map(|dscp| vec![dscp]).map_err(E::custom)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut markings = Vec::new();
+                while let Some(dscp) = seq.next_element()? {
+                    markings.push(dscp);
+                }
+                Ok(markings)
+            }
+        }
+
+        deserializer.deserialize_any(Markings)
+    }
+}
@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use serde::{de, Deserialize, Deserializer, Serializer};
+
+// Durations are written as `500ms`, `5s`, `1m`, `1h` or `30d`; a bare number is taken as seconds.
+pub fn parse(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{}`", value))?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        "d" => number * 86400.0,
+        _ => {
+            return Err(format!(
+                "invalid duration unit in `{}`, expected ms, s, m, h or d",
+                value
+            ))
+        }
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("duration `{}` is too long", value))
+}
+
+pub fn format(duration: &Duration) -> String {
+    if duration.subsec_millis() != 0 || duration.as_secs() == 0 {
+        format!("{}ms", duration.as_millis())
+    } else {
+        format!("{}s", duration.as_secs())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Raw {
+    Seconds(f64),
+    Text(String),
+}
+
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format(duration))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    match Raw::deserialize(deserializer)? {
+        Raw::Seconds(seconds) => Duration::try_from_secs_f64(seconds)
+            .map_err(|_| de::Error::custom(format!("invalid duration `{}`", seconds))),
+        Raw::Text(text) => parse(&text).map_err(de::Error::custom),
+    }
+}
+
+pub mod option {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => super::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(deserialize_with = "super::deserialize")] Duration);
+
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(duration)| duration))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Config {
+        #[serde(deserialize_with = "deserialize")]
+        interval: Duration,
+        #[serde(default, deserialize_with = "option::deserialize")]
+        timeout: Option<Duration>,
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse("5s"), Ok(Duration::from_secs(5)));
+        assert_eq!(parse(" 1.5m "), Ok(Duration::from_secs(90)));
+        assert_eq!(parse("2 h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse("30d"), Ok(Duration::from_secs(30 * 86400)));
+        assert_eq!(parse("10"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse("0"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert_eq!(parse("fast"), Err("invalid duration `fast`".to_string()));
+        assert_eq!(parse("-5s"), Err("invalid duration `-5s`".to_string()));
+        assert_eq!(parse("1.2.3s"), Err("invalid duration `1.2.3s`".to_string()));
+        assert_eq!(
+            parse("5w"),
+            Err("invalid duration unit in `5w`, expected ms, s, m, h or d".to_string())
+        );
+        assert_eq!(
+            parse("99999999999999999999999d"),
+            Err("duration `99999999999999999999999d` is too long".to_string())
+        );
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format(&Duration::from_millis(1500)), "1500ms");
+        assert_eq!(format(&Duration::from_secs(60)), "60s");
+        assert_eq!(format(&Duration::ZERO), "0ms");
+        assert_eq!(
+            parse(&format(&Duration::from_millis(250))),
+            Ok(Duration::from_millis(250))
+        );
+    }
+
+    #[test]
+    fn deserializes_numbers_and_strings() {
+        let config: Config = serde_yaml::from_str("interval: 2.5\ntimeout: 750ms").unwrap();
+        assert_eq!(config.interval, Duration::from_millis(2500));
+        assert_eq!(config.timeout, Some(Duration::from_millis(750)));
+
+        let config: Config = serde_yaml::from_str("interval: 1m").unwrap();
+        assert_eq!(config.timeout, None);
+
+        assert!(serde_yaml::from_str::<Config>("interval: -1").is_err());
+        assert!(serde_yaml::from_str::<Config>("interval: 1e300").is_err());
+        assert!(serde_yaml::from_str::<Config>("interval: 5x").is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use crate::status;
+
+// The exemplars of one histogram by the upper bound of their bucket.
+type Buckets = Vec<(f64, Exemplar)>;
+
+// The latest observation in each bucket of every `ping_latency` histogram, by the labels of the series, with the trace
+// of the probe it came from. Like the histograms themselves they are kept for the whole process.
+fn store() -> &'static Mutex<HashMap<String, Buckets>> {
+    static STORE: OnceLock<Mutex<HashMap<String, Buckets>>> = OnceLock::new();
+    STORE.get_or_init(Mutex::default)
+}
+
+#[derive(Debug, Clone)]
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    timestamp: f64,
+}
+
+// Keeps an observation of the histogram with these labels as the exemplar of the bucket it fell in.
+pub(crate) fn observe(labels: &[(&str, &str)], buckets: &[f64], value: f64, trace_id: &str) {
+    let bound = buckets
+        .iter()
+        .copied()
+        .find(|bound| value <= *bound)
+        .unwrap_or(f64::INFINITY);
+    let exemplar = Exemplar {
+        trace_id: trace_id.to_string(),
+        value,
+        timestamp: status::unix_time(),
+    };
+
+    let mut store = store().lock().unwrap();
+    let series = store.entry(key(labels)).or_default();
+    match series.iter_mut().find(|(existing, _)| *existing == bound) {
+        Some((_, existing)) => *existing = exemplar,
+        None => series.push((bound, exemplar)),
+    }
+}
+
+// Forgets the exemplars of a histogram that is unregistered, such as for an endpoint that was removed.
+pub(crate) fn remove(labels: &[(&str, &str)]) {
+    store().lock().unwrap().remove(&key(labels));
+}
+
+fn key(labels: &[(&str, &str)]) -> String {
+    let mut pairs: Vec<(&str, String)> = labels
+        .iter()
+        .map(|(name, value)| (*name, escape(value)))
+        .collect();
+    pairs.sort();
+    series_key(&pairs)
+}
+
+// Appends the exemplar of each bucket of the latency histograms, named `name` after the metrics section, of an
+// OpenMetrics exposition that has one.
+pub(crate) fn annotate(openmetrics: &str, name: &str) -> String {
+    let prefix = format!("{}_bucket{{", name);
+    let store = store().lock().unwrap();
+    if store.is_empty() {
+        return openmetrics.to_string();
+    }
+
+    let mut annotated = String::with_capacity(openmetrics.len());
+    for line in openmetrics.lines() {
+        annotated.push_str(line);
+        if let Some(exemplar) = line
+            .strip_prefix(prefix.as_str())
+            .and_then(|labels| exemplar(&store, labels))
+        {
+            annotated.push_str(&format!(
+                " # {{trace_id=\"{}\"}} {} {}",
+                exemplar.trace_id, exemplar.value, exemplar.timestamp
+            ));
+        }
+        annotated.push('\n');
+    }
+    annotated
+}
+
+fn exemplar<'a>(store: &'a HashMap<String, Buckets>, labels: &str) -> Option<&'a Exemplar> {
+    let mut pairs = parse_labels(labels)?;
+    let index = pairs.iter().position(|(name, _)| *name == "le")?;
+    let (_, bound) = pairs.remove(index);
+    let bound: f64 = bound.parse().ok()?;
+    pairs.sort();
+    store
+        .get(&series_key(&pairs))?
+        .iter()
+        .find(|(existing, _)| *existing == bound)
+        .map(|(_, exemplar)| exemplar)
+}
+
+// The labels sorted by name, as the key of the series.
+fn series_key(pairs: &[(&str, String)]) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// The labels of a sample up to the closing brace, with their values still escaped as in the exposition.
+fn parse_labels(mut labels: &str) -> Option<Vec<(&str, String)>> {
+    let mut pairs = Vec::new();
+    loop {
+        labels = labels.trim_start_matches(',');
+        if labels.starts_with('}') {
+            return Some(pairs);
+        }
+        let (name, rest) = labels.split_once("=\"")?;
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        let end = loop {
+            match chars.next()? {
+                (_, '\\') => {
+                    value.push('\\');
+                    value.push(chars.next()?.1);
+                }
+                (index, '"') => break index,
+                (_, c) => value.push(c),
+            }
+        };
+        pairs.push((name, value));
+        labels = &rest[end + 1..];
+    }
+}
+
+// Label values as the text format writes them.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_labels() {
+        assert_eq!(
+            parse_labels("endpoint=\"gw\",le=\"0.005\"} 3"),
+            Some(vec![("endpoint", "gw".to_string()), ("le", "0.005".to_string())])
+        );
+        // Values stay escaped, and may hold braces, commas and escaped quotes.
+        assert_eq!(
+            parse_labels("name=\"a \\\"b\\\", {c}\",x=\"\\\\\"}"),
+            Some(vec![
+                ("name", "a \\\"b\\\", {c}".to_string()),
+                ("x", "\\\\".to_string())
+            ])
+        );
+        assert_eq!(parse_labels("} 1"), Some(Vec::new()));
+        assert_eq!(parse_labels("endpoint=\"gw"), None);
+        assert_eq!(parse_labels("endpoint=gw}"), None);
+    }
+
+    #[test]
+    fn keys_series_by_sorted_escaped_labels() {
+        assert_eq!(
+            key(&[("location", "b"), ("endpoint", "a\"1")]),
+            "endpoint=\"a\\\"1\",location=\"b\""
+        );
+        assert_eq!(escape("a\\b\nc"), "a\\\\b\\nc");
+    }
+
+    #[test]
+    fn annotates_buckets() {
+        let labels = [("endpoint", "exemplar \"test\""), ("location", "lab")];
+        let buckets = [0.005, 0.01, 0.025];
+        observe(&labels, &buckets, 0.004, "4bf92f3577b34da6a3ce929d0e0e4736");
+        observe(&labels, &buckets, 0.007, "00f067aa0ba902b7");
+        observe(&labels, &buckets, 0.008, "b7ad6b7169203331");
+        observe(&labels, &buckets, 1.5, "ffffffffffffffff");
+
+        let exposition = "\
+# TYPE netstab_ping_latency histogram
+netstab_ping_latency_bucket{endpoint=\"exemplar \\\"test\\\"\",le=\"0.005\",location=\"lab\"} 1
+netstab_ping_latency_bucket{endpoint=\"exemplar \\\"test\\\"\",le=\"0.01\",location=\"lab\"} 3
+netstab_ping_latency_bucket{endpoint=\"exemplar \\\"test\\\"\",le=\"0.025\",location=\"lab\"} 3
+netstab_ping_latency_bucket{endpoint=\"exemplar \\\"test\\\"\",le=\"+Inf\",location=\"lab\"} 4
+netstab_ping_latency_bucket{endpoint=\"other\",le=\"0.005\",location=\"lab\"} 0
+netstab_ping_latency_count{endpoint=\"exemplar \\\"test\\\"\",location=\"lab\"} 4
+# EOF
+";
+        let annotated: Vec<String> = annotate(exposition, "netstab_ping_latency")
+            .lines()
+            .map(|line| match line.rsplit_once(' ') {
+                // The timestamp of an exemplar is when it was observed.
+                Some((rest, timestamp)) if line.contains("trace_id") => {
+                    assert!(timestamp.parse::<f64>().unwrap() > 1.7e9);
+                    rest.to_string()
+                }
+                _ => line.to_string(),
+            })
+            .collect();
+        let lines: Vec<&str> = exposition.lines().collect();
+        assert_eq!(annotated[0], lines[0]);
+        assert_eq!(
+            annotated[1],
+            format!("{} # {{trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\"}} 0.004", lines[1])
+        );
+        assert_eq!(
+            annotated[2],
+            format!("{} # {{trace_id=\"b7ad6b7169203331\"}} 0.008", lines[2])
+        );
+        assert_eq!(annotated[3], lines[3]);
+        assert_eq!(
+            annotated[4],
+            format!("{} # {{trace_id=\"ffffffffffffffff\"}} 1.5", lines[4])
+        );
+        assert_eq!(annotated[5..], lines[5..]);
+
+        remove(&labels);
+        assert!(!annotate(exposition, "netstab_ping_latency").contains("b7ad6b7169203331"));
+    }
+}
@@ -0,0 +1,262 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use serde::{Deserialize, Serialize};
+
+// More addresses than this from a single entry is much more likely a mistake in the prefix length than intended.
+const MAX_ADDRESSES: u128 = 65536;
+
+// The addresses of a CIDR block such as `10.0.5.0/28` or an inclusive range such as `10.0.5.10-10.0.5.50`, or `None`
+// when `address` is neither. The network and broadcast addresses of IPv4 blocks larger than /31 are left out.
+pub(crate) fn addresses(address: &str) -> Option<Result<Vec<IpAddr>, String>> {
+    if let Some((ip, prefix)) = address.split_once('/') {
+        let ip = ip.trim().parse::<IpAddr>().ok()?;
+        return Some(cidr(ip, prefix.trim()).map_err(|err| format!("`{}` {}", address, err)));
+    }
+    let (first, last) = address.split_once('-')?;
+    let first = first.trim().parse::<IpAddr>().ok()?;
+    let last = last.trim().parse::<IpAddr>().ok()?;
+    Some(range(first, last).map_err(|err| format!("`{}` {}", address, err)))
+}
+
+fn cidr(ip: IpAddr, prefix: &str) -> Result<Vec<IpAddr>, String> {
+    let bits = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix
+        .parse::<u32>()
+        .ok()
+        .filter(|prefix| *prefix <= bits)
+        .ok_or_else(|| format!("needs a prefix length between 0 and {}", bits))?;
+
+    let host_bits = bits - prefix;
+    let mask = if host_bits == 128 { 0 } else { u128::MAX << host_bits };
+    let network = to_int(ip) & mask;
+    let size = if host_bits == 128 {
+        u128::MAX
+    } else {
+        (1 << host_bits) - 1
+    };
+    let (first, last) = if ip.is_ipv4() && host_bits > 1 {
+        (network + 1, network + size - 1)
+    } else {
+        (network, network + size)
+    };
+    collect(ip.is_ipv4(), first, last)
+}
+
+fn range(first: IpAddr, last: IpAddr) -> Result<Vec<IpAddr>, String> {
+    if first.is_ipv4() != last.is_ipv4() {
+        return Err("mixes IPv4 and IPv6 addresses".to_string());
+    }
+    if to_int(first) > to_int(last) {
+        return Err("ends before it starts".to_string());
+    }
+    collect(first.is_ipv4(), to_int(first), to_int(last))
+}
+
+fn collect(ipv4: bool, first: u128, last: u128) -> Result<Vec<IpAddr>, String> {
+    if last - first >= MAX_ADDRESSES {
+        return Err(format!("expands to more than {} addresses", MAX_ADDRESSES));
+    }
+    Ok((first..=last)
+        .map(|ip| {
+            if ipv4 {
+                IpAddr::V4(Ipv4Addr::from(ip as u32))
+            } else {
+                IpAddr::V6(Ipv6Addr::from(ip))
+            }
+        })
+        .collect())
+}
+
+// A CIDR block that addresses are matched against rather than expanded, such as `10.0.0.0/8`. A plain address is a
+// block of its own. Read from the config as a string, and written back as one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Block {
+    ipv4: bool,
+    network: u128,
+    mask: u128,
+}
+
+impl Block {
+    pub(crate) fn parse(block: &str) -> Result<Block, String> {
+        let invalid = || format!("`{}` is not a CIDR block or an IP address", block);
+        let (ip, prefix) = match block.split_once('/') {
+            Some((ip, prefix)) => (ip.trim(), Some(prefix.trim())),
+            None => (block.trim(), None),
+        };
+        let ip = ip.parse::<IpAddr>().map_err(|_| invalid())?;
+        let bits = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u32>()
+                .ok()
+                .filter(|prefix| *prefix <= bits)
+                .ok_or_else(|| format!("`{}` needs a prefix length between 0 and {}", block, bits))?,
+            None => bits,
+        };
+        let host_bits = bits - prefix;
+        let mask = if host_bits == 128 { 0 } else { u128::MAX << host_bits };
+        Ok(Block {
+            ipv4: ip.is_ipv4(),
+            network: to_int(ip) & mask,
+            mask,
+        })
+    }
+
+    // IPv4 clients of a socket listening on IPv6 show up as IPv4-mapped addresses, which match IPv4 blocks.
+    pub(crate) fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_ipv4() == self.ipv4 && to_int(ip) & self.mask == self.network
+    }
+}
+
+impl TryFrom<String> for Block {
+    type Error = String;
+
+    fn try_from(block: String) -> Result<Block, String> {
+        Block::parse(&block)
+    }
+}
+
+impl From<Block> for String {
+    fn from(block: Block) -> String {
+        block.to_string()
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (ip, prefix) = match self.ipv4 {
+            true => (
+                IpAddr::V4(Ipv4Addr::from(self.network as u32)),
+                self.mask.count_ones() - 96,
+            ),
+            false => (IpAddr::V6(Ipv6Addr::from(self.network)), self.mask.count_ones()),
+        };
+        write!(f, "{}/{}", ip, prefix)
+    }
+}
+
+fn to_int(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u32::from(ip) as u128,
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+// The name of one of the endpoints an entry expands to. `{address}` and `{index}` (counting from 1) in the name of the
+// entry are filled in, and names without either get the address appended so that they stay unique.
+pub(crate) fn name(template: &str, ip: IpAddr, index: usize) -> String {
+    if template.contains("{address}") || template.contains("{index}") {
+        template
+            .replace("{address}", &ip.to_string())
+            .replace("{index}", &index.to_string())
+    } else {
+        format!("{} {}", template, ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(address: &str) -> Option<Result<Vec<String>, String>> {
+        addresses(address).map(|ips| ips.map(|ips| ips.iter().map(IpAddr::to_string).collect()))
+    }
+
+    #[test]
+    fn expands_cidr_blocks() {
+        assert_eq!(
+            expand("10.0.5.0/30"),
+            Some(Ok(vec!["10.0.5.1".to_string(), "10.0.5.2".to_string()]))
+        );
+        // The address need not be the network address, and /31 and /32 keep every address.
+        assert_eq!(
+            expand("10.0.5.7/31"),
+            Some(Ok(vec!["10.0.5.6".to_string(), "10.0.5.7".to_string()]))
+        );
+        assert_eq!(expand("10.0.5.7/32"), Some(Ok(vec!["10.0.5.7".to_string()])));
+        assert_eq!(addresses("192.168.0.0/24").unwrap().unwrap().len(), 254);
+        assert_eq!(
+            expand("2001:db8::/127"),
+            Some(Ok(vec!["2001:db8::".to_string(), "2001:db8::1".to_string()]))
+        );
+        assert_eq!(addresses("2001:db8::/112").unwrap().unwrap().len(), 65536);
+    }
+
+    #[test]
+    fn expands_ranges() {
+        assert_eq!(
+            expand("10.0.5.254 - 10.0.6.1"),
+            Some(Ok(vec![
+                "10.0.5.254".to_string(),
+                "10.0.5.255".to_string(),
+                "10.0.6.0".to_string(),
+                "10.0.6.1".to_string(),
+            ]))
+        );
+        assert_eq!(
+            expand("fe80::1-fe80::2"),
+            Some(Ok(vec!["fe80::1".to_string(), "fe80::2".to_string()]))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_blocks_and_ranges() {
+        assert_eq!(
+            expand("10.0.0.0/33"),
+            Some(Err("`10.0.0.0/33` needs a prefix length between 0 and 32".to_string()))
+        );
+        assert_eq!(
+            expand("10.0.0.0/8"),
+            Some(Err("`10.0.0.0/8` expands to more than 65536 addresses".to_string()))
+        );
+        assert_eq!(
+            expand("::/0"),
+            Some(Err("`::/0` expands to more than 65536 addresses".to_string()))
+        );
+        assert_eq!(
+            expand("10.0.0.9-10.0.0.1"),
+            Some(Err("`10.0.0.9-10.0.0.1` ends before it starts".to_string()))
+        );
+        assert_eq!(
+            expand("10.0.0.1-::1"),
+            Some(Err("`10.0.0.1-::1` mixes IPv4 and IPv6 addresses".to_string()))
+        );
+    }
+
+    #[test]
+    fn leaves_other_addresses_alone() {
+        assert_eq!(expand("10.0.0.1"), None);
+        assert_eq!(expand("example.com"), None);
+        assert_eq!(expand("my-router.lan"), None);
+        assert_eq!(expand("example.com/24"), None);
+    }
+
+    #[test]
+    fn matches_blocks() {
+        let block = Block::parse("10.1.0.0/16").unwrap();
+        assert!(block.contains("10.1.255.3".parse().unwrap()));
+        assert!(!block.contains("10.2.0.1".parse().unwrap()));
+        assert!(block.contains("::ffff:10.1.0.1".parse().unwrap()));
+        assert!(!block.contains("::1".parse().unwrap()));
+
+        let single = Block::parse("2001:db8::1").unwrap();
+        assert!(single.contains("2001:db8::1".parse().unwrap()));
+        assert!(!single.contains("2001:db8::2".parse().unwrap()));
+        assert!(Block::parse("0.0.0.0/0")
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+        assert!(Block::parse("10.0.0.0/40").is_err());
+        assert!(Block::parse("example.com").is_err());
+    }
+
+    #[test]
+    fn names_expanded_endpoints() {
+        let ip = "10.0.0.7".parse().unwrap();
+        assert_eq!(name("Host {index} ({address})", ip, 3), "Host 3 (10.0.0.7)");
+        assert_eq!(name("Rack", ip, 3), "Rack 10.0.0.7");
+    }
+}
@@ -0,0 +1,114 @@
+use prometheus::{Encoder, Gauge, Registry, TextEncoder};
+use serde::Deserialize;
+use tokio::time::{Duration, Instant};
+use warp::http::StatusCode;
+
+use crate::config::{self, Config, Endpoint, IpVersion, ProbeType};
+use crate::expand;
+use crate::metrics::Metrics;
+use crate::probe::{self, failure_reason};
+use crate::targets::Rejection;
+
+// Left of the scrape timeout for Prometheus to receive the metrics, like the blackbox exporter does.
+const SCRAPE_TIMEOUT_OFFSET: Duration = Duration::from_millis(500);
+
+// The target of `/probe`, whatever is not given is taken from the defaults of the config.
+#[derive(Deserialize)]
+pub(crate) struct ProbeQuery {
+    target: String,
+    #[serde(rename = "type")]
+    probe: Option<ProbeType>,
+    port: Option<u16>,
+    ip_version: Option<IpVersion>,
+}
+
+// Probes the target once and returns the metrics of just that probe. The metrics the probe records itself go to a
+// registry of their own, so they never show up in `/metrics`.
+pub(crate) async fn probe(
+    query: ProbeQuery,
+    config: &Config,
+    scrape_timeout: Option<Duration>,
+) -> Result<String, Rejection> {
+    let mut endpoint = Endpoint::new(query.target.clone(), query.target);
+    endpoint.probe = query.probe;
+    endpoint.port = query.port;
+    endpoint.ip_version = query.ip_version;
+    endpoint.inherit(&config.defaults);
+    if expand::addresses(&endpoint.address).is_some() || endpoint.ip_version == Some(IpVersion::Both) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "target must be a single address probed over a single IP version".to_string(),
+        ));
+    }
+    let problems = config::endpoint_problems(&endpoint, config);
+    if !problems.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, problems.join("; ")));
+    }
+
+    let mut settings = endpoint.settings(config);
+    let scrape_timeout = scrape_timeout
+        .and_then(|scrape_timeout| scrape_timeout.checked_sub(SCRAPE_TIMEOUT_OFFSET))
+        .filter(|scrape_timeout| !scrape_timeout.is_zero());
+    if let Some(scrape_timeout) = scrape_timeout {
+        settings.timeout = settings.timeout.min(scrape_timeout);
+    }
+
+    let internal = |err: prometheus::Error| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+    let registry = Registry::new();
+    let scoped = Config {
+        endpoints: vec![endpoint.clone()],
+        ..config.clone()
+    };
+    let metrics = Metrics::register_in(&scoped, &registry).map_err(internal)?;
+    let probe = probe::build(&endpoint, &settings, &metrics);
+    let started = Instant::now();
+    let retries = metrics
+        .retries
+        .with_label_values(&metrics.labels(&endpoint));
+    let attempt = probe::attempt(&*probe, &settings, || retries.inc());
+    // Retries are cut short where the scrape would time out.
+    let output = match scrape_timeout {
+        Some(scrape_timeout) => match tokio::time::timeout(scrape_timeout, attempt).await {
+            Ok(output) => output,
+            Err(elapsed) => Err(elapsed.into()),
+        },
+        None => attempt.await,
+    };
+    let duration = started.elapsed();
+
+    let gauge = |name: &str, help: &str, value: f64| {
+        let gauge = Gauge::new(name, help)?;
+        gauge.set(value);
+        registry.register(Box::new(gauge))
+    };
+    gauge(
+        "probe_success",
+        "Whether the probe succeeded",
+        output.is_ok() as u8 as f64,
+    )
+    .map_err(internal)?;
+    gauge(
+        "probe_duration_seconds",
+        "How long the probe took, resolving the target included, in seconds",
+        duration.as_secs_f64(),
+    )
+    .map_err(internal)?;
+    match &output {
+        Ok(rtt) => gauge(
+            "probe_rtt_seconds",
+            "Round trip time the probe measured in seconds",
+            rtt.as_secs_f64(),
+        )
+        .map_err(internal)?,
+        Err(err) => metrics
+            .errors
+            .with_label_values(&[metrics.labels(&endpoint), vec![failure_reason(err)]].concat())
+            .inc(),
+    }
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&registry.gather(), &mut buffer)
+        .map_err(internal)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
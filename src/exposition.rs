@@ -0,0 +1,195 @@
+use std::collections::HashSet;
+#[cfg(feature = "gzip")]
+use std::io::{self, Write};
+
+#[cfg(feature = "gzip")]
+use flate2::write::GzEncoder;
+#[cfg(feature = "gzip")]
+use flate2::Compression;
+
+use crate::aggregator::sample_name;
+
+pub(crate) const OPENMETRICS: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// Whether the `Accept` header of a scrape prefers the OpenMetrics text format over the classic one, as Prometheus does
+// when it is enabled. Media types are weighed by their `q` parameter, and one that is not listed at all is refused.
+pub(crate) fn wants_openmetrics(accept: Option<&str>) -> bool {
+    let accept = match accept {
+        Some(accept) => accept,
+        None => return false,
+    };
+    let (mut openmetrics, mut text) = (0.0, 0.0);
+    for media_range in accept.split(',') {
+        let mut params = media_range.split(';').map(str::trim);
+        let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+        let q = params
+            .filter_map(|param| param.strip_prefix("q="))
+            .find_map(|q| q.parse::<f64>().ok())
+            .unwrap_or(1.0);
+        match media_type.as_str() {
+            "application/openmetrics-text" => openmetrics = f64::max(openmetrics, q),
+            "text/plain" | "text/*" | "*/*" => text = f64::max(text, q),
+            _ => {}
+        }
+    }
+    openmetrics > 0.0 && openmetrics > text
+}
+
+// Whether the `Accept-Encoding` header allows a gzip response.
+#[cfg(feature = "gzip")]
+pub(crate) fn accepts_gzip(accept_encoding: Option<&str>) -> bool {
+    accept_encoding.is_some_and(|accept_encoding| {
+        accept_encoding.split(',').any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let q = params
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|q| q.parse::<f64>().ok())
+                .unwrap_or(1.0);
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && q > 0.0
+        })
+    })
+}
+
+// Rewrites an exposition in the classic text format into OpenMetrics. Counter families are named without `_total`,
+// which their samples all end with instead, untyped families are `unknown`, other comments are dropped and the end is
+// marked with `# EOF`.
+pub(crate) fn openmetrics(text: &str) -> String {
+    let counters: HashSet<&str> = text
+        .lines()
+        .filter_map(|line| line.strip_prefix("# TYPE "))
+        .filter_map(|kind| kind.split_once(' '))
+        .filter(|(_, kind)| kind.trim() == "counter")
+        .map(|(name, _)| name)
+        .collect();
+    let family = |name: &str| -> String {
+        if counters.contains(name) {
+            name.strip_suffix("_total").unwrap_or(name).to_string()
+        } else {
+            name.to_string()
+        }
+    };
+
+    let mut openmetrics = String::with_capacity(text.len() + 8);
+    for line in text.lines() {
+        if let Some(comment) = line.strip_prefix("# ") {
+            let mut parts = comment.splitn(3, ' ');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some("HELP"), Some(name), help) => {
+                    openmetrics.push_str(&format!("# HELP {} {}\n", family(name), help.unwrap_or_default()))
+                }
+                (Some("TYPE"), Some(name), Some(kind)) => {
+                    let kind = match kind.trim() {
+                        "untyped" => "unknown",
+                        kind => kind,
+                    };
+                    openmetrics.push_str(&format!("# TYPE {} {}\n", family(name), kind));
+                }
+                _ => {}
+            }
+            continue;
+        }
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let name = sample_name(line);
+        if counters.contains(name) && !name.ends_with("_total") {
+            openmetrics.push_str(name);
+            openmetrics.push_str("_total");
+            openmetrics.push_str(&line[name.len()..]);
+        } else {
+            openmetrics.push_str(line);
+        }
+        openmetrics.push('\n');
+    }
+    openmetrics.push_str("# EOF\n");
+    openmetrics
+}
+
+#[cfg(feature = "gzip")]
+pub(crate) fn gzip(body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_the_text_format() {
+        let text = "\
+# HELP netstab_ping_failures_total Failed probes
+# TYPE netstab_ping_failures_total counter
+netstab_ping_failures_total{endpoint=\"gw\"} 3
+# HELP netstab_up Whether the endpoint answers
+# TYPE netstab_up gauge
+netstab_up{endpoint=\"gw\"} 1
+
+# TYPE netstab_build_info untyped
+netstab_build_info{version=\"0.1.0\"} 1
+# A comment of some other kind
+# TYPE netstab_resets counter
+netstab_resets 2
+";
+        assert_eq!(
+            openmetrics(text),
+            "\
+# HELP netstab_ping_failures Failed probes
+# TYPE netstab_ping_failures counter
+netstab_ping_failures_total{endpoint=\"gw\"} 3
+# HELP netstab_up Whether the endpoint answers
+# TYPE netstab_up gauge
+netstab_up{endpoint=\"gw\"} 1
+# TYPE netstab_build_info unknown
+netstab_build_info{version=\"0.1.0\"} 1
+# TYPE netstab_resets counter
+netstab_resets_total 2
+# EOF
+"
+        );
+        assert_eq!(openmetrics(""), "# EOF\n");
+    }
+
+    #[test]
+    fn negotiates_the_format() {
+        assert!(!wants_openmetrics(None));
+        assert!(!wants_openmetrics(Some("text/plain")));
+        assert!(wants_openmetrics(Some("application/openmetrics-text")));
+        // What Prometheus sends when scraping OpenMetrics is enabled.
+        assert!(wants_openmetrics(Some(
+            "application/openmetrics-text;version=1.0.0;q=0.5,application/openmetrics-text;version=0.0.1;q=0.4,\
+             text/plain;version=0.0.4;q=0.3,*/*;q=0.2"
+        )));
+        assert!(!wants_openmetrics(Some(
+            "application/openmetrics-text;q=0.2, text/plain"
+        )));
+        assert!(!wants_openmetrics(Some("application/openmetrics-text;q=0")));
+        assert!(!wants_openmetrics(Some("*/*")));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compresses_with_gzip() {
+        use std::io::Read;
+
+        assert!(accepts_gzip(Some("gzip")));
+        assert!(accepts_gzip(Some("deflate, GZIP;q=0.5")));
+        assert!(accepts_gzip(Some("*")));
+        assert!(!accepts_gzip(Some("gzip;q=0")));
+        assert!(!accepts_gzip(Some("br")));
+        assert!(!accepts_gzip(None));
+
+        let body = "netstab_up 1\n".repeat(100);
+        let compressed = gzip(body.as_bytes()).unwrap();
+        assert_eq!(compressed[..2], [0x1f, 0x8b]);
+        assert!(compressed.len() < body.len());
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+    }
+}
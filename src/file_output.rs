@@ -0,0 +1,231 @@
+use std::io;
+use std::time::Instant;
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tokio::time::Duration;
+
+use crate::duration;
+use crate::status::ProbeEvent;
+
+const CSV_HEADER: &str =
+    "timestamp,name,address,location,probe_type,ip_version,success,rtt_seconds,loss_ratio,reason,error\n";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub path: String,
+    #[serde(default)]
+    pub format: Format,
+    // The file is rotated once it grows past `max_size` bytes or once it is older than `rotate_interval`, keeping
+    // `keep` rotated files as `<path>.1` (the newest) to `<path>.<keep>`.
+    pub max_size: Option<u64>,
+    #[serde(default, with = "duration::option")]
+    pub rotate_interval: Option<Duration>,
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+}
+
+fn default_keep() -> usize {
+    5
+}
+
+impl FileConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.path.is_empty() {
+            return Err("path must not be empty".to_string());
+        }
+        if self.max_size == Some(0) {
+            return Err("max_size must be at least 1".to_string());
+        }
+        if self
+            .rotate_interval
+            .is_some_and(|interval| interval.is_zero())
+        {
+            return Err("rotate_interval must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: f64,
+    name: &'a str,
+    address: &'a str,
+    location: Option<&'a str>,
+    probe_type: &'a str,
+    ip_version: Option<&'a str>,
+    success: bool,
+    rtt_seconds: Option<f64>,
+    loss_ratio: f64,
+    reason: Option<&'a str>,
+    error: Option<&'a str>,
+}
+
+// Appends a line for every probe result to the file until `stop` is set.
+pub async fn run(config: FileConfig, mut events: broadcast::Receiver<ProbeEvent>, mut stop: watch::Receiver<bool>) {
+    let mut log = Log::new(config);
+
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = stop.changed() => break,
+        };
+        match event {
+            Ok(event) => log.append(&event).await,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("File output fell behind and skipped {} probe results", skipped);
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+
+    // Results published by the last probes still get written before returning.
+    while let Ok(event) = events.try_recv() {
+        log.append(&event).await;
+    }
+}
+
+struct Log {
+    config: FileConfig,
+    file: Option<File>,
+    size: u64,
+    opened: Instant,
+}
+
+impl Log {
+    fn new(config: FileConfig) -> Self {
+        Log {
+            config,
+            file: None,
+            size: 0,
+            opened: Instant::now(),
+        }
+    }
+
+    async fn append(&mut self, event: &ProbeEvent) {
+        if let Err(err) = self.try_append(event).await {
+            error!("Failed to write to {}: {}", self.config.path, err);
+            self.file = None;
+        }
+    }
+
+    async fn try_append(&mut self, event: &ProbeEvent) -> io::Result<()> {
+        let due = self
+            .config
+            .max_size
+            .is_some_and(|max_size| self.size >= max_size)
+            || self
+                .config
+                .rotate_interval
+                .is_some_and(|interval| self.opened.elapsed() >= interval);
+        if self.file.is_some() && due {
+            self.file = None;
+            self.rotate().await?;
+        }
+        if self.file.is_none() {
+            self.open().await?;
+        }
+
+        let line = self.line(event)?;
+        self.file
+            .as_mut()
+            .unwrap()
+            .write_all(line.as_bytes())
+            .await?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    async fn open(&mut self) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)
+            .await?;
+        self.size = file.metadata().await?.len();
+        if self.size == 0 && self.config.format == Format::Csv {
+            file.write_all(CSV_HEADER.as_bytes()).await?;
+            self.size = CSV_HEADER.len() as u64;
+        }
+        self.file = Some(file);
+        self.opened = Instant::now();
+        Ok(())
+    }
+
+    async fn rotate(&self) -> io::Result<()> {
+        let path = &self.config.path;
+        if self.config.keep == 0 {
+            return fs::remove_file(path).await;
+        }
+
+        let _ = fs::remove_file(format!("{}.{}", path, self.config.keep)).await;
+        for index in (1..self.config.keep).rev() {
+            let _ = fs::rename(format!("{}.{}", path, index), format!("{}.{}", path, index + 1)).await;
+        }
+        fs::rename(path, format!("{}.1", path)).await?;
+        info!("Rotated {}", path);
+        Ok(())
+    }
+
+    fn line(&self, event: &ProbeEvent) -> io::Result<String> {
+        let record = Record {
+            timestamp: event.timestamp,
+            name: &event.name,
+            address: &event.address,
+            location: event.location.as_deref(),
+            probe_type: event.probe_type,
+            ip_version: event.ip_version,
+            success: event.error.is_none(),
+            rtt_seconds: event.rtt_seconds,
+            loss_ratio: event.loss_ratio,
+            reason: event.reason,
+            error: event.error.as_deref(),
+        };
+
+        match self.config.format {
+            Format::Json => Ok(serde_json::to_string(&record)? + "\n"),
+            Format::Csv => {
+                let fields = [
+                    record.timestamp.to_string(),
+                    csv_field(record.name),
+                    csv_field(record.address),
+                    csv_field(record.location.unwrap_or_default()),
+                    record.probe_type.to_string(),
+                    record.ip_version.unwrap_or_default().to_string(),
+                    record.success.to_string(),
+                    record
+                        .rtt_seconds
+                        .map(|rtt| rtt.to_string())
+                        .unwrap_or_default(),
+                    record.loss_ratio.to_string(),
+                    record.reason.unwrap_or_default().to_string(),
+                    csv_field(record.error.unwrap_or_default()),
+                ];
+                Ok(fields.join(",") + "\n")
+            }
+        }
+    }
+}
+
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+use crate::config::{Defaults, Endpoint};
+use crate::discovery::Update;
+use crate::duration;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileSdConfig {
+    // Files of Prometheus style target groups, given as paths, directories, or paths with `*` in the file name.
+    pub files: Vec<String>,
+    #[serde(default = "default_refresh_interval", with = "duration")]
+    pub refresh_interval: Duration,
+    // Settings for the endpoints found in the files, like the top-level `defaults`.
+    #[serde(default)]
+    pub defaults: Defaults,
+}
+
+fn default_refresh_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+impl FileSdConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.files.is_empty() {
+            return Err("files must not be empty".to_string());
+        }
+        if let Some(pattern) = self.files.iter().find(|pattern| {
+            Path::new(pattern)
+                .parent()
+                .is_some_and(|parent| parent.to_string_lossy().contains('*'))
+        }) {
+            return Err(format!("`{}` has a `*` outside of the file name", pattern));
+        }
+        if self.refresh_interval.is_zero() {
+            return Err("refresh_interval must be longer than 0s".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TargetGroup {
+    #[serde(default)]
+    targets: Vec<String>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+}
+
+// The endpoints read from each file, along with when the file was last modified so they are only read again once it
+// changes.
+#[derive(Default, PartialEq)]
+pub(crate) struct Files {
+    read: BTreeMap<PathBuf, (Option<SystemTime>, Vec<Endpoint>)>,
+}
+
+impl Files {
+    pub(crate) fn read(config: &FileSdConfig) -> Files {
+        Files::default().refresh(config)
+    }
+
+    // Reads the files that are new or changed. A file that cannot be read keeps the endpoints it had before.
+    fn refresh(&self, config: &FileSdConfig) -> Files {
+        let mut read = BTreeMap::new();
+        for path in paths(&config.files) {
+            let modified = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            let previous = self.read.get(&path);
+            if let Some((previous_modified, endpoints)) = previous {
+                if *previous_modified == modified {
+                    read.insert(path, (modified, endpoints.clone()));
+                    continue;
+                }
+            }
+
+            let endpoints = match read_file(&path, config) {
+                Ok(endpoints) => endpoints,
+                Err(err) => {
+                    warn!("Failed to read targets from {}: {}", path.display(), err);
+                    previous
+                        .map(|(_, endpoints)| endpoints.clone())
+                        .unwrap_or_default()
+                }
+            };
+            read.insert(path, (modified, endpoints));
+        }
+        Files { read }
+    }
+
+    pub(crate) fn endpoints(&self) -> Vec<Endpoint> {
+        self.read
+            .values()
+            .flat_map(|(_, endpoints)| endpoints.iter().cloned())
+            .collect()
+    }
+}
+
+// Checks the files for changes every `refresh_interval`, sending the endpoints whenever they changed.
+pub(crate) async fn run(config: FileSdConfig, mut files: Files, source: String, updates: mpsc::Sender<Update>) {
+    loop {
+        tokio::time::sleep(config.refresh_interval).await;
+
+        let refreshed = files.refresh(&config);
+        if refreshed == files {
+            continue;
+        }
+        files = refreshed;
+
+        let endpoints = files.endpoints();
+        info!("Discovered {} endpoints from {}", endpoints.len(), source);
+        let update = Update {
+            source: source.clone(),
+            endpoints,
+        };
+        if updates.send(update).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn paths(patterns: &[String]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let path = Path::new(pattern);
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        // Directories are read for every file in them that looks like targets.
+        let (directory, pattern) = if path.is_dir() {
+            (path, None)
+        } else if name.contains('*') {
+            let directory = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty());
+            (directory.unwrap_or(Path::new(".")), Some(name))
+        } else {
+            if path.is_file() {
+                paths.push(path.to_path_buf());
+            }
+            continue;
+        };
+        let matches = |name: &str| match &pattern {
+            Some(pattern) => wildcard(pattern, name),
+            None => name.ends_with(".json") || name.ends_with(".yaml") || name.ends_with(".yml"),
+        };
+
+        if let Ok(entries) = fs::read_dir(directory) {
+            let mut matched: Vec<PathBuf> = entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file())
+                .filter(|path| matches(&path.file_name().unwrap_or_default().to_string_lossy()))
+                .collect();
+            matched.sort();
+            paths.extend(matched);
+        }
+    }
+    paths
+}
+
+// Matches `*` against any number of characters.
+fn wildcard(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            name.starts_with(prefix)
+                && (0..=name.len() - prefix.len())
+                    .filter(|skip| name.is_char_boundary(prefix.len() + skip))
+                    .any(|skip| wildcard(rest, &name[prefix.len() + skip..]))
+        }
+    }
+}
+
+fn read_file(path: &Path, config: &FileSdConfig) -> Result<Vec<Endpoint>, String> {
+    let source = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let groups: Vec<TargetGroup> = if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        serde_json::from_str(&source).map_err(|err| err.to_string())?
+    } else {
+        serde_yaml::from_str(&source).map_err(|err| err.to_string())?
+    };
+
+    Ok(endpoints(&groups, &config.defaults))
+}
+
+pub(crate) fn endpoints(groups: &[TargetGroup], defaults: &Defaults) -> Vec<Endpoint> {
+    groups
+        .iter()
+        .flat_map(|group| {
+            group
+                .targets
+                .iter()
+                .map(move |target| endpoint(target, &group.labels, defaults))
+        })
+        .collect()
+}
+
+// Targets are written as `host`, `host:port`, `[v6]:port` or a url. The `name` and `location` labels set those of the
+// endpoint, which is otherwise named after the target, and labels starting with `__` are left out as Prometheus does.
+fn endpoint(target: &str, labels: &BTreeMap<String, String>, defaults: &Defaults) -> Endpoint {
+    let (address, port) = split_target(target);
+    let name = labels
+        .get("name")
+        .cloned()
+        .unwrap_or_else(|| target.to_string());
+    let mut endpoint = Endpoint::new(name, address.to_string());
+    endpoint.port = port;
+    endpoint.location = labels.get("location").cloned();
+    endpoint.labels = labels
+        .iter()
+        .filter(|(name, _)| !matches!(name.as_str(), "name" | "location") && !name.starts_with("__"))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    endpoint.inherit(defaults);
+    endpoint
+}
+
+fn split_target(target: &str) -> (&str, Option<u16>) {
+    if target.contains("://") {
+        return (target, None);
+    }
+    if let Some(rest) = target.strip_prefix('[') {
+        if let Some((host, port)) = rest.split_once("]:") {
+            if let Ok(port) = port.parse() {
+                return (host, Some(port));
+            }
+        }
+        return (target.trim_matches(|c| c == '[' || c == ']'), None);
+    }
+    match target.split_once(':') {
+        Some((host, port)) if !port.contains(':') => match port.parse() {
+            Ok(port) => (host, Some(port)),
+            Err(_) => (target, None),
+        },
+        _ => (target, None),
+    }
+}
@@ -0,0 +1,228 @@
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use hyper::body::HttpBody;
+use hyper::header::{CONTENT_TYPE, TE, USER_AGENT};
+use hyper::{Body, HeaderMap, Method, Request, StatusCode};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use crate::source::Source;
+use crate::{http, BoxError};
+
+// The values of HealthCheckResponse.ServingStatus, by number.
+pub(crate) const SERVING_STATUSES: [&str; 4] = ["UNKNOWN", "SERVING", "NOT_SERVING", "SERVICE_UNKNOWN"];
+const SERVING: u64 = 1;
+
+const STATUS_CODES: [&str; 17] = [
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+
+pub struct Health {
+    pub status: u64,
+    // How long the call took, leaving out connecting and the TLS handshake.
+    pub duration: Duration,
+}
+
+impl Health {
+    pub fn serving(&self) -> bool {
+        self.status == SERVING
+    }
+
+    pub fn status_name(&self) -> &'static str {
+        SERVING_STATUSES
+            .get(self.status as usize)
+            .copied()
+            .unwrap_or("UNKNOWN")
+    }
+}
+
+// Calls grpc.health.v1.Health/Check for `service`, the server as a whole when empty. `host` is sent as the authority
+// and, with `tls`, is the name the certificate is verified against unless `verify` is off.
+pub async fn check(
+    ip: IpAddr,
+    port: u16,
+    host: &str,
+    service: &str,
+    tls: bool,
+    verify: bool,
+    source: &Source,
+) -> Result<Health, BoxError> {
+    let authority = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        _ => format!("{}:{}", host, port),
+    };
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!(
+            "{}://{}/grpc.health.v1.Health/Check",
+            if tls { "https" } else { "http" },
+            authority
+        ))
+        .header(CONTENT_TYPE, "application/grpc")
+        .header(TE, "trailers")
+        .header(USER_AGENT, concat!("rust-net-stab/", env!("CARGO_PKG_VERSION")))
+        .body(Body::from(health_check_request(service)))?;
+
+    let stream = source.connect((ip, port).into()).await?;
+    if tls {
+        call_tls(stream, host, verify, request).await
+    } else {
+        call(stream, request).await
+    }
+}
+
+#[cfg(feature = "tls")]
+async fn call_tls(stream: TcpStream, host: &str, verify: bool, request: Request<Body>) -> Result<Health, BoxError> {
+    call(crate::tls::connect_h2(stream, host, verify).await?, request).await
+}
+
+#[cfg(not(feature = "tls"))]
+async fn call_tls(_stream: TcpStream, _host: &str, _verify: bool, _request: Request<Body>) -> Result<Health, BoxError> {
+    Err("tls needs rust-net-stab to be built with the `tls` feature".into())
+}
+
+async fn call<T>(io: T, request: Request<Body>) -> Result<Health, BoxError>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake(io)
+        .await?;
+    let connection = tokio::spawn(connection);
+
+    let health = async {
+        let start = Instant::now();
+        let response = sender.send_request(request).await?;
+        if response.status() != StatusCode::OK {
+            return Err(format!("grpc server answered with http status {}", response.status()).into());
+        }
+        let headers = response.headers().clone();
+        let mut body = response.into_body();
+        let mut message = Vec::new();
+        while let Some(chunk) = body.data().await {
+            message.extend_from_slice(&chunk?);
+        }
+        // A call that fails straight away may have its status in the headers, without a body or trailers.
+        let trailers = body.trailers().await?.unwrap_or(headers);
+        let duration = start.elapsed();
+
+        grpc_status(&trailers)?;
+        Ok(Health {
+            status: serving_status(&message)?,
+            duration,
+        })
+    }
+    .await;
+    connection.abort();
+
+    health
+}
+
+// A HealthCheckRequest with its only field, the service, in a message frame that is not compressed.
+fn health_check_request(service: &str) -> Vec<u8> {
+    let mut message = Vec::new();
+    if !service.is_empty() {
+        message.push(0x0a);
+        varint(service.len() as u64, &mut message);
+        message.extend_from_slice(service.as_bytes());
+    }
+
+    let mut frame = vec![0];
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    frame
+}
+
+fn grpc_status(trailers: &HeaderMap) -> Result<(), BoxError> {
+    let code = trailers
+        .get("grpc-status")
+        .and_then(|code| code.to_str().ok())
+        .and_then(|code| code.parse::<usize>().ok())
+        .ok_or("grpc server sent no grpc-status")?;
+    if code == 0 {
+        return Ok(());
+    }
+
+    let name = STATUS_CODES.get(code).copied().unwrap_or("UNKNOWN");
+    match trailers
+        .get("grpc-message")
+        .and_then(|message| message.to_str().ok())
+    {
+        Some(message) if !message.is_empty() => {
+            Err(format!("grpc status {} ({}): {}", code, name, http::percent_decode(message)).into())
+        }
+        _ => Err(format!("grpc status {} ({})", code, name).into()),
+    }
+}
+
+// Reads the status, field 1, of the HealthCheckResponse in the first message frame. Proto3 leaves out fields with the
+// default value, so a response without it is UNKNOWN.
+fn serving_status(frame: &[u8]) -> Result<u64, BoxError> {
+    if frame.len() < 5 {
+        return Err("grpc server sent no response".into());
+    }
+    if frame[0] != 0 {
+        return Err("grpc server sent a compressed response".into());
+    }
+    let len = u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]) as usize;
+    let mut message = frame.get(5..5 + len).ok_or("grpc response is truncated")?;
+
+    let mut status = 0;
+    while !message.is_empty() {
+        let key = read_varint(&mut message)?;
+        let skip = match key & 0x07 {
+            0 => {
+                let value = read_varint(&mut message)?;
+                if key >> 3 == 1 {
+                    status = value;
+                }
+                0
+            }
+            1 => 8,
+            2 => read_varint(&mut message)? as usize,
+            5 => 4,
+            _ => return Err("grpc response is not a HealthCheckResponse".into()),
+        };
+        message = message.get(skip..).ok_or("grpc response is truncated")?;
+    }
+    Ok(status)
+}
+
+fn varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, BoxError> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().ok_or("grpc response is truncated")?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("grpc response is not a HealthCheckResponse".into())
+}
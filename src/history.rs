@@ -0,0 +1,427 @@
+use std::sync::{Arc, Mutex};
+
+use log::{error, info, warn};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+
+use crate::status::{self, ProbeEvent};
+use crate::{duration, BoxError};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS results (
+        timestamp REAL NOT NULL,
+        name TEXT NOT NULL,
+        address TEXT NOT NULL,
+        location TEXT,
+        probe_type TEXT NOT NULL,
+        ip_version TEXT,
+        up INTEGER NOT NULL,
+        success INTEGER NOT NULL,
+        rtt_seconds REAL,
+        loss_ratio REAL NOT NULL,
+        reason TEXT,
+        error TEXT
+    );
+    CREATE INDEX IF NOT EXISTS results_name_timestamp ON results (name, timestamp);
+    CREATE INDEX IF NOT EXISTS results_timestamp ON results (timestamp);
+";
+
+const COLUMNS: &str =
+    "timestamp, name, address, location, probe_type, ip_version, up, success, rtt_seconds, loss_ratio, \
+                       reason, error";
+
+// Results are written in one transaction per second, or sooner once this many are waiting.
+const BATCH_SIZE: usize = 500;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryConfig {
+    // The SQLite database, created if it does not exist.
+    pub path: String,
+    #[serde(default = "default_retention", with = "duration")]
+    pub retention: Duration,
+}
+
+fn default_retention() -> Duration {
+    Duration::from_secs(30 * 86400)
+}
+
+impl HistoryConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.path.is_empty() {
+            return Err("path must not be empty".to_string());
+        }
+        if self.retention.is_zero() {
+            return Err("retention must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// What the history endpoints accept as query parameters. Times are Unix timestamps in seconds.
+#[derive(Debug, Default, Deserialize)]
+pub struct Query {
+    pub endpoint: Option<String>,
+    pub from: Option<f64>,
+    pub to: Option<f64>,
+    pub limit: Option<u32>,
+}
+
+impl Query {
+    // Without `from` the last 24 hours are covered.
+    fn range(&self) -> (f64, f64) {
+        let to = self.to.unwrap_or_else(status::unix_time);
+        (self.from.unwrap_or(to - 86400.0), to)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Record {
+    pub timestamp: f64,
+    pub name: String,
+    pub address: String,
+    pub location: Option<String>,
+    pub probe_type: String,
+    pub ip_version: Option<String>,
+    pub up: bool,
+    pub success: bool,
+    pub rtt_seconds: Option<f64>,
+    pub loss_ratio: f64,
+    pub reason: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    pub name: String,
+    pub ip_version: Option<String>,
+    pub probes: i64,
+    pub failures: i64,
+    // The share of probes that succeeded.
+    pub availability: f64,
+    pub rtt_min_seconds: Option<f64>,
+    pub rtt_avg_seconds: Option<f64>,
+    pub rtt_max_seconds: Option<f64>,
+    pub loss_ratio_avg: f64,
+    pub first: f64,
+    pub last: f64,
+}
+
+// The probe results stored in SQLite. Queries run on the blocking thread pool, one at a time.
+#[derive(Clone)]
+pub struct History {
+    connection: Arc<Mutex<Connection>>,
+    retention: Duration,
+}
+
+impl History {
+    pub fn open(config: &HistoryConfig) -> Result<History, BoxError> {
+        let connection =
+            Connection::open(&config.path).map_err(|err| format!("failed to open {}: {}", config.path, err))?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(History {
+            connection: Arc::new(Mutex::new(connection)),
+            retention: config.retention,
+        })
+    }
+
+    async fn with<T, F>(&self, f: F) -> Result<T, BoxError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, rusqlite::Error> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut connection)
+        })
+        .await?;
+        Ok(result?)
+    }
+
+    async fn insert(&self, events: Vec<ProbeEvent>) -> Result<(), BoxError> {
+        self.with(move |connection| {
+            let transaction = connection.transaction()?;
+            {
+                let mut statement = transaction.prepare(&format!(
+                    "INSERT INTO results ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                    COLUMNS
+                ))?;
+                for event in &events {
+                    statement.execute(params![
+                        event.timestamp,
+                        event.name,
+                        event.address,
+                        event.location,
+                        event.probe_type,
+                        event.ip_version,
+                        event.up,
+                        event.error.is_none(),
+                        event.rtt_seconds,
+                        event.loss_ratio,
+                        event.reason,
+                        event.error,
+                    ])?;
+                }
+            }
+            transaction.commit()
+        })
+        .await
+    }
+
+    async fn prune(&self, before: f64) -> Result<usize, BoxError> {
+        self.with(move |connection| connection.execute("DELETE FROM results WHERE timestamp < ?1", params![before]))
+            .await
+    }
+
+    // The results in the range, newest first.
+    pub async fn results(&self, query: &Query) -> Result<Vec<Record>, BoxError> {
+        let (from, to) = query.range();
+        let endpoint = query.endpoint.clone();
+        let limit = query.limit.unwrap_or(1000);
+        self.with(move |connection| {
+            let mut statement = connection.prepare(&format!(
+                "SELECT {} FROM results WHERE (?1 IS NULL OR name = ?1) AND timestamp >= ?2 AND timestamp < ?3 ORDER BY \
+                 timestamp DESC LIMIT ?4",
+                COLUMNS
+            ))?;
+            let records = statement.query_map(params![endpoint, from, to, limit], record)?;
+            records.collect()
+        })
+        .await
+    }
+
+    // Every result in the range, oldest first per endpoint and IP version.
+    pub(crate) async fn series(&self, endpoint: Option<String>, from: f64, to: f64) -> Result<Vec<Record>, BoxError> {
+        self.with(move |connection| {
+            let mut statement = connection.prepare(&format!(
+                "SELECT {} FROM results WHERE (?1 IS NULL OR name = ?1) AND timestamp >= ?2 AND timestamp < ?3 ORDER BY \
+                 name, ip_version, timestamp",
+                COLUMNS
+            ))?;
+            let records = statement.query_map(params![endpoint, from, to], record)?;
+            records.collect()
+        })
+        .await
+    }
+
+    // Totals per endpoint and IP version over the range.
+    pub async fn stats(&self, query: &Query) -> Result<Vec<Stats>, BoxError> {
+        let (from, to) = query.range();
+        let endpoint = query.endpoint.clone();
+        self.with(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT name, ip_version, COUNT(*), SUM(1 - success), MIN(rtt_seconds), AVG(rtt_seconds), \
+                 MAX(rtt_seconds), AVG(loss_ratio), MIN(timestamp), MAX(timestamp) FROM results WHERE (?1 IS NULL OR \
+                 name = ?1) AND timestamp >= ?2 AND timestamp < ?3 GROUP BY name, ip_version ORDER BY name, ip_version",
+            )?;
+            let stats = statement.query_map(params![endpoint, from, to], |row| {
+                let probes: i64 = row.get(2)?;
+                let failures: i64 = row.get(3)?;
+                Ok(Stats {
+                    name: row.get(0)?,
+                    ip_version: row.get(1)?,
+                    probes,
+                    failures,
+                    availability: (probes - failures) as f64 / probes as f64,
+                    rtt_min_seconds: row.get(4)?,
+                    rtt_avg_seconds: row.get(5)?,
+                    rtt_max_seconds: row.get(6)?,
+                    loss_ratio_avg: row.get(7)?,
+                    first: row.get(8)?,
+                    last: row.get(9)?,
+                })
+            })?;
+            stats.collect()
+        })
+        .await
+    }
+}
+
+fn record(row: &rusqlite::Row<'_>) -> Result<Record, rusqlite::Error> {
+    Ok(Record {
+        timestamp: row.get(0)?,
+        name: row.get(1)?,
+        address: row.get(2)?,
+        location: row.get(3)?,
+        probe_type: row.get(4)?,
+        ip_version: row.get(5)?,
+        up: row.get(6)?,
+        success: row.get(7)?,
+        rtt_seconds: row.get(8)?,
+        loss_ratio: row.get(9)?,
+        reason: row.get(10)?,
+        error: row.get(11)?,
+    })
+}
+
+// Stores every probe result until `stop` is set, deleting results older than `retention` every hour.
+pub async fn run(history: History, mut events: broadcast::Receiver<ProbeEvent>, mut stop: watch::Receiver<bool>) {
+    let mut pending = Vec::new();
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut next_prune = Instant::now();
+
+    loop {
+        let stopping = tokio::select! {
+            _ = ticker.tick() => false,
+            event = events.recv() => {
+                // Once the channel is closed no more results are coming, so the insert that follows is the last one.
+                let closed = match event {
+                    // Results marked as maintenance would count as outages in the stats and reports.
+                    Ok(event) if event.maintenance => false,
+                    Ok(event) => {
+                        pending.push(event);
+                        false
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("History fell behind and skipped {} probe results", skipped);
+                        false
+                    }
+                    Err(RecvError::Closed) => true,
+                };
+                if !closed && pending.len() < BATCH_SIZE {
+                    continue;
+                }
+                closed
+            }
+            _ = stop.changed() => true,
+        };
+
+        // Results published by the last probes are still stored before returning.
+        if stopping {
+            while let Ok(event) = events.try_recv() {
+                if !event.maintenance {
+                    pending.push(event);
+                }
+            }
+        }
+        if !pending.is_empty() {
+            if let Err(err) = history.insert(std::mem::take(&mut pending)).await {
+                error!("Failed to store probe results: {}", err);
+            }
+        }
+        if stopping {
+            break;
+        }
+
+        if Instant::now() >= next_prune {
+            next_prune = Instant::now() + PRUNE_INTERVAL;
+            match history
+                .prune(status::unix_time() - history.retention.as_secs_f64())
+                .await
+            {
+                Ok(0) => {}
+                Ok(deleted) => info!("Deleted {} expired probe results", deleted),
+                Err(err) => error!("Failed to delete old probe results: {}", err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str, timestamp: f64, rtt_seconds: Option<f64>) -> ProbeEvent {
+        ProbeEvent {
+            name: name.to_string(),
+            address: "192.0.2.1".to_string(),
+            location: None,
+            probe_type: "icmp",
+            ip_version: Some("4"),
+            up: rtt_seconds.is_some(),
+            rtt_seconds,
+            loss_ratio: if rtt_seconds.is_some() { 0.0 } else { 1.0 },
+            error: rtt_seconds.is_none().then(|| "timed out".to_string()),
+            reason: rtt_seconds.is_none().then_some("timeout"),
+            maintenance: false,
+            timestamp,
+            trace_id: None,
+        }
+    }
+
+    fn memory() -> History {
+        History::open(&HistoryConfig {
+            path: ":memory:".to_string(),
+            retention: default_retention(),
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn stores_and_queries_results() {
+        let history = memory();
+        history
+            .insert(vec![
+                event("gateway", 100.0, Some(0.01)),
+                event("gateway", 110.0, None),
+                event("gateway", 120.0, Some(0.03)),
+                event("dns", 105.0, Some(0.02)),
+            ])
+            .await
+            .unwrap();
+
+        let query = Query {
+            endpoint: Some("gateway".to_string()),
+            from: Some(0.0),
+            to: Some(200.0),
+            limit: None,
+        };
+        let records = history.results(&query).await.unwrap();
+        let timestamps: Vec<f64> = records.iter().map(|record| record.timestamp).collect();
+        assert_eq!(timestamps, [120.0, 110.0, 100.0]);
+        assert!(!records[1].success);
+        assert_eq!(records[1].reason.as_deref(), Some("timeout"));
+        assert_eq!(records[1].error.as_deref(), Some("timed out"));
+        assert_eq!(records[0].ip_version.as_deref(), Some("4"));
+        assert_eq!(records[0].location, None);
+
+        let stats = history.stats(&query).await.unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].probes, stats[0].failures), (3, 1));
+        assert_eq!(stats[0].rtt_min_seconds, Some(0.01));
+        assert_eq!(stats[0].rtt_max_seconds, Some(0.03));
+        assert_eq!((stats[0].first, stats[0].last), (100.0, 120.0));
+
+        assert_eq!(history.prune(110.0).await.unwrap(), 2);
+        let all = Query {
+            from: Some(0.0),
+            to: Some(200.0),
+            ..Query::default()
+        };
+        assert_eq!(history.results(&all).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn run_stores_the_last_results_on_stop() {
+        let history = memory();
+        let (events, receiver) = broadcast::channel(16);
+        let (stop, stopped) = watch::channel(false);
+        let task = tokio::spawn(run(history.clone(), receiver, stopped));
+
+        let mut skipped = event("gateway", 100.0, Some(0.01));
+        skipped.maintenance = true;
+        events.send(skipped).unwrap();
+        events.send(event("gateway", 101.0, Some(0.01))).unwrap();
+        stop.send(true).unwrap();
+        task.await.unwrap();
+
+        let query = Query {
+            from: Some(0.0),
+            to: Some(200.0),
+            ..Query::default()
+        };
+        let records = history.results(&query).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].timestamp, 101.0);
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::header::{ACCEPT, CONTENT_TYPE, HOST, USER_AGENT};
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use crate::source::Source;
+use crate::BoxError;
+
+// How the certificate of an https server is checked: against the bundled roots, against the CA certificates in a PEM
+// file, or not at all.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Verify {
+    #[default]
+    Roots,
+    CaFile(PathBuf),
+    Skip,
+}
+
+// Either an exact status code such as `204`, or a whole class such as `2xx`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatusPattern {
+    Code(u16),
+    Class(u16),
+}
+
+impl StatusPattern {
+    pub fn matches(&self, status: StatusCode) -> bool {
+        match *self {
+            StatusPattern::Code(code) => status.as_u16() == code,
+            StatusPattern::Class(class) => status.as_u16() / 100 == class,
+        }
+    }
+}
+
+impl std::str::FromStr for StatusPattern {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid status `{}`, expected a code like `204` or a class like `2xx`",
+                value
+            )
+        };
+
+        let lower = value.to_ascii_lowercase();
+        let pattern = match lower.strip_suffix("xx") {
+            Some(class) => StatusPattern::Class(class.parse().map_err(|_| invalid())?),
+            None => StatusPattern::Code(lower.parse().map_err(|_| invalid())?),
+        };
+
+        match pattern {
+            StatusPattern::Code(100..=599) | StatusPattern::Class(1..=5) => Ok(pattern),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for StatusPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatusPattern::Code(code) => write!(f, "{}", code),
+            StatusPattern::Class(class) => write!(f, "{}xx", class),
+        }
+    }
+}
+
+impl Serialize for StatusPattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for StatusPattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Code(u16),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Code(code) => code.to_string().parse().map_err(de::Error::custom),
+            Raw::Text(text) => text.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+pub fn default_expected_status() -> Vec<StatusPattern> {
+    vec![StatusPattern::Class(2)]
+}
+
+pub fn parse_url(url: &str) -> Result<Uri, BoxError> {
+    let uri: Uri = url.parse()?;
+    match uri.scheme_str() {
+        Some("http") | Some("https") if uri.host().is_some() => Ok(uri),
+        _ => Err(format!("`{}` is not an http or https url", url).into()),
+    }
+}
+
+// The host of the url, without the brackets around IPv6 addresses.
+pub fn host(uri: &Uri) -> &str {
+    uri.host()
+        .unwrap_or_default()
+        .trim_matches(|c| c == '[' || c == ']')
+}
+
+// Connects to `ip` rather than resolving the host of the url. Resolves once the response headers have arrived, so
+// timing it gives the time to first byte.
+pub async fn get(url: &str, expected_status: &[StatusPattern], ip: IpAddr, source: &Source) -> Result<(), BoxError> {
+    let uri = parse_url(url)?;
+    let request = request(&uri, Method::GET).body(Body::empty())?;
+    let status = send(&uri, request, ip, source, &Verify::Roots, false)
+        .await?
+        .status();
+
+    if expected_status
+        .iter()
+        .any(|pattern| pattern.matches(status))
+    {
+        Ok(())
+    } else {
+        Err(format!("unexpected status {}", status).into())
+    }
+}
+
+pub async fn post(
+    url: &str,
+    content_type: &str,
+    headers: &BTreeMap<String, String>,
+    body: Vec<u8>,
+) -> Result<(), BoxError> {
+    upload(Method::POST, url, content_type, headers, body).await
+}
+
+// Sends `body` with the method, failing unless the response has a success status.
+pub async fn upload(
+    method: Method,
+    url: &str,
+    content_type: &str,
+    headers: &BTreeMap<String, String>,
+    body: Vec<u8>,
+) -> Result<(), BoxError> {
+    let uri = parse_url(url)?;
+    let mut request = request(&uri, method).header(CONTENT_TYPE, content_type);
+    for (name, value) in headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    let request = request.body(Body::from(body))?;
+    let ip = crate::icmp::resolve(host(&uri), None).await?;
+    let status = send(&uri, request, ip, &Source::default(), &Verify::Roots, false)
+        .await?
+        .status();
+
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("unexpected status {}", status).into())
+    }
+}
+
+// Posts `body` to `ip` rather than resolving the host of the url and reads the whole response, whatever its status.
+// The response is asked to be of the same type.
+pub async fn exchange(
+    uri: &Uri,
+    content_type: &str,
+    body: Vec<u8>,
+    ip: IpAddr,
+    source: &Source,
+    verify: &Verify,
+) -> Result<Response<Vec<u8>>, BoxError> {
+    let request = request(uri, Method::POST)
+        .header(CONTENT_TYPE, content_type)
+        .header(ACCEPT, content_type)
+        .body(Body::from(body))?;
+    send(uri, request, ip, source, verify, true).await
+}
+
+// Gets the url with the headers and reads the whole body, whatever the status of the response.
+pub async fn fetch(
+    url: &str,
+    headers: &BTreeMap<String, String>,
+    verify: &Verify,
+) -> Result<Response<Vec<u8>>, BoxError> {
+    let uri = parse_url(url)?;
+    let mut request = request(&uri, Method::GET);
+    for (name, value) in headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    let request = request.body(Body::empty())?;
+    let ip = crate::icmp::resolve(host(&uri), None).await?;
+    send(&uri, request, ip, &Source::default(), verify, true).await
+}
+
+// Encodes everything but unreserved characters, for use in a query string or a path segment.
+pub fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+pub fn basic_auth(username: &str, password: &str) -> String {
+    format!("Basic {}", STANDARD.encode(format!("{}:{}", username, password)))
+}
+
+fn request(uri: &Uri, method: Method) -> hyper::http::request::Builder {
+    Request::builder()
+        .method(method)
+        .uri(uri.path_and_query().map_or("/", |path| path.as_str()))
+        .header(HOST, uri.authority().map_or("", |authority| authority.as_str()))
+        .header(USER_AGENT, concat!("rust-net-stab/", env!("CARGO_PKG_VERSION")))
+}
+
+// Without `read_body` the response is returned as soon as its headers have arrived, with an empty body.
+async fn send(
+    uri: &Uri,
+    request: Request<Body>,
+    ip: IpAddr,
+    source: &Source,
+    verify: &Verify,
+    read_body: bool,
+) -> Result<Response<Vec<u8>>, BoxError> {
+    let https = uri.scheme_str() == Some("https");
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+    let stream = source.connect((ip, port).into()).await?;
+    if https {
+        send_https(stream, host(uri), request, verify, read_body).await
+    } else {
+        send_on(stream, request, read_body).await
+    }
+}
+
+#[cfg(feature = "tls")]
+async fn send_https(
+    stream: TcpStream,
+    host: &str,
+    request: Request<Body>,
+    verify: &Verify,
+    read_body: bool,
+) -> Result<Response<Vec<u8>>, BoxError> {
+    let stream = match verify {
+        Verify::Roots => crate::tls::connect(stream, host, true).await?,
+        Verify::CaFile(path) => crate::tls::connect_with_ca(stream, host, path).await?,
+        Verify::Skip => crate::tls::connect(stream, host, false).await?,
+    };
+    send_on(stream, request, read_body).await
+}
+
+#[cfg(not(feature = "tls"))]
+async fn send_https(
+    _stream: TcpStream,
+    _host: &str,
+    _request: Request<Body>,
+    _verify: &Verify,
+    _read_body: bool,
+) -> Result<Response<Vec<u8>>, BoxError> {
+    Err("https urls need rust-net-stab to be built with the `tls` feature".into())
+}
+
+async fn send_on<T>(io: T, request: Request<Body>, read_body: bool) -> Result<Response<Vec<u8>>, BoxError>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::handshake(io).await?;
+    let connection = tokio::spawn(connection);
+
+    let response = async {
+        let (parts, body) = sender.send_request(request).await?.into_parts();
+        let body = if read_body {
+            hyper::body::to_bytes(body).await?.to_vec()
+        } else {
+            Vec::new()
+        };
+        Ok::<_, hyper::Error>(Response::from_parts(parts, body))
+    }
+    .await;
+    connection.abort();
+
+    Ok(response?)
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use hyper::header::{AUTHORIZATION, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use tokio::time::{timeout, Duration};
+
+use crate::config::{self, Defaults, Endpoint};
+use crate::duration;
+use crate::file_sd::{self, TargetGroup};
+use crate::http::{self, Verify};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpSdConfig {
+    // Returns target groups as JSON, like the files of `file` discovery.
+    pub url: String,
+    #[serde(default = "default_refresh_interval", with = "duration")]
+    pub refresh_interval: Duration,
+    pub username: Option<String>,
+    pub password_env: Option<String>,
+    pub password_file: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub defaults: Defaults,
+}
+
+fn default_refresh_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl HttpSdConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        http::parse_url(&self.url).map_err(|err| err.to_string())?;
+        if self.password_env.is_some() && self.password_file.is_some() {
+            return Err("set only one of password_env or password_file".to_string());
+        }
+        if (self.password_env.is_some() || self.password_file.is_some()) && self.username.is_none() {
+            return Err("a password is set but no username".to_string());
+        }
+        self.password()?;
+        if self.refresh_interval.is_zero() {
+            return Err("refresh_interval must be longer than 0s".to_string());
+        }
+        Ok(())
+    }
+
+    fn password(&self) -> Result<Option<String>, String> {
+        config::secret("password", &self.password_env, &self.password_file)
+    }
+}
+
+// The validators of the last response, sent along with the next request so that an unchanged target list is not sent
+// again.
+#[derive(Default)]
+pub(crate) struct HttpSd {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    endpoints: Vec<Endpoint>,
+}
+
+impl HttpSd {
+    // Returns the endpoints of the last response when the server says nothing changed. A failure is returned as is,
+    // so that the endpoints found before can be kept.
+    pub(crate) async fn lookup(&mut self, config: &HttpSdConfig) -> Result<Vec<Endpoint>, String> {
+        let mut headers = config.headers.clone();
+        if let Some(username) = &config.username {
+            let password = config.password()?.unwrap_or_default();
+            headers.insert(AUTHORIZATION.to_string(), http::basic_auth(username, &password));
+        }
+        if let Some(etag) = &self.etag {
+            headers.insert(IF_NONE_MATCH.to_string(), etag.clone());
+        }
+        if let Some(last_modified) = &self.last_modified {
+            headers.insert(IF_MODIFIED_SINCE.to_string(), last_modified.clone());
+        }
+
+        let response = timeout(REQUEST_TIMEOUT, http::fetch(&config.url, &headers, &Verify::Roots))
+            .await
+            .map_err(|_| format!("requesting {} timed out", config.url))?
+            .map_err(|err| format!("requesting {}: {}", config.url, err))?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(self.endpoints.clone());
+        }
+        if !response.status().is_success() {
+            return Err(format!(
+                "requesting {}: unexpected status {}",
+                config.url,
+                response.status()
+            ));
+        }
+
+        let groups: Vec<TargetGroup> =
+            serde_json::from_slice(response.body()).map_err(|err| format!("requesting {}: {}", config.url, err))?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        self.etag = header(ETAG);
+        self.last_modified = header(LAST_MODIFIED);
+        self.endpoints = file_sd::endpoints(&groups, &config.defaults);
+        Ok(self.endpoints.clone())
+    }
+}
@@ -0,0 +1,337 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+#[cfg(unix)]
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use crate::config::IpVersion;
+#[cfg(windows)]
+use crate::icmp_api;
+#[cfg(unix)]
+use crate::pinger;
+use crate::source::Source;
+
+const ECHO_REQUEST_V4: u8 = 8;
+pub(crate) const ECHO_REPLY_V4: u8 = 0;
+const ECHO_REQUEST_V6: u8 = 128;
+pub(crate) const ECHO_REPLY_V6: u8 = 129;
+const TIME_EXCEEDED_V4: u8 = 11;
+const TIME_EXCEEDED_V6: u8 = 3;
+pub(crate) const PAYLOAD: &[u8] = b"rust-net-stab...";
+// The largest payload that fits an IPv4 packet along with the IP and ICMP headers.
+pub(crate) const MAX_PAYLOAD: usize = 65507;
+
+static NEXT_IDENTIFIER: AtomicU16 = AtomicU16::new(0);
+
+// Every traceroute and shared socket gets its own identifier so that their replies on raw sockets can be told apart.
+pub fn next_identifier() -> u16 {
+    (std::process::id() as u16).wrapping_add(NEXT_IDENTIFIER.fetch_add(1, Ordering::Relaxed))
+}
+
+#[derive(Debug)]
+pub struct ResolveError {
+    address: String,
+    source: io::Error,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to resolve {}: {}", self.address, self.source)
+    }
+}
+
+impl std::error::Error for ResolveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+// Picks the first address of the IP version, or whichever the system resolver returns first without one.
+pub async fn resolve(address: &str, ip_version: Option<IpVersion>) -> Result<IpAddr, ResolveError> {
+    let error = |source| ResolveError {
+        address: address.to_string(),
+        source,
+    };
+    let not_found = || {
+        let message = match ip_version {
+            Some(IpVersion::V4) => "no IPv4 addresses found",
+            Some(IpVersion::V6) => "no IPv6 addresses found",
+            _ => "no addresses found",
+        };
+        error(io::Error::new(io::ErrorKind::NotFound, message))
+    };
+    let accepts = |ip: &IpAddr| ip_version.is_none_or(|ip_version| ip_version.matches(*ip));
+
+    if let Ok(ip) = address.parse() {
+        return Some(ip).filter(accepts).ok_or_else(not_found);
+    }
+
+    tokio::net::lookup_host((address, 0))
+        .await
+        .map_err(error)?
+        .map(|addr| addr.ip())
+        .find(accepts)
+        .ok_or_else(not_found)
+}
+
+// How echo requests are sent, picked once by what the process is permitted to do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    // Raw sockets need root or `CAP_NET_RAW`, and are the only ones to see destination unreachable messages.
+    #[cfg(unix)]
+    Raw,
+    // Unprivileged ICMP sockets, allowed on Linux by `net.ipv4.ping_group_range` and always on macOS.
+    #[cfg(unix)]
+    Datagram,
+    // Running the system `ping` for every request.
+    #[cfg(unix)]
+    Command,
+    // `IcmpSendEcho2` of the Windows ICMP API, which needs no privileges.
+    #[cfg(windows)]
+    IcmpApi,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(unix)]
+            Mode::Raw => "raw",
+            #[cfg(unix)]
+            Mode::Datagram => "datagram",
+            #[cfg(unix)]
+            Mode::Command => "command",
+            #[cfg(windows)]
+            Mode::IcmpApi => "icmp_api",
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn mode() -> Mode {
+    static MODE: OnceLock<Mode> = OnceLock::new();
+    *MODE.get_or_init(|| {
+        let permitted = |kind| Socket::new(Domain::IPV4, kind, Some(Protocol::ICMPV4)).is_ok();
+        if permitted(Type::RAW) {
+            Mode::Raw
+        } else if permitted(Type::DGRAM) {
+            Mode::Datagram
+        } else {
+            Mode::Command
+        }
+    })
+}
+
+#[cfg(windows)]
+pub fn mode() -> Mode {
+    Mode::IcmpApi
+}
+
+// Returns the round trip time of an echo request to the address.
+pub struct Echo {
+    pub rtt: Duration,
+    // The TTL, or hop limit, the reply arrived with, where the socket tells it.
+    pub ttl: Option<u8>,
+}
+
+// Sends an echo request with `size` bytes of payload.
+pub async fn echo(ip: IpAddr, size: usize, timeout: Duration, source: &Source) -> io::Result<Echo> {
+    match mode() {
+        #[cfg(unix)]
+        Mode::Raw | Mode::Datagram => pinger::echo(ip, size, timeout, source).await,
+        #[cfg(unix)]
+        Mode::Command => pinger::ping_command(ip, size, timeout, source).await,
+        #[cfg(windows)]
+        Mode::IcmpApi => icmp_api::echo(ip, size, timeout, source).await,
+    }
+}
+
+pub struct Hop {
+    pub address: IpAddr,
+    pub rtt: Duration,
+    // Whether the answer came from the target itself rather than from a router on the way.
+    pub reached: bool,
+}
+
+// Sends an echo request for every TTL up to `max_hops` at once, using the TTL as the sequence number, and collects
+// the answers of the routers along the way. Only raw sockets get to see the time exceeded messages.
+pub async fn trace(
+    ip: IpAddr,
+    identifier: u16,
+    max_hops: u8,
+    timeout: Duration,
+    source: Source,
+) -> io::Result<Vec<Option<Hop>>> {
+    tokio::task::spawn_blocking(move || trace_blocking(ip, identifier, max_hops, timeout, &source))
+        .await
+        .map_err(io::Error::other)?
+}
+
+fn trace_blocking(
+    ip: IpAddr,
+    identifier: u16,
+    max_hops: u8,
+    timeout: Duration,
+    source: &Source,
+) -> io::Result<Vec<Option<Hop>>> {
+    let (domain, protocol) = domain(ip);
+    let socket = Socket::new(domain, Type::RAW, Some(protocol))?;
+    source.bind(&socket, ip)?;
+    let target = SocketAddr::new(ip, 0).into();
+
+    let mut sent = Vec::with_capacity(max_hops as usize);
+    for ttl in 1..=max_hops {
+        match ip {
+            IpAddr::V4(_) => socket.set_ttl(u32::from(ttl))?,
+            IpAddr::V6(_) => socket.set_unicast_hops_v6(u32::from(ttl))?,
+        }
+        sent.push(Instant::now());
+        socket.send_to(&echo_request(ip, identifier, u16::from(ttl)), &target)?;
+    }
+
+    let mut hops: Vec<Option<Hop>> = (0..max_hops).map(|_| None).collect();
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0u8; 1500];
+    // Waiting stops early once the target answered and so did every router before it.
+    while !complete(&hops) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_millis(0) {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let (len, from) = match recv_from(&socket, &mut buffer) {
+            Ok(received) => received,
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+            Err(err) => return Err(err),
+        };
+        let (address, (sequence, reached)) = match (from.as_socket(), hop_reply(ip, &buffer[..len], identifier)) {
+            (Some(from), Some(reply)) => (from.ip(), reply),
+            _ => continue,
+        };
+
+        let index = usize::from(sequence).wrapping_sub(1);
+        if let Some(hop) = hops.get_mut(index).filter(|hop| hop.is_none()) {
+            *hop = Some(Hop {
+                address,
+                rtt: sent[index].elapsed(),
+                reached,
+            });
+        }
+    }
+
+    Ok(hops)
+}
+
+fn complete(hops: &[Option<Hop>]) -> bool {
+    match hops
+        .iter()
+        .position(|hop| hop.as_ref().is_some_and(|hop| hop.reached))
+    {
+        Some(end) => hops[..end].iter().all(Option::is_some),
+        None => false,
+    }
+}
+
+pub(crate) fn recv_from(socket: &Socket, buffer: &mut [u8]) -> io::Result<(usize, SockAddr)> {
+    // SAFETY: `recv_from` only ever writes initialized bytes, and the buffer is initialized to begin with.
+    let buffer = unsafe { &mut *(buffer as *mut [u8] as *mut [MaybeUninit<u8>]) };
+    socket.recv_from(buffer)
+}
+
+pub(crate) fn domain(ip: IpAddr) -> (Domain, Protocol) {
+    match ip {
+        IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+        IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+    }
+}
+
+pub(crate) fn echo_request(ip: IpAddr, identifier: u16, sequence: u16) -> Vec<u8> {
+    echo_request_sized(ip, identifier, sequence, 8 + PAYLOAD.len())
+}
+
+// An echo request of `len` bytes, ICMP header included, with the payload repeated to fill it.
+pub(crate) fn echo_request_sized(ip: IpAddr, identifier: u16, sequence: u16, len: usize) -> Vec<u8> {
+    let kind = if ip.is_ipv4() { ECHO_REQUEST_V4 } else { ECHO_REQUEST_V6 };
+
+    let mut packet = vec![kind, 0, 0, 0];
+    packet.extend_from_slice(&identifier.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend(PAYLOAD.iter().cycle().take(len.saturating_sub(8)));
+
+    // The kernel fills in the ICMPv6 checksum since it covers the IPv6 pseudo-header.
+    if ip.is_ipv4() {
+        let checksum = checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    packet
+}
+
+// Raw IPv4 sockets hand us the IP header as well.
+pub(crate) fn skip_ipv4_header(packet: &[u8]) -> &[u8] {
+    let header_len = packet.first().map_or(0, |b| (b & 0x0f) as usize * 4);
+    packet.get(header_len..).unwrap_or_default()
+}
+
+// Returns the sequence number of the request a raw socket answer is about, and whether it came from the target.
+fn hop_reply(ip: IpAddr, packet: &[u8], identifier: u16) -> Option<(u16, bool)> {
+    let (packet, echo_reply, time_exceeded) = match ip {
+        IpAddr::V4(_) => (skip_ipv4_header(packet), ECHO_REPLY_V4, TIME_EXCEEDED_V4),
+        IpAddr::V6(_) => (packet, ECHO_REPLY_V6, TIME_EXCEEDED_V6),
+    };
+
+    let kind = *packet.first()?;
+    let (reply_identifier, sequence, reached) = if kind == echo_reply && packet.len() >= 8 {
+        let reply_identifier = u16::from_be_bytes([packet[4], packet[5]]);
+        (reply_identifier, u16::from_be_bytes([packet[6], packet[7]]), true)
+    } else if kind == time_exceeded {
+        let (_, reply_identifier, sequence) = quoted_request(ip, packet)?;
+        (reply_identifier, sequence, false)
+    } else {
+        return None;
+    };
+
+    (reply_identifier == identifier).then_some((sequence, reached))
+}
+
+// ICMP error messages quote the IP header and the start of the request they are about after their own 8 byte header.
+// Returns the target of the request with its identifier and sequence number.
+pub(crate) fn quoted_request(ip: IpAddr, packet: &[u8]) -> Option<(IpAddr, u16, u16)> {
+    let quoted = packet.get(8..)?;
+    let (target, request): (IpAddr, _) = match ip {
+        IpAddr::V4(_) => {
+            let target: [u8; 4] = quoted.get(16..20)?.try_into().ok()?;
+            (target.into(), skip_ipv4_header(quoted))
+        }
+        IpAddr::V6(_) => {
+            let target: [u8; 16] = quoted.get(24..40)?.try_into().ok()?;
+            (target.into(), quoted.get(40..)?)
+        }
+    };
+    if request.len() < 8 {
+        return None;
+    }
+
+    Some((
+        target,
+        u16::from_be_bytes([request[4], request[5]]),
+        u16::from_be_bytes([request[6], request[7]]),
+    ))
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|chunk| u32::from(u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)])))
+        .sum::<u32>();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,178 @@
+use std::ffi::c_void;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv6Addr};
+use std::ptr;
+use std::time::{Duration, Instant};
+
+use windows_sys::Win32::Foundation::{HANDLE, INVALID_HANDLE_VALUE};
+use windows_sys::Win32::NetworkManagement::IpHelper::{
+    Icmp6CreateFile, Icmp6SendEcho2, IcmpCloseHandle, IcmpCreateFile, IcmpSendEcho2Ex, ICMPV6_ECHO_REPLY_LH,
+    ICMP_ECHO_REPLY,
+};
+use windows_sys::Win32::Networking::WinSock::{AF_INET6, SOCKADDR_IN6};
+
+use crate::icmp::{Echo, PAYLOAD};
+use crate::source::Source;
+
+// Status codes of the ICMP API, from ipexport.h. Those below them are Windows error codes.
+const IP_SUCCESS: u32 = 0;
+const IP_STATUS_BASE: u32 = 11000;
+const IP_DEST_NET_UNREACHABLE: u32 = 11002;
+const IP_DEST_HOST_UNREACHABLE: u32 = 11003;
+const IP_DEST_PROT_UNREACHABLE: u32 = 11004;
+const IP_DEST_PORT_UNREACHABLE: u32 = 11005;
+const IP_REQ_TIMED_OUT: u32 = 11010;
+const IP_TTL_EXPIRED_TRANSIT: u32 = 11013;
+
+// Closes the handle however the request ends.
+struct Handle(HANDLE);
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        // SAFETY: the handle was opened by `IcmpCreateFile` or `Icmp6CreateFile` and is closed only here.
+        unsafe {
+            IcmpCloseHandle(self.0);
+        }
+    }
+}
+
+pub(crate) async fn echo(ip: IpAddr, size: usize, timeout: Duration, source: &Source) -> io::Result<Echo> {
+    if source.interface.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "source_interface is only supported on Linux",
+        ));
+    }
+    if source.dscp.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "dscp is not supported on Windows",
+        ));
+    }
+    if source.dont_fragment {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "dont_fragment is only supported on Linux",
+        ));
+    }
+    let local = source.local(ip)?.ip();
+    tokio::task::spawn_blocking(move || echo_blocking(ip, local, size, timeout))
+        .await
+        .map_err(io::Error::other)?
+}
+
+// Without an event to signal the reply, the request blocks its thread until the reply arrives or it times out. The
+// round trip time the API reports is in whole milliseconds, so it is measured around the call instead.
+fn echo_blocking(ip: IpAddr, local: IpAddr, size: usize, timeout: Duration) -> io::Result<Echo> {
+    // SAFETY: neither function takes arguments, failing returns INVALID_HANDLE_VALUE.
+    let handle = Handle(unsafe {
+        if ip.is_ipv4() {
+            IcmpCreateFile()
+        } else {
+            Icmp6CreateFile()
+        }
+    });
+    if handle.0 == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+
+    let payload: Vec<u8> = PAYLOAD.iter().cycle().take(size).copied().collect();
+    // Room for the reply, the data it echoes and an ICMP error message, aligned for the reply structures.
+    let mut buffer = vec![0u64; 128 + size / mem::size_of::<u64>()];
+    let size = (buffer.len() * mem::size_of::<u64>()) as u32;
+    let timeout = timeout.as_millis().clamp(1, u128::from(u32::MAX)) as u32;
+    let data = payload.as_ptr() as *const c_void;
+    let reply = buffer.as_mut_ptr() as *mut c_void;
+
+    let start = Instant::now();
+    // SAFETY: the handle is open, the addresses and the data outlive the call, which returns once it is answered or
+    // timed out as no event or APC routine is given, and `size` is the size of the reply buffer.
+    let replies = unsafe {
+        match (ip, local) {
+            (IpAddr::V4(ip), IpAddr::V4(local)) => IcmpSendEcho2Ex(
+                handle.0,
+                0,
+                None,
+                ptr::null(),
+                u32::from_ne_bytes(local.octets()),
+                u32::from_ne_bytes(ip.octets()),
+                data,
+                payload.len() as u16,
+                ptr::null(),
+                reply,
+                size,
+                timeout,
+            ),
+            (IpAddr::V6(ip), IpAddr::V6(local)) => {
+                let (local, ip) = (sockaddr(local), sockaddr(ip));
+                Icmp6SendEcho2(
+                    handle.0,
+                    0,
+                    None,
+                    ptr::null(),
+                    &local,
+                    &ip,
+                    data,
+                    payload.len() as u16,
+                    ptr::null(),
+                    reply,
+                    size,
+                    timeout,
+                )
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} cannot reach {}", local, ip),
+                ))
+            }
+        }
+    };
+    let elapsed = start.elapsed();
+
+    // Only the IPv4 reply says what TTL it arrived with.
+    let (status, ttl) = if replies == 0 {
+        let status = io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or_default() as u32;
+        (status, None)
+    } else if ip.is_ipv4() {
+        // SAFETY: a reply was written to the start of the buffer, which is aligned for it.
+        let reply = unsafe { &*(buffer.as_ptr() as *const ICMP_ECHO_REPLY) };
+        (reply.Status, Some(reply.Options.Ttl))
+    } else {
+        // SAFETY: as above.
+        (
+            unsafe { (*(buffer.as_ptr() as *const ICMPV6_ECHO_REPLY_LH)).Status },
+            None,
+        )
+    };
+    match status {
+        IP_SUCCESS => Ok(Echo { rtt: elapsed, ttl }),
+        IP_REQ_TIMED_OUT => Err(io::Error::new(io::ErrorKind::TimedOut, "echo request timed out")),
+        IP_DEST_NET_UNREACHABLE | IP_DEST_HOST_UNREACHABLE | IP_DEST_PROT_UNREACHABLE | IP_DEST_PORT_UNREACHABLE => {
+            Err(io::Error::new(
+                io::ErrorKind::HostUnreachable,
+                "destination unreachable",
+            ))
+        }
+        IP_TTL_EXPIRED_TRANSIT => Err(io::Error::new(
+            io::ErrorKind::HostUnreachable,
+            "time to live exceeded in transit",
+        )),
+        status if status < IP_STATUS_BASE => Err(io::Error::from_raw_os_error(status as i32)),
+        status => Err(io::Error::other(format!(
+            "echo request failed with ICMP status {}",
+            status
+        ))),
+    }
+}
+
+fn sockaddr(ip: Ipv6Addr) -> SOCKADDR_IN6 {
+    // SAFETY: all zeros is a valid SOCKADDR_IN6.
+    let mut address: SOCKADDR_IN6 = unsafe { mem::zeroed() };
+    address.sin6_family = AF_INET6;
+    address.sin6_addr.u.Byte = ip.octets();
+    address
+}
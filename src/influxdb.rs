@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tokio::time::{Duration, MissedTickBehavior};
+
+use crate::status::ProbeEvent;
+use crate::{config, duration, http};
+
+// Lines that could not be written are kept for the next attempt, up to this many.
+const MAX_PENDING_LINES: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDbConfig {
+    pub url: String,
+    pub org: String,
+    pub bucket: String,
+    pub token_env: Option<String>,
+    pub token_file: Option<String>,
+    #[serde(default = "default_measurement")]
+    pub measurement: String,
+    #[serde(default = "default_interval", with = "duration")]
+    pub interval: Duration,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+}
+
+fn default_measurement() -> String {
+    "probe".to_string()
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_batch_size() -> usize {
+    1000
+}
+
+impl InfluxDbConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        http::parse_url(&self.url).map_err(|err| err.to_string())?;
+        if self.token_env.is_some() && self.token_file.is_some() {
+            return Err("set only one of token_env or token_file".to_string());
+        }
+        self.token()?;
+        if self.interval.is_zero() {
+            return Err("interval must be greater than 0".to_string());
+        }
+        if self.batch_size == 0 {
+            return Err("batch_size must be at least 1".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn token(&self) -> Result<Option<String>, String> {
+        config::secret("token", &self.token_env, &self.token_file)
+    }
+
+    fn write_url(&self) -> String {
+        format!(
+            "{}/api/v2/write?org={}&bucket={}&precision=ns",
+            self.url.trim_end_matches('/'),
+            http::percent_encode(&self.org),
+            http::percent_encode(&self.bucket)
+        )
+    }
+}
+
+// Writes a line for every probe result, in batches of up to `batch_size` and at least every `interval`.
+pub async fn run(config: InfluxDbConfig, mut events: broadcast::Receiver<ProbeEvent>, mut stop: watch::Receiver<bool>) {
+    let mut lines = Vec::new();
+    let mut ticker = tokio::time::interval(config.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        let stopping = tokio::select! {
+            _ = ticker.tick() => false,
+            event = events.recv() => {
+                // Once the channel is closed no more results are coming, so the write that follows is the last one.
+                let closed = match event {
+                    Ok(event) => {
+                        lines.push(line(&config.measurement, &event));
+                        false
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("InfluxDB output fell behind and skipped {} probe results", skipped);
+                        false
+                    }
+                    Err(RecvError::Closed) => true,
+                };
+                if !closed && lines.len() < config.batch_size {
+                    continue;
+                }
+                closed
+            }
+            _ = stop.changed() => true,
+        };
+
+        // Results published by the last probes still go out with the final write.
+        if stopping {
+            while let Ok(event) = events.try_recv() {
+                lines.push(line(&config.measurement, &event));
+            }
+        }
+        while !lines.is_empty() {
+            let batch: Vec<String> = lines.drain(..lines.len().min(config.batch_size)).collect();
+            if let Err(err) = write(&config, &batch).await {
+                error!("Failed to write to InfluxDB at {}: {}", config.url, err);
+                lines.splice(0..0, batch);
+                break;
+            }
+        }
+        if lines.len() > MAX_PENDING_LINES {
+            lines.drain(..lines.len() - MAX_PENDING_LINES);
+        }
+        if stopping {
+            break;
+        }
+    }
+}
+
+async fn write(config: &InfluxDbConfig, lines: &[String]) -> Result<(), crate::BoxError> {
+    let mut headers = BTreeMap::new();
+    if let Some(token) = config.token()? {
+        headers.insert("Authorization".to_string(), format!("Token {}", token));
+    }
+
+    http::post(
+        &config.write_url(),
+        "text/plain; charset=utf-8",
+        &headers,
+        lines.join("\n").into_bytes(),
+    )
+    .await
+}
+
+// A line of the InfluxDB line protocol, with empty tags left out since the protocol does not allow them.
+fn line(measurement: &str, event: &ProbeEvent) -> String {
+    let mut line = escape(measurement, &[',', ' ']);
+    let tags = [
+        ("name", event.name.as_str()),
+        ("address", event.address.as_str()),
+        ("location", event.location.as_deref().unwrap_or_default()),
+        ("probe_type", event.probe_type),
+        ("ip_version", event.ip_version.unwrap_or_default()),
+    ];
+    for (key, value) in tags.iter().filter(|(_, value)| !value.is_empty()) {
+        line.push_str(&format!(",{}={}", key, escape(value, &[',', '=', ' '])));
+    }
+
+    line.push_str(&format!(" up={},loss_ratio={}", event.up, event.loss_ratio));
+    if let Some(rtt) = event.rtt_seconds {
+        line.push_str(&format!(",rtt_seconds={}", rtt));
+    }
+    if let Some(error) = &event.error {
+        let error = error.replace(['\r', '\n'], " ");
+        line.push_str(&format!(",error=\"{}\"", escape(&error, &['"', '\\'])));
+    }
+    line.push_str(&format!(" {}", (event.timestamp * 1e9) as u64));
+    line
+}
+
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
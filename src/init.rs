@@ -0,0 +1,36 @@
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+
+use crate::{config, BoxError};
+
+pub const EXAMPLE: &str = include_str!("example.yaml");
+
+// The `init` command, which writes the example config to `config_path` unless something is already there.
+pub fn run(config_path: &str, force: bool) -> Result<(), BoxError> {
+    if config::Format::of(config_path) != config::Format::Yaml {
+        return Err(format!(
+            "the example config is YAML, {} would be read as another format",
+            config_path
+        )
+        .into());
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    let mut file = options.open(config_path).map_err(|err| match err.kind() {
+        ErrorKind::AlreadyExists => format!("{} already exists, pass --force to overwrite it", config_path),
+        _ => format!("failed to create {}: {}", config_path, err),
+    })?;
+    file.write_all(EXAMPLE.as_bytes())?;
+
+    println!(
+        "Wrote an example config to {}, edit it and start probing with `rust-net-stab --config {}`",
+        config_path, config_path
+    );
+    Ok(())
+}
@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use hyper::header::AUTHORIZATION;
+use serde::{Deserialize, Serialize};
+use tokio::time::{timeout, Duration};
+
+use crate::config::{Defaults, Endpoint};
+use crate::duration;
+use crate::http::{self, Verify};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const SERVICE_ACCOUNT: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Pod,
+    Service,
+    Endpoints,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KubernetesSdConfig {
+    pub role: Role,
+    // Every namespace when empty.
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+    pub label_selector: Option<String>,
+    // The port of the endpoints, instead of the first port of the pod, service or endpoints.
+    pub port: Option<u16>,
+    // Without one the API server of the cluster the monitor runs in is used, with the token and CA of its service
+    // account.
+    pub api_server: Option<String>,
+    pub token_file: Option<PathBuf>,
+    pub ca_file: Option<PathBuf>,
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    #[serde(default = "default_refresh_interval", with = "duration")]
+    pub refresh_interval: Duration,
+    #[serde(default)]
+    pub defaults: Defaults,
+}
+
+fn default_refresh_interval() -> Duration {
+    Duration::from_secs(30)
+}
+
+impl KubernetesSdConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(api_server) = &self.api_server {
+            http::parse_url(api_server).map_err(|err| err.to_string())?;
+        }
+        if let Some(namespace) = self
+            .namespaces
+            .iter()
+            .find(|namespace| namespace.is_empty())
+        {
+            return Err(format!("`{}` is not a valid namespace", namespace));
+        }
+        if self.refresh_interval.is_zero() {
+            return Err("refresh_interval must be longer than 0s".to_string());
+        }
+        Ok(())
+    }
+
+    fn api_server(&self) -> Result<String, String> {
+        if let Some(api_server) = &self.api_server {
+            return Ok(api_server.trim_end_matches('/').to_string());
+        }
+        match (env::var("KUBERNETES_SERVICE_HOST"), env::var("KUBERNETES_SERVICE_PORT")) {
+            (Ok(host), Ok(port)) if host.contains(':') => Ok(format!("https://[{}]:{}", host, port)),
+            (Ok(host), Ok(port)) => Ok(format!("https://{}:{}", host, port)),
+            _ => Err("not running in a cluster, set an api_server".to_string()),
+        }
+    }
+
+    // The files of the service account are only used in the cluster, not with an `api_server` of its own.
+    fn service_account_file(&self, file: &Option<PathBuf>, name: &str) -> Option<PathBuf> {
+        file.clone().or_else(|| {
+            let path = Path::new(SERVICE_ACCOUNT).join(name);
+            (self.api_server.is_none() && path.exists()).then_some(path)
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct List<T> {
+    items: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    name: String,
+    #[serde(default)]
+    namespace: String,
+}
+
+#[derive(Deserialize)]
+struct Pod {
+    metadata: Metadata,
+    #[serde(default)]
+    spec: PodSpec,
+    #[serde(default)]
+    status: PodStatus,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PodSpec {
+    node_name: Option<String>,
+    #[serde(default)]
+    containers: Vec<Container>,
+}
+
+#[derive(Deserialize)]
+struct Container {
+    #[serde(default)]
+    ports: Vec<ContainerPort>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ContainerPort {
+    container_port: u16,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PodStatus {
+    phase: Option<String>,
+    #[serde(rename = "podIP")]
+    pod_ip: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Service {
+    metadata: Metadata,
+    #[serde(default)]
+    spec: ServiceSpec,
+}
+
+#[derive(Default, Deserialize)]
+struct ServiceSpec {
+    #[serde(rename = "clusterIP")]
+    cluster_ip: Option<String>,
+    #[serde(default)]
+    ports: Vec<Port>,
+}
+
+#[derive(Deserialize)]
+struct Port {
+    port: u16,
+}
+
+#[derive(Deserialize)]
+struct Endpoints {
+    metadata: Metadata,
+    #[serde(default)]
+    subsets: Vec<Subset>,
+}
+
+#[derive(Deserialize)]
+struct Subset {
+    #[serde(default)]
+    addresses: Vec<Address>,
+    #[serde(default)]
+    ports: Vec<Port>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Address {
+    ip: String,
+    node_name: Option<String>,
+    target_ref: Option<Reference>,
+}
+
+#[derive(Deserialize)]
+struct Reference {
+    kind: String,
+    name: String,
+}
+
+// Lists the objects of the role in every namespace, and returns the first failure so that the endpoints found before
+// can be kept.
+pub(crate) async fn lookup(config: &KubernetesSdConfig) -> Result<Vec<Endpoint>, String> {
+    let resource = match config.role {
+        Role::Pod => "pods",
+        Role::Service => "services",
+        Role::Endpoints => "endpoints",
+    };
+    let api_server = config.api_server()?;
+    let query = config
+        .label_selector
+        .as_ref()
+        .map(|selector| format!("?labelSelector={}", http::percent_encode(selector)))
+        .unwrap_or_default();
+    let urls: Vec<String> = if config.namespaces.is_empty() {
+        vec![format!("{}/api/v1/{}{}", api_server, resource, query)]
+    } else {
+        config
+            .namespaces
+            .iter()
+            .map(|namespace| {
+                format!(
+                    "{}/api/v1/namespaces/{}/{}{}",
+                    api_server,
+                    http::percent_encode(namespace),
+                    resource,
+                    query
+                )
+            })
+            .collect()
+    };
+
+    let mut headers = BTreeMap::new();
+    if let Some(token_file) = config.service_account_file(&config.token_file, "token") {
+        let token = fs::read_to_string(&token_file)
+            .map_err(|err| format!("failed to read {}: {}", token_file.display(), err))?;
+        headers.insert(AUTHORIZATION.to_string(), format!("Bearer {}", token.trim()));
+    }
+    let verify = match config.service_account_file(&config.ca_file, "ca.crt") {
+        _ if config.insecure_skip_verify => Verify::Skip,
+        Some(ca_file) => Verify::CaFile(ca_file),
+        None => Verify::Roots,
+    };
+
+    let mut endpoints = Vec::new();
+    for url in urls {
+        let response = timeout(REQUEST_TIMEOUT, http::fetch(&url, &headers, &verify))
+            .await
+            .map_err(|_| format!("listing {} timed out", resource))?
+            .map_err(|err| format!("listing {}: {}", resource, err))?;
+        if !response.status().is_success() {
+            return Err(format!("listing {}: unexpected status {}", resource, response.status()));
+        }
+        let body = response.body();
+        let parsed = match config.role {
+            Role::Pod => serde_json::from_slice(body).map(|list| pods(config, list)),
+            Role::Service => serde_json::from_slice(body).map(|list| services(config, list)),
+            Role::Endpoints => serde_json::from_slice(body).map(|list| endpoints_of(config, list)),
+        };
+        endpoints.extend(parsed.map_err(|err| format!("listing {}: {}", resource, err))?);
+    }
+    Ok(endpoints)
+}
+
+// Only running pods with an IP are probed.
+fn pods(config: &KubernetesSdConfig, list: List<Pod>) -> Vec<Endpoint> {
+    list.items
+        .into_iter()
+        .filter(|pod| pod.status.phase.as_deref() == Some("Running"))
+        .filter_map(|pod| {
+            let ip = pod.status.pod_ip?;
+            let port = pod
+                .spec
+                .containers
+                .iter()
+                .flat_map(|container| &container.ports)
+                .map(|port| port.container_port)
+                .next();
+            let mut labels = BTreeMap::new();
+            labels.insert("namespace".to_string(), pod.metadata.namespace.clone());
+            labels.insert("pod".to_string(), pod.metadata.name.clone());
+            if let Some(node) = pod.spec.node_name {
+                labels.insert("node".to_string(), node);
+            }
+            let name = format!("{}/{}", pod.metadata.namespace, pod.metadata.name);
+            Some(endpoint(config, name, ip, port, labels))
+        })
+        .collect()
+}
+
+// Headless services have no cluster IP to probe.
+fn services(config: &KubernetesSdConfig, list: List<Service>) -> Vec<Endpoint> {
+    list.items
+        .into_iter()
+        .filter_map(|service| {
+            let ip = service
+                .spec
+                .cluster_ip
+                .filter(|ip| !ip.is_empty() && ip != "None")?;
+            let port = service.spec.ports.first().map(|port| port.port);
+            let mut labels = BTreeMap::new();
+            labels.insert("namespace".to_string(), service.metadata.namespace.clone());
+            labels.insert("service".to_string(), service.metadata.name.clone());
+            let name = format!("{}/{}", service.metadata.namespace, service.metadata.name);
+            Some(endpoint(config, name, ip, port, labels))
+        })
+        .collect()
+}
+
+// Every ready address of the endpoints, named after the pod behind it when there is one.
+fn endpoints_of(config: &KubernetesSdConfig, list: List<Endpoints>) -> Vec<Endpoint> {
+    let mut found = Vec::new();
+    for endpoints in list.items {
+        let metadata = &endpoints.metadata;
+        for subset in endpoints.subsets {
+            let port = subset.ports.first().map(|port| port.port);
+            for address in subset.addresses {
+                let mut labels = BTreeMap::new();
+                labels.insert("namespace".to_string(), metadata.namespace.clone());
+                labels.insert("service".to_string(), metadata.name.clone());
+                let name = match &address.target_ref {
+                    Some(target) if target.kind == "Pod" => {
+                        labels.insert("pod".to_string(), target.name.clone());
+                        format!("{}/{}", metadata.namespace, target.name)
+                    }
+                    _ => format!("{}/{}/{}", metadata.namespace, metadata.name, address.ip),
+                };
+                if let Some(node) = address.node_name {
+                    labels.insert("node".to_string(), node);
+                }
+                found.push(endpoint(config, name, address.ip, port, labels));
+            }
+        }
+    }
+    found
+}
+
+fn endpoint(
+    config: &KubernetesSdConfig,
+    name: String,
+    address: String,
+    port: Option<u16>,
+    labels: BTreeMap<String, String>,
+) -> Endpoint {
+    let mut endpoint = Endpoint::new(name, address);
+    endpoint.port = config.port.or(port);
+    endpoint.labels = labels;
+    endpoint.inherit(&config.defaults);
+    endpoint
+}
@@ -0,0 +1,377 @@
+mod agent;
+mod aggregator;
+pub mod alerts;
+mod arp;
+mod auth;
+mod builtin_targets;
+pub mod check;
+pub mod cli;
+pub mod config;
+mod consul_sd;
+mod cron;
+mod database;
+mod discovery;
+mod dns;
+mod dns_sd;
+mod dscp;
+mod duration;
+mod exemplars;
+mod expand;
+mod exporter;
+mod exposition;
+mod file_output;
+mod file_sd;
+mod grpc;
+mod history;
+mod http;
+mod http_sd;
+mod icmp;
+#[cfg(windows)]
+mod icmp_api;
+mod influxdb;
+pub mod init;
+mod kubernetes_sd;
+mod maintenance;
+pub mod metrics;
+mod mqtt;
+mod ntp;
+mod otlp;
+pub mod outputs;
+#[cfg(unix)]
+mod pinger;
+mod pmtu;
+mod presets;
+pub mod probe;
+mod pushgateway;
+#[cfg(feature = "quic")]
+mod quic;
+mod remote_write;
+pub mod report;
+mod resolver;
+pub mod scheduler;
+pub mod server;
+mod sip;
+mod smtp;
+mod snmp;
+mod source;
+mod ssh;
+mod statsd;
+pub mod status;
+pub mod sweep;
+mod targets;
+mod throughput;
+mod timezone;
+#[cfg(feature = "tls")]
+mod tls;
+mod tui;
+pub mod validate;
+mod websocket;
+
+use std::fs;
+use std::time::SystemTime;
+
+use log::{error, info, warn};
+use prometheus::{Gauge, IntCounter, IntGaugeVec};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::{timeout_at, Duration, Instant, MissedTickBehavior};
+
+use crate::aggregator::Aggregator;
+use crate::alerts::AlertConfig;
+use crate::auth::Credentials;
+use crate::cli::Cli;
+use crate::config::{Config, DEFAULT_LISTEN};
+use crate::discovery::Discovery;
+use crate::history::History;
+use crate::metrics::Metrics;
+use crate::scheduler::Scheduler;
+use crate::status::StatusBoard;
+use crate::targets::{Command, Targets, TargetsApi};
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// Reloads the config when the file changes on disk or, on Unix, when the process receives SIGHUP, and applies the
+// endpoints found by service discovery and the changes made through the targets API as they come in.
+async fn watch_config(
+    path: &str,
+    scheduler: &mut Scheduler,
+    mut config: Config,
+    mut discovery: Discovery,
+    mut commands: mpsc::Receiver<Command>,
+    current: watch::Sender<Config>,
+) {
+    let mut targets = Targets::default();
+    let (reload_tx, mut reload_rx) = mpsc::channel::<()>(1);
+
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup()).expect("failed to install SIGHUP handler");
+        while hangup.recv().await.is_some() {
+            let _ = reload_tx.send(()).await;
+        }
+    });
+    #[cfg(not(unix))]
+    let _reload_tx = reload_tx;
+
+    let mut last_modified = modified(path);
+    let mut poll = tokio::time::interval(Duration::from_secs(2));
+    poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = poll.tick() => {
+                let current = modified(path);
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+            }
+            _ = reload_rx.recv() => {}
+            _ = discovery.updated() => {
+                if let Err(err) = scheduler.apply(&discovery.merge(&targets.apply(&config))) {
+                    error!("Failed to apply the discovered endpoints: {}", err);
+                }
+                continue;
+            }
+            Some(command) = commands.recv() => {
+                // A change that was written to the file is applied by loading it again right away.
+                if targets.handle(command, &config, &discovery, path) {
+                    last_modified = modified(path);
+                } else {
+                    if let Err(err) = scheduler.apply(&discovery.merge(&targets.apply(&config))) {
+                        error!("Failed to apply the targets API change: {}", err);
+                    }
+                    continue;
+                }
+            }
+        }
+
+        info!("Reloading {}", path);
+        match config::load(path) {
+            Ok(new) => {
+                if new.listen != config.listen || new.tls != config.tls {
+                    warn!("Changing the listen address or tls section requires a restart");
+                }
+                if new.outputs != config.outputs {
+                    warn!("Changing the outputs requires a restart");
+                }
+                if new.history != config.history {
+                    warn!("Changing the history requires a restart");
+                }
+                if new.api != config.api {
+                    warn!("Changing the api section requires a restart");
+                }
+                if new.agent != config.agent || new.aggregator != config.aggregator {
+                    warn!("Changing the agent or aggregator section requires a restart");
+                }
+                if new.auth != config.auth {
+                    warn!("Changing the auth section requires a restart");
+                }
+                if new.metrics != config.metrics {
+                    warn!("Changing the metrics section requires a restart");
+                }
+                discovery.reconfigure(&new).await;
+                config = new;
+                let _ = current.send(config.clone());
+                if let Err(err) = scheduler.apply(&discovery.merge(&targets.apply(&config))) {
+                    error!("Failed to apply {}: {}", path, err);
+                }
+            }
+            Err(err) => error!("Failed to reload {}, keeping the previous config: {}", path, err),
+        }
+    }
+}
+
+// Runs the monitor as the binary does, until Ctrl-C or SIGTERM. The logger is left to the caller to set up.
+pub async fn run(cli: Cli) -> Result<(), BoxError> {
+    if cli.tui {
+        tui::check_terminal()?;
+    }
+    let config = config::load(&cli.config)?;
+    targets::check_persist(&config, &cli.config)?;
+
+    let system_gauge =
+        |name, help| Gauge::with_opts(config.metrics.opts(name, help)).and_then(metrics::register_global);
+    let cpu_gauge = system_gauge("system_cpu_cores", "Number of CPU cores")?;
+    let load_avg_gauge = system_gauge("system_load_average", "System load average")?;
+    let mem_total_gauge = system_gauge("system_memory_total", "Total system memory")?;
+    let icmp_mode = icmp::mode();
+    match icmp_mode {
+        #[cfg(unix)]
+        icmp::Mode::Command => warn!(
+            "Neither raw nor unprivileged ICMP sockets are permitted, so pings run the system ping; grant CAP_NET_RAW or \
+             allow the group in net.ipv4.ping_group_range to avoid it"
+        ),
+        #[cfg(windows)]
+        icmp::Mode::IcmpApi => info!("Sending pings through the Windows ICMP API"),
+        #[cfg(unix)]
+        mode => info!("Sending pings over {} ICMP sockets", mode.name()),
+    }
+    IntGaugeVec::new(
+        config
+            .metrics
+            .opts("icmp_mode_info", "How pings are sent, by mode"),
+        &["mode"],
+    )
+    .and_then(metrics::register_global)?
+    .with_label_values(&[icmp_mode.name()])
+    .set(1);
+
+    let discovery = Discovery::start(&config).await;
+    let merged = discovery.merge(&config);
+    let metrics = Metrics::register(&merged)?;
+
+    let listen = match (cli.listen, &config.listen) {
+        (Some(listen), _) => listen,
+        (None, Some(listen)) => cli::parse_listen(listen)?,
+        (None, None) => DEFAULT_LISTEN.into(),
+    };
+    let listen = server::Listen {
+        address: listen,
+        #[cfg(feature = "tls")]
+        tls: config.tls.as_ref().map(tls::server_config).transpose()?,
+    };
+    let scheme = if config.tls.is_some() { "https" } else { "http" };
+    info!(
+        "Prometheus metrics are being exposed at {}://{}/metrics",
+        scheme, listen.address
+    );
+
+    let board = StatusBoard::default();
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let (alerts_tx, alerts_rx) = watch::channel(AlertConfig::default());
+    let (alerts_stop, alerts_stopped) = oneshot::channel();
+    let alerts = tokio::spawn(alerts::run(board.subscribe(), alerts_rx, alerts_stopped));
+    let (outputs_stop, outputs_stopped) = watch::channel(false);
+    let outputs = tokio::spawn(outputs::run(
+        config.outputs.clone(),
+        board.clone(),
+        outputs_stopped.clone(),
+    ));
+    let agent = config
+        .agent
+        .clone()
+        .map(|agent| tokio::spawn(agent::run(agent, outputs_stopped.clone())));
+    let history = config.history.as_ref().map(History::open).transpose()?;
+    let history_task = history
+        .clone()
+        .map(|history| tokio::spawn(history::run(history, board.subscribe(), outputs_stopped)));
+
+    let mut scheduler = Scheduler::new(metrics, board.clone(), alerts_tx, shutdown_rx.clone());
+    scheduler.apply(&merged)?;
+    let shutdown_timeout = config.shutdown_timeout;
+
+    tokio::spawn(metrics::update_system_metrics(
+        cpu_gauge,
+        load_avg_gauge,
+        mem_total_gauge,
+    ));
+    let (commands_tx, commands) = mpsc::channel(16);
+    let targets_api = match &config.api {
+        Some(api) => Some(TargetsApi {
+            token: api.token()?.unwrap_or_default(),
+            commands: commands_tx,
+        }),
+        None => None,
+    };
+    let aggregator = config
+        .aggregator
+        .as_ref()
+        .map(|aggregator| Aggregator::new(aggregator, &config.metrics))
+        .transpose()?;
+    let credentials = config.auth.as_ref().map(Credentials::new).transpose()?;
+    // Started last, a failure before this would otherwise leave the terminal in raw mode.
+    let (quit_tx, quit_rx) = oneshot::channel();
+    let tui = if cli.tui {
+        Some(tokio::spawn(tui::run(
+            tui::init()?,
+            board.clone(),
+            shutdown_rx.clone(),
+            quit_tx,
+        )))
+    } else {
+        None
+    };
+    let (current_tx, current_rx) = watch::channel(config.clone());
+    let denied = IntCounter::with_opts(config.metrics.opts(
+        "http_requests_denied_total",
+        "Requests to the HTTP server refused for coming from outside allowed_cidrs",
+    ))
+    .and_then(metrics::register_global)?;
+    let server = tokio::spawn(server::serve(
+        listen,
+        board,
+        history,
+        targets_api,
+        aggregator,
+        credentials,
+        denied,
+        current_rx,
+        shutdown_rx,
+    ));
+
+    tokio::select! {
+        _ = watch_config(&cli.config, &mut scheduler, config, discovery, commands, current_tx) => {}
+        _ = shutdown_signal() => {}
+        Ok(()) = quit_rx => {}
+    }
+
+    info!("Shutting down, waiting up to {:?} for running probes", shutdown_timeout);
+    let deadline = Instant::now() + shutdown_timeout;
+    let _ = shutdown_tx.send(true);
+    scheduler.drain(deadline).await;
+
+    // Alerts for the last probe results still go out before exiting.
+    let _ = alerts_stop.send(());
+    if timeout_at(deadline, alerts).await.is_err() {
+        warn!("Alert notifications did not finish before the shutdown timeout");
+    }
+    let _ = outputs_stop.send(true);
+    if timeout_at(deadline, outputs).await.is_err() {
+        warn!("Outputs did not finish before the shutdown timeout");
+    }
+    if let Some(agent) = agent {
+        if timeout_at(deadline, agent).await.is_err() {
+            warn!("The last push to the aggregator did not finish before the shutdown timeout");
+        }
+    }
+    if let Some(history_task) = history_task {
+        if timeout_at(deadline, history_task).await.is_err() {
+            warn!("History did not finish storing results before the shutdown timeout");
+        }
+    }
+    if timeout_at(deadline, server).await.is_err() {
+        warn!("HTTP server did not shut down before the shutdown timeout");
+    }
+    if let Some(tui) = tui {
+        let _ = tui.await;
+    }
+    info!("Shutdown complete");
+
+    Ok(())
+}
+
+// Resolves on Ctrl-C and, on Unix, on SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
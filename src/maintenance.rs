@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::cron::Cron;
+use crate::duration;
+use crate::report;
+use crate::timezone::Timezone;
+
+// A time during which an endpoint is not expected to be reachable, either recurring or once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceWindow {
+    // A cron expression for when the window starts, lasting `duration` every time.
+    pub schedule: Option<String>,
+    #[serde(default, with = "duration::option")]
+    pub duration: Option<Duration>,
+    // A single window, written as `2024-05-01 22:00`.
+    pub start: Option<String>,
+    pub end: Option<String>,
+    // The timezone `schedule`, `start` and `end` are in, UTC unless set.
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub mode: MaintenanceMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaintenanceMode {
+    // No probes are sent during the window.
+    #[default]
+    Skip,
+    // Probes are sent, but their results are marked as maintenance rather than counting towards the metrics, the
+    // up/down state, the history and alerts.
+    Mark,
+}
+
+impl MaintenanceWindow {
+    pub fn validate(&self) -> Result<(), String> {
+        Window::new(self).map(|_| ())
+    }
+}
+
+enum Times {
+    Recurring(Cron, i64),
+    Once(i64, i64),
+}
+
+struct Window {
+    times: Times,
+    timezone: Timezone,
+    mode: MaintenanceMode,
+}
+
+impl Window {
+    fn new(window: &MaintenanceWindow) -> Result<Window, String> {
+        let timezone = match &window.timezone {
+            Some(timezone) => Timezone::parse(timezone)?,
+            None => Timezone::Fixed(0),
+        };
+        let time = |value: &str| {
+            report::parse_date(value)
+                .map(|local| timezone.utc(local as i64))
+                .ok_or_else(|| format!("invalid time `{}`, expected YYYY-MM-DD HH:MM", value))
+        };
+
+        let times = match window {
+            MaintenanceWindow {
+                schedule: Some(schedule),
+                duration: Some(duration),
+                start: None,
+                end: None,
+                ..
+            } => {
+                if duration.as_secs() < 60 {
+                    return Err("duration must be at least 1m".to_string());
+                }
+                Times::Recurring(Cron::parse(schedule)?, duration.as_secs() as i64)
+            }
+            MaintenanceWindow {
+                schedule: None,
+                duration: None,
+                start: Some(start),
+                end: Some(end),
+                ..
+            } => {
+                let (start, end) = (time(start)?, time(end)?);
+                if end <= start {
+                    return Err("end must be after start".to_string());
+                }
+                Times::Once(start, end)
+            }
+            _ => return Err("set either schedule and duration, or start and end".to_string()),
+        };
+
+        Ok(Window {
+            times,
+            timezone,
+            mode: window.mode,
+        })
+    }
+
+    fn contains(&self, now: i64) -> bool {
+        match &self.times {
+            Times::Recurring(cron, duration) => cron.previous(now, now - duration, &self.timezone).is_some(),
+            Times::Once(start, end) => *start <= now && now < *end,
+        }
+    }
+}
+
+// The maintenance windows of an endpoint.
+#[derive(Default)]
+pub(crate) struct Maintenance {
+    windows: Vec<Window>,
+}
+
+impl Maintenance {
+    // Windows that do not parse are left out, the config they come from has been validated already.
+    pub(crate) fn new(windows: &[MaintenanceWindow]) -> Maintenance {
+        Maintenance {
+            windows: windows
+                .iter()
+                .filter_map(|window| Window::new(window).ok())
+                .collect(),
+        }
+    }
+
+    // The mode of the window the time falls in, skipping when it falls in windows of both modes.
+    pub(crate) fn at(&self, now: f64) -> Option<MaintenanceMode> {
+        let modes = self
+            .windows
+            .iter()
+            .filter(|window| window.contains(now as i64))
+            .map(|window| window.mode);
+        modes.reduce(|mode, other| if mode == MaintenanceMode::Skip { mode } else { other })
+    }
+}
@@ -0,0 +1,777 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use log::warn;
+use prometheus::core::{Collector, MetricVec, MetricVecBuilder};
+use prometheus::{Gauge, GaugeVec, Histogram, HistogramOpts, IntCounterVec, IntGaugeVec, Opts, Registry};
+use serde::{Deserialize, Serialize};
+use sys_info::{cpu_num, loadavg, mem_info};
+use tokio::time::Duration;
+
+use crate::config::{self, Config, Dscp, Endpoint, LABELS, RESERVED_LABELS};
+use crate::exemplars;
+
+// Like the `register_*_vec!` macros of prometheus, but with the registry to register with and the metrics section to
+// name and label them by.
+macro_rules! register {
+    ($registry:expr, $config:expr, $vec:ty, $name:expr, $help:expr, $labels:expr) => {{
+        let vec = <$vec>::new($config.opts($name, $help), $labels)?;
+        $registry.register(Box::new(vec.clone())).map(|_| vec)
+    }};
+}
+
+// How every exported series is named and labelled, so that they stay apart from those of other exporters in a shared
+// Prometheus.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    // Put in front of every metric name with an underscore, such as `netstab` for `netstab_ping_success`.
+    pub namespace: Option<String>,
+    // Labels with the same value on every series, such as the `region` or `tenant` of this instance.
+    #[serde(default)]
+    pub const_labels: BTreeMap<String, String>,
+}
+
+impl MetricsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(namespace) = &self.namespace {
+            let mut chars = namespace.chars();
+            let valid = chars
+                .next()
+                .is_some_and(|first| first.is_ascii_alphabetic() || first == '_' || first == ':')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':');
+            if !valid {
+                return Err(format!("`{}` cannot be used as a namespace", namespace));
+            }
+        }
+        // Besides those of the endpoints, `mode` labels `icmp_mode_info`, `source` and `destination` mesh endpoints,
+        // and `site` the metrics of agents.
+        for name in self.const_labels.keys() {
+            let taken = LABELS
+                .iter()
+                .chain(RESERVED_LABELS)
+                .chain(&["mode", "source", "destination", "site"])
+                .any(|taken| taken == name);
+            if !config::valid_label_name(name) || taken {
+                return Err(format!("`{}` cannot be used as a const label name", name));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn name(&self, name: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{}_{}", namespace, name),
+            None => name.to_string(),
+        }
+    }
+
+    pub(crate) fn opts(&self, name: &str, help: &str) -> Opts {
+        let opts = Opts::new(name, help).const_labels(self.const_labels.clone().into_iter().collect());
+        match &self.namespace {
+            Some(namespace) => opts.namespace(namespace.clone()),
+            None => opts,
+        }
+    }
+}
+
+// Registers a metric of the process as a whole, rather than of the endpoints, with the default registry.
+pub(crate) fn register_global<T: Collector + Clone + 'static>(metric: T) -> Result<T, prometheus::Error> {
+    prometheus::register(Box::new(metric.clone()))?;
+    Ok(metric)
+}
+
+#[derive(Clone)]
+pub struct Metrics {
+    pub(crate) extra_labels: Vec<String>,
+    pub(crate) config: MetricsConfig,
+    // Whether latency observations keep the trace of their probe as an exemplar, for the spans of the OTLP output.
+    pub(crate) exemplars: bool,
+    pub(crate) success: IntCounterVec,
+    pub(crate) fail: IntCounterVec,
+    pub(crate) timeout: IntCounterVec,
+    pub(crate) errors: IntCounterVec,
+    pub(crate) retries: IntCounterVec,
+    pub(crate) throttled: IntCounterVec,
+    pub(crate) up: IntGaugeVec,
+    pub(crate) state_changes: IntCounterVec,
+    pub(crate) consecutive_failures: IntGaugeVec,
+    pub(crate) last_success: GaugeVec,
+    pub(crate) loss_ratio: GaugeVec,
+    pub(crate) jitter: GaugeVec,
+    pub(crate) estimated_mos: GaugeVec,
+    pub(crate) cycle_rtt_min: GaugeVec,
+    pub(crate) cycle_rtt_avg: GaugeVec,
+    pub(crate) cycle_rtt_max: GaugeVec,
+    pub(crate) cycle_loss_ratio: GaugeVec,
+    pub(crate) dns_responses: IntCounterVec,
+    pub(crate) dns_answer_match: IntGaugeVec,
+    pub(crate) ntp_offset: GaugeVec,
+    pub(crate) ntp_delay: GaugeVec,
+    pub(crate) ntp_stratum: IntGaugeVec,
+    pub(crate) ssh_banner: IntGaugeVec,
+    pub(crate) grpc_health_status: IntGaugeVec,
+    pub(crate) websocket_handshake: GaugeVec,
+    pub(crate) snmp_value: GaugeVec,
+    pub(crate) snmp_value_info: IntGaugeVec,
+    pub(crate) sip_response_code: IntGaugeVec,
+    pub(crate) throughput_upload: GaugeVec,
+    pub(crate) throughput_download: GaugeVec,
+    pub(crate) throughput_failures: IntCounterVec,
+    pub(crate) payload_size: IntGaugeVec,
+    pub(crate) reply_ttl: IntGaugeVec,
+    pub(crate) path_mtu: IntGaugeVec,
+    pub(crate) traceroute_hop_count: IntGaugeVec,
+    pub(crate) traceroute_hop_rtt: GaugeVec,
+    pub(crate) traceroute_hop_loss_ratio: GaugeVec,
+    pub(crate) traceroute_hop_info: IntGaugeVec,
+    pub(crate) traceroute_hop_rtt_avg: GaugeVec,
+    pub(crate) traceroute_hop_rtt_best: GaugeVec,
+    pub(crate) traceroute_hop_rtt_worst: GaugeVec,
+    pub(crate) traceroute_hop_rtt_stddev: GaugeVec,
+    pub(crate) path_changed: IntCounterVec,
+    pub(crate) resolved_address: IntGaugeVec,
+    pub(crate) resolution_failures: IntCounterVec,
+    pub(crate) resolution_duration: GaugeVec,
+    pub(crate) in_maintenance: IntGaugeVec,
+    pub(crate) interval: GaugeVec,
+    #[cfg(feature = "tls")]
+    pub(crate) tls_handshake_duration: GaugeVec,
+    #[cfg(feature = "tls")]
+    pub(crate) tls_cert_expiry: GaugeVec,
+}
+
+impl Metrics {
+    // The user defined label names are fixed once the metrics are registered, so they are taken from the initial config.
+    pub fn register(config: &Config) -> Result<Self, prometheus::Error> {
+        let mut metrics = Metrics::register_in(config, prometheus::default_registry())?;
+        // Only the default registry is exposed on /metrics, where the exemplars are shown.
+        metrics.exemplars = config.outputs.otlp.as_ref().is_some_and(|otlp| otlp.spans);
+        Ok(metrics)
+    }
+
+    pub(crate) fn register_in(config: &Config, registry: &Registry) -> Result<Self, prometheus::Error> {
+        // The endpoints of a mesh are often only found once the other agents have pushed, after this.
+        let mesh_labels = config
+            .agent
+            .iter()
+            .filter(|agent| agent.mesh.is_some())
+            .flat_map(|_| ["source".to_string(), "destination".to_string()]);
+        let extra_labels: Vec<String> = config
+            .endpoints
+            .iter()
+            .flat_map(|endpoint| endpoint.labels.keys().cloned())
+            .chain(mesh_labels)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let names: Vec<&str> = LABELS
+            .iter()
+            .copied()
+            .chain(extra_labels.iter().map(String::as_str))
+            .collect();
+        let names = names.as_slice();
+
+        Ok(Metrics {
+            success: register!(
+                registry,
+                &config.metrics,
+                IntCounterVec,
+                "ping_success",
+                "Count of successful pings",
+                names
+            )?,
+            fail: register!(
+                registry,
+                &config.metrics,
+                IntCounterVec,
+                "ping_fail",
+                "Count of failed pings",
+                names
+            )?,
+            timeout: register!(
+                registry,
+                &config.metrics,
+                IntCounterVec,
+                "ping_timeout",
+                "Count of pings that timed out",
+                names
+            )?,
+            errors: register!(
+                registry,
+                &config.metrics,
+                IntCounterVec,
+                "probe_errors_total",
+                "Count of failed probes by reason",
+                &[names, &["reason"]].concat()
+            )?,
+            retries: register!(
+                registry,
+                &config.metrics,
+                IntCounterVec,
+                "probe_retries_total",
+                "Count of failed requests that were tried again",
+                names
+            )?,
+            throttled: register!(
+                registry,
+                &config.metrics,
+                IntCounterVec,
+                "probes_throttled_total",
+                "Count of requests held back by max_probe_rate",
+                names
+            )?,
+            up: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "probe_up",
+                "Whether the endpoint is considered up",
+                names
+            )?,
+            state_changes: register!(
+                registry,
+                &config.metrics,
+                IntCounterVec,
+                "probe_state_changes_total",
+                "Count of transitions between up and down",
+                names
+            )?,
+            consecutive_failures: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "probe_consecutive_failures",
+                "Number of probes that failed in a row",
+                names
+            )?,
+            last_success: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "probe_last_success_timestamp_seconds",
+                "Time of the last successful probe as a Unix timestamp",
+                names
+            )?,
+            loss_ratio: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "ping_loss_ratio",
+                "Ratio of failed probes over the last loss_window probes",
+                names
+            )?,
+            jitter: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "ping_jitter_seconds",
+                "Smoothed inter-probe latency variation in seconds (RFC 3550)",
+                names
+            )?,
+            estimated_mos: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "probe_estimated_mos",
+                "Mean Opinion Score estimated from the latency, jitter and loss of a voice endpoint, from 1 to 4.5",
+                names
+            )?,
+            cycle_rtt_min: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "ping_cycle_rtt_min_seconds",
+                "Lowest round trip time of the last probe cycle in seconds",
+                names
+            )?,
+            cycle_rtt_avg: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "ping_cycle_rtt_avg_seconds",
+                "Average round trip time of the last probe cycle in seconds",
+                names
+            )?,
+            cycle_rtt_max: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "ping_cycle_rtt_max_seconds",
+                "Highest round trip time of the last probe cycle in seconds",
+                names
+            )?,
+            cycle_loss_ratio: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "ping_cycle_loss_ratio",
+                "Ratio of requests of the last probe cycle that got no reply",
+                names
+            )?,
+            dns_responses: register!(
+                registry,
+                &config.metrics,
+                IntCounterVec,
+                "dns_responses",
+                "Count of DNS responses by response code",
+                &[names, &["rcode"]].concat()
+            )?,
+            dns_answer_match: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "dns_answer_match",
+                "Whether the last DNS answer contained the expected value",
+                names
+            )?,
+            ntp_offset: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "ntp_offset_seconds",
+                "How far the NTP server's clock was ahead of the local one at the last answer",
+                names
+            )?,
+            ntp_delay: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "ntp_delay_seconds",
+                "Round trip delay of the last NTP exchange, without the time the server took to answer",
+                names
+            )?,
+            ntp_stratum: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "ntp_stratum",
+                "Stratum of the NTP server at the last answer",
+                names
+            )?,
+            ssh_banner: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "ssh_banner_info",
+                "Identification string the SSH server sent at the last probe",
+                &[names, &["banner"]].concat()
+            )?,
+            grpc_health_status: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "grpc_health_status",
+                "Whether the gRPC health check last answered with the serving status",
+                &[names, &["serving_status"]].concat()
+            )?,
+            websocket_handshake: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "websocket_handshake_seconds",
+                "Time from connecting to the WebSocket upgrade being accepted at the last probe in seconds",
+                names
+            )?,
+            snmp_value: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "snmp_value",
+                "Value of the OID the SNMP probe last fetched, when it is a number",
+                names
+            )?,
+            snmp_value_info: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "snmp_value_info",
+                "Value of the OID the SNMP probe last fetched, when it is text",
+                &[names, &["value"]].concat()
+            )?,
+            sip_response_code: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "sip_response_code",
+                "Status code of the final response to the last SIP OPTIONS request",
+                names
+            )?,
+            throughput_upload: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "throughput_upload_mbps",
+                "Throughput to the iperf3 server in the last test in megabits per second",
+                names
+            )?,
+            throughput_download: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "throughput_download_mbps",
+                "Throughput from the iperf3 server in the last test in megabits per second",
+                names
+            )?,
+            throughput_failures: register!(
+                registry,
+                &config.metrics,
+                IntCounterVec,
+                "throughput_test_failures_total",
+                "Count of throughput tests that failed or timed out",
+                names
+            )?,
+            payload_size: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "probe_payload_size_bytes",
+                "ICMP payload size of the echo requests the probe sends in bytes",
+                names
+            )?,
+            reply_ttl: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "ping_reply_ttl",
+                "TTL, or hop limit over IPv6, of the last echo reply",
+                names
+            )?,
+            path_mtu: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "probe_path_mtu_bytes",
+                "Largest packet that got through to the target unfragmented in the last path MTU discovery",
+                names
+            )?,
+            traceroute_hop_count: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "traceroute_hop_count",
+                "Number of hops to the target in the last traceroute",
+                names
+            )?,
+            traceroute_hop_rtt: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "traceroute_hop_rtt_seconds",
+                "Round trip time to the hop in the last traceroute in seconds",
+                &[names, &["hop"]].concat()
+            )?,
+            traceroute_hop_loss_ratio: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "traceroute_hop_loss_ratio",
+                "Ratio of the last loss_window traceroutes the hop did not answer",
+                &[names, &["hop"]].concat()
+            )?,
+            traceroute_hop_info: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "traceroute_hop_info",
+                "Address of the hop in the last traceroute it answered",
+                &[names, &["hop", "hop_address"]].concat()
+            )?,
+            traceroute_hop_rtt_avg: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "traceroute_hop_rtt_avg_seconds",
+                "Average round trip time to the hop over the last loss_window traceroutes in seconds",
+                &[names, &["hop"]].concat()
+            )?,
+            traceroute_hop_rtt_best: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "traceroute_hop_rtt_best_seconds",
+                "Lowest round trip time to the hop over the last loss_window traceroutes in seconds",
+                &[names, &["hop"]].concat()
+            )?,
+            traceroute_hop_rtt_worst: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "traceroute_hop_rtt_worst_seconds",
+                "Highest round trip time to the hop over the last loss_window traceroutes in seconds",
+                &[names, &["hop"]].concat()
+            )?,
+            traceroute_hop_rtt_stddev: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "traceroute_hop_rtt_stddev_seconds",
+                "Standard deviation of the round trip time to the hop over the last loss_window traceroutes in seconds",
+                &[names, &["hop"]].concat()
+            )?,
+            path_changed: register!(
+                registry,
+                &config.metrics,
+                IntCounterVec,
+                "path_changed_total",
+                "Count of traceroutes that found a different path than the one before",
+                names
+            )?,
+            resolved_address: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "probe_resolved_address_info",
+                "Address the endpoint's host currently resolves to",
+                &[names, &["resolved_address"]].concat()
+            )?,
+            resolution_failures: register!(
+                registry,
+                &config.metrics,
+                IntCounterVec,
+                "probe_resolution_failures_total",
+                "Count of failed attempts to resolve the endpoint's host",
+                names
+            )?,
+            resolution_duration: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "probe_dns_resolution_seconds",
+                "Duration of the last lookup of the endpoint's host in seconds",
+                names
+            )?,
+            in_maintenance: register!(
+                registry,
+                &config.metrics,
+                IntGaugeVec,
+                "probe_in_maintenance",
+                "Whether the endpoint is in a maintenance window",
+                names
+            )?,
+            interval: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "probe_interval_seconds",
+                "Interval an adaptive endpoint is currently probed at in seconds",
+                names
+            )?,
+            #[cfg(feature = "tls")]
+            tls_handshake_duration: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "tls_handshake_duration_seconds",
+                "Duration of the last TLS handshake in seconds",
+                names
+            )?,
+            #[cfg(feature = "tls")]
+            tls_cert_expiry: register!(
+                registry,
+                &config.metrics,
+                GaugeVec,
+                "tls_cert_expiry_timestamp_seconds",
+                "Expiry of the leaf certificate as a Unix timestamp",
+                names
+            )?,
+            extra_labels,
+            config: config.metrics.clone(),
+            exemplars: false,
+        })
+    }
+
+    pub(crate) fn remove(&self, labels: &[&str]) {
+        exemplars::remove(&self.latency_labels(labels));
+        remove_series(&self.success, labels);
+        remove_series(&self.fail, labels);
+        remove_series(&self.timeout, labels);
+        remove_series(&self.errors, labels);
+        remove_series(&self.retries, labels);
+        remove_series(&self.throttled, labels);
+        remove_series(&self.up, labels);
+        remove_series(&self.state_changes, labels);
+        remove_series(&self.consecutive_failures, labels);
+        remove_series(&self.last_success, labels);
+        remove_series(&self.loss_ratio, labels);
+        remove_series(&self.jitter, labels);
+        remove_series(&self.estimated_mos, labels);
+        remove_series(&self.cycle_rtt_min, labels);
+        remove_series(&self.cycle_rtt_avg, labels);
+        remove_series(&self.cycle_rtt_max, labels);
+        remove_series(&self.cycle_loss_ratio, labels);
+        remove_series(&self.dns_responses, labels);
+        remove_series(&self.dns_answer_match, labels);
+        remove_series(&self.ntp_offset, labels);
+        remove_series(&self.ntp_delay, labels);
+        remove_series(&self.ntp_stratum, labels);
+        remove_series(&self.ssh_banner, labels);
+        remove_series(&self.grpc_health_status, labels);
+        remove_series(&self.websocket_handshake, labels);
+        remove_series(&self.snmp_value, labels);
+        remove_series(&self.snmp_value_info, labels);
+        remove_series(&self.sip_response_code, labels);
+        remove_series(&self.throughput_upload, labels);
+        remove_series(&self.throughput_download, labels);
+        remove_series(&self.throughput_failures, labels);
+        remove_series(&self.payload_size, labels);
+        remove_series(&self.reply_ttl, labels);
+        remove_series(&self.path_mtu, labels);
+        remove_series(&self.traceroute_hop_count, labels);
+        remove_series(&self.traceroute_hop_rtt, labels);
+        remove_series(&self.traceroute_hop_loss_ratio, labels);
+        remove_series(&self.traceroute_hop_info, labels);
+        remove_series(&self.traceroute_hop_rtt_avg, labels);
+        remove_series(&self.traceroute_hop_rtt_best, labels);
+        remove_series(&self.traceroute_hop_rtt_worst, labels);
+        remove_series(&self.traceroute_hop_rtt_stddev, labels);
+        remove_series(&self.path_changed, labels);
+        remove_series(&self.resolved_address, labels);
+        remove_series(&self.resolution_failures, labels);
+        remove_series(&self.resolution_duration, labels);
+        remove_series(&self.in_maintenance, labels);
+        remove_series(&self.interval, labels);
+        #[cfg(feature = "tls")]
+        remove_series(&self.tls_handshake_duration, labels);
+        #[cfg(feature = "tls")]
+        remove_series(&self.tls_cert_expiry, labels);
+    }
+
+    // Label values in the order the metrics were registered with, leaving out labels that were added after startup.
+    pub(crate) fn labels<'a>(&self, endpoint: &'a Endpoint) -> Vec<&'a str> {
+        let mut labels = vec![
+            endpoint.name.as_str(),
+            endpoint.address.as_str(),
+            endpoint.location.as_deref().unwrap_or_default(),
+            endpoint.probe_type().as_str(),
+            endpoint
+                .ip_version
+                .map_or("", |ip_version| ip_version.as_str()),
+            endpoint.dscp.first().map_or("", Dscp::as_str),
+        ];
+        labels.extend(
+            self.extra_labels
+                .iter()
+                .map(|name| endpoint.labels.get(name).map_or("", String::as_str)),
+        );
+        labels
+    }
+
+    // The names of the labels of every series, in the order `labels` gives their values in.
+    pub(crate) fn label_names(&self) -> Vec<&str> {
+        LABELS
+            .iter()
+            .copied()
+            .chain(self.extra_labels.iter().map(String::as_str))
+            .collect()
+    }
+
+    // The labels of the latency histogram of an endpoint, those of the metrics section included.
+    pub(crate) fn latency_labels<'a>(&'a self, labels: &[&'a str]) -> Vec<(&'a str, &'a str)> {
+        self.label_names()
+            .into_iter()
+            .zip(labels.iter().copied())
+            .chain(
+                self.config
+                    .const_labels
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str())),
+            )
+            .collect()
+    }
+
+    // Each endpoint gets its own histogram, labelled with constant labels, so buckets can differ between endpoints.
+    pub(crate) fn register_latency(&self, labels: &[&str], buckets: Vec<f64>) -> Result<Histogram, prometheus::Error> {
+        let const_labels = self
+            .latency_labels(labels)
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let opts = HistogramOpts::from(self.config.opts("ping_latency", "Ping latency in seconds"))
+            .const_labels(const_labels)
+            .buckets(buckets);
+
+        let histogram = Histogram::with_opts(opts)?;
+        prometheus::register(Box::new(histogram.clone()))?;
+        Ok(histogram)
+    }
+}
+
+// Removes every series of `vec` belonging to the endpoint, whatever values its extra labels have.
+pub(crate) fn remove_series<T: MetricVecBuilder>(vec: &MetricVec<T>, labels: &[&str]) {
+    // Samples carry the const labels of the metrics section too, which `remove` does not take.
+    let variable_labels: Vec<String> = vec
+        .desc()
+        .iter()
+        .flat_map(|desc| desc.variable_labels.clone())
+        .collect();
+    for family in vec.collect() {
+        for metric in family.get_metric() {
+            let pairs: HashMap<&str, &str> = metric
+                .get_label()
+                .iter()
+                .filter(|pair| variable_labels.iter().any(|name| name == pair.get_name()))
+                .map(|pair| (pair.get_name(), pair.get_value()))
+                .collect();
+            if LABELS
+                .iter()
+                .zip(labels)
+                .all(|(name, value)| pairs.get(name) == Some(value))
+            {
+                if let Err(err) = vec.remove(&pairs) {
+                    warn!("Failed to remove a series of {}: {}", family.get_name(), err);
+                }
+            }
+        }
+    }
+}
+
+pub async fn update_system_metrics(cpu_gauge: Gauge, load_avg_gauge: Gauge, mem_total_gauge: Gauge) {
+    loop {
+        if let Ok(cpu_count) = cpu_num() {
+            cpu_gauge.set(cpu_count as f64);
+        }
+
+        if let Ok(load) = loadavg() {
+            load_avg_gauge.set(load.one);
+        }
+
+        if let Ok(mem) = mem_info() {
+            mem_total_gauge.set(mem.total as f64);
+        }
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Format};
+
+    #[test]
+    fn removes_series_with_const_labels() {
+        let config = config::parse(
+            "metrics:\n  const_labels:\n    tenant: acme\nendpoints:\n  - name: gateway\n    address: 10.0.0.1\n",
+            Format::Yaml,
+        )
+        .unwrap();
+        let registry = Registry::new();
+        let metrics = Metrics::register_in(&config, &registry).unwrap();
+        let labels = metrics.labels(&config.endpoints[0]);
+        metrics.success.with_label_values(&labels).inc();
+        metrics.up.with_label_values(&labels).set(1);
+        let samples = |name: &str| -> usize {
+            registry
+                .gather()
+                .iter()
+                .filter(|family| family.get_name() == name)
+                .map(|family| family.get_metric().len())
+                .sum()
+        };
+        assert_eq!(samples("ping_success"), 1);
+
+        metrics.remove(&labels);
+        assert_eq!(samples("ping_success"), 0);
+        assert_eq!(samples("probe_up"), 0);
+    }
+}
@@ -0,0 +1,120 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::source::Source;
+use crate::BoxError;
+
+pub(crate) const MQTT_PORT: u16 = 1883;
+pub(crate) const MQTTS_PORT: u16 = 8883;
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const DISCONNECT: u8 = 0xe0;
+const PROTOCOL_LEVEL: u8 = 4;
+const CLEAN_SESSION: u8 = 0x02;
+const PASSWORD: u8 = 0x40;
+const USERNAME: u8 = 0x80;
+const KEEP_ALIVE: u16 = 30;
+
+// Sends an MQTT 3.1.1 CONNECT and returns how long the broker took to accept it with a CONNACK, leaving out connecting
+// and the TLS handshake. With `tls` the certificate is verified against `host` unless `verify` is off.
+pub async fn connect(
+    server: SocketAddr,
+    host: &str,
+    tls: bool,
+    verify: bool,
+    credentials: Option<(&str, Option<String>)>,
+    source: &Source,
+) -> Result<Duration, BoxError> {
+    let request = connect_packet(credentials);
+    let stream = source.connect(server).await?;
+    if tls {
+        connect_tls(stream, host, verify, &request).await
+    } else {
+        exchange(stream, &request).await
+    }
+}
+
+#[cfg(feature = "tls")]
+async fn connect_tls(stream: TcpStream, host: &str, verify: bool, request: &[u8]) -> Result<Duration, BoxError> {
+    exchange(crate::tls::connect(stream, host, verify).await?, request).await
+}
+
+#[cfg(not(feature = "tls"))]
+async fn connect_tls(_stream: TcpStream, _host: &str, _verify: bool, _request: &[u8]) -> Result<Duration, BoxError> {
+    Err("tls needs rust-net-stab to be built with the `tls` feature".into())
+}
+
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, request: &[u8]) -> Result<Duration, BoxError> {
+    let start = Instant::now();
+    stream.write_all(request).await?;
+
+    let mut connack = [0u8; 4];
+    stream
+        .read_exact(&mut connack)
+        .await
+        .map_err(|_| "the broker closed the connection without a CONNACK")?;
+    let elapsed = start.elapsed();
+    if connack[0] != CONNACK || connack[1] != 2 {
+        return Err("the broker did not answer with a CONNACK".into());
+    }
+
+    match connack[3] {
+        0 => {
+            let _ = stream.write_all(&[DISCONNECT, 0]).await;
+            Ok(elapsed)
+        }
+        1 => Err("the broker does not accept MQTT 3.1.1".into()),
+        2 => Err("the broker rejected the client identifier".into()),
+        3 => Err("the broker is unavailable".into()),
+        4 => Err("the broker rejected the username or password".into()),
+        5 => Err("the broker did not authorize the client".into()),
+        code => Err(format!("the broker refused the connection with code {}", code).into()),
+    }
+}
+
+// A clean session under a random client identifier, since some brokers refuse an empty one.
+fn connect_packet(credentials: Option<(&str, Option<String>)>) -> Vec<u8> {
+    let mut flags = CLEAN_SESSION;
+    let mut body = Vec::new();
+    string(b"MQTT", &mut body);
+    body.push(PROTOCOL_LEVEL);
+    let flags_at = body.len();
+    body.push(0);
+    body.extend_from_slice(&KEEP_ALIVE.to_be_bytes());
+
+    string(
+        format!("rust-net-stab-{:08x}", rand::random::<u32>()).as_bytes(),
+        &mut body,
+    );
+    if let Some((username, password)) = credentials {
+        flags |= USERNAME;
+        string(username.as_bytes(), &mut body);
+        if let Some(password) = password {
+            flags |= PASSWORD;
+            string(password.as_bytes(), &mut body);
+        }
+    }
+    body[flags_at] = flags;
+
+    let mut packet = vec![CONNECT];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend_from_slice(&body);
+    packet
+}
+
+fn string(value: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
@@ -0,0 +1,99 @@
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::source::Source;
+use crate::BoxError;
+
+pub(crate) const NTP_PORT: u16 = 123;
+// Seconds from the NTP epoch, 1900, to the Unix one.
+const UNIX_OFFSET: f64 = 2_208_988_800.0;
+const ERA: f64 = 4_294_967_296.0;
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+const LEAP_ALARM: u8 = 3;
+
+// What an SNTP exchange found out about the server's clock.
+#[derive(Debug)]
+pub struct Sample {
+    // How far the server's clock is ahead of ours, in seconds.
+    pub offset: f64,
+    // The round trip time without the time the server took to answer.
+    pub delay: Duration,
+    pub stratum: u8,
+}
+
+// Sends an SNTP client request as described in RFC 4330 and works out the offset and delay from the four timestamps
+// of the exchange.
+pub async fn query(server: SocketAddr, source: &Source) -> Result<Sample, BoxError> {
+    let socket = source.udp(server).await?;
+    socket.connect(server).await?;
+
+    let mut request = [0u8; 48];
+    request[0] = VERSION << 3 | MODE_CLIENT;
+    let sent = now();
+    // The server copies the transmit timestamp into its answer, which tells its answer apart from stray ones.
+    let transmit = timestamp(sent);
+    request[40..48].copy_from_slice(&transmit.to_be_bytes());
+    socket.send(&request).await?;
+
+    let mut buffer = [0u8; 1024];
+    loop {
+        let len = socket.recv(&mut buffer).await?;
+        let received = now();
+        let message = &buffer[..len];
+        if message.len() < 48 || message[24..32] != transmit.to_be_bytes() {
+            continue;
+        }
+
+        let leap = message[0] >> 6;
+        let mode = message[0] & 0x07;
+        let stratum = message[1];
+        if mode != MODE_SERVER {
+            continue;
+        }
+        if stratum == 0 {
+            // A kiss-o'-death packet, its reference identifier an ASCII code such as RATE or DENY.
+            let code = String::from_utf8_lossy(&message[12..16]);
+            return Err(format!("the server refused to answer: {}", code.trim_end_matches('\0')).into());
+        }
+        if leap == LEAP_ALARM {
+            return Err("the server's clock is not synchronized".into());
+        }
+
+        let read = |at: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&message[at..at + 8]);
+            unix(u64::from_be_bytes(bytes), sent)
+        };
+        let (server_received, server_sent) = (read(32), read(40));
+        let offset = ((server_received - sent) + (server_sent - received)) / 2.0;
+        let delay = (received - sent) - (server_sent - server_received);
+        return Ok(Sample {
+            offset,
+            delay: Duration::from_secs_f64(delay.max(0.0)),
+            stratum,
+        });
+    }
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+// Seconds since the NTP epoch in the upper 32 bits and the fraction of a second in the lower ones.
+fn timestamp(unix: f64) -> u64 {
+    let ntp = (unix + UNIX_OFFSET).rem_euclid(ERA);
+    let seconds = ntp.trunc();
+    (seconds as u64) << 32 | ((ntp - seconds) * ERA) as u64
+}
+
+// Timestamps wrap around every 136 years, so they are taken to be from the era closest to `near`.
+fn unix(timestamp: u64, near: f64) -> f64 {
+    let ntp = (timestamp >> 32) as f64 + (timestamp & 0xffff_ffff) as f64 / ERA;
+    let unix = ntp - UNIX_OFFSET;
+    unix + ((near - unix) / ERA).round() * ERA
+}
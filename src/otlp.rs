@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+
+use log::{error, warn};
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tokio::time::{Duration, MissedTickBehavior};
+
+use crate::status::{self, ProbeEvent};
+use crate::{duration, http};
+
+// Spans waiting for the next export beyond this are dropped, so an unreachable collector cannot use up the memory.
+const MAX_PENDING_SPANS: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OtlpConfig {
+    // The base url of an OTLP/HTTP receiver, `/v1/metrics` and `/v1/traces` are appended to it.
+    pub endpoint: String,
+    #[serde(default = "default_interval", with = "duration")]
+    pub interval: Duration,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub spans: bool,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(15)
+}
+
+fn default_service_name() -> String {
+    "rust-net-stab".to_string()
+}
+
+impl OtlpConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        http::parse_url(&self.endpoint).map_err(|err| err.to_string())?;
+        if self.interval.is_zero() {
+            return Err("interval must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.endpoint.trim_end_matches('/'), path)
+    }
+}
+
+// Exports every metric, and with `spans` every probe result, each `interval` until `stop` is set.
+pub async fn run(config: OtlpConfig, mut events: broadcast::Receiver<ProbeEvent>, mut stop: watch::Receiver<bool>) {
+    let start = nanos(status::unix_time());
+    let mut spans = Vec::new();
+    let mut subscribed = config.spans;
+    let mut ticker = tokio::time::interval(config.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker.tick().await;
+
+    loop {
+        let stopping = tokio::select! {
+            _ = ticker.tick() => false,
+            event = events.recv(), if subscribed => {
+                match event {
+                    Ok(event) => push(&mut spans, &event),
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("OTLP export fell behind and skipped {} probe results", skipped);
+                    }
+                    Err(RecvError::Closed) => subscribed = false,
+                }
+                continue;
+            }
+            _ = stop.changed() => true,
+        };
+
+        // Results published by the last probes still go out with the final export.
+        if stopping && config.spans {
+            while let Ok(event) = events.try_recv() {
+                push(&mut spans, &event);
+            }
+        }
+        export(&config, "v1/metrics", metrics(&config, &start)).await;
+        if !spans.is_empty() {
+            export(&config, "v1/traces", traces(&config, std::mem::take(&mut spans))).await;
+        }
+        if stopping {
+            break;
+        }
+    }
+}
+
+fn push(spans: &mut Vec<Value>, event: &ProbeEvent) {
+    if spans.len() < MAX_PENDING_SPANS {
+        spans.push(span(event));
+    }
+}
+
+async fn export(config: &OtlpConfig, path: &str, body: Value) {
+    let url = config.url(path);
+    let body = serde_json::to_vec(&body).unwrap_or_default();
+    if let Err(err) = http::post(&url, "application/json", &config.headers, body).await {
+        error!("Failed to export to {}: {}", url, err);
+    }
+}
+
+fn resource(config: &OtlpConfig) -> Value {
+    json!({ "attributes": [attribute("service.name", json!({ "stringValue": config.service_name }))] })
+}
+
+fn scope() -> Value {
+    json!({ "name": "rust-net-stab", "version": env!("CARGO_PKG_VERSION") })
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+// OTLP/JSON writes 64 bit integers as strings.
+fn nanos(seconds: f64) -> String {
+    ((seconds * 1e9) as u64).to_string()
+}
+
+fn metrics(config: &OtlpConfig, start: &str) -> Value {
+    let now = nanos(status::unix_time());
+    let metrics: Vec<Value> = prometheus::gather()
+        .iter()
+        .filter_map(|family| metric(family, start, &now))
+        .collect();
+
+    json!({
+        "resourceMetrics": [{
+            "resource": resource(config),
+            "scopeMetrics": [{ "scope": scope(), "metrics": metrics }],
+        }]
+    })
+}
+
+// Counters become cumulative sums and histograms keep their buckets, which OTLP counts per bucket rather than
+// cumulatively.
+fn metric(family: &MetricFamily, start: &str, now: &str) -> Option<Value> {
+    let points = |value: &dyn Fn(&Metric) -> Value| -> Vec<Value> {
+        family
+            .get_metric()
+            .iter()
+            .map(|metric| {
+                let mut point = value(metric);
+                point["attributes"] = metric
+                    .get_label()
+                    .iter()
+                    .map(|pair| attribute(pair.get_name(), json!({ "stringValue": pair.get_value() })))
+                    .collect();
+                point["startTimeUnixNano"] = json!(start);
+                point["timeUnixNano"] = json!(now);
+                point
+            })
+            .collect()
+    };
+
+    let data = match family.get_field_type() {
+        MetricType::GAUGE => json!({
+            "gauge": { "dataPoints": points(&|metric| json!({ "asDouble": metric.get_gauge().get_value() })) }
+        }),
+        MetricType::COUNTER => json!({
+            "sum": {
+                "aggregationTemporality": 2,
+                "isMonotonic": true,
+                "dataPoints": points(&|metric| json!({ "asDouble": metric.get_counter().get_value() })),
+            }
+        }),
+        MetricType::HISTOGRAM => json!({
+            "histogram": {
+                "aggregationTemporality": 2,
+                "dataPoints": points(&|metric| {
+                    let histogram = metric.get_histogram();
+                    let mut previous = 0;
+                    let mut counts: Vec<String> = histogram
+                        .get_bucket()
+                        .iter()
+                        .map(|bucket| {
+                            let count = bucket.get_cumulative_count() - previous;
+                            previous = bucket.get_cumulative_count();
+                            count.to_string()
+                        })
+                        .collect();
+                    counts.push((histogram.get_sample_count() - previous).to_string());
+                    let bounds: Vec<f64> = histogram
+                        .get_bucket()
+                        .iter()
+                        .map(|bucket| bucket.get_upper_bound())
+                        .collect();
+                    json!({
+                        "count": histogram.get_sample_count().to_string(),
+                        "sum": histogram.get_sample_sum(),
+                        "bucketCounts": counts,
+                        "explicitBounds": bounds,
+                    })
+                }),
+            }
+        }),
+        _ => return None,
+    };
+
+    let mut metric = json!({ "name": family.get_name(), "description": family.get_help() });
+    metric.as_object_mut()?.extend(data.as_object()?.clone());
+    Some(metric)
+}
+
+// A client span per probe result, ending when the result was recorded and lasting as long as the round trip.
+fn span(event: &ProbeEvent) -> Value {
+    let end = event.timestamp;
+    let start = end - event.rtt_seconds.unwrap_or_default();
+    let mut attributes = vec![
+        attribute("endpoint.name", json!({ "stringValue": event.name })),
+        attribute("endpoint.address", json!({ "stringValue": event.address })),
+        attribute("probe.type", json!({ "stringValue": event.probe_type })),
+        attribute("probe.up", json!({ "boolValue": event.up })),
+        attribute("probe.loss_ratio", json!({ "doubleValue": event.loss_ratio })),
+    ];
+    if let Some(location) = &event.location {
+        attributes.push(attribute("endpoint.location", json!({ "stringValue": location })));
+    }
+    if let Some(ip_version) = event.ip_version {
+        attributes.push(attribute("network.type", json!({ "stringValue": ip_version })));
+    }
+    let status = match &event.error {
+        Some(error) => json!({ "code": 2, "message": error }),
+        None => json!({ "code": 1 }),
+    };
+
+    json!({
+        "traceId": event
+            .trace_id
+            .clone()
+            .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>())),
+        "spanId": format!("{:016x}", rand::random::<u64>()),
+        "name": format!("probe {}", event.probe_type),
+        "kind": 3,
+        "startTimeUnixNano": nanos(start),
+        "endTimeUnixNano": nanos(end),
+        "attributes": attributes,
+        "status": status,
+    })
+}
+
+fn traces(config: &OtlpConfig, spans: Vec<Value>) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": resource(config),
+            "scopeSpans": [{ "scope": scope(), "spans": spans }],
+        }]
+    })
+}
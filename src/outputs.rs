@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+use crate::file_output::{self, FileConfig};
+use crate::influxdb::{self, InfluxDbConfig};
+use crate::otlp::{self, OtlpConfig};
+use crate::pushgateway::{self, PushgatewayConfig};
+use crate::remote_write::{self, RemoteWriteConfig};
+use crate::statsd::{self, GraphiteConfig, StatsdConfig};
+use crate::status::StatusBoard;
+
+// Where probe results are sent besides the metrics endpoint.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Outputs {
+    pub otlp: Option<OtlpConfig>,
+    pub influxdb: Option<InfluxDbConfig>,
+    pub remote_write: Option<RemoteWriteConfig>,
+    pub pushgateway: Option<PushgatewayConfig>,
+    pub statsd: Option<StatsdConfig>,
+    pub graphite: Option<GraphiteConfig>,
+    pub file: Option<FileConfig>,
+}
+
+impl Outputs {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(otlp) = &self.otlp {
+            otlp.validate().map_err(|err| format!("otlp: {}", err))?;
+        }
+        if let Some(influxdb) = &self.influxdb {
+            influxdb
+                .validate()
+                .map_err(|err| format!("influxdb: {}", err))?;
+        }
+        if let Some(remote_write) = &self.remote_write {
+            remote_write
+                .validate()
+                .map_err(|err| format!("remote_write: {}", err))?;
+        }
+        if let Some(pushgateway) = &self.pushgateway {
+            pushgateway
+                .validate()
+                .map_err(|err| format!("pushgateway: {}", err))?;
+        }
+        if let Some(statsd) = &self.statsd {
+            statsd
+                .validate()
+                .map_err(|err| format!("statsd: {}", err))?;
+        }
+        if let Some(graphite) = &self.graphite {
+            graphite
+                .validate()
+                .map_err(|err| format!("graphite: {}", err))?;
+        }
+        if let Some(file) = &self.file {
+            file.validate().map_err(|err| format!("file: {}", err))?;
+        }
+
+        Ok(())
+    }
+}
+
+// Runs every configured output until `stop` is set, after which each sends what it has left.
+pub async fn run(outputs: Outputs, board: StatusBoard, stop: watch::Receiver<bool>) {
+    let mut tasks = JoinSet::new();
+    if let Some(config) = outputs.otlp {
+        tasks.spawn(otlp::run(config, board.subscribe(), stop.clone()));
+    }
+    if let Some(config) = outputs.influxdb {
+        tasks.spawn(influxdb::run(config, board.subscribe(), stop.clone()));
+    }
+    if let Some(config) = outputs.remote_write {
+        tasks.spawn(remote_write::run(config, stop.clone()));
+    }
+    if let Some(config) = outputs.pushgateway {
+        tasks.spawn(pushgateway::run(config, stop.clone()));
+    }
+    if let Some(config) = outputs.statsd {
+        tasks.spawn(statsd::run_statsd(config, board.subscribe(), stop.clone()));
+    }
+    if let Some(config) = outputs.graphite {
+        tasks.spawn(statsd::run_graphite(config, board.subscribe(), stop.clone()));
+    }
+    if let Some(config) = outputs.file {
+        tasks.spawn(file_output::run(config, board.subscribe(), stop.clone()));
+    }
+
+    while tasks.join_next().await.is_some() {}
+}
@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::io::AsRawFd;
+use std::process::Stdio;
+use std::ptr;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use socket2::{SockAddr, Socket, Type};
+use tokio::io::unix::AsyncFd;
+use tokio::sync::oneshot;
+
+use crate::icmp::{self, Echo, Mode, ECHO_REPLY_V4, ECHO_REPLY_V6};
+use crate::source::{self, Source};
+
+const DEST_UNREACHABLE_V4: u8 = 3;
+const DEST_UNREACHABLE_V6: u8 = 1;
+const RECEIVE_BUFFER: usize = 1 << 20;
+
+// Echo requests of all probes go out over shared sockets, one for each IP version and source, and a task per socket
+// hands the replies to whoever waits for them. Sockets stay open once opened, so probing thousands of endpoints does
+// not open and close a socket for every request.
+static PINGERS: Mutex<Vec<Arc<Pinger>>> = Mutex::new(Vec::new());
+
+// When the reply to a request arrived and with what TTL, or why it will not.
+type Answer = oneshot::Sender<io::Result<(Instant, Option<u8>)>>;
+
+struct Pinger {
+    socket: AsyncFd<Socket>,
+    ipv4: bool,
+    source: Source,
+    raw: bool,
+    identifier: u16,
+    sequence: AtomicU16,
+    // The requests sent and not answered yet by target and sequence number, with where to send the answer.
+    waiting: Mutex<HashMap<(IpAddr, u16), Answer>>,
+}
+
+// Returns the round trip time, measured from sending the request to receiving the matching reply.
+pub(crate) async fn echo(ip: IpAddr, size: usize, timeout: Duration, source: &Source) -> io::Result<Echo> {
+    let pinger = pinger(ip, source)?;
+    let sequence = pinger.sequence.fetch_add(1, Ordering::Relaxed);
+    let (sender, receiver) = oneshot::channel();
+    let _waiting = Waiting::new(&pinger, (ip, sequence), sender);
+    let request = icmp::echo_request_sized(ip, pinger.identifier, sequence, 8 + size);
+    let target: SockAddr = SocketAddr::new(ip, 0).into();
+
+    let start = Instant::now();
+    loop {
+        let mut guard = pinger.socket.writable().await?;
+        if let Ok(sent) = guard.try_io(|socket| socket.get_ref().send_to(&request, &target)) {
+            sent.map_err(|err| match err.raw_os_error() {
+                // Only sent with the don't fragment bit set.
+                Some(libc::EMSGSIZE) => io::Error::other("the echo request is larger than the path MTU"),
+                _ => err,
+            })?;
+            break;
+        }
+    }
+
+    match tokio::time::timeout(timeout, receiver).await {
+        Ok(Ok(received)) => received.map(|(received, ttl)| Echo {
+            rtt: received.saturating_duration_since(start),
+            ttl,
+        }),
+        Ok(Err(_)) => Err(io::Error::other("the ICMP socket was closed")),
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "echo request timed out")),
+    }
+}
+
+// Takes the request off the waiting list however the wait for its reply ends.
+struct Waiting<'a> {
+    pinger: &'a Pinger,
+    key: (IpAddr, u16),
+}
+
+impl<'a> Waiting<'a> {
+    fn new(pinger: &'a Pinger, key: (IpAddr, u16), sender: Answer) -> Self {
+        pinger.waiting.lock().unwrap().insert(key, sender);
+        Waiting { pinger, key }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.pinger.waiting.lock().unwrap().remove(&self.key);
+    }
+}
+
+// Opening a socket that fails, say for lack of permission, is tried again by the next request.
+fn pinger(ip: IpAddr, source: &Source) -> io::Result<Arc<Pinger>> {
+    let mut pingers = PINGERS.lock().unwrap();
+    if let Some(pinger) = pingers
+        .iter()
+        .find(|pinger| pinger.ipv4 == ip.is_ipv4() && pinger.source == *source)
+    {
+        return Ok(pinger.clone());
+    }
+
+    let (socket, raw) = open_socket(ip)?;
+    source.bind(&socket, ip)?;
+    socket.set_nonblocking(true)?;
+    // Replies come with their TTL, or hop limit, alongside.
+    match ip {
+        IpAddr::V4(_) => source::set_option((&socket).into(), libc::IPPROTO_IP, libc::IP_RECVTTL, 1)?,
+        IpAddr::V6(_) => source::set_option((&socket).into(), libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?,
+    }
+    // Replies to many requests sent at once arrive together.
+    let _ = socket.set_recv_buffer_size(RECEIVE_BUFFER);
+    let pinger = Arc::new(Pinger {
+        socket: AsyncFd::new(socket)?,
+        ipv4: ip.is_ipv4(),
+        source: source.clone(),
+        raw,
+        identifier: icmp::next_identifier(),
+        sequence: AtomicU16::new(0),
+        waiting: Mutex::default(),
+    });
+    tokio::spawn(receive(pinger.clone()));
+    pingers.push(pinger.clone());
+    Ok(pinger)
+}
+
+async fn receive(pinger: Arc<Pinger>) {
+    let mut buffer = [0u8; 1500];
+    loop {
+        let received = match pinger.socket.readable().await {
+            Ok(mut guard) => match guard.try_io(|socket| recv_with_ttl(socket.get_ref(), &mut buffer)) {
+                Ok(received) => received,
+                Err(_) => continue,
+            },
+            Err(err) => Err(err),
+        };
+        let at = Instant::now();
+        match received {
+            Ok((len, from, ttl)) => {
+                if let Some(from) = from.as_socket() {
+                    pinger.dispatch(from.ip(), &buffer[..len], at, ttl);
+                }
+            }
+            Err(err) => {
+                warn!("Failed to receive from an ICMP socket: {}", err);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+impl Pinger {
+    fn dispatch(&self, from: IpAddr, packet: &[u8], at: Instant, ttl: Option<u8>) {
+        let (key, answer) = if let Some((identifier, sequence)) = echo_reply(from, self.raw, packet) {
+            // Datagram sockets rewrite the identifier and only deliver replies for this socket.
+            if self.raw && identifier != self.identifier {
+                return;
+            }
+            ((from, sequence), Ok((at, ttl)))
+        } else if let Some((target, identifier, sequence)) = unreachable(from, packet).filter(|_| self.raw) {
+            if identifier != self.identifier {
+                return;
+            }
+            (
+                (target, sequence),
+                Err(io::Error::new(
+                    io::ErrorKind::HostUnreachable,
+                    "destination unreachable",
+                )),
+            )
+        } else {
+            return;
+        };
+
+        if let Some(sender) = self.waiting.lock().unwrap().remove(&key) {
+            let _ = sender.send(answer);
+        }
+    }
+}
+
+// Receives like `recv_from`, along with the TTL or hop limit of the packet when the socket was asked for it.
+fn recv_with_ttl(socket: &Socket, buffer: &mut [u8]) -> io::Result<(usize, SockAddr, Option<u8>)> {
+    // Aligned for the control message headers.
+    let mut control = [0u64; 16];
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+    // SAFETY: the message points at the buffers above, which outlive the call, with their lengths, and `init` hands
+    // over storage for the address along with its length.
+    let ((len, ttl), from) = unsafe {
+        SockAddr::init(|storage, storage_len| {
+            let mut message: libc::msghdr = mem::zeroed();
+            message.msg_name = storage as *mut libc::c_void;
+            message.msg_namelen = *storage_len;
+            message.msg_iov = &mut iov;
+            message.msg_iovlen = 1;
+            message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            message.msg_controllen = mem::size_of_val(&control) as _;
+            let len = libc::recvmsg(socket.as_raw_fd(), &mut message, 0);
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            *storage_len = message.msg_namelen;
+            Ok((len as usize, ttl(&message)))
+        })?
+    };
+    Ok((len, from, ttl))
+}
+
+// Linux hands the TTL over as an int in an IP_TTL message, the BSDs as a byte in an IP_RECVTTL one.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const TTL_MESSAGE: (libc::c_int, bool) = (libc::IP_TTL, true);
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const TTL_MESSAGE: (libc::c_int, bool) = (libc::IP_RECVTTL, false);
+
+// SAFETY: the message must have been filled in by `recvmsg`.
+unsafe fn ttl(message: &libc::msghdr) -> Option<u8> {
+    let mut header = libc::CMSG_FIRSTHDR(message);
+    while !header.is_null() {
+        let data = libc::CMSG_DATA(header);
+        match ((*header).cmsg_level, (*header).cmsg_type) {
+            (libc::IPPROTO_IP, kind) if kind == TTL_MESSAGE.0 && TTL_MESSAGE.1 => {
+                return Some(ptr::read_unaligned(data as *const libc::c_int) as u8)
+            }
+            (libc::IPPROTO_IP, kind) if kind == TTL_MESSAGE.0 => return Some(*data),
+            (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                return Some(ptr::read_unaligned(data as *const libc::c_int) as u8)
+            }
+            _ => header = libc::CMSG_NXTHDR(message, header),
+        }
+    }
+    None
+}
+
+fn open_socket(ip: IpAddr) -> io::Result<(Socket, bool)> {
+    let (domain, protocol) = icmp::domain(ip);
+    let raw = icmp::mode() == Mode::Raw;
+    let kind = if raw { Type::RAW } else { Type::DGRAM };
+    Socket::new(domain, kind, Some(protocol)).map(|socket| (socket, raw))
+}
+
+// The system `ping` could not be run, or failed with an error of its own rather than for lack of a reply.
+#[derive(Debug)]
+pub(crate) struct ExecError(pub(crate) String);
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+// The last resort where no ICMP socket may be opened: the system `ping`, which is allowed to open one itself. Options
+// are those of iputils and BusyBox, and the round trip time is the one it reports.
+pub(crate) async fn ping_command(ip: IpAddr, size: usize, timeout: Duration, source: &Source) -> io::Result<Echo> {
+    let mut command = tokio::process::Command::new("ping");
+    let seconds = timeout.as_millis().div_ceil(1000).max(1);
+    command
+        .args(["-n", "-c", "1", "-W"])
+        .arg(seconds.to_string());
+    if ip.is_ipv6() {
+        command.arg("-6");
+    }
+    // `-I` takes either an interface or an address, the interface being the narrower of the two.
+    match (&source.interface, source.ip) {
+        (Some(interface), _) => {
+            command.arg("-I").arg(interface);
+        }
+        (None, Some(source)) => {
+            command.arg("-I").arg(source.to_string());
+        }
+        (None, None) => {}
+    }
+    command.arg("-s").arg(size.to_string());
+    // iputils only, BusyBox has no way to mark its requests or to set the don't fragment bit.
+    if let Some(dscp) = source.dscp {
+        command.arg("-Q").arg(dscp.tos().to_string());
+    }
+    if source.dont_fragment {
+        command.args(["-M", "do"]);
+    }
+    command
+        .arg(ip.to_string())
+        .env("LC_ALL", "C")
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+
+    let start = Instant::now();
+    let timed_out = || io::Error::new(io::ErrorKind::TimedOut, "echo request timed out");
+    let output = match tokio::time::timeout(timeout, command.output()).await {
+        Ok(Err(err)) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                ExecError("no ICMP socket may be opened and there is no ping command to fall back to".to_string()),
+            ))
+        }
+        Ok(Err(err)) => {
+            return Err(io::Error::new(
+                err.kind(),
+                ExecError(format!("failed to run ping: {}", err)),
+            ))
+        }
+        Ok(Ok(output)) => output,
+        Err(_) => return Err(timed_out()),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        // `ping` exits with 1 when no reply came, and with 2 on errors.
+        return Err(if stdout.contains("Unreachable") {
+            io::Error::new(io::ErrorKind::HostUnreachable, "destination unreachable")
+        } else if output.status.code() == Some(1) {
+            timed_out()
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            io::Error::other(ExecError(format!("ping failed: {}", stderr.trim())))
+        });
+    }
+
+    let field = |name: &str| {
+        stdout.find(name).and_then(|at| {
+            let value = &stdout[at + name.len()..];
+            let end = value
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(value.len());
+            value[..end].parse::<f64>().ok()
+        })
+    };
+    let rtt = field("time=").map_or_else(
+        || start.elapsed(),
+        |milliseconds| Duration::from_secs_f64(milliseconds / 1000.0),
+    );
+    Ok(Echo {
+        rtt,
+        ttl: field("ttl=").map(|ttl| ttl as u8),
+    })
+}
+
+// The identifier and sequence number of an echo reply, `ip` being of the same IP version as it.
+fn echo_reply(ip: IpAddr, raw: bool, packet: &[u8]) -> Option<(u16, u16)> {
+    let packet = if raw && ip.is_ipv4() {
+        icmp::skip_ipv4_header(packet)
+    } else {
+        packet
+    };
+    let kind = if ip.is_ipv4() { ECHO_REPLY_V4 } else { ECHO_REPLY_V6 };
+    if packet.len() < 8 || packet[0] != kind {
+        return None;
+    }
+
+    Some((
+        u16::from_be_bytes([packet[4], packet[5]]),
+        u16::from_be_bytes([packet[6], packet[7]]),
+    ))
+}
+
+// The request a destination unreachable message is about.
+fn unreachable(ip: IpAddr, packet: &[u8]) -> Option<(IpAddr, u16, u16)> {
+    let (packet, kind) = match ip {
+        IpAddr::V4(_) => (icmp::skip_ipv4_header(packet), DEST_UNREACHABLE_V4),
+        IpAddr::V6(_) => (packet, DEST_UNREACHABLE_V6),
+    };
+
+    if packet.first() != Some(&kind) {
+        return None;
+    }
+    icmp::quoted_request(ip, packet)
+}
@@ -0,0 +1,224 @@
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::source::Source;
+
+pub(crate) const DEFAULT_MAX_MTU: u16 = 1500;
+
+pub struct Discovery {
+    // The largest packet, IP header included, that made it to the target and back.
+    pub mtu: u16,
+    // The round trip time of the echo request of that size.
+    pub rtt: Duration,
+}
+
+// Finds the largest packet up to `max_mtu` that gets through to the target unfragmented, with echo requests that must
+// not be fragmented. Paths that drop large packets without saying so, black holes, are found as well as those whose
+// routers report the MTU.
+#[cfg(target_os = "linux")]
+pub async fn discover(
+    ip: IpAddr,
+    identifier: u16,
+    max_mtu: u16,
+    timeout: Duration,
+    source: Source,
+) -> io::Result<Discovery> {
+    tokio::task::spawn_blocking(move || linux::discover(ip, identifier, max_mtu, timeout, &source))
+        .await
+        .map_err(io::Error::other)?
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn discover(
+    _ip: IpAddr,
+    _identifier: u16,
+    _max_mtu: u16,
+    _timeout: Duration,
+    _source: Source,
+) -> io::Result<Discovery> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the pmtu probe is only supported on Linux",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::HashMap;
+    use std::io;
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::{Duration, Instant};
+
+    use socket2::{Socket, Type};
+
+    use super::Discovery;
+    use crate::icmp;
+    use crate::source::{self, Source};
+
+    const DESTINATION_UNREACHABLE_V4: u8 = 3;
+    const FRAGMENTATION_NEEDED: u8 = 4;
+    const PACKET_TOO_BIG_V6: u8 = 2;
+    // The smallest MTU each IP version has to support, which every path is taken to allow.
+    const MIN_MTU_V4: u16 = 68;
+    const MIN_MTU_V6: u16 = 1280;
+    // Sizes tried at once, which narrow 1500 bytes down to one in three rounds.
+    const ROUND_SIZES: u16 = 16;
+    // Each round gets this share of the timeout, and there are rarely more than three.
+    const ROUND_SHARE: u32 = 4;
+
+    pub fn discover(
+        ip: IpAddr,
+        identifier: u16,
+        max_mtu: u16,
+        timeout: Duration,
+        source: &Source,
+    ) -> io::Result<Discovery> {
+        let (domain, protocol) = icmp::domain(ip);
+        let socket = Socket::new(domain, Type::RAW, Some(protocol))?;
+        source.bind(&socket, ip)?;
+        dont_fragment(&socket, ip)?;
+        let target = SocketAddr::new(ip, 0).into();
+        let (min_mtu, ip_header) = match ip {
+            IpAddr::V4(_) => (MIN_MTU_V4, 20),
+            IpAddr::V6(_) => (MIN_MTU_V6, 40),
+        };
+        let min_mtu = min_mtu.min(max_mtu);
+
+        // Sizes up to `good` made it, and sizes from `bad` on did not.
+        let mut good: Option<(u16, Duration)> = None;
+        let mut bad = u32::from(max_mtu) + 1;
+        let mut buffer = vec![0u8; usize::from(max_mtu) + 512];
+        // Room for the replies to a whole round, which the default buffer lacks for jumbo frames.
+        socket.set_recv_buffer_size(usize::from(ROUND_SIZES) * buffer.len() * 2)?;
+        loop {
+            let low = good.map_or(min_mtu, |(mtu, _)| mtu + 1);
+            if u32::from(low) >= bad {
+                break;
+            }
+            let high = (bad - 1) as u16;
+            let count = ROUND_SIZES.min(high - low + 1);
+            let sizes: Vec<u16> = (0..count)
+                .map(|index| match count {
+                    1 => high,
+                    _ => low + ((u32::from(high - low) * u32::from(index)) / u32::from(count - 1)) as u16,
+                })
+                .collect();
+
+            // The sequence number of each request is its size.
+            let mut sent = HashMap::new();
+            for &size in &sizes {
+                let packet = icmp::echo_request_sized(ip, identifier, size, usize::from(size) - ip_header);
+                match socket.send_to(&packet, &target) {
+                    Ok(_) => {
+                        sent.insert(size, Instant::now());
+                    }
+                    // Larger than the MTU of the interface, or than the path MTU the kernel already knows of.
+                    Err(err) if err.raw_os_error() == Some(libc::EMSGSIZE) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+
+            let mut answered = HashMap::new();
+            let deadline = Instant::now() + timeout / ROUND_SHARE;
+            while answered.len() < sent.len() {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                socket.set_read_timeout(Some(remaining))?;
+                let len = match icmp::recv_from(&socket, &mut buffer) {
+                    Ok((len, _)) => len,
+                    Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                    Err(err) => return Err(err),
+                };
+                match reply(ip, &buffer[..len], identifier) {
+                    Some(Reply::Echo(size)) => {
+                        if let Some(sent) = sent.get(&size) {
+                            answered.entry(size).or_insert_with(|| sent.elapsed());
+                        }
+                    }
+                    // A router on the way says how large packets may be past it.
+                    Some(Reply::TooBig(mtu)) if mtu >= min_mtu => bad = bad.min(u32::from(mtu) + 1),
+                    _ => {}
+                }
+            }
+
+            match answered.iter().max_by_key(|(size, _)| **size) {
+                Some((&size, &rtt)) if good.is_none_or(|(mtu, _)| size > mtu) => good = Some((size, rtt)),
+                Some(_) => {}
+                None if good.is_none() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("no reply to an echo request of {} bytes", min_mtu),
+                    ))
+                }
+                None => {}
+            }
+            let largest = good.map_or(0, |(mtu, _)| mtu);
+            if let Some(size) = sizes
+                .iter()
+                .filter(|size| **size > largest && !answered.contains_key(size))
+                .min()
+            {
+                bad = bad.min(u32::from(*size));
+            }
+        }
+
+        let (mtu, rtt) = good.unwrap_or((min_mtu, Duration::ZERO));
+        Ok(Discovery { mtu, rtt })
+    }
+
+    enum Reply {
+        Echo(u16),
+        TooBig(u16),
+    }
+
+    fn reply(ip: IpAddr, packet: &[u8], identifier: u16) -> Option<Reply> {
+        let (packet, echo_reply) = match ip {
+            IpAddr::V4(_) => (icmp::skip_ipv4_header(packet), icmp::ECHO_REPLY_V4),
+            IpAddr::V6(_) => (packet, icmp::ECHO_REPLY_V6),
+        };
+        let kind = *packet.first()?;
+        if kind == echo_reply && packet.len() >= 8 {
+            let reply_identifier = u16::from_be_bytes([packet[4], packet[5]]);
+            return (reply_identifier == identifier).then(|| Reply::Echo(u16::from_be_bytes([packet[6], packet[7]])));
+        }
+
+        // Fragmentation needed carries the MTU in the second half of its rest of header, and packet too big in all
+        // of it.
+        let mtu = match ip {
+            IpAddr::V4(_) if kind == DESTINATION_UNREACHABLE_V4 && packet.get(1) == Some(&FRAGMENTATION_NEEDED) => {
+                u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?])
+            }
+            IpAddr::V6(_) if kind == PACKET_TOO_BIG_V6 => {
+                u32::from_be_bytes([*packet.get(4)?, *packet.get(5)?, *packet.get(6)?, *packet.get(7)?])
+                    .min(u32::from(u16::MAX)) as u16
+            }
+            _ => return None,
+        };
+        match icmp::quoted_request(ip, packet)? {
+            (target, reply_identifier, _) if target == ip && reply_identifier == identifier => Some(Reply::TooBig(mtu)),
+            _ => None,
+        }
+    }
+
+    // Sets the don't fragment bit, and has the kernel send packets of any size rather than only up to the path MTU it
+    // has learned, so that every probe finds the MTU afresh.
+    fn dont_fragment(socket: &Socket, ip: IpAddr) -> io::Result<()> {
+        match ip {
+            IpAddr::V4(_) => source::set_option(
+                socket.into(),
+                libc::IPPROTO_IP,
+                libc::IP_MTU_DISCOVER,
+                libc::IP_PMTUDISC_PROBE,
+            ),
+            IpAddr::V6(_) => source::set_option(
+                socket.into(),
+                libc::IPPROTO_IPV6,
+                libc::IPV6_MTU_DISCOVER,
+                libc::IPV6_PMTUDISC_PROBE,
+            ),
+        }
+    }
+}
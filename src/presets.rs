@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Endpoint, ProbeType};
+
+// Well-known targets that together show whether the internet as a whole is reachable, so that a config does not have
+// to list its own. Anycast services are answered close to wherever the monitor runs.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    // The public resolvers of Cloudflare, Google and Quad9, pinged.
+    PublicDns,
+    // The same over IPv6.
+    PublicDnsV6,
+    // HTTPS connections to the edges of the largest CDNs, which most websites are served from.
+    MajorCdns,
+}
+
+impl Preset {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Preset::PublicDns => "public-dns",
+            Preset::PublicDnsV6 => "public-dns-v6",
+            Preset::MajorCdns => "major-cdns",
+        }
+    }
+
+    pub(crate) fn endpoints(&self) -> Vec<Endpoint> {
+        let (probe, port, targets): (ProbeType, Option<u16>, &[(&str, &str)]) = match self {
+            Preset::PublicDns => (
+                ProbeType::Icmp,
+                None,
+                &[("Cloudflare", "1.1.1.1"), ("Google", "8.8.8.8"), ("Quad9", "9.9.9.9")],
+            ),
+            Preset::PublicDnsV6 => (
+                ProbeType::Icmp,
+                None,
+                &[
+                    ("Cloudflare", "2606:4700:4700::1111"),
+                    ("Google", "2001:4860:4860::8888"),
+                    ("Quad9", "2620:fe::fe"),
+                ],
+            ),
+            Preset::MajorCdns => (
+                ProbeType::Tcp,
+                Some(443),
+                &[
+                    ("Cloudflare", "www.cloudflare.com"),
+                    ("Akamai", "www.akamai.com"),
+                    ("Fastly", "www.fastly.com"),
+                    ("CloudFront", "aws.amazon.com"),
+                ],
+            ),
+        };
+
+        // Named with the address as well, so that they do not take the names of endpoints such as `Google DNS`.
+        targets
+            .iter()
+            .map(|(name, address)| {
+                let mut endpoint = Endpoint::new(format!("{} {}", name, address), address.to_string());
+                endpoint.probe = Some(probe);
+                endpoint.port = port;
+                endpoint
+            })
+            .collect()
+    }
+}
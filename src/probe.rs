@@ -0,0 +1,1398 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use hyper::StatusCode;
+use log::info;
+use prometheus::Histogram;
+use tokio::time::Duration;
+
+use crate::config::{Endpoint, MaintenanceMode, ProbeType, Settings, SipTransport, SnmpVersion, StatusPattern};
+use crate::database::{self, Database};
+use crate::http::Verify;
+use crate::maintenance::Maintenance;
+use crate::metrics::{self, Metrics};
+#[cfg(unix)]
+use crate::pinger;
+use crate::resolver::Resolver;
+use crate::scheduler::Schedule;
+use crate::source::Source;
+use crate::status::{self, ProbeResult, StatusBoard};
+use crate::{arp, dns, exemplars, grpc, http, icmp, mqtt, ntp, pmtu, sip, smtp, snmp, ssh, websocket, BoxError};
+
+// Runs the probe whenever the schedule says so until it shuts down, recording the results in the metrics and on the board.
+pub async fn run(
+    probe: Box<dyn Probe>,
+    endpoint: Endpoint,
+    settings: Settings,
+    metrics: Metrics,
+    latency_metric: Histogram,
+    board: StatusBoard,
+    mut schedule: Schedule,
+) {
+    let Settings {
+        count,
+        loss_window,
+        failure_threshold,
+        recovery_threshold,
+        ..
+    } = settings;
+
+    let key = endpoint.key();
+    let labels = metrics.labels(&endpoint);
+    let success_metric = metrics.success.with_label_values(&labels);
+    let fail_metric = metrics.fail.with_label_values(&labels);
+    let retries_metric = metrics.retries.with_label_values(&labels);
+    let throttled_metric = metrics.throttled.with_label_values(&labels);
+    let timeout_metric = metrics.timeout.with_label_values(&labels);
+    let up_metric = metrics.up.with_label_values(&labels);
+    let state_changes_metric = metrics.state_changes.with_label_values(&labels);
+    let consecutive_failures_metric = metrics.consecutive_failures.with_label_values(&labels);
+    let last_success_metric = metrics.last_success.with_label_values(&labels);
+    let loss_metric = metrics.loss_ratio.with_label_values(&labels);
+    let jitter_metric = metrics.jitter.with_label_values(&labels);
+    let maintenance_metric = metrics.in_maintenance.with_label_values(&labels);
+    let maintenance = Maintenance::new(endpoint.maintenance.as_deref().unwrap_or_default());
+
+    let latency_labels = metrics.latency_labels(&labels);
+    let mut recent = VecDeque::with_capacity(loss_window);
+    let mut last_latency: Option<f64> = None;
+    let mut jitter = 0.0;
+
+    let interval_metric = metrics.interval.with_label_values(&labels);
+    let mut interval = settings.interval;
+    if settings.adaptive.is_some() {
+        interval_metric.set(interval.as_secs_f64());
+    }
+
+    let mut up: Option<bool> = None;
+    let mut successes = 0;
+    let mut failures = 0;
+
+    // A probe that is already running is never interrupted, shutting down waits for it instead.
+    while let Some(_slot) = schedule.next().await {
+        let in_maintenance = maintenance.at(status::unix_time());
+        maintenance_metric.set(in_maintenance.is_some() as i64);
+        if in_maintenance == Some(MaintenanceMode::Skip) {
+            board.set_maintenance(&key, true);
+            continue;
+        }
+        let marked = in_maintenance == Some(MaintenanceMode::Mark);
+        // The span the OTLP output exports for the cycle gets the same trace, so that the exemplars lead to it.
+        let trace_id = metrics
+            .exemplars
+            .then(|| format!("{:032x}", rand::random::<u128>()));
+
+        // Every request of a cycle counts towards the per-request metrics, the cycle as a whole towards the state.
+        let mut rtts = Vec::with_capacity(count as usize);
+        let mut error = None;
+        for _ in 0..count {
+            if schedule.pace().await {
+                throttled_metric.inc();
+            }
+            let output = attempt(&*probe, &settings, || retries_metric.inc()).await;
+            if marked {
+                match output {
+                    Ok(duration) => rtts.push(duration),
+                    Err(err) => error = Some(err),
+                }
+                continue;
+            }
+
+            if recent.len() == loss_window {
+                recent.pop_front();
+            }
+            recent.push_back(output.is_ok());
+
+            match output {
+                Ok(duration) => {
+                    let latency = duration.as_secs_f64();
+                    success_metric.inc();
+                    latency_metric.observe(latency);
+                    if let Some(trace_id) = &trace_id {
+                        exemplars::observe(&latency_labels, &settings.latency_buckets, latency, trace_id);
+                    }
+
+                    if let Some(last) = last_latency {
+                        jitter += ((latency - last).abs() - jitter) / 16.0;
+                        jitter_metric.set(jitter);
+                    }
+                    last_latency = Some(latency);
+                    rtts.push(duration);
+                }
+                Err(err) => {
+                    let reason = failure_reason(&err);
+                    fail_metric.inc();
+                    if reason == "timeout" {
+                        timeout_metric.inc();
+                    }
+                    metrics
+                        .errors
+                        .with_label_values(&[&labels[..], &[reason]].concat())
+                        .inc();
+                    error = Some(err);
+                }
+            }
+        }
+
+        let lost = recent.iter().filter(|ok| !**ok).count();
+        let loss_ratio = lost as f64 / recent.len().max(1) as f64;
+        if marked {
+            let output = match error {
+                Some(err) if rtts.is_empty() => Err(err),
+                _ => Ok(average(&rtts)),
+            };
+            board.record(
+                &key,
+                ProbeResult {
+                    up,
+                    rtt: output.as_ref().ok().copied(),
+                    loss_ratio,
+                    error: output.as_ref().err().map(|err| err.to_string()),
+                    reason: output.as_ref().err().map(failure_reason),
+                    maintenance: true,
+                    trace_id,
+                },
+            );
+            continue;
+        }
+        loss_metric.set(loss_ratio);
+        if endpoint.voice {
+            let latency = if rtts.is_empty() {
+                last_latency
+            } else {
+                Some(average(&rtts).as_secs_f64())
+            };
+            if let Some(latency) = latency {
+                metrics
+                    .estimated_mos
+                    .with_label_values(&labels)
+                    .set(estimated_mos(latency, jitter, loss_ratio));
+            }
+        }
+
+        // The cycle series are only exported for endpoints that send more than one request per cycle.
+        if count > 1 {
+            if let (Some(min), Some(max)) = (rtts.iter().min(), rtts.iter().max()) {
+                metrics
+                    .cycle_rtt_min
+                    .with_label_values(&labels)
+                    .set(min.as_secs_f64());
+                metrics
+                    .cycle_rtt_avg
+                    .with_label_values(&labels)
+                    .set(average(&rtts).as_secs_f64());
+                metrics
+                    .cycle_rtt_max
+                    .with_label_values(&labels)
+                    .set(max.as_secs_f64());
+            }
+            metrics
+                .cycle_loss_ratio
+                .with_label_values(&labels)
+                .set(1.0 - rtts.len() as f64 / count as f64);
+        }
+
+        // A cycle succeeds when any of its requests got a reply, like `ping -c` does.
+        let output = match error {
+            Some(err) if rtts.is_empty() => Err(err),
+            _ => Ok(average(&rtts)),
+        };
+
+        // The state only flips after enough consecutive results, so a single lost probe on a lossy link is not an outage.
+        if output.is_ok() {
+            successes += 1;
+            failures = 0;
+            last_success_metric.set(status::unix_time());
+        } else {
+            failures += 1;
+            successes = 0;
+        }
+        consecutive_failures_metric.set(failures as i64);
+        let next = match up {
+            Some(true) | None if failures >= failure_threshold => Some(false),
+            Some(false) | None if successes >= recovery_threshold => Some(true),
+            _ => up,
+        };
+        if next != up {
+            if up.is_some() {
+                state_changes_metric.inc();
+            }
+            up = next;
+        }
+        if let Some(up) = up {
+            up_metric.set(up as i64);
+        }
+
+        // Adaptive endpoints are probed more often while they fail or are slow, and less often again as they recover.
+        if let Some(adaptive) = &settings.adaptive {
+            let slow = match (&output, adaptive.latency_above) {
+                (Ok(rtt), Some(limit)) => *rtt > limit,
+                _ => false,
+            };
+            let next = if output.is_err() || slow {
+                (interval / 2).max(adaptive.min_interval)
+            } else {
+                (interval * 2).min(settings.interval)
+            };
+            if next != interval {
+                interval = next;
+                schedule.set_interval(interval);
+                interval_metric.set(interval.as_secs_f64());
+            }
+        }
+
+        board.record(
+            &key,
+            ProbeResult {
+                up,
+                rtt: output.as_ref().ok().copied(),
+                loss_ratio,
+                error: output.as_ref().err().map(|err| err.to_string()),
+                reason: output.as_ref().err().map(failure_reason),
+                maintenance: false,
+                trace_id,
+            },
+        );
+    }
+}
+
+// Runs a single request, and while it fails and there are retries left, tries it again after a backoff that doubles
+// every time.
+pub(crate) async fn attempt(
+    probe: &dyn Probe,
+    settings: &Settings,
+    mut retried: impl FnMut(),
+) -> Result<Duration, BoxError> {
+    let mut backoff = settings.retry_backoff;
+    let mut retries = settings.retries;
+    loop {
+        let output = match tokio::time::timeout(settings.timeout, probe.run()).await {
+            Ok(output) => output,
+            Err(elapsed) => Err(elapsed.into()),
+        };
+        if output.is_ok() || retries == 0 {
+            return output;
+        }
+        retries -= 1;
+        retried();
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+fn average(rtts: &[Duration]) -> Duration {
+    rtts.iter().sum::<Duration>() / rtts.len().max(1) as u32
+}
+
+// The simplified E-model of ITU-T G.107 commonly used for monitoring: jitter counts double towards the delay, which
+// costs more past 160ms, every percent of loss costs 2.5 off the R factor, and R is mapped to a MOS.
+fn estimated_mos(latency: f64, jitter: f64, loss_ratio: f64) -> f64 {
+    let delay = (latency + 2.0 * jitter) * 1000.0 + 10.0;
+    let r = if delay < 160.0 {
+        93.2 - delay / 40.0
+    } else {
+        93.2 - (delay - 120.0) / 10.0
+    };
+    let r = (r - 2.5 * loss_ratio * 100.0).clamp(0.0, 100.0);
+
+    1.0 + 0.035 * r + 0.000007 * r * (r - 60.0) * (100.0 - r)
+}
+
+pub(crate) fn failure_reason(err: &BoxError) -> &'static str {
+    if err.is::<tokio::time::error::Elapsed>() {
+        return "timeout";
+    }
+    if err.is::<icmp::ResolveError>() {
+        return "dns_error";
+    }
+    #[cfg(unix)]
+    if err
+        .downcast_ref::<io::Error>()
+        .and_then(io::Error::get_ref)
+        .is_some_and(|err| err.is::<pinger::ExecError>())
+    {
+        return "exec_error";
+    }
+
+    match err.downcast_ref::<io::Error>().map(io::Error::kind) {
+        Some(io::ErrorKind::TimedOut) => "timeout",
+        Some(io::ErrorKind::PermissionDenied) => "permission_denied",
+        Some(io::ErrorKind::ConnectionRefused | io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable) => {
+            "unreachable"
+        }
+        _ => "error",
+    }
+}
+
+const DEFAULT_MAX_HOPS: u8 = 30;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// A kind of check. `run` returns the round trip time of a successful check, the runner takes care of the timeout,
+// the metrics and the up/down state.
+pub trait Probe: Send + Sync {
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>>;
+}
+
+// Picks the probe for the endpoint's `probe` type.
+pub fn build(endpoint: &Endpoint, settings: &Settings, metrics: &Metrics) -> Box<dyn Probe> {
+    let resolver = |host: &str| Resolver::new(host, endpoint, settings, metrics);
+    let database_probe = |database: Database| -> Box<dyn Probe> {
+        Box::new(DatabaseHandshake {
+            database,
+            resolver: resolver(&endpoint.address),
+            source: Source::of(endpoint),
+            port: endpoint.port.unwrap_or(database.default_port()),
+        })
+    };
+    match endpoint.probe_type() {
+        ProbeType::Icmp => Box::new(Icmp {
+            endpoint: endpoint.clone(),
+            metrics: metrics.clone(),
+            resolver: resolver(&endpoint.address),
+            source: Source::of(endpoint),
+            size: endpoint.size.map_or(icmp::PAYLOAD.len(), usize::from),
+            timeout: settings.timeout,
+        }),
+        ProbeType::Tcp => Box::new(Tcp {
+            resolver: resolver(&endpoint.address),
+            source: Source::of(endpoint),
+            port: endpoint.port.unwrap_or_default(),
+        }),
+        ProbeType::Http => Box::new(Http {
+            url: endpoint.address.clone(),
+            expected_status: endpoint.expected_status.clone(),
+            resolver: resolver(
+                http::parse_url(&endpoint.address)
+                    .as_ref()
+                    .map_or("", http::host),
+            ),
+            source: Source::of(endpoint),
+        }),
+        ProbeType::Dns => Box::new(Dns {
+            endpoint: endpoint.clone(),
+            metrics: metrics.clone(),
+            doh_resolver: match dns::parse_server(endpoint.resolver.as_deref().unwrap_or_default()) {
+                Ok(dns::Server::Https(url)) => Some(resolver(http::host(&url))),
+                _ => None,
+            },
+        }),
+        ProbeType::Tls => Box::new(Tls {
+            endpoint: endpoint.clone(),
+            metrics: metrics.clone(),
+            resolver: resolver(&endpoint.address),
+        }),
+        ProbeType::Traceroute => Box::new(Traceroute {
+            endpoint: endpoint.clone(),
+            resolver: resolver(&endpoint.address),
+            metrics: metrics.clone(),
+            identifier: icmp::next_identifier(),
+            max_hops: endpoint.max_hops.unwrap_or(DEFAULT_MAX_HOPS),
+            timeout: settings.timeout,
+            loss_window: settings.loss_window,
+            path: Mutex::default(),
+        }),
+        ProbeType::Udp => Box::new(Udp {
+            resolver: resolver(&endpoint.address),
+            source: Source::of(endpoint),
+            port: endpoint.port.unwrap_or_default(),
+            payload: endpoint.payload().unwrap_or_default(),
+            timeout: settings.timeout,
+        }),
+        ProbeType::Ntp => Box::new(Ntp {
+            endpoint: endpoint.clone(),
+            metrics: metrics.clone(),
+            resolver: resolver(&endpoint.address),
+        }),
+        ProbeType::Ssh => Box::new(Ssh {
+            endpoint: endpoint.clone(),
+            metrics: metrics.clone(),
+            resolver: resolver(&endpoint.address),
+            banner: Mutex::default(),
+        }),
+        ProbeType::Smtp => Box::new(Smtp {
+            endpoint: endpoint.clone(),
+            resolver: resolver(&endpoint.address),
+        }),
+        ProbeType::Grpc => Box::new(Grpc {
+            endpoint: endpoint.clone(),
+            metrics: metrics.clone(),
+            resolver: resolver(&endpoint.address),
+        }),
+        ProbeType::Quic => Box::new(Quic {
+            endpoint: endpoint.clone(),
+            resolver: resolver(
+                http::parse_url(&endpoint.address)
+                    .as_ref()
+                    .map_or(endpoint.address.as_str(), http::host),
+            ),
+        }),
+        ProbeType::Websocket => Box::new(Websocket {
+            endpoint: endpoint.clone(),
+            metrics: metrics.clone(),
+            resolver: resolver(
+                websocket::parse_url(&endpoint.address)
+                    .as_ref()
+                    .map_or("", http::host),
+            ),
+        }),
+        ProbeType::Mqtt => Box::new(Mqtt {
+            endpoint: endpoint.clone(),
+            resolver: resolver(&endpoint.address),
+        }),
+        ProbeType::Redis => database_probe(Database::Redis),
+        ProbeType::Postgres => database_probe(Database::Postgres),
+        ProbeType::Mysql => database_probe(Database::Mysql),
+        ProbeType::Snmp => Box::new(Snmp {
+            endpoint: endpoint.clone(),
+            metrics: metrics.clone(),
+            resolver: resolver(&endpoint.address),
+            oid: snmp::parse_oid(endpoint.oid.as_deref().unwrap_or(snmp::DEFAULT_OID)).unwrap_or_default(),
+            text: Mutex::default(),
+        }),
+        ProbeType::Sip => Box::new(Sip {
+            endpoint: endpoint.clone(),
+            metrics: metrics.clone(),
+            resolver: resolver(&endpoint.address),
+        }),
+        ProbeType::Pmtu => Box::new(Pmtu {
+            endpoint: endpoint.clone(),
+            metrics: metrics.clone(),
+            resolver: resolver(&endpoint.address),
+            identifier: icmp::next_identifier(),
+            max_mtu: endpoint.max_mtu.unwrap_or(pmtu::DEFAULT_MAX_MTU),
+            timeout: settings.timeout,
+            mtu: Mutex::default(),
+        }),
+        ProbeType::Arp => Box::new(Arp {
+            resolver: resolver(&endpoint.address),
+            source: Source::of(endpoint),
+            timeout: settings.timeout,
+        }),
+    }
+}
+
+struct Icmp {
+    endpoint: Endpoint,
+    metrics: Metrics,
+    resolver: Resolver,
+    source: Source,
+    size: usize,
+    timeout: Duration,
+}
+
+impl Probe for Icmp {
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            self.metrics
+                .payload_size
+                .with_label_values(&self.metrics.labels(&self.endpoint))
+                .set(self.size as i64);
+            let ip = self.resolver.resolve().await?;
+
+            let echo = icmp::echo(ip, self.size, self.timeout, &self.source).await?;
+            if let Some(ttl) = echo.ttl {
+                self.metrics
+                    .reply_ttl
+                    .with_label_values(&self.metrics.labels(&self.endpoint))
+                    .set(i64::from(ttl));
+            }
+            Ok(echo.rtt)
+        })
+    }
+}
+
+struct Arp {
+    resolver: Resolver,
+    source: Source,
+    timeout: Duration,
+}
+
+impl Probe for Arp {
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+
+            Ok(arp::request(ip, self.timeout, self.source.clone()).await?)
+        })
+    }
+}
+
+struct Pmtu {
+    endpoint: Endpoint,
+    metrics: Metrics,
+    resolver: Resolver,
+    identifier: u16,
+    max_mtu: u16,
+    timeout: Duration,
+    // The MTU last found, so that changes are logged.
+    mtu: Mutex<Option<u16>>,
+}
+
+impl Probe for Pmtu {
+    // The latency is that of the largest echo request that got through.
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+            // Discovery takes several rounds of requests, and leaves time to record the result before the probe times
+            // out.
+            let source = Source::of(&self.endpoint);
+            let discovery = pmtu::discover(ip, self.identifier, self.max_mtu, self.timeout * 4 / 5, source).await?;
+
+            let previous = self.mtu.lock().unwrap().replace(discovery.mtu);
+            if let Some(previous) = previous.filter(|previous| *previous != discovery.mtu) {
+                info!(
+                    "Path MTU to {} changed from {} to {} bytes",
+                    self.endpoint.key(),
+                    previous,
+                    discovery.mtu
+                );
+            }
+            self.metrics
+                .path_mtu
+                .with_label_values(&self.metrics.labels(&self.endpoint))
+                .set(i64::from(discovery.mtu));
+
+            Ok(discovery.rtt)
+        })
+    }
+}
+
+struct Tcp {
+    resolver: Resolver,
+    source: Source,
+    port: u16,
+}
+
+impl Probe for Tcp {
+    // The address is resolved up front so that only the handshake is timed.
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+
+            let start = Instant::now();
+            self.source.connect((ip, self.port).into()).await?;
+
+            Ok(start.elapsed())
+        })
+    }
+}
+
+struct Udp {
+    resolver: Resolver,
+    source: Source,
+    port: u16,
+    payload: Vec<u8>,
+    timeout: Duration,
+}
+
+impl Probe for Udp {
+    // Succeeds on any reply. A connected socket learns of the ICMP port unreachable the target may answer with
+    // instead, which fails the probe as refused, and silence is left to the timeout. The socket is read blocking, as
+    // the async one is never woken up for the error.
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+            let target = (ip, self.port).into();
+            let socket = self.source.udp(target).await?.into_std()?;
+            let payload = self.payload.clone();
+            let timeout = self.timeout;
+
+            let exchange = move || -> io::Result<Duration> {
+                socket.set_nonblocking(false)?;
+                socket.set_read_timeout(Some(timeout))?;
+                socket.connect(target)?;
+
+                let start = Instant::now();
+                socket.send(&payload)?;
+                let mut buffer = [0u8; 1500];
+                match socket.recv(&mut buffer) {
+                    Ok(_) => Ok(start.elapsed()),
+                    Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                        Err(io::Error::new(io::ErrorKind::ConnectionRefused, "port unreachable"))
+                    }
+                    Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                        Err(io::Error::new(io::ErrorKind::TimedOut, "no reply"))
+                    }
+                    Err(err) => Err(err),
+                }
+            };
+            Ok(tokio::task::spawn_blocking(exchange)
+                .await
+                .map_err(io::Error::other)??)
+        })
+    }
+}
+
+struct Http {
+    url: String,
+    expected_status: Vec<StatusPattern>,
+    resolver: Resolver,
+    source: Source,
+}
+
+impl Probe for Http {
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+
+            let start = Instant::now();
+            http::get(&self.url, &self.expected_status, ip, &self.source).await?;
+
+            Ok(start.elapsed())
+        })
+    }
+}
+
+struct Dns {
+    endpoint: Endpoint,
+    metrics: Metrics,
+    // Looks up the host of a DoH url ahead of the query, so that only the query is timed.
+    doh_resolver: Option<Resolver>,
+}
+
+impl Probe for Dns {
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let doh_ip = match &self.doh_resolver {
+                Some(resolver) => Some(resolver.resolve().await?),
+                None => None,
+            };
+
+            let start = Instant::now();
+            dns_lookup(&self.endpoint, &self.metrics, doh_ip).await?;
+
+            Ok(start.elapsed())
+        })
+    }
+}
+
+struct Ntp {
+    endpoint: Endpoint,
+    metrics: Metrics,
+    resolver: Resolver,
+}
+
+impl Probe for Ntp {
+    // The round trip time is the delay of the exchange, which leaves out how long the server took to answer.
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+            let server = (ip, self.endpoint.port.unwrap_or(ntp::NTP_PORT)).into();
+            let sample = ntp::query(server, &Source::of(&self.endpoint)).await?;
+
+            let labels = self.metrics.labels(&self.endpoint);
+            self.metrics
+                .ntp_offset
+                .with_label_values(&labels)
+                .set(sample.offset);
+            self.metrics
+                .ntp_delay
+                .with_label_values(&labels)
+                .set(sample.delay.as_secs_f64());
+            self.metrics
+                .ntp_stratum
+                .with_label_values(&labels)
+                .set(i64::from(sample.stratum));
+            if let Some(max_offset) = self.endpoint.max_offset {
+                if sample.offset.abs() > max_offset.as_secs_f64() {
+                    return Err(format!("clock is off by {:.3}s, more than {:?}", sample.offset, max_offset).into());
+                }
+            }
+
+            Ok(sample.delay)
+        })
+    }
+}
+
+struct Ssh {
+    endpoint: Endpoint,
+    metrics: Metrics,
+    resolver: Resolver,
+    // The banner of the last probe, whose series is replaced when the server sends a different one.
+    banner: Mutex<Option<String>>,
+}
+
+impl Probe for Ssh {
+    // Times the handshake up to the server's banner.
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+            let server = (ip, self.endpoint.port.unwrap_or(ssh::SSH_PORT)).into();
+
+            let start = Instant::now();
+            let banner = ssh::banner(server, &Source::of(&self.endpoint)).await?;
+            let rtt = start.elapsed();
+
+            let labels = self.metrics.labels(&self.endpoint);
+            let mut last = self.banner.lock().unwrap();
+            match last.as_deref() {
+                Some(previous) if previous == banner => {}
+                Some(previous) => {
+                    info!(
+                        "SSH banner of {} changed from {} to {}",
+                        self.endpoint.key(),
+                        previous,
+                        banner
+                    );
+                    let _ = self
+                        .metrics
+                        .ssh_banner
+                        .remove_label_values(&[&labels[..], &[previous]].concat());
+                }
+                None => metrics::remove_series(&self.metrics.ssh_banner, &labels),
+            }
+            self.metrics
+                .ssh_banner
+                .with_label_values(&[&labels[..], &[&banner]].concat())
+                .set(1);
+            *last = Some(banner);
+
+            Ok(rtt)
+        })
+    }
+}
+
+struct Smtp {
+    endpoint: Endpoint,
+    resolver: Resolver,
+}
+
+impl Probe for Smtp {
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+            let server = (ip, self.endpoint.port.unwrap_or(smtp::SMTP_PORT)).into();
+            let server_name = self
+                .endpoint
+                .server_name
+                .as_deref()
+                .unwrap_or(&self.endpoint.address);
+
+            smtp::probe(
+                server,
+                server_name,
+                self.endpoint.starttls,
+                !self.endpoint.insecure_skip_verify,
+                &Source::of(&self.endpoint),
+            )
+            .await
+        })
+    }
+}
+
+struct Grpc {
+    endpoint: Endpoint,
+    metrics: Metrics,
+    resolver: Resolver,
+}
+
+impl Probe for Grpc {
+    // Succeeds when the service is SERVING. The round trip time is that of the call alone.
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+            let health = grpc::check(
+                ip,
+                self.endpoint.port.unwrap_or_default(),
+                self.endpoint
+                    .server_name
+                    .as_deref()
+                    .unwrap_or(&self.endpoint.address),
+                self.endpoint.service.as_deref().unwrap_or_default(),
+                self.endpoint.tls,
+                !self.endpoint.insecure_skip_verify,
+                &Source::of(&self.endpoint),
+            )
+            .await?;
+
+            let labels = self.metrics.labels(&self.endpoint);
+            for (status, name) in grpc::SERVING_STATUSES.iter().enumerate() {
+                self.metrics
+                    .grpc_health_status
+                    .with_label_values(&[&labels[..], &[name]].concat())
+                    .set((health.status == status as u64) as i64);
+            }
+            if !health.serving() {
+                return Err(format!("service is {}", health.status_name()).into());
+            }
+
+            Ok(health.duration)
+        })
+    }
+}
+
+struct Quic {
+    endpoint: Endpoint,
+    resolver: Resolver,
+}
+
+impl Probe for Quic {
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+
+            quic_handshake(&self.endpoint, ip).await
+        })
+    }
+}
+
+struct Websocket {
+    endpoint: Endpoint,
+    metrics: Metrics,
+    resolver: Resolver,
+}
+
+impl Probe for Websocket {
+    // The round trip time is that of the ping with `ping`, and of the handshake without.
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let url = websocket::parse_url(&self.endpoint.address)?;
+            let ip = self.resolver.resolve().await?;
+            let exchange = websocket::connect(
+                &url,
+                ip,
+                self.endpoint.ping,
+                !self.endpoint.insecure_skip_verify,
+                &Source::of(&self.endpoint),
+            )
+            .await?;
+
+            self.metrics
+                .websocket_handshake
+                .with_label_values(&self.metrics.labels(&self.endpoint))
+                .set(exchange.handshake.as_secs_f64());
+
+            Ok(exchange.pong.unwrap_or(exchange.handshake))
+        })
+    }
+}
+
+struct Mqtt {
+    endpoint: Endpoint,
+    resolver: Resolver,
+}
+
+impl Probe for Mqtt {
+    // The password is read for every probe, so a rotated one is picked up.
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+            let default_port = if self.endpoint.tls {
+                mqtt::MQTTS_PORT
+            } else {
+                mqtt::MQTT_PORT
+            };
+            let server = (ip, self.endpoint.port.unwrap_or(default_port)).into();
+            let password = self.endpoint.password()?;
+
+            mqtt::connect(
+                server,
+                self.endpoint
+                    .server_name
+                    .as_deref()
+                    .unwrap_or(&self.endpoint.address),
+                self.endpoint.tls,
+                !self.endpoint.insecure_skip_verify,
+                self.endpoint
+                    .username
+                    .as_deref()
+                    .map(|username| (username, password)),
+                &Source::of(&self.endpoint),
+            )
+            .await
+        })
+    }
+}
+
+struct DatabaseHandshake {
+    database: Database,
+    resolver: Resolver,
+    source: Source,
+    port: u16,
+}
+
+impl Probe for DatabaseHandshake {
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+
+            database::handshake(self.database, (ip, self.port).into(), &self.source).await
+        })
+    }
+}
+
+struct Snmp {
+    endpoint: Endpoint,
+    metrics: Metrics,
+    resolver: Resolver,
+    oid: Vec<u32>,
+    // The text of the last probe, whose series is replaced when the value changes.
+    text: Mutex<Option<String>>,
+}
+
+impl Probe for Snmp {
+    // The round trip time is that of the GET. A number is kept in snmp_value and anything else in snmp_value_info,
+    // where the series of the previous value is replaced.
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+            let server = (ip, self.endpoint.port.unwrap_or(snmp::SNMP_PORT)).into();
+            let password;
+            let security = match (self.endpoint.snmp_version, &self.endpoint.username) {
+                (Some(SnmpVersion::V3), Some(username)) => {
+                    password = self.endpoint.password()?;
+                    snmp::Security::User(username, password)
+                }
+                _ => snmp::Security::Community(
+                    self.endpoint
+                        .community
+                        .as_deref()
+                        .unwrap_or(snmp::DEFAULT_COMMUNITY),
+                ),
+            };
+            let reading = snmp::get(server, &self.oid, security, &Source::of(&self.endpoint)).await?;
+
+            let labels = self.metrics.labels(&self.endpoint);
+            let mut last = self.text.lock().unwrap();
+            let text = match reading.value {
+                snmp::Value::Number(value) => {
+                    self.metrics
+                        .snmp_value
+                        .with_label_values(&labels)
+                        .set(value);
+                    None
+                }
+                snmp::Value::Text(value) => Some(value),
+            };
+            if *last != text {
+                metrics::remove_series(&self.metrics.snmp_value_info, &labels);
+            }
+            if let Some(text) = &text {
+                metrics::remove_series(&self.metrics.snmp_value, &labels);
+                self.metrics
+                    .snmp_value_info
+                    .with_label_values(&[&labels[..], &[text]].concat())
+                    .set(1);
+            }
+            *last = text;
+
+            Ok(reading.duration)
+        })
+    }
+}
+
+struct Sip {
+    endpoint: Endpoint,
+    metrics: Metrics,
+    resolver: Resolver,
+}
+
+impl Probe for Sip {
+    // Any final response shows the server is up, but only one whose code matches `expected_status` passes.
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+            let server = (ip, self.endpoint.port.unwrap_or(sip::SIP_PORT)).into();
+            let response = sip::options(
+                server,
+                &self.endpoint.address,
+                self.endpoint.transport.unwrap_or(SipTransport::Udp),
+                &Source::of(&self.endpoint),
+            )
+            .await?;
+
+            self.metrics
+                .sip_response_code
+                .with_label_values(&self.metrics.labels(&self.endpoint))
+                .set(i64::from(response.code));
+            let expected = StatusCode::from_u16(response.code).is_ok_and(|status| {
+                self.endpoint
+                    .expected_status
+                    .iter()
+                    .any(|pattern| pattern.matches(status))
+            });
+            if !expected {
+                return Err(format!("unexpected response {} {}", response.code, response.reason).into());
+            }
+
+            Ok(response.duration)
+        })
+    }
+}
+
+struct Tls {
+    endpoint: Endpoint,
+    metrics: Metrics,
+    resolver: Resolver,
+}
+
+impl Probe for Tls {
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+
+            let start = Instant::now();
+            tls_handshake(&self.endpoint, &self.metrics, ip).await?;
+
+            Ok(start.elapsed())
+        })
+    }
+}
+
+struct Traceroute {
+    endpoint: Endpoint,
+    resolver: Resolver,
+    metrics: Metrics,
+    identifier: u16,
+    max_hops: u8,
+    timeout: Duration,
+    loss_window: usize,
+    path: Mutex<Path>,
+}
+
+#[derive(Default)]
+struct Path {
+    hops: Vec<HopHistory>,
+    // The number of hops to the target the last time it answered.
+    length: Option<usize>,
+}
+
+// The answers of a hop over the last `loss_window` traceroutes.
+#[derive(Default)]
+struct HopHistory {
+    answered: VecDeque<bool>,
+    rtts: VecDeque<f64>,
+    address: Option<IpAddr>,
+}
+
+impl Probe for Traceroute {
+    fn run(&self) -> BoxFuture<'_, Result<Duration, BoxError>> {
+        Box::pin(async move {
+            let ip = self.resolver.resolve().await?;
+            // Answers are collected for most of the timeout, leaving time to record them before the probe times out.
+            let source = Source::of(&self.endpoint);
+            let hops = icmp::trace(ip, self.identifier, self.max_hops, self.timeout * 4 / 5, source).await?;
+
+            let reached = hops
+                .iter()
+                .position(|hop| hop.as_ref().is_some_and(|hop| hop.reached));
+            // When the target did not answer, the path is taken to end at the last router that did.
+            let count = match reached {
+                Some(index) => index + 1,
+                None => hops
+                    .iter()
+                    .rposition(Option::is_some)
+                    .map_or(0, |index| index + 1),
+            };
+            self.record(&hops[..count], reached.is_some());
+
+            match reached.and_then(|index| hops[index].as_ref()) {
+                Some(hop) => Ok(hop.rtt),
+                None => Err(io::Error::new(
+                    io::ErrorKind::HostUnreachable,
+                    format!("{} was not reached within {} hops", ip, self.max_hops),
+                )
+                .into()),
+            }
+        })
+    }
+}
+
+impl Traceroute {
+    fn record(&self, hops: &[Option<icmp::Hop>], reached: bool) {
+        let labels = self.metrics.labels(&self.endpoint);
+        let mut path = self.path.lock().unwrap();
+
+        // Series of hops past the end of the path are removed, so a shorter path does not leave stale hops behind.
+        for (index, previous) in path.hops.iter().enumerate().skip(hops.len()) {
+            self.remove_hop(&labels, index, previous.address);
+        }
+        path.hops.resize_with(hops.len(), HopHistory::default);
+
+        // Hops that did not answer say nothing about the path, only a different router answering or the target
+        // being a different number of hops away counts as a change.
+        let mut changed = reached && path.length.is_some_and(|length| length != hops.len());
+        if reached {
+            path.length = Some(hops.len());
+        }
+
+        for (index, (hop, history)) in hops.iter().zip(path.hops.iter_mut()).enumerate() {
+            let number = (index + 1).to_string();
+            let hop_labels = [&labels[..], &[number.as_str()]].concat();
+
+            if history.answered.len() == self.loss_window {
+                history.answered.pop_front();
+            }
+            history.answered.push_back(hop.is_some());
+            let lost = history
+                .answered
+                .iter()
+                .filter(|answered| !**answered)
+                .count();
+            self.metrics
+                .traceroute_hop_loss_ratio
+                .with_label_values(&hop_labels)
+                .set(lost as f64 / history.answered.len() as f64);
+
+            let hop = match hop {
+                Some(hop) => hop,
+                None => {
+                    let _ = self
+                        .metrics
+                        .traceroute_hop_rtt
+                        .remove_label_values(&hop_labels);
+                    continue;
+                }
+            };
+            let rtt = hop.rtt.as_secs_f64();
+            self.metrics
+                .traceroute_hop_rtt
+                .with_label_values(&hop_labels)
+                .set(rtt);
+
+            if history.address != Some(hop.address) {
+                if let Some(previous) = history.address {
+                    changed = true;
+                    let previous = previous.to_string();
+                    let _ = self
+                        .metrics
+                        .traceroute_hop_info
+                        .remove_label_values(&[&labels[..], &[&number, &previous]].concat());
+                }
+                let address = hop.address.to_string();
+                self.metrics
+                    .traceroute_hop_info
+                    .with_label_values(&[&labels[..], &[&number, &address]].concat())
+                    .set(1);
+                history.address = Some(hop.address);
+            }
+
+            // The rolling statistics cover the answers of the last `loss_window` traceroutes, like mtr shows them.
+            if history.rtts.len() == self.loss_window {
+                history.rtts.pop_front();
+            }
+            history.rtts.push_back(rtt);
+            let samples = history.rtts.len() as f64;
+            let mean = history.rtts.iter().sum::<f64>() / samples;
+            let variance = history
+                .rtts
+                .iter()
+                .map(|rtt| (rtt - mean).powi(2))
+                .sum::<f64>()
+                / samples;
+            self.metrics
+                .traceroute_hop_rtt_avg
+                .with_label_values(&hop_labels)
+                .set(mean);
+            self.metrics
+                .traceroute_hop_rtt_best
+                .with_label_values(&hop_labels)
+                .set(history.rtts.iter().copied().fold(f64::INFINITY, f64::min));
+            self.metrics
+                .traceroute_hop_rtt_worst
+                .with_label_values(&hop_labels)
+                .set(history.rtts.iter().copied().fold(0.0, f64::max));
+            self.metrics
+                .traceroute_hop_rtt_stddev
+                .with_label_values(&hop_labels)
+                .set(variance.sqrt());
+        }
+
+        self.metrics
+            .traceroute_hop_count
+            .with_label_values(&labels)
+            .set(hops.len() as i64);
+        if changed {
+            info!("Path to {} changed", self.endpoint.name);
+            self.metrics.path_changed.with_label_values(&labels).inc();
+        }
+    }
+
+    fn remove_hop(&self, labels: &[&str], index: usize, address: Option<IpAddr>) {
+        let number = (index + 1).to_string();
+        let hop_labels = [labels, &[number.as_str()]].concat();
+
+        for vec in [
+            &self.metrics.traceroute_hop_rtt,
+            &self.metrics.traceroute_hop_loss_ratio,
+            &self.metrics.traceroute_hop_rtt_avg,
+            &self.metrics.traceroute_hop_rtt_best,
+            &self.metrics.traceroute_hop_rtt_worst,
+            &self.metrics.traceroute_hop_rtt_stddev,
+        ]
+        .iter()
+        {
+            let _ = vec.remove_label_values(&hop_labels);
+        }
+        if let Some(address) = address {
+            let address = address.to_string();
+            let _ = self
+                .metrics
+                .traceroute_hop_info
+                .remove_label_values(&[labels, &[&number, &address]].concat());
+        }
+    }
+}
+
+async fn dns_lookup(endpoint: &Endpoint, metrics: &Metrics, doh_ip: Option<IpAddr>) -> Result<(), BoxError> {
+    let source = Source::of(endpoint);
+    let response = match dns::parse_server(endpoint.resolver.as_deref().unwrap_or_default())? {
+        dns::Server::Udp(resolver) => dns::query(resolver, &endpoint.address, endpoint.record_type, &source).await?,
+        dns::Server::Tls(resolver) => {
+            let server_name = endpoint
+                .server_name
+                .clone()
+                .unwrap_or_else(|| resolver.ip().to_string());
+            dns::query_tls(
+                resolver,
+                &server_name,
+                !endpoint.insecure_skip_verify,
+                &endpoint.address,
+                endpoint.record_type,
+                &source,
+            )
+            .await?
+        }
+        dns::Server::Https(url) => {
+            let verify = if endpoint.insecure_skip_verify {
+                Verify::Skip
+            } else {
+                Verify::Roots
+            };
+            dns::query_https(
+                &url,
+                doh_ip.ok_or("the host of the DoH url was not resolved")?,
+                &verify,
+                &endpoint.address,
+                endpoint.record_type,
+                &source,
+            )
+            .await?
+        }
+    };
+
+    let labels = metrics.labels(endpoint);
+    let rcode = response.rcode_name();
+    metrics
+        .dns_responses
+        .with_label_values(&[&labels[..], &[&rcode]].concat())
+        .inc();
+    if response.rcode != 0 {
+        return Err(format!("resolver answered {}", rcode).into());
+    }
+
+    if let Some(expected) = &endpoint.expected_answer {
+        let matched = response.matches(expected);
+        metrics
+            .dns_answer_match
+            .with_label_values(&labels)
+            .set(matched as i64);
+        if !matched {
+            return Err(format!("answer did not contain `{}`", expected).into());
+        }
+    }
+
+    Ok(())
+}
+
+// The address is either a host, which is only connected to, or an https url that is also fetched over HTTP/3.
+#[cfg(feature = "quic")]
+async fn quic_handshake(endpoint: &Endpoint, ip: IpAddr) -> Result<Duration, BoxError> {
+    let url = http::parse_url(&endpoint.address).ok();
+    let host = url.as_ref().map_or(endpoint.address.as_str(), http::host);
+    let port = endpoint
+        .port
+        .or_else(|| url.as_ref().and_then(|url| url.port_u16()))
+        .unwrap_or(443);
+
+    crate::quic::probe(
+        (ip, port).into(),
+        endpoint.server_name.as_deref().unwrap_or(host),
+        !endpoint.insecure_skip_verify,
+        url.as_ref()
+            .map(|url| (url, endpoint.expected_status.as_slice())),
+        &Source::of(endpoint),
+    )
+    .await
+}
+
+#[cfg(not(feature = "quic"))]
+async fn quic_handshake(_endpoint: &Endpoint, _ip: IpAddr) -> Result<Duration, BoxError> {
+    Err("the quic probe needs rust-net-stab to be built with the `quic` feature".into())
+}
+
+#[cfg(feature = "tls")]
+async fn tls_handshake(endpoint: &Endpoint, metrics: &Metrics, ip: IpAddr) -> Result<(), BoxError> {
+    let server_name = endpoint.server_name.as_deref().unwrap_or(&endpoint.address);
+    let port = endpoint.port.unwrap_or(443);
+    let handshake = crate::tls::handshake(
+        ip,
+        port,
+        server_name,
+        !endpoint.insecure_skip_verify,
+        &Source::of(endpoint),
+    )
+    .await?;
+
+    let labels = metrics.labels(endpoint);
+    metrics
+        .tls_handshake_duration
+        .with_label_values(&labels)
+        .set(handshake.duration.as_secs_f64());
+    if let Some(not_after) = handshake.not_after {
+        metrics
+            .tls_cert_expiry
+            .with_label_values(&labels)
+            .set(not_after as f64);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "tls"))]
+async fn tls_handshake(_endpoint: &Endpoint, _metrics: &Metrics, _ip: IpAddr) -> Result<(), BoxError> {
+    Err("the tls probe needs rust-net-stab to be built with the `tls` feature".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(err: impl Into<BoxError>) -> &'static str {
+        failure_reason(&err.into())
+    }
+
+    #[test]
+    fn names_the_reason_of_a_failure() {
+        assert_eq!(reason(io::Error::new(io::ErrorKind::TimedOut, "timed out")), "timeout");
+        assert_eq!(
+            reason(io::Error::new(io::ErrorKind::PermissionDenied, "no ICMP socket")),
+            "permission_denied"
+        );
+        assert_eq!(
+            reason(io::Error::new(io::ErrorKind::ConnectionRefused, "refused")),
+            "unreachable"
+        );
+        assert_eq!(reason("unexpected status 500"), "error");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn counts_ping_command_failures_as_exec_errors() {
+        let exec_error = |kind, message: &str| io::Error::new(kind, pinger::ExecError(message.to_string()));
+        assert_eq!(
+            reason(exec_error(io::ErrorKind::PermissionDenied, "no ping command")),
+            "exec_error"
+        );
+        assert_eq!(
+            reason(exec_error(io::ErrorKind::Other, "ping failed: bad option")),
+            "exec_error"
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+
+use base64::engine::general_purpose::URL_SAFE;
+use base64::Engine;
+use hyper::Method;
+use log::{error, info};
+use prometheus::{Encoder, TextEncoder};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio::time::{Duration, MissedTickBehavior};
+
+use crate::{config, duration, http, BoxError};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PushgatewayConfig {
+    pub url: String,
+    #[serde(default = "default_job")]
+    pub job: String,
+    // Further labels of the group the metrics are pushed to, such as `instance`.
+    #[serde(default)]
+    pub grouping: BTreeMap<String, String>,
+    #[serde(default = "default_interval", with = "duration")]
+    pub interval: Duration,
+    pub username: Option<String>,
+    pub password_env: Option<String>,
+    pub password_file: Option<String>,
+    #[serde(default)]
+    pub delete_on_shutdown: bool,
+}
+
+fn default_job() -> String {
+    "rust-net-stab".to_string()
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(15)
+}
+
+impl PushgatewayConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        http::parse_url(&self.url).map_err(|err| err.to_string())?;
+        if self.job.is_empty() {
+            return Err("job must not be empty".to_string());
+        }
+        if self.password_env.is_some() && self.password_file.is_some() {
+            return Err("set only one of password_env or password_file".to_string());
+        }
+        if (self.password_env.is_some() || self.password_file.is_some()) && self.username.is_none() {
+            return Err("a password is set but no username".to_string());
+        }
+        self.password()?;
+        if self.interval.is_zero() {
+            return Err("interval must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn password(&self) -> Result<Option<String>, String> {
+        config::secret("password", &self.password_env, &self.password_file)
+    }
+
+    // The grouping key goes into the path, with values that cannot be written there encoded as base64.
+    fn group_url(&self) -> String {
+        let mut url = format!(
+            "{}/metrics/{}",
+            self.url.trim_end_matches('/'),
+            segment("job", &self.job)
+        );
+        for (name, value) in &self.grouping {
+            url.push('/');
+            url.push_str(&segment(name, value));
+        }
+        url
+    }
+}
+
+fn segment(name: &str, value: &str) -> String {
+    if value.is_empty() {
+        format!("{}@base64/=", name)
+    } else if value.contains('/') {
+        format!("{}@base64/{}", name, URL_SAFE.encode(value))
+    } else {
+        format!("{}/{}", name, http::percent_encode(value))
+    }
+}
+
+// Replaces the group's metrics with the current ones each `interval` until `stop` is set, then pushes once more or,
+// with `delete_on_shutdown`, deletes the group.
+pub async fn run(config: PushgatewayConfig, mut stop: watch::Receiver<bool>) {
+    let mut ticker = tokio::time::interval(config.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        let stopping = tokio::select! {
+            _ = ticker.tick() => false,
+            _ = stop.changed() => true,
+        };
+
+        if stopping && config.delete_on_shutdown {
+            match send(&config, Method::DELETE, Vec::new()).await {
+                Ok(()) => info!("Deleted the metrics group from {}", config.url),
+                Err(err) => error!("Failed to delete the metrics group from {}: {}", config.url, err),
+            }
+            break;
+        }
+
+        let mut body = Vec::new();
+        if let Err(err) = TextEncoder::new().encode(&prometheus::gather(), &mut body) {
+            error!("Failed to encode metrics for {}: {}", config.url, err);
+        } else if let Err(err) = send(&config, Method::PUT, body).await {
+            error!("Failed to push to {}: {}", config.url, err);
+        }
+        if stopping {
+            break;
+        }
+    }
+}
+
+async fn send(config: &PushgatewayConfig, method: Method, body: Vec<u8>) -> Result<(), BoxError> {
+    let mut headers = BTreeMap::new();
+    if let Some(username) = &config.username {
+        let password = config.password()?.unwrap_or_default();
+        headers.insert("Authorization".to_string(), http::basic_auth(username, &password));
+    }
+
+    http::upload(
+        method,
+        &config.group_url(),
+        TextEncoder::new().format_type(),
+        &headers,
+        body,
+    )
+    .await
+}
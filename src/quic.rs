@@ -0,0 +1,73 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use hyper::header::USER_AGENT;
+use hyper::{Request, Uri};
+use tokio_rustls::rustls::ClientConfig;
+
+use crate::http::StatusPattern;
+use crate::source::Source;
+use crate::BoxError;
+
+// Connects over QUIC offering HTTP/3, verified against `server_name` unless `verify` is off, and returns how long the
+// handshake took. With a `url` it is then fetched over the connection and its status checked.
+pub async fn probe(
+    server: SocketAddr,
+    server_name: &str,
+    verify: bool,
+    url: Option<(&Uri, &[StatusPattern])>,
+    source: &Source,
+) -> Result<Duration, BoxError> {
+    let socket = source.udp(server).await?.into_std()?;
+    let mut endpoint = quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        None,
+        socket,
+        Arc::new(quinn::TokioRuntime),
+    )?;
+    let mut crypto = ClientConfig::clone(&crate::tls::client_config(verify));
+    crypto.alpn_protocols = vec![b"h3".to_vec()];
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+
+    let start = Instant::now();
+    let connection = endpoint.connect(server, server_name)?.await?;
+    let duration = start.elapsed();
+
+    let fetched = match url {
+        Some((url, expected_status)) => get(connection.clone(), url, expected_status).await,
+        None => Ok(()),
+    };
+    connection.close(quinn::VarInt::from_u32(0), b"");
+    // The endpoint has to stay around for the close to be sent.
+    tokio::spawn(async move { endpoint.wait_idle().await });
+    fetched?;
+
+    Ok(duration)
+}
+
+async fn get(connection: quinn::Connection, url: &Uri, expected_status: &[StatusPattern]) -> Result<(), BoxError> {
+    let (mut driver, mut sender) = h3::client::new(h3_quinn::Connection::new(connection)).await?;
+    let driver = tokio::spawn(async move { std::future::poll_fn(|cx| driver.poll_close(cx)).await });
+
+    let status = async {
+        let request = Request::get(url.clone())
+            .header(USER_AGENT, concat!("rust-net-stab/", env!("CARGO_PKG_VERSION")))
+            .body(())?;
+        let mut stream = sender.send_request(request).await?;
+        stream.finish().await?;
+        Ok::<_, BoxError>(stream.recv_response().await?.status())
+    }
+    .await;
+    driver.abort();
+
+    let status = status?;
+    if expected_status
+        .iter()
+        .any(|pattern| pattern.matches(status))
+    {
+        Ok(())
+    } else {
+        Err(format!("unexpected status {}", status).into())
+    }
+}
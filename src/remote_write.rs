@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+
+use log::error;
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio::time::{Duration, MissedTickBehavior};
+
+use crate::{config, duration, http, status};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteWriteConfig {
+    pub url: String,
+    #[serde(default = "default_interval", with = "duration")]
+    pub interval: Duration,
+    pub username: Option<String>,
+    pub password_env: Option<String>,
+    pub password_file: Option<String>,
+    // Added to every series, usually to tell instances apart since there is no scrape to add `instance`.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(15)
+}
+
+impl RemoteWriteConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        http::parse_url(&self.url).map_err(|err| err.to_string())?;
+        if self.password_env.is_some() && self.password_file.is_some() {
+            return Err("set only one of password_env or password_file".to_string());
+        }
+        if (self.password_env.is_some() || self.password_file.is_some()) && self.username.is_none() {
+            return Err("a password is set but no username".to_string());
+        }
+        self.password()?;
+        if self.interval.is_zero() {
+            return Err("interval must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn password(&self) -> Result<Option<String>, String> {
+        config::secret("password", &self.password_env, &self.password_file)
+    }
+}
+
+// Sends every metric each `interval` until `stop` is set, and once more before returning.
+pub async fn run(config: RemoteWriteConfig, mut stop: watch::Receiver<bool>) {
+    let mut ticker = tokio::time::interval(config.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker.tick().await;
+
+    loop {
+        let stopping = tokio::select! {
+            _ = ticker.tick() => false,
+            _ = stop.changed() => true,
+        };
+
+        if let Err(err) = write(&config).await {
+            error!("Failed to remote write to {}: {}", config.url, err);
+        }
+        if stopping {
+            break;
+        }
+    }
+}
+
+async fn write(config: &RemoteWriteConfig) -> Result<(), crate::BoxError> {
+    let mut headers = BTreeMap::new();
+    headers.insert("Content-Encoding".to_string(), "snappy".to_string());
+    headers.insert("X-Prometheus-Remote-Write-Version".to_string(), "0.1.0".to_string());
+    if let Some(username) = &config.username {
+        let password = config.password()?.unwrap_or_default();
+        headers.insert("Authorization".to_string(), http::basic_auth(username, &password));
+    }
+
+    let timestamp = (status::unix_time() * 1000.0) as i64;
+    let mut request = Vec::new();
+    for family in prometheus::gather() {
+        for (labels, value) in samples(&family) {
+            let mut labels: Vec<(String, String)> = labels
+                .into_iter()
+                .chain(
+                    config
+                        .labels
+                        .iter()
+                        .map(|(name, value)| (name.clone(), value.clone())),
+                )
+                .collect();
+            // Configured labels come last, so the series' own labels win over them. Empty labels count as absent.
+            labels.sort_by(|a, b| a.0.cmp(&b.0));
+            labels.dedup_by(|a, b| a.0 == b.0);
+            labels.retain(|(_, value)| !value.is_empty());
+            message(&mut request, 1, &time_series(&labels, value, timestamp));
+        }
+    }
+
+    http::post(&config.url, "application/x-protobuf", &headers, snappy(&request)).await
+}
+
+// The samples of a family as exposed on /metrics, with histograms split into their bucket, sum and count series.
+fn samples(family: &MetricFamily) -> Vec<(Vec<(String, String)>, f64)> {
+    let name = family.get_name();
+    let labels = |metric: &Metric, name: String| -> Vec<(String, String)> {
+        let mut labels = vec![("__name__".to_string(), name)];
+        labels.extend(
+            metric
+                .get_label()
+                .iter()
+                .map(|pair| (pair.get_name().to_string(), pair.get_value().to_string())),
+        );
+        labels
+    };
+
+    let mut samples = Vec::new();
+    for metric in family.get_metric() {
+        match family.get_field_type() {
+            MetricType::GAUGE => samples.push((labels(metric, name.to_string()), metric.get_gauge().get_value())),
+            MetricType::COUNTER => samples.push((labels(metric, name.to_string()), metric.get_counter().get_value())),
+            MetricType::HISTOGRAM => {
+                let histogram = metric.get_histogram();
+                for bucket in histogram.get_bucket() {
+                    let mut bucket_labels = labels(metric, format!("{}_bucket", name));
+                    bucket_labels.push(("le".to_string(), bucket.get_upper_bound().to_string()));
+                    samples.push((bucket_labels, bucket.get_cumulative_count() as f64));
+                }
+                let mut infinity = labels(metric, format!("{}_bucket", name));
+                infinity.push(("le".to_string(), "+Inf".to_string()));
+                samples.push((infinity, histogram.get_sample_count() as f64));
+                samples.push((labels(metric, format!("{}_sum", name)), histogram.get_sample_sum()));
+                samples.push((
+                    labels(metric, format!("{}_count", name)),
+                    histogram.get_sample_count() as f64,
+                ));
+            }
+            _ => {}
+        }
+    }
+    samples
+}
+
+// A `TimeSeries` of the remote write protobuf, holding a single sample.
+fn time_series(labels: &[(String, String)], value: f64, timestamp: i64) -> Vec<u8> {
+    let mut series = Vec::new();
+    for (name, value) in labels {
+        let mut label = Vec::new();
+        message(&mut label, 1, name.as_bytes());
+        message(&mut label, 2, value.as_bytes());
+        message(&mut series, 1, &label);
+    }
+
+    let mut sample = Vec::new();
+    sample.push(1 << 3 | 1);
+    sample.extend_from_slice(&value.to_le_bytes());
+    sample.push(2 << 3);
+    varint(&mut sample, timestamp as u64);
+    message(&mut series, 2, &sample);
+    series
+}
+
+// A length delimited protobuf field, which is how strings and nested messages are encoded.
+fn message(buffer: &mut Vec<u8>, field: u8, bytes: &[u8]) {
+    buffer.push(field << 3 | 2);
+    varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+// Snappy block format made up of literals only. It does not make the request any smaller, but it is what remote
+// write receivers expect and is cheap to produce.
+fn snappy(data: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::with_capacity(data.len() + data.len() / 65536 * 3 + 8);
+    varint(&mut compressed, data.len() as u64);
+    for chunk in data.chunks(65536) {
+        let length = chunk.len() - 1;
+        if length < 60 {
+            compressed.push((length as u8) << 2);
+        } else if length < 256 {
+            compressed.push(60 << 2);
+            compressed.push(length as u8);
+        } else {
+            compressed.push(61 << 2);
+            compressed.extend_from_slice(&(length as u16).to_le_bytes());
+        }
+        compressed.extend_from_slice(chunk);
+    }
+    compressed
+}
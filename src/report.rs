@@ -0,0 +1,391 @@
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::file_output::csv_field;
+use crate::history::{History, Record};
+use crate::smtp::format_timestamp;
+use crate::{config, duration, status, BoxError};
+
+const PERCENTILES: [f64; 4] = [0.5, 0.9, 0.95, 0.99];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+    Html,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Result<Format, String> {
+        match value {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "html" => Ok(Format::Html),
+            _ => Err(format!("invalid report format `{}`, expected json, csv or html", value)),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+// The range and endpoint a report covers, as given on the command line or to /api/report.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ReportOptions {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub endpoint: Option<String>,
+    #[serde(default)]
+    pub format: Format,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub from: f64,
+    pub to: f64,
+    pub endpoints: Vec<EndpointReport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointReport {
+    pub name: String,
+    pub ip_version: Option<String>,
+    pub probes: u64,
+    pub failures: u64,
+    // The share of the time between the first and the last probe that the endpoint was not in an outage.
+    pub availability: f64,
+    // An outage lasts from the first failed probe until the next successful one.
+    pub outages: u64,
+    pub downtime_seconds: f64,
+    // The mean duration of the outages that ended within the range.
+    pub mttr_seconds: Option<f64>,
+    pub rtt_p50_seconds: Option<f64>,
+    pub rtt_p90_seconds: Option<f64>,
+    pub rtt_p95_seconds: Option<f64>,
+    pub rtt_p99_seconds: Option<f64>,
+}
+
+// A point in time given as a Unix timestamp, a UTC date such as `2024-01-31` or `2024-01-31T12:00:00`, or a duration
+// such as `30d` meaning that long ago.
+pub fn parse_time(value: &str) -> Result<f64, String> {
+    let value = value.trim();
+    if let Ok(timestamp) = value.parse::<f64>() {
+        return Ok(timestamp);
+    }
+    if value.len() >= 10 && value.as_bytes()[4] == b'-' {
+        return parse_date(value).ok_or_else(|| format!("invalid date `{}`, expected YYYY-MM-DD[THH:MM:SS]", value));
+    }
+    let ago = duration::parse(value).map_err(|_| {
+        format!(
+            "invalid time `{}`, expected a Unix timestamp, a date or a duration such as 30d",
+            value
+        )
+    })?;
+    Ok(status::unix_time() - ago.as_secs_f64())
+}
+
+// Dates are written as `2024-05-01`, optionally followed by the time as `T12:30:00` or ` 12:30`.
+pub(crate) fn parse_date(value: &str) -> Option<f64> {
+    let value = value.trim_end_matches('Z');
+    let (date, time) = value.split_once(['T', ' ']).unwrap_or((value, "00:00:00"));
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>());
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.splitn(3, ':').map(|part| part.parse::<i64>());
+    let hour = time.next().unwrap_or(Ok(0)).ok()?;
+    let minute = time.next().unwrap_or(Ok(0)).ok()?;
+    let second = time.next().unwrap_or(Ok(0)).ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    let days = status::days_from_civil(year, month, day);
+    Some((days * 86400 + hour * 3600 + minute * 60 + second) as f64)
+}
+
+// Covers the last 30 days unless the options say otherwise.
+pub async fn generate(history: &History, options: &ReportOptions) -> Result<Report, BoxError> {
+    let to = match &options.to {
+        Some(to) => parse_time(to)?,
+        None => status::unix_time(),
+    };
+    let from = match &options.from {
+        Some(from) => parse_time(from)?,
+        None => to - 30.0 * 86400.0,
+    };
+    if from >= to {
+        return Err("the start of the report must be before its end".into());
+    }
+
+    let records = history.series(options.endpoint.clone(), from, to).await?;
+    let endpoints = records
+        .chunk_by(|a, b| a.name == b.name && a.ip_version == b.ip_version)
+        .map(endpoint_report)
+        .collect();
+
+    Ok(Report { from, to, endpoints })
+}
+
+fn endpoint_report(records: &[Record]) -> EndpointReport {
+    let mut failures = 0;
+    let mut outages = Vec::new();
+    let mut outage_start = None;
+    for record in records {
+        if !record.success {
+            failures += 1;
+            outage_start.get_or_insert(record.timestamp);
+        } else if let Some(start) = outage_start.take() {
+            outages.push(record.timestamp - start);
+        }
+    }
+    let resolved = outages.len();
+    let last = records[records.len() - 1].timestamp;
+    if let Some(start) = outage_start {
+        outages.push(last - start);
+    }
+
+    let downtime = outages.iter().fold(0.0, |total, outage| total + outage);
+    let span = last - records[0].timestamp;
+    let availability = if span > 0.0 {
+        1.0 - downtime / span
+    } else {
+        (failures == 0) as u8 as f64
+    };
+
+    let mut rtts: Vec<f64> = records
+        .iter()
+        .filter_map(|record| record.rtt_seconds)
+        .collect();
+    rtts.sort_by(|a, b| a.total_cmp(b));
+    let [p50, p90, p95, p99] = PERCENTILES.map(|percentile| {
+        let rank = (percentile * rtts.len() as f64).ceil() as usize;
+        rtts.get(rank.saturating_sub(1)).copied()
+    });
+
+    EndpointReport {
+        name: records[0].name.clone(),
+        ip_version: records[0].ip_version.clone(),
+        probes: records.len() as u64,
+        failures,
+        availability,
+        outages: outages.len() as u64,
+        downtime_seconds: downtime,
+        mttr_seconds: (resolved > 0).then(|| {
+            outages[..resolved]
+                .iter()
+                .fold(0.0, |total, outage| total + outage)
+                / resolved as f64
+        }),
+        rtt_p50_seconds: p50,
+        rtt_p90_seconds: p90,
+        rtt_p95_seconds: p95,
+        rtt_p99_seconds: p99,
+    }
+}
+
+pub fn render(report: &Report, format: Format) -> String {
+    match format {
+        Format::Json => serde_json::to_string_pretty(report).unwrap_or_default() + "\n",
+        Format::Csv => csv(report),
+        Format::Html => html(report),
+    }
+}
+
+fn optional(value: Option<f64>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn csv(report: &Report) -> String {
+    let mut csv = String::from(
+        "name,ip_version,probes,failures,availability,outages,downtime_seconds,mttr_seconds,rtt_p50_seconds,\
+         rtt_p90_seconds,rtt_p95_seconds,rtt_p99_seconds\n",
+    );
+    for endpoint in &report.endpoints {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{}\n",
+            csv_field(&endpoint.name),
+            endpoint.ip_version.as_deref().unwrap_or_default(),
+            endpoint.probes,
+            endpoint.failures,
+            endpoint.availability,
+            endpoint.outages,
+            endpoint.downtime_seconds,
+            optional(endpoint.mttr_seconds),
+            optional(endpoint.rtt_p50_seconds),
+            optional(endpoint.rtt_p90_seconds),
+            optional(endpoint.rtt_p95_seconds),
+            optional(endpoint.rtt_p99_seconds),
+        ));
+    }
+    csv
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn html(report: &Report) -> String {
+    let seconds = |value: Option<f64>| match value {
+        Some(value) => format!("{:.0} s", value),
+        None => "-".to_string(),
+    };
+    let milliseconds = |value: Option<f64>| match value {
+        Some(value) => format!("{:.2} ms", value * 1000.0),
+        None => "-".to_string(),
+    };
+
+    let mut rows = String::new();
+    for endpoint in &report.endpoints {
+        let name = match &endpoint.ip_version {
+            Some(ip_version) => format!("{} ({})", endpoint.name, ip_version),
+            None => endpoint.name.clone(),
+        };
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{:.3}%</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+             <td>{}</td><td>{}</td></tr>\n",
+            escape(&name),
+            endpoint.availability * 100.0,
+            endpoint.probes,
+            endpoint.failures,
+            endpoint.outages,
+            seconds(Some(endpoint.downtime_seconds)),
+            seconds(endpoint.mttr_seconds),
+            milliseconds(endpoint.rtt_p50_seconds),
+            milliseconds(endpoint.rtt_p95_seconds),
+            milliseconds(endpoint.rtt_p99_seconds),
+        ));
+    }
+
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Availability report</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ padding: 0.3em 0.8em; border-bottom: 1px solid #ddd; text-align: right; }}
+th:first-child, td:first-child {{ text-align: left; }}
+</style>
+</head>
+<body>
+<h1>Availability report</h1>
+<p>{} to {}</p>
+<table>
+<tr><th>Endpoint</th><th>Availability</th><th>Probes</th><th>Failures</th><th>Outages</th><th>Downtime</th>\
+<th>MTTR</th><th>p50 RTT</th><th>p95 RTT</th><th>p99 RTT</th></tr>
+{}</table>
+</body>
+</html>
+",
+        format_timestamp(report.from),
+        format_timestamp(report.to),
+        rows
+    )
+}
+
+// The `report` command, which prints a report from the history store named in the config.
+pub async fn print(config_path: &str, options: &ReportOptions) -> Result<(), BoxError> {
+    let config = config::load(config_path)?;
+    let history = config
+        .history
+        .as_ref()
+        .ok_or_else(|| format!("{} has no history section to report from", config_path))?;
+    let report = generate(&History::open(history)?, options).await?;
+
+    io::stdout().write_all(render(&report, options.format).as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::{broadcast, watch};
+    use tokio::time::Duration;
+
+    use super::*;
+    use crate::history::{self, HistoryConfig};
+    use crate::status::ProbeEvent;
+
+    fn event(timestamp: f64, rtt_seconds: Option<f64>) -> ProbeEvent {
+        ProbeEvent {
+            name: "gateway".to_string(),
+            address: "192.0.2.1".to_string(),
+            location: None,
+            probe_type: "icmp",
+            ip_version: None,
+            up: rtt_seconds.is_some(),
+            rtt_seconds,
+            loss_ratio: 0.0,
+            error: rtt_seconds.is_none().then(|| "timed out".to_string()),
+            reason: None,
+            maintenance: false,
+            timestamp,
+            trace_id: None,
+        }
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0.0));
+        assert_eq!(parse_date("2024-01-31"), Some(1706659200.0));
+        assert_eq!(parse_date("2024-02-29T12:30:15Z"), Some(1709209815.0));
+        assert_eq!(parse_date("2024-05-01 12:30"), Some(1714566600.0));
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(parse_date("2024-01-01T24:00:00"), None);
+        assert_eq!(parse_time("1700000000").unwrap(), 1700000000.0);
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[tokio::test]
+    async fn reports_outages_from_the_history() {
+        let history = History::open(&HistoryConfig {
+            path: ":memory:".to_string(),
+            retention: Duration::from_secs(86400),
+        })
+        .unwrap();
+        let (events, receiver) = broadcast::channel(16);
+        let (stop, stopped) = watch::channel(false);
+        let task = tokio::spawn(history::run(history.clone(), receiver, stopped));
+        for (timestamp, rtt) in [
+            (100.0, Some(0.01)),
+            (110.0, None),
+            (120.0, None),
+            (130.0, Some(0.02)),
+            (140.0, Some(0.03)),
+        ] {
+            events.send(event(timestamp, rtt)).unwrap();
+        }
+        stop.send(true).unwrap();
+        task.await.unwrap();
+
+        let options = ReportOptions {
+            from: Some("0".to_string()),
+            to: Some("1000".to_string()),
+            ..ReportOptions::default()
+        };
+        let report = generate(&history, &options).await.unwrap();
+        assert_eq!(report.endpoints.len(), 1);
+        let endpoint = &report.endpoints[0];
+        assert_eq!((endpoint.probes, endpoint.failures, endpoint.outages), (5, 2, 1));
+        assert_eq!(endpoint.downtime_seconds, 20.0);
+        assert_eq!(endpoint.mttr_seconds, Some(20.0));
+        assert_eq!(endpoint.availability, 0.5);
+        assert_eq!(endpoint.rtt_p50_seconds, Some(0.02));
+        assert_eq!(endpoint.rtt_p99_seconds, Some(0.03));
+
+        let csv = render(&report, Format::Csv);
+        assert_eq!(csv.lines().nth(1), Some("gateway,,5,2,0.5,1,20,20,0.02,0.03,0.03,0.03"));
+    }
+}
@@ -0,0 +1,132 @@
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use log::{info, warn};
+use tokio::time::Duration;
+
+use crate::config::{Endpoint, Settings};
+use crate::icmp::{self, ResolveError};
+use crate::metrics::{self, Metrics};
+use crate::source::Source;
+
+// Resolves the host an endpoint is probed at, reusing the address until `resolve_interval` has passed.
+pub struct Resolver {
+    host: String,
+    endpoint: Endpoint,
+    metrics: Metrics,
+    interval: Duration,
+    cached: Mutex<Option<(IpAddr, Instant)>>,
+}
+
+impl Resolver {
+    pub fn new(host: &str, endpoint: &Endpoint, settings: &Settings, metrics: &Metrics) -> Self {
+        Resolver {
+            host: host.to_string(),
+            endpoint: endpoint.clone(),
+            metrics: metrics.clone(),
+            interval: settings.resolve_interval,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub async fn resolve(&self) -> Result<IpAddr, ResolveError> {
+        let cached = *self.cached.lock().unwrap();
+        if let Some((ip, resolved_at)) = cached {
+            if resolved_at.elapsed() < self.interval {
+                return Ok(ip);
+            }
+        }
+
+        let labels = self.metrics.labels(&self.endpoint);
+        let start = Instant::now();
+        // Without an IP version the source address decides which family the host is resolved to.
+        let ip_version = self
+            .endpoint
+            .ip_version
+            .or_else(|| Source::of(&self.endpoint).ip_version());
+        let resolved = icmp::resolve(&self.host, ip_version).await;
+        // Addresses that are IP literals are not looked up, so there is nothing to time.
+        if self.host.parse::<IpAddr>().is_err() {
+            self.metrics
+                .resolution_duration
+                .with_label_values(&labels)
+                .set(start.elapsed().as_secs_f64());
+        }
+        let ip = match resolved {
+            Ok(ip) => ip,
+            Err(err) => {
+                self.metrics
+                    .resolution_failures
+                    .with_label_values(&labels)
+                    .inc();
+                // A failed lookup keeps the last address in use, so a flaky resolver does not take the endpoint down.
+                // It is kept for another interval, rather than looked up again by every probe while the resolver is
+                // down.
+                return match cached {
+                    Some((ip, _)) => {
+                        warn!("Still probing {} at {}: {}", self.endpoint.key(), ip, err);
+                        *self.cached.lock().unwrap() = Some((ip, Instant::now()));
+                        Ok(ip)
+                    }
+                    None => Err(err),
+                };
+            }
+        };
+
+        match cached {
+            Some((previous, _)) if previous == ip => {}
+            Some((previous, _)) => {
+                info!("Address of {} changed from {} to {}", self.endpoint.key(), previous, ip);
+                let previous = previous.to_string();
+                let _ = self
+                    .metrics
+                    .resolved_address
+                    .remove_label_values(&[&labels[..], &[&previous]].concat());
+            }
+            // A restarted probe may have left the series of an address from before behind.
+            None => metrics::remove_series(&self.metrics.resolved_address, &labels),
+        }
+        let address = ip.to_string();
+        self.metrics
+            .resolved_address
+            .with_label_values(&[&labels[..], &[&address]].concat())
+            .set(1);
+
+        *self.cached.lock().unwrap() = Some((ip, Instant::now()));
+        Ok(ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Format};
+    use prometheus::Registry;
+
+    #[tokio::test]
+    async fn failed_lookup_waits_an_interval() {
+        let config = config::parse(
+            "resolve_interval: 1m\nendpoints:\n  - name: gone\n    address: gone.invalid\n",
+            Format::Yaml,
+        )
+        .unwrap();
+        let endpoint = &config.endpoints[0];
+        let metrics = Metrics::register_in(&config, &Registry::new()).unwrap();
+        let resolver = Resolver::new(&endpoint.address, endpoint, &endpoint.settings(&config), &metrics);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let stale = Instant::now() - Duration::from_secs(120);
+        *resolver.cached.lock().unwrap() = Some((ip, stale));
+
+        assert_eq!(resolver.resolve().await.unwrap(), ip);
+        let failures = || {
+            metrics
+                .resolution_failures
+                .with_label_values(&metrics.labels(endpoint))
+                .get()
+        };
+        assert_eq!(failures(), 1);
+        assert_eq!(resolver.resolve().await.unwrap(), ip);
+        assert_eq!(failures(), 1);
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use log::{info, warn};
+use prometheus::Histogram;
+use rand::Rng;
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, timeout_at, Duration, Instant};
+
+use crate::alerts::AlertConfig;
+use crate::config::{Config, Endpoint, Settings};
+use crate::cron::Cron;
+use crate::metrics::Metrics;
+use crate::status::{unix_time, StatusBoard};
+use crate::timezone::Timezone;
+use crate::BoxError;
+use crate::{probe, throughput};
+
+struct RunningProbe {
+    endpoint: Endpoint,
+    settings: Settings,
+    latency: Histogram,
+    handle: JoinHandle<()>,
+    throughput: Option<JoinHandle<()>>,
+}
+
+impl RunningProbe {
+    fn abort(&self) {
+        self.handle.abort();
+        if let Some(throughput) = &self.throughput {
+            throughput.abort();
+        }
+    }
+}
+
+// Owns one probe task per endpoint and reconciles them with the config whenever it changes.
+pub struct Scheduler {
+    metrics: Metrics,
+    board: StatusBoard,
+    alerts: watch::Sender<AlertConfig>,
+    shutdown: watch::Receiver<bool>,
+    max_concurrency: Option<usize>,
+    limit: Option<Arc<Semaphore>>,
+    max_probe_rate: Option<f64>,
+    rate_limit: Option<Arc<RateLimit>>,
+    running: HashMap<String, RunningProbe>,
+}
+
+impl Scheduler {
+    pub fn new(
+        metrics: Metrics,
+        board: StatusBoard,
+        alerts: watch::Sender<AlertConfig>,
+        shutdown: watch::Receiver<bool>,
+    ) -> Self {
+        Scheduler {
+            metrics,
+            board,
+            alerts,
+            shutdown,
+            max_concurrency: None,
+            limit: None,
+            max_probe_rate: None,
+            rate_limit: None,
+            running: HashMap::new(),
+        }
+    }
+
+    pub fn apply(&mut self, config: &Config) -> Result<(), BoxError> {
+        let targets: Vec<Endpoint> = config
+            .endpoints
+            .iter()
+            .flat_map(Endpoint::targets)
+            .collect();
+        let keys: HashSet<String> = targets.iter().map(Endpoint::key).collect();
+        self.alerts.send_replace(AlertConfig {
+            rules: config.alerts.clone(),
+            smtp: config.smtp.clone(),
+            endpoints: keys.clone(),
+        });
+        let removed: Vec<String> = self
+            .running
+            .keys()
+            .filter(|key| !keys.contains(*key))
+            .cloned()
+            .collect();
+        for key in removed {
+            if let Some(probe) = self.running.remove(&key) {
+                probe.abort();
+                self.metrics.remove(&self.metrics.labels(&probe.endpoint));
+                let _ = prometheus::unregister(Box::new(probe.latency));
+                self.board.remove(&key);
+                info!("Stopped probing {}", key);
+            }
+        }
+
+        // Probes keep the semaphore and rate limit they were started with, so they all have to move over to the new
+        // ones.
+        let limit_changed =
+            config.max_concurrency != self.max_concurrency || config.max_probe_rate != self.max_probe_rate;
+        if limit_changed {
+            self.max_concurrency = config.max_concurrency;
+            self.limit = config
+                .max_concurrency
+                .map(|limit| Arc::new(Semaphore::new(limit)));
+            self.max_probe_rate = config.max_probe_rate;
+            self.rate_limit = config
+                .max_probe_rate
+                .map(|rate| Arc::new(RateLimit::new(rate)));
+        }
+
+        let mut errors = Vec::new();
+        for endpoint in &targets {
+            let key = endpoint.key();
+            let settings = endpoint.settings(config);
+
+            // Series are kept for endpoints that only changed settings, so their counters carry on.
+            let mut latency = None;
+            if let Some(probe) = self.running.get(&key) {
+                if !limit_changed && probe.endpoint == *endpoint && probe.settings == settings {
+                    continue;
+                }
+
+                let probe = self.running.remove(&key).unwrap();
+                probe.abort();
+                let same_labels = self.metrics.labels(&probe.endpoint) == self.metrics.labels(endpoint);
+                if !same_labels {
+                    self.metrics.remove(&self.metrics.labels(&probe.endpoint));
+                }
+                if same_labels && probe.settings.latency_buckets == settings.latency_buckets {
+                    latency = Some(probe.latency);
+                } else {
+                    let _ = prometheus::unregister(Box::new(probe.latency));
+                }
+                info!("Restarting probe for {}", key);
+            }
+
+            let latency = match latency {
+                Some(latency) => latency,
+                None => match self
+                    .metrics
+                    .register_latency(&self.metrics.labels(endpoint), settings.latency_buckets.clone())
+                {
+                    Ok(latency) => latency,
+                    Err(err) => {
+                        errors.push(format!(
+                            "endpoint `{}`: invalid latency_buckets: {}",
+                            endpoint.name, err
+                        ));
+                        continue;
+                    }
+                },
+            };
+
+            for name in endpoint.labels.keys() {
+                if !self.metrics.extra_labels.contains(name) {
+                    warn!(
+                        "Endpoint `{}`: label `{}` was added after startup and is not exported until a restart",
+                        endpoint.name, name
+                    );
+                }
+            }
+
+            self.board.insert(endpoint.status());
+            let handle = tokio::spawn(probe::run(
+                probe::build(endpoint, &settings, &self.metrics),
+                endpoint.clone(),
+                settings.clone(),
+                self.metrics.clone(),
+                latency.clone(),
+                self.board.clone(),
+                Schedule::new(
+                    &settings,
+                    self.limit.clone(),
+                    self.rate_limit.clone(),
+                    self.shutdown.clone(),
+                ),
+            ));
+            // Throughput tests are spread out over their first interval as well, so they do not all saturate the links
+            // at once, and are not held to `max_concurrency` or `max_probe_rate`.
+            let throughput = endpoint.throughput.as_ref().map(|throughput| {
+                let throughput_settings = Settings {
+                    interval: throughput.interval(),
+                    stagger: true,
+                    schedule: throughput.schedule.clone(),
+                    timezone: throughput.timezone.clone(),
+                    adaptive: None,
+                    ..settings.clone()
+                };
+                tokio::spawn(throughput::run(
+                    endpoint.clone(),
+                    throughput.clone(),
+                    self.metrics.clone(),
+                    Schedule::new(&throughput_settings, None, None, self.shutdown.clone()),
+                ))
+            });
+            self.running.insert(
+                key,
+                RunningProbe {
+                    endpoint: endpoint.clone(),
+                    settings,
+                    latency,
+                    handle,
+                    throughput,
+                },
+            );
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; ").into())
+        }
+    }
+
+    // Waits for the probe tasks to finish after the shutdown signal went out, giving up at the deadline.
+    // Throughput tests take too long to wait for and are stopped right away.
+    pub async fn drain(self, deadline: Instant) {
+        for (name, probe) in self.running {
+            if let Some(throughput) = &probe.throughput {
+                throughput.abort();
+            }
+            if timeout_at(deadline, probe.handle).await.is_err() {
+                warn!("Probe for {} did not finish before the shutdown timeout", name);
+            }
+        }
+    }
+}
+
+// Decides when an endpoint is probed: every interval give or take the jitter, or whenever its cron schedule matches,
+// and only while fewer than `max_concurrency` probes are running.
+pub struct Schedule {
+    next: Instant,
+    started: Instant,
+    interval: Duration,
+    jitter: f64,
+    cron: Option<(Cron, Timezone)>,
+    limit: Option<Arc<Semaphore>>,
+    rate_limit: Option<Arc<RateLimit>>,
+    shutdown: watch::Receiver<bool>,
+}
+
+// Held for as long as the probe runs, freeing its place under `max_concurrency` when dropped.
+pub struct Slot {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Schedule {
+    pub fn new(
+        settings: &Settings,
+        limit: Option<Arc<Semaphore>>,
+        rate_limit: Option<Arc<RateLimit>>,
+        shutdown: watch::Receiver<bool>,
+    ) -> Self {
+        // Staggered endpoints start at a random point of their first interval instead of all at once.
+        let offset = if settings.stagger {
+            settings.interval.mul_f64(rand::random())
+        } else {
+            Duration::ZERO
+        };
+
+        // The config has been validated, so the schedule and its timezone parse.
+        let cron = settings.schedule.as_deref().and_then(|schedule| {
+            let timezone = match &settings.timezone {
+                Some(timezone) => Timezone::parse(timezone).ok()?,
+                None => Timezone::Fixed(0),
+            };
+            Some((Cron::parse(schedule).ok()?, timezone))
+        });
+
+        let mut schedule = Schedule {
+            next: Instant::now() + offset,
+            started: Instant::now(),
+            interval: settings.interval,
+            jitter: settings.interval_jitter,
+            cron,
+            limit,
+            rate_limit,
+            shutdown,
+        };
+        if schedule.cron.is_some() {
+            schedule.next = schedule.scheduled();
+        }
+        schedule
+    }
+
+    // Waits until the next probe is due, returning `None` once shutting down.
+    pub async fn next(&mut self) -> Option<Slot> {
+        // A probe that overran its interval is followed by the next one right away, with the interval counted from then.
+        let due = self.next;
+        let late = Instant::now() >= due;
+        tokio::select! {
+            _ = sleep_until(due) => {}
+            _ = self.shutdown.changed() => return None,
+        }
+        let start = if late { Instant::now() } else { due };
+        self.started = start;
+        self.next = if self.cron.is_some() {
+            self.scheduled()
+        } else {
+            start + self.jittered()
+        };
+
+        let permit = match &self.limit {
+            Some(limit) => tokio::select! {
+                permit = limit.clone().acquire_owned() => permit.ok(),
+                _ = self.shutdown.changed() => return None,
+            },
+            None => None,
+        };
+
+        Some(Slot { _permit: permit })
+    }
+
+    // Waits for the turn of the next request under `max_probe_rate`, returning whether it had to wait.
+    pub async fn pace(&self) -> bool {
+        match &self.rate_limit {
+            Some(rate_limit) => rate_limit.wait().await,
+            None => false,
+        }
+    }
+
+    // Changes the interval from the probe that was started last on, as adaptive endpoints do.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+        self.next = self.started + self.jittered();
+    }
+
+    // The next time the cron schedule matches. Those are whole minutes, so a probe that is due now, and woke up a
+    // little early, is not due again until the next one.
+    fn scheduled(&self) -> Instant {
+        let now = unix_time();
+        let next = self
+            .cron
+            .as_ref()
+            .and_then(|(cron, timezone)| cron.next(now as i64 + 1, timezone));
+        match next {
+            Some(next) => Instant::now() + Duration::from_secs_f64((next as f64 - now).max(0.0)),
+            None => Instant::now() + Duration::from_secs(86400),
+        }
+    }
+
+    fn jittered(&self) -> Duration {
+        if self.jitter == 0.0 {
+            return self.interval;
+        }
+        let factor = 1.0 + rand::thread_rng().gen_range(-self.jitter..=self.jitter);
+        self.interval.mul_f64(factor)
+    }
+}
+
+// Spaces requests out evenly across every endpoint, so that no more than `rate` go out a second.
+pub struct RateLimit {
+    spacing: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimit {
+    pub fn new(rate: f64) -> Self {
+        RateLimit {
+            spacing: Duration::from_secs_f64(1.0 / rate),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Returns whether the request had to wait for its turn.
+    pub async fn wait(&self) -> bool {
+        let now = Instant::now();
+        let turn = {
+            let mut next = self.next.lock().unwrap();
+            let turn = (*next).max(now);
+            *next = turn + self.spacing;
+            turn
+        };
+        if turn <= now {
+            return false;
+        }
+        sleep_until(turn).await;
+        true
+    }
+}
@@ -0,0 +1,599 @@
+use std::net::SocketAddr;
+
+#[cfg(feature = "gzip")]
+use log::warn;
+use prometheus::{Encoder, IntCounter, TextEncoder};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, watch};
+use tokio::time::{Duration, Instant};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+use warp::http::StatusCode;
+use warp::reply::{self, Reply};
+use warp::sse::Event;
+use warp::Filter;
+
+use crate::aggregator::{Aggregator, PushQuery};
+use crate::auth::{self, Credentials};
+use crate::config::Config;
+use crate::duration;
+use crate::exemplars;
+use crate::expand::Block;
+use crate::exporter::{self, ProbeQuery};
+use crate::exposition;
+use crate::history::{History, Query};
+use crate::http::percent_decode;
+use crate::report::{self, ReportOptions};
+use crate::status::StatusBoard;
+use crate::targets::{Command, Rejection, TargetsApi};
+
+// Serves the metrics and the API over HTTPS rather than plain HTTP, for networks where scrape targets have to be
+// encrypted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    // PEM files with the certificate chain, the server's own certificate first, and its private key.
+    pub cert_file: String,
+    pub key_file: String,
+    // Only let in clients with a certificate signed by one of the CAs in this PEM file.
+    pub client_ca_file: Option<String>,
+}
+
+impl TlsConfig {
+    // Loads the files, so that a wrong path or a key that does not match the certificate fails validation.
+    #[cfg(feature = "tls")]
+    pub fn validate(&self) -> Result<(), String> {
+        crate::tls::server_config(self).map(|_| ())
+    }
+
+    #[cfg(not(feature = "tls"))]
+    pub fn validate(&self) -> Result<(), String> {
+        Err("serving over TLS needs rust-net-stab to be built with the `tls` feature".to_string())
+    }
+}
+
+// Settings of the HTTP server that apply to every route.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    // Only requests from these CIDR blocks or addresses are answered, from anywhere when empty.
+    #[serde(default)]
+    pub allowed_cidrs: Vec<Block>,
+    // `/readyz` answers ready after this long even when some endpoints have not been probed yet, as those with long
+    // intervals or staggered starts take a while to be.
+    #[serde(default = "default_ready_grace_period", with = "duration")]
+    pub ready_grace_period: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            allowed_cidrs: Vec::new(),
+            ready_grace_period: default_ready_grace_period(),
+        }
+    }
+}
+
+fn default_ready_grace_period() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl ServerConfig {
+    // Requests whose source is not known are let through only when there is no allowlist.
+    fn allows(&self, remote: Option<SocketAddr>) -> bool {
+        self.allowed_cidrs.is_empty()
+            || remote.is_some_and(|remote| {
+                self.allowed_cidrs
+                    .iter()
+                    .any(|block| block.contains(remote.ip()))
+            })
+    }
+}
+
+// What `/readyz` answers: ready once every endpoint has been probed or the grace period is over, and until the
+// shutdown starts.
+#[derive(Debug, Serialize)]
+struct Readiness {
+    ready: bool,
+    endpoints: usize,
+    probed: usize,
+    uptime_seconds: f64,
+    stopping: bool,
+}
+
+impl Readiness {
+    fn of(board: &StatusBoard, uptime: Duration, grace_period: Duration, stopping: bool) -> Readiness {
+        let endpoints = board.snapshot().endpoints;
+        // Endpoints whose probes are skipped for maintenance have no results to wait for.
+        let probed = endpoints
+            .iter()
+            .filter(|endpoint| endpoint.last_probe.is_some() || endpoint.maintenance)
+            .count();
+        Readiness {
+            ready: !stopping && (probed == endpoints.len() || uptime >= grace_period),
+            endpoints: endpoints.len(),
+            probed,
+            uptime_seconds: uptime.as_secs_f64(),
+            stopping,
+        }
+    }
+}
+
+// The address a connection came from, added to the requests of connections the server accepts itself rather than
+// through warp, which only knows the address of its own.
+#[derive(Debug, Clone, Copy)]
+pub struct Peer(pub SocketAddr);
+
+// Where the server listens, and with a tls section the certificate it serves there.
+pub struct Listen {
+    pub address: SocketAddr,
+    #[cfg(feature = "tls")]
+    pub tls: Option<std::sync::Arc<tokio_rustls::rustls::ServerConfig>>,
+}
+
+// Serves the metrics, the status API and the dashboard until `shutdown` changes.
+#[allow(clippy::too_many_arguments)]
+pub async fn serve(
+    listen: Listen,
+    board: StatusBoard,
+    history: Option<History>,
+    targets: Option<TargetsApi>,
+    aggregator: Option<Aggregator>,
+    credentials: Option<Credentials>,
+    // Counts the requests refused for coming from outside `allowed_cidrs`.
+    denied: IntCounter,
+    config: watch::Receiver<Config>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let started = Instant::now();
+    let allowed_config = config.clone();
+    let ready_config = config.clone();
+    let ready_board = board.clone();
+    let ready_shutdown = shutdown.clone();
+    let metrics_aggregator = aggregator.clone();
+    let latency_name = config.borrow().metrics.name("ping_latency");
+    let agents_aggregator = aggregator.clone();
+    // Prometheus asks for OpenMetrics with `Accept` where it is enabled, and is answered in the classic text format
+    // otherwise.
+    let metrics_route = warp::path!("metrics")
+        .and(warp::header::optional::<String>("accept"))
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .map(move |accept: Option<String>, accept_encoding: Option<String>| {
+            let encoder = TextEncoder::new();
+            let mut buffer = Vec::new();
+            let metric_families = prometheus::gather();
+            encoder.encode(&metric_families, &mut buffer).unwrap();
+
+            let metrics = String::from_utf8(buffer).unwrap();
+            let metrics = match &metrics_aggregator {
+                Some(aggregator) => aggregator.merge(&metrics),
+                None => metrics,
+            };
+            let (metrics, content_type) = if exposition::wants_openmetrics(accept.as_deref()) {
+                (
+                    exemplars::annotate(&exposition::openmetrics(&metrics), &latency_name),
+                    exposition::OPENMETRICS,
+                )
+            } else {
+                (metrics, encoder.format_type())
+            };
+            metrics_response(metrics, content_type, accept_encoding.as_deref())
+        });
+
+    // Agents replace what their site pushed before with each push.
+    let push_route = warp::path!("api" / "agents" / String)
+        .and(warp::put())
+        .and(warp::query::<PushQuery>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::content_length_limit(16 * 1024 * 1024))
+        .and(warp::body::bytes())
+        .map(
+            move |site: String, query: PushQuery, authorization: Option<String>, body: warp::hyper::body::Bytes| {
+                let aggregator = match authorize_agent(&aggregator, authorization) {
+                    Ok(aggregator) => aggregator,
+                    Err((status, message)) => return error(status, &message),
+                };
+                let metrics = match String::from_utf8(body.to_vec()) {
+                    Ok(metrics) => metrics,
+                    Err(_) => return error(StatusCode::BAD_REQUEST, "metrics must be in the text format"),
+                };
+                match aggregator.push(&site, metrics, query.mesh_address) {
+                    Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                    Err(err) => error(StatusCode::BAD_REQUEST, &err),
+                }
+            },
+        );
+    // Agents with a mesh find each other here.
+    let agents_route = warp::path!("api" / "agents")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .map(
+            move |authorization: Option<String>| match authorize_agent(&agents_aggregator, authorization) {
+                Ok(aggregator) => reply::json(&aggregator.agents()).into_response(),
+                Err((status, message)) => error(status, &message),
+            },
+        );
+
+    // Prometheus tells how long it waits for the scrape, which the probe has to fit in.
+    let probe_route = warp::path!("probe")
+        .and(warp::query::<ProbeQuery>())
+        .and(warp::header::optional::<f64>("x-prometheus-scrape-timeout-seconds"))
+        .then(move |query: ProbeQuery, scrape_timeout: Option<f64>| {
+            let config = config.borrow().clone();
+            async move {
+                let scrape_timeout = scrape_timeout.and_then(|seconds| Duration::try_from_secs_f64(seconds).ok());
+                match exporter::probe(query, &config, scrape_timeout).await {
+                    Ok(metrics) => metrics.into_response(),
+                    Err((status, message)) => error(status, &message),
+                }
+            }
+        });
+
+    let status_board = board.clone();
+    let status_route = warp::path!("api" / "status").map(move || warp::reply::json(&status_board.snapshot()));
+    let stream_route = warp::path!("api" / "stream").map(move || {
+        // Subscribers that fall behind skip the results they missed rather than disconnecting.
+        let events = BroadcastStream::new(board.subscribe()).filter_map(|event| {
+            let event = event.ok()?;
+            Some(Event::default().event("probe").json_data(event))
+        });
+        warp::sse::reply(warp::sse::keep_alive().stream(events))
+    });
+    // Liveness only says the server answers, while readiness waits for the first results.
+    let healthz_route = warp::path!("healthz").map(|| "ok");
+    let readyz_route = warp::path!("readyz").map(move || {
+        let grace_period = ready_config.borrow().server.ready_grace_period;
+        let readiness = Readiness::of(&ready_board, started.elapsed(), grace_period, *ready_shutdown.borrow());
+        let status = if readiness.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        reply::with_status(reply::json(&readiness), status).into_response()
+    });
+
+    let dashboard_route = warp::path::end().map(|| warp::reply::html(include_str!("dashboard.html")));
+    let history = warp::any().map(move || history.clone());
+    let history_route = warp::path!("api" / "history")
+        .and(warp::query::<Query>())
+        .and(history.clone())
+        .then(|query: Query, history: Option<History>| async move {
+            match history {
+                Some(history) => json_or_error(history.results(&query).await),
+                None => not_enabled(),
+            }
+        });
+    let history_stats_route = warp::path!("api" / "history" / "stats")
+        .and(warp::query::<Query>())
+        .and(history.clone())
+        .then(|query: Query, history: Option<History>| async move {
+            match history {
+                Some(history) => json_or_error(history.stats(&query).await),
+                None => not_enabled(),
+            }
+        });
+    let report_route = warp::path!("api" / "report")
+        .and(warp::query::<ReportOptions>())
+        .and(history)
+        .then(|options: ReportOptions, history: Option<History>| async move {
+            let history = match history {
+                Some(history) => history,
+                None => return not_enabled(),
+            };
+            match report::generate(&history, &options).await {
+                Ok(generated) => reply::with_header(
+                    report::render(&generated, options.format),
+                    "Content-Type",
+                    options.format.content_type(),
+                )
+                .into_response(),
+                Err(err) => error(StatusCode::BAD_REQUEST, &err.to_string()),
+            }
+        });
+
+    let targets = warp::any()
+        .map(move || targets.clone())
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(
+            |targets: Option<TargetsApi>, authorization: Option<String>| async move {
+                Ok::<_, warp::Rejection>(authorize(targets, authorization))
+            },
+        );
+    let list_targets_route = warp::path!("api" / "targets")
+        .and(warp::get())
+        .and(targets.clone())
+        .then(|targets: Result<TargetsApi, Rejection>| async move {
+            let targets = match targets {
+                Ok(targets) => targets,
+                Err((status, message)) => return error(status, &message),
+            };
+            let (reply, listed) = oneshot::channel();
+            if targets.commands.send(Command::List(reply)).await.is_err() {
+                return shutting_down();
+            }
+            match listed.await {
+                Ok(listed) => reply::json(&listed).into_response(),
+                Err(_) => shutting_down(),
+            }
+        });
+    let add_target_route = warp::path!("api" / "targets")
+        .and(warp::post())
+        .and(targets.clone())
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::bytes())
+        .then(
+            |targets: Result<TargetsApi, Rejection>, body: warp::hyper::body::Bytes| async move {
+                let targets = match targets {
+                    Ok(targets) => targets,
+                    Err((status, message)) => return error(status, &message),
+                };
+                let body = match serde_json::from_slice(&body) {
+                    Ok(body) => body,
+                    Err(err) => return error(StatusCode::BAD_REQUEST, &err.to_string()),
+                };
+                let (reply, added) = oneshot::channel();
+                if targets
+                    .commands
+                    .send(Command::Add(body, reply))
+                    .await
+                    .is_err()
+                {
+                    return shutting_down();
+                }
+                changed(added.await, StatusCode::CREATED)
+            },
+        );
+    let remove_target_route = warp::path!("api" / "targets" / String)
+        .and(warp::delete())
+        .and(targets)
+        .then(|name: String, targets: Result<TargetsApi, Rejection>| async move {
+            let targets = match targets {
+                Ok(targets) => targets,
+                Err((status, message)) => return error(status, &message),
+            };
+            let (reply, removed) = oneshot::channel();
+            if targets
+                .commands
+                .send(Command::Remove(percent_decode(&name), reply))
+                .await
+                .is_err()
+            {
+                return shutting_down();
+            }
+            changed(removed.await, StatusCode::NO_CONTENT)
+        });
+
+    // Everything but the routes with tokens of their own needs the credentials of the auth section, when there is one.
+    // The allowlist is checked before anything else, so that other sources learn nothing of what is served. It is read
+    // from the current config, so reloads apply to it.
+    let allowed = warp::addr::remote()
+        .and(warp::ext::optional::<Peer>())
+        .and_then(move |remote: Option<SocketAddr>, peer: Option<Peer>| {
+            let remote = peer.map(|Peer(peer)| peer).or(remote);
+            let allowed = allowed_config.borrow().server.allows(remote);
+            if !allowed {
+                denied.inc();
+            }
+            async move {
+                if allowed {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Forbidden))
+                }
+            }
+        })
+        .untuple_one();
+
+    let challenge = match &credentials {
+        Some(credentials) if credentials.basic() => "Basic realm=\"rust-net-stab\"",
+        _ => "Bearer",
+    };
+    let authenticated = warp::header::optional::<String>("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let allowed = credentials
+                .as_ref()
+                .is_none_or(|credentials| credentials.allow(authorization.as_deref()));
+            async move {
+                if allowed {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Unauthorized(challenge)))
+                }
+            }
+        })
+        .untuple_one();
+    let protected = metrics_route
+        .or(probe_route)
+        .or(status_route)
+        .or(stream_route)
+        .or(history_route)
+        .or(history_stats_route)
+        .or(report_route)
+        .or(dashboard_route);
+    // Health checks come from kubelets and load balancers, which need neither credentials nor to be allowed.
+    let routes = healthz_route
+        .or(readyz_route)
+        .or(allowed.and(
+            list_targets_route
+                .or(add_target_route)
+                .or(remove_target_route)
+                .or(push_route)
+                .or(agents_route)
+                .or(authenticated.and(protected)),
+        ))
+        .recover(rejected);
+    #[cfg(feature = "tls")]
+    {
+        if let Some(tls) = listen.tls {
+            crate::tls::serve(listen.address, tls, warp::service(routes), shutdown).await;
+            return;
+        }
+    }
+    let (_, metrics_server) = warp::serve(routes).bind_with_graceful_shutdown(listen.address, async move {
+        let _ = shutdown.changed().await;
+    });
+    metrics_server.await;
+}
+
+// Gzipped when the client accepts it, as the exposition of many endpoints runs to megabytes that compress well.
+fn metrics_response(metrics: String, content_type: &str, accept_encoding: Option<&str>) -> reply::Response {
+    let response = warp::http::Response::builder()
+        .header("Content-Type", content_type)
+        .header("Vary", "Accept, Accept-Encoding");
+    #[cfg(feature = "gzip")]
+    {
+        if exposition::accepts_gzip(accept_encoding) {
+            match exposition::gzip(metrics.as_bytes()) {
+                Ok(body) => {
+                    return response
+                        .header("Content-Encoding", "gzip")
+                        .body(body.into())
+                        .unwrap()
+                }
+                Err(err) => warn!("Failed to compress the metrics: {}", err),
+            }
+        }
+    }
+    #[cfg(not(feature = "gzip"))]
+    let _ = accept_encoding;
+    response.body(metrics.into()).unwrap()
+}
+
+fn json_or_error<T: Serialize>(result: Result<T, crate::BoxError>) -> reply::Response {
+    match result {
+        Ok(value) => reply::json(&value).into_response(),
+        Err(err) => error(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    }
+}
+
+// Requests to the targets API need `Authorization: Bearer <token>` with the token of the api section.
+fn authorize(targets: Option<TargetsApi>, authorization: Option<String>) -> Result<TargetsApi, Rejection> {
+    let targets = targets.ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            "the targets API is not enabled, add an api section to the config".to_string(),
+        )
+    })?;
+    if bearer(authorization.as_deref(), &targets.token) {
+        Ok(targets)
+    } else {
+        Err((StatusCode::UNAUTHORIZED, "missing or wrong bearer token".to_string()))
+    }
+}
+
+// Agents need `Authorization: Bearer <token>` with the token of the aggregator section.
+fn authorize_agent(aggregator: &Option<Aggregator>, authorization: Option<String>) -> Result<&Aggregator, Rejection> {
+    let aggregator = aggregator.as_ref().ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            "this instance is not an aggregator, add an aggregator section to the config".to_string(),
+        )
+    })?;
+    if bearer(authorization.as_deref(), &aggregator.token) {
+        Ok(aggregator)
+    } else {
+        Err((StatusCode::UNAUTHORIZED, "missing or wrong bearer token".to_string()))
+    }
+}
+
+// Whether the `Authorization` header is `Bearer <token>`.
+fn bearer(authorization: Option<&str>, token: &str) -> bool {
+    authorization
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        .is_some_and(|given| auth::same(given.as_bytes(), token.as_bytes()))
+}
+
+// A request without the credentials of the auth section, along with how the client is asked for them.
+#[derive(Debug)]
+struct Unauthorized(&'static str);
+
+impl warp::reject::Reject for Unauthorized {}
+
+// A request from outside `allowed_cidrs`.
+#[derive(Debug)]
+struct Forbidden;
+
+impl warp::reject::Reject for Forbidden {}
+
+async fn rejected(rejection: warp::Rejection) -> Result<reply::Response, warp::Rejection> {
+    if rejection.find::<Forbidden>().is_some() {
+        return Ok(error(
+            StatusCode::FORBIDDEN,
+            "requests from this address are not allowed",
+        ));
+    }
+    match rejection.find::<Unauthorized>() {
+        Some(Unauthorized(challenge)) => Ok(reply::with_header(
+            error(StatusCode::UNAUTHORIZED, "missing or wrong credentials"),
+            "WWW-Authenticate",
+            *challenge,
+        )
+        .into_response()),
+        None => Err(rejection),
+    }
+}
+
+fn changed(result: Result<Result<(), Rejection>, oneshot::error::RecvError>, status: StatusCode) -> reply::Response {
+    match result {
+        Ok(Ok(())) => status.into_response(),
+        Ok(Err((status, message))) => error(status, &message),
+        Err(_) => shutting_down(),
+    }
+}
+
+fn shutting_down() -> reply::Response {
+    error(StatusCode::SERVICE_UNAVAILABLE, "shutting down")
+}
+
+fn not_enabled() -> reply::Response {
+    error(
+        StatusCode::NOT_FOUND,
+        "history is not enabled, add a history section to the config",
+    )
+}
+
+fn error(status: StatusCode, message: &str) -> reply::Response {
+    reply::with_status(reply::json(&serde_json::json!({ "error": message })), status).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Format};
+
+    #[test]
+    fn allows_only_peers_in_the_allowed_cidrs() {
+        let config = config::parse(
+            "server:\n  allowed_cidrs: [10.0.0.0/8, 192.0.2.7, \"2001:db8::/32\"]\n",
+            Format::Yaml,
+        )
+        .unwrap();
+        let allows = |peer: &str| config.server.allows(Some(peer.parse().unwrap()));
+        assert!(allows("10.1.2.3:40000"));
+        assert!(allows("192.0.2.7:40000"));
+        assert!(!allows("192.0.2.8:40000"));
+        assert!(allows("[2001:db8::1]:40000"));
+        assert!(!allows("[2001:db9::1]:40000"));
+        // IPv4 clients of a server listening on `[::]`.
+        assert!(allows("[::ffff:10.1.2.3]:40000"));
+        assert!(!allows("[::ffff:172.16.0.1]:40000"));
+        assert!(!config.server.allows(None));
+
+        assert!(ServerConfig::default().allows(None));
+        assert_eq!(
+            serde_yaml::to_value(&config.server.allowed_cidrs).unwrap(),
+            serde_yaml::from_str::<serde_yaml::Value>("[10.0.0.0/8, 192.0.2.7/32, \"2001:db8::/32\"]").unwrap()
+        );
+        assert!(config::parse("server:\n  allowed_cidrs: [10.0.0.0/33]\n", Format::Yaml).is_err());
+    }
+
+    #[test]
+    fn checks_bearer_tokens() {
+        assert!(bearer(Some("Bearer t0ken"), "t0ken"));
+        assert!(!bearer(Some("Bearer t0ke"), "t0ken"));
+        assert!(!bearer(Some("bearer t0ken"), "t0ken"));
+        assert!(!bearer(Some("t0ken"), "t0ken"));
+        assert!(!bearer(Some("Bearer "), "t0ken"));
+        assert!(!bearer(None, "t0ken"));
+    }
+}
@@ -0,0 +1,180 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+use crate::source::Source;
+use crate::BoxError;
+
+pub(crate) const SIP_PORT: u16 = 5060;
+// Larger responses than this are not read.
+const MAX_MESSAGE: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SipTransport {
+    Udp,
+    Tcp,
+}
+
+impl SipTransport {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SipTransport::Udp => "UDP",
+            SipTransport::Tcp => "TCP",
+        }
+    }
+}
+
+pub struct Response {
+    pub code: u16,
+    pub reason: String,
+    // From sending the request up to the final response, leaving out provisional ones such as 100 Trying.
+    pub duration: Duration,
+}
+
+// Sends an OPTIONS request for `sip:<host>` and waits for its final response.
+pub async fn options(
+    server: SocketAddr,
+    host: &str,
+    transport: SipTransport,
+    source: &Source,
+) -> Result<Response, BoxError> {
+    match transport {
+        SipTransport::Udp => {
+            let socket = source.udp(server).await?;
+            socket.connect(server).await?;
+            let (call_id, request) = request(host, server, socket.local_addr()?, transport);
+
+            let start = Instant::now();
+            socket.send(&request).await?;
+            let mut buffer = vec![0u8; MAX_MESSAGE];
+            loop {
+                let len = socket.recv(&mut buffer).await?;
+                let duration = start.elapsed();
+                // Stray datagrams and responses to earlier probes are left alone.
+                if let Some(response) = parse(&buffer[..len], &call_id, duration) {
+                    return Ok(response);
+                }
+            }
+        }
+        SipTransport::Tcp => {
+            let stream = source.connect(server).await?;
+            let (call_id, request) = request(host, server, stream.local_addr()?, transport);
+            let mut stream = BufReader::new(stream);
+            let start = Instant::now();
+            stream.get_mut().write_all(&request).await?;
+
+            loop {
+                let mut message = Vec::new();
+                let mut content_length = 0;
+                loop {
+                    let start_of_line = message.len();
+                    if stream.read_until(b'\n', &mut message).await? == 0 {
+                        return Err("the server closed the connection without a final response".into());
+                    }
+                    let line = String::from_utf8_lossy(&message[start_of_line..]).into_owned();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    match header(line) {
+                        Some((name, value)) if name == "content-length" || name == "l" => {
+                            content_length = value.parse().unwrap_or_default();
+                        }
+                        _ => {}
+                    }
+                    if message.len() > MAX_MESSAGE {
+                        return Err("the server's response is too long".into());
+                    }
+                }
+                let duration = start.elapsed();
+                let mut body = vec![0; content_length.min(MAX_MESSAGE)];
+                stream.read_exact(&mut body).await?;
+                if let Some(response) = parse(&message, &call_id, duration) {
+                    return Ok(response);
+                }
+            }
+        }
+    }
+}
+
+// Returns the Call-ID along with the request.
+fn request(host: &str, server: SocketAddr, local: SocketAddr, transport: SipTransport) -> (String, Vec<u8>) {
+    let tag = || format!("{:08x}", rand::random::<u32>());
+    let local_host = match local.ip() {
+        IpAddr::V6(ip) => format!("[{}]", ip),
+        ip => ip.to_string(),
+    };
+    let uri_host = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]", ip),
+        _ => host.to_string(),
+    };
+    let uri = if server.port() == SIP_PORT {
+        format!("sip:{}", uri_host)
+    } else {
+        format!("sip:{}:{}", uri_host, server.port())
+    };
+    let call_id = format!("{}{}@{}", tag(), tag(), local_host);
+
+    // The `z9hG4bK` prefix marks the branch as following RFC 3261.
+    let request = format!(
+        "OPTIONS {uri} SIP/2.0\r\n\
+         Via: SIP/2.0/{transport} {local_host}:{local_port};branch=z9hG4bK{branch};rport\r\n\
+         Max-Forwards: 70\r\n\
+         From: <sip:rust-net-stab@{local_host}>;tag={from_tag}\r\n\
+         To: <{uri}>\r\n\
+         Call-ID: {call_id}\r\n\
+         CSeq: 1 OPTIONS\r\n\
+         Contact: <sip:rust-net-stab@{local_host}:{local_port};transport={transport_param}>\r\n\
+         Accept: application/sdp\r\n\
+         User-Agent: rust-net-stab/{version}\r\n\
+         Content-Length: 0\r\n\
+         \r\n",
+        uri = uri,
+        transport = transport.as_str(),
+        transport_param = transport.as_str().to_lowercase(),
+        local_host = local_host,
+        local_port = local.port(),
+        branch = tag(),
+        from_tag = tag(),
+        call_id = call_id,
+        version = env!("CARGO_PKG_VERSION"),
+    );
+    (call_id, request.into_bytes())
+}
+
+// Returns the response when it is the final one for `call_id`, and nothing for provisional responses and messages that
+// belong to another call.
+fn parse(message: &[u8], call_id: &str, duration: Duration) -> Option<Response> {
+    let message = String::from_utf8_lossy(message);
+    let mut lines = message.lines();
+    let status = lines.next().unwrap_or_default();
+    let mut parts = status.splitn(3, ' ');
+    let (code, reason) = match (parts.next(), parts.next().map(str::parse::<u16>)) {
+        (Some("SIP/2.0"), Some(Ok(code))) if (100..700).contains(&code) => (code, parts.next().unwrap_or_default()),
+        _ => return None,
+    };
+
+    let ours = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(header)
+        .any(|(name, value)| matches!(name.as_str(), "call-id" | "i") && value == call_id);
+    if !ours || code < 200 {
+        return None;
+    }
+
+    Some(Response {
+        code,
+        reason: reason.to_string(),
+        duration,
+    })
+}
+
+// A header's lowercased name and its value. SIP allows a single letter in place of some names, such as `i` for
+// Call-ID.
+fn header(line: &str) -> Option<(String, &str)> {
+    let (name, value) = line.split_once(':')?;
+    Some((name.trim().to_ascii_lowercase(), value.trim()))
+}
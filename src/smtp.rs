@@ -0,0 +1,307 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::source::Source;
+use crate::{config, status, BoxError};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub server: String,
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: Security,
+    pub username: Option<String>,
+    // The password is never part of the config itself, it is read from the environment or a file instead.
+    pub password_env: Option<String>,
+    pub password_file: Option<String>,
+    pub from: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    #[default]
+    Starttls,
+    Tls,
+    None,
+}
+
+impl SmtpConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.password_env.is_some() && self.password_file.is_some() {
+            return Err("set only one of password_env or password_file".to_string());
+        }
+        if (self.password_env.is_some() || self.password_file.is_some()) && self.username.is_none() {
+            return Err("a password is set but no username".to_string());
+        }
+        self.password()?;
+
+        Ok(())
+    }
+
+    fn port(&self) -> u16 {
+        self.port.unwrap_or(match self.security {
+            Security::Starttls => 587,
+            Security::Tls => 465,
+            Security::None => 25,
+        })
+    }
+
+    fn password(&self) -> Result<Option<String>, String> {
+        config::secret("password", &self.password_env, &self.password_file)
+    }
+}
+
+pub struct Email {
+    pub to: Vec<String>,
+    pub subject: String,
+    pub body: String,
+}
+
+pub async fn send(config: &SmtpConfig, email: &Email) -> Result<(), BoxError> {
+    let ip = crate::icmp::resolve(&config.server, None).await?;
+    let stream = TcpStream::connect((ip, config.port())).await?;
+
+    match config.security {
+        Security::Tls => send_tls(stream, config, email, true).await,
+        Security::Starttls => {
+            let mut session = Session::new(stream);
+            session.reply(220).await?;
+            session
+                .command(&format!("EHLO {}", hostname()), 250)
+                .await?;
+            session.command("STARTTLS", 220).await?;
+            send_tls(session.into_inner(), config, email, false).await
+        }
+        Security::None => deliver(Session::new(stream), config, email, true).await,
+    }
+}
+
+pub(crate) const SMTP_PORT: u16 = 25;
+
+// Checks that the server greets and answers EHLO, and with `starttls` that it offers STARTTLS and completes the
+// handshake, which is verified against `server_name` unless `verify` is off. Returns the time from connecting up to the
+// last answer to EHLO, nothing is sent after it but QUIT.
+pub async fn probe(
+    server: SocketAddr,
+    server_name: &str,
+    starttls: bool,
+    verify: bool,
+    source: &Source,
+) -> Result<Duration, BoxError> {
+    let start = Instant::now();
+    let mut session = Session::new(source.connect(server).await?);
+    session.reply(220).await?;
+    let ehlo = session
+        .command(&format!("EHLO {}", hostname()), 250)
+        .await?;
+    if !starttls {
+        let elapsed = start.elapsed();
+        let _ = session.command("QUIT", 221).await;
+        return Ok(elapsed);
+    }
+
+    // The first line of the reply is the greeting, the ones after it the extensions.
+    let offered = ehlo.lines().skip(1).any(|line| {
+        line.get(4..)
+            .and_then(|extension| extension.split_whitespace().next())
+            .is_some_and(|keyword| keyword.eq_ignore_ascii_case("STARTTLS"))
+    });
+    if !offered {
+        return Err("smtp server does not offer STARTTLS".into());
+    }
+    session.command("STARTTLS", 220).await?;
+    starttls_session(session.into_inner(), server_name, verify, start).await
+}
+
+#[cfg(feature = "tls")]
+async fn starttls_session(
+    stream: TcpStream,
+    server_name: &str,
+    verify: bool,
+    start: Instant,
+) -> Result<Duration, BoxError> {
+    let mut session = Session::new(crate::tls::connect(stream, server_name, verify).await?);
+    session
+        .command(&format!("EHLO {}", hostname()), 250)
+        .await?;
+    let elapsed = start.elapsed();
+    let _ = session.command("QUIT", 221).await;
+    Ok(elapsed)
+}
+
+#[cfg(not(feature = "tls"))]
+async fn starttls_session(
+    _stream: TcpStream,
+    _server_name: &str,
+    _verify: bool,
+    _start: Instant,
+) -> Result<Duration, BoxError> {
+    Err("starttls needs rust-net-stab to be built with the `tls` feature".into())
+}
+
+#[cfg(feature = "tls")]
+async fn send_tls(stream: TcpStream, config: &SmtpConfig, email: &Email, greeting: bool) -> Result<(), BoxError> {
+    let stream = crate::tls::connect(stream, &config.server, true).await?;
+    deliver(Session::new(stream), config, email, greeting).await
+}
+
+#[cfg(not(feature = "tls"))]
+async fn send_tls(_stream: TcpStream, _config: &SmtpConfig, _email: &Email, _greeting: bool) -> Result<(), BoxError> {
+    Err("smtp over tls needs rust-net-stab to be built with the `tls` feature, or `security: none`".into())
+}
+
+async fn deliver<S>(mut session: Session<S>, config: &SmtpConfig, email: &Email, greeting: bool) -> Result<(), BoxError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if greeting {
+        session.reply(220).await?;
+    }
+    session
+        .command(&format!("EHLO {}", hostname()), 250)
+        .await?;
+
+    if let Some(username) = &config.username {
+        let password = config.password()?.unwrap_or_default();
+        let token = STANDARD.encode(format!("\0{}\0{}", username, password));
+        session
+            .command(&format!("AUTH PLAIN {}", token), 235)
+            .await?;
+    }
+
+    session
+        .command(&format!("MAIL FROM:<{}>", address(&config.from)), 250)
+        .await?;
+    for to in &email.to {
+        session
+            .command(&format!("RCPT TO:<{}>", address(to)), 250)
+            .await?;
+    }
+    session.command("DATA", 354).await?;
+    session.command(&message(config, email), 250).await?;
+    let _ = session.command("QUIT", 221).await;
+
+    Ok(())
+}
+
+// Accepts both `ops@example.com` and `Ops <ops@example.com>`.
+fn address(mailbox: &str) -> &str {
+    match (mailbox.find('<'), mailbox.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+        _ => mailbox.trim(),
+    }
+}
+
+fn hostname() -> String {
+    sys_info::hostname().unwrap_or_else(|_| "localhost".to_string())
+}
+
+// Builds the DATA section, ending with the lone dot that terminates it.
+fn message(config: &SmtpConfig, email: &Email) -> String {
+    let subject = if email.subject.is_ascii() {
+        email.subject.clone()
+    } else {
+        format!("=?utf-8?B?{}?=", STANDARD.encode(&email.subject))
+    };
+
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        config.from,
+        email.to.join(", "),
+        subject,
+        rfc2822_date(crate::status::unix_time())
+    );
+    for line in email.body.lines() {
+        // Lines starting with a dot are escaped so they are not taken for the end of the message.
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push('.');
+
+    message
+}
+
+struct Session<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Session<S> {
+    fn new(stream: S) -> Self {
+        Session {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    async fn command(&mut self, command: &str, expected: u16) -> Result<String, BoxError> {
+        let stream = self.stream.get_mut();
+        stream.write_all(command.as_bytes()).await?;
+        stream.write_all(b"\r\n").await?;
+        stream.flush().await?;
+
+        self.reply(expected).await
+    }
+
+    // Replies can span several lines, all but the last having a dash after the code.
+    async fn reply(&mut self, expected: u16) -> Result<String, BoxError> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                return Err("smtp server closed the connection".into());
+            }
+            reply.push_str(&line);
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        }
+
+        match reply.get(..3).and_then(|code| code.parse::<u16>().ok()) {
+            Some(code) if code == expected => Ok(reply),
+            _ => Err(format!("smtp server answered `{}`", reply.trim_end()).into()),
+        }
+    }
+}
+
+pub fn format_timestamp(timestamp: f64) -> String {
+    let (year, month, day, hour, minute, second) = status::civil(timestamp);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year, month, day, hour, minute, second
+    )
+}
+
+fn rfc2822_date(timestamp: f64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let (year, month, day, hour, minute, second) = status::civil(timestamp);
+    let weekday = WEEKDAYS[(timestamp as i64).div_euclid(86400).rem_euclid(7) as usize];
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} +0000",
+        weekday,
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hour,
+        minute,
+        second
+    )
+}
@@ -0,0 +1,811 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tokio::net::UdpSocket;
+
+use crate::source::Source;
+use crate::BoxError;
+
+pub(crate) const SNMP_PORT: u16 = 161;
+// sysUpTime.0
+pub(crate) const DEFAULT_OID: &str = "1.3.6.1.2.1.1.3.0";
+pub(crate) const DEFAULT_COMMUNITY: &str = "public";
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const IP_ADDRESS: u8 = 0x40;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIME_TICKS: u8 = 0x43;
+const COUNTER64: u8 = 0x46;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const NO_SUCH_INSTANCE: u8 = 0x81;
+const END_OF_MIB_VIEW: u8 = 0x82;
+const GET_REQUEST: u8 = 0xa0;
+const RESPONSE: u8 = 0xa2;
+const REPORT: u8 = 0xa8;
+
+const VERSION_2C: i64 = 1;
+const VERSION_3: i64 = 3;
+const MAX_MESSAGE: i64 = 65507;
+const USM: i64 = 3;
+const AUTH: u8 = 0x01;
+const REPORTABLE: u8 = 0x04;
+// HMAC-SHA-96 sends the first 12 bytes of the digest.
+const AUTH_LEN: usize = 12;
+// usmStats, the counters a report names to say why a request was refused.
+const USM_STATS: [u32; 9] = [1, 3, 6, 1, 6, 3, 15, 1, 1];
+const NOT_IN_TIME_WINDOWS: u32 = 2;
+
+const ERROR_STATUSES: [&str; 19] = [
+    "noError",
+    "tooBig",
+    "noSuchName",
+    "badValue",
+    "readOnly",
+    "genErr",
+    "noAccess",
+    "wrongType",
+    "wrongLength",
+    "wrongEncoding",
+    "wrongValue",
+    "noCreation",
+    "inconsistentValue",
+    "resourceUnavailable",
+    "commitFailed",
+    "undoFailed",
+    "authorizationError",
+    "notWritable",
+    "inconsistentName",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnmpVersion {
+    V2c,
+    V3,
+}
+
+pub enum Security<'a> {
+    Community(&'a str),
+    // With a password requests are authenticated with HMAC-SHA-96, without one they are not.
+    User(&'a str, Option<String>),
+}
+
+pub enum Value {
+    Number(f64),
+    Text(String),
+}
+
+pub struct Reading {
+    pub value: Value,
+    // The round trip of the GET, leaving out the discovery of a v3 agent.
+    pub duration: Duration,
+}
+
+struct Pdu {
+    kind: u8,
+    request_id: i64,
+    error_status: i64,
+    varbinds: Vec<(Vec<u32>, u8, Vec<u8>)>,
+}
+
+// Fetches `oid` from the agent, over SNMPv2c for a community and SNMPv3 for a user.
+pub async fn get(
+    server: SocketAddr,
+    oid: &[u32],
+    security: Security<'_>,
+    source: &Source,
+) -> Result<Reading, BoxError> {
+    let socket = source.udp(server).await?;
+    socket.connect(server).await?;
+
+    match security {
+        Security::Community(community) => {
+            let id = random_id();
+            let request = tlv(
+                SEQUENCE,
+                &[
+                    integer(VERSION_2C),
+                    tlv(OCTET_STRING, community.as_bytes()),
+                    get_request(id, Some(oid)),
+                ]
+                .concat(),
+            );
+
+            let start = Instant::now();
+            socket.send(&request).await?;
+            loop {
+                let message = receive(&socket).await?;
+                let duration = start.elapsed();
+                let pdu = match parse_v2c(&message) {
+                    Ok(pdu) if pdu.request_id == id => pdu,
+                    _ => continue,
+                };
+                return Ok(Reading {
+                    value: value(&pdu, oid)?,
+                    duration,
+                });
+            }
+        }
+        Security::User(user, password) => get_v3(&socket, user, password, oid).await,
+    }
+}
+
+// Discovers the agent's engine ID, boots and time as RFC 5343 describes, then sends the GET. An agent whose clock has
+// moved on since answers with notInTimeWindows and its current time, after which the GET is sent once more.
+async fn get_v3(socket: &UdpSocket, user: &str, password: Option<String>, oid: &[u32]) -> Result<Reading, BoxError> {
+    let (id, request) = v3_request(&[], 0, 0, "", None, None);
+    socket.send(&request).await?;
+    let discovery = loop {
+        let message = receive(socket).await?;
+        match parse_v3(&message) {
+            Ok(response) if response.msg_id == id => break response,
+            _ => continue,
+        }
+    };
+    let engine_id = discovery.engine_id;
+    let key = password.map(|password| localize(&password_key(&password), &engine_id));
+    let (mut boots, mut time) = (discovery.boots, discovery.time);
+
+    for attempt in 0..2 {
+        let (id, request) = v3_request(&engine_id, boots, time, user, key.as_ref(), Some(oid));
+        let start = Instant::now();
+        socket.send(&request).await?;
+        let (response, message) = loop {
+            let message = receive(socket).await?;
+            match parse_v3(&message) {
+                Ok(response) if response.msg_id == id => break (response, message),
+                _ => continue,
+            }
+        };
+        let duration = start.elapsed();
+
+        if let Some(key) = &key {
+            if response.flags & AUTH != 0 && !authentic(&message, response.auth_offset, key) {
+                return Err("the agent's response failed authentication".into());
+            }
+        }
+        if response.pdu.kind != REPORT {
+            return Ok(Reading {
+                value: value(&response.pdu, oid)?,
+                duration,
+            });
+        }
+
+        let reason = response
+            .pdu
+            .varbinds
+            .first()
+            .filter(|(oid, ..)| oid.starts_with(&USM_STATS))
+            .and_then(|(oid, ..)| oid.get(USM_STATS.len()).copied());
+        if reason == Some(NOT_IN_TIME_WINDOWS) && attempt == 0 {
+            boots = response.boots;
+            time = response.time;
+            continue;
+        }
+        return Err(match reason {
+            Some(1) => "the agent does not support the security level".into(),
+            Some(2) => "the agent's clock is not in the time window".into(),
+            Some(3) => format!("the agent does not know the user `{}`", user).into(),
+            Some(4) => "the agent does not know its engine ID".into(),
+            Some(5) => "the agent rejected the auth password".into(),
+            _ => "the agent answered with a report".into(),
+        });
+    }
+    Err("the agent's clock is not in the time window".into())
+}
+
+struct V3Response {
+    msg_id: i64,
+    flags: u8,
+    engine_id: Vec<u8>,
+    boots: i64,
+    time: i64,
+    // Where the authentication parameters start in the message.
+    auth_offset: usize,
+    pdu: Pdu,
+}
+
+// Returns the message ID along with the message. With a key the message is authenticated, which needs the offset of
+// the authentication parameters, worked out from the lengths of everything ahead of them.
+fn v3_request(
+    engine_id: &[u8],
+    boots: i64,
+    time: i64,
+    user: &str,
+    key: Option<&[u8; 20]>,
+    oid: Option<&[u32]>,
+) -> (i64, Vec<u8>) {
+    let id = random_id();
+    let flags = REPORTABLE | if key.is_some() { AUTH } else { 0 };
+    let global = tlv(
+        SEQUENCE,
+        &[
+            integer(id),
+            integer(MAX_MESSAGE),
+            tlv(OCTET_STRING, &[flags]),
+            integer(USM),
+        ]
+        .concat(),
+    );
+    let ahead_of_auth = [
+        tlv(OCTET_STRING, engine_id),
+        integer(boots),
+        integer(time),
+        tlv(OCTET_STRING, user.as_bytes()),
+    ]
+    .concat();
+    let auth = if key.is_some() { vec![0; AUTH_LEN] } else { Vec::new() };
+    let usm_fields = [ahead_of_auth.clone(), tlv(OCTET_STRING, &auth), tlv(OCTET_STRING, &[])].concat();
+    let usm = tlv(SEQUENCE, &usm_fields);
+    let scoped = tlv(
+        SEQUENCE,
+        &[
+            tlv(OCTET_STRING, engine_id),
+            tlv(OCTET_STRING, &[]),
+            get_request(random_id(), oid),
+        ]
+        .concat(),
+    );
+    let version = integer(VERSION_3);
+    let content = [version.clone(), global.clone(), tlv(OCTET_STRING, &usm), scoped].concat();
+    let mut message = tlv(SEQUENCE, &content);
+
+    if let Some(key) = key {
+        let offset = header_len(content.len())
+            + version.len()
+            + global.len()
+            + header_len(usm.len())
+            + header_len(usm_fields.len())
+            + ahead_of_auth.len()
+            + 2;
+        let digest = hmac_sha1(key)
+            .chain_update(&message)
+            .finalize()
+            .into_bytes();
+        message[offset..offset + AUTH_LEN].copy_from_slice(&digest[..AUTH_LEN]);
+    }
+    (id, message)
+}
+
+// The digest is checked in constant time, over the message with the zeros it was computed with in its place.
+fn authentic(message: &[u8], offset: usize, key: &[u8; 20]) -> bool {
+    let mut zeroed = message.to_vec();
+    let received = match message.get(offset..offset + AUTH_LEN) {
+        Some(received) => received,
+        None => return false,
+    };
+    zeroed[offset..offset + AUTH_LEN].fill(0);
+    hmac_sha1(key)
+        .chain_update(&zeroed)
+        .verify_truncated_left(received)
+        .is_ok()
+}
+
+// RFC 3414 A.2.2: the password repeated to a megabyte and hashed, then hashed again around the engine ID.
+fn password_key(password: &str) -> [u8; 20] {
+    let password = password.as_bytes();
+    let mut hasher = Sha1::new();
+    let mut block = [0u8; 64];
+    for index in (0..1_048_576).step_by(64) {
+        for (offset, byte) in block.iter_mut().enumerate() {
+            *byte = password[(index + offset) % password.len()];
+        }
+        hasher.update(block);
+    }
+    hasher.finalize().into()
+}
+
+fn localize(key: &[u8; 20], engine_id: &[u8]) -> [u8; 20] {
+    Sha1::new()
+        .chain_update(key)
+        .chain_update(engine_id)
+        .chain_update(key)
+        .finalize()
+        .into()
+}
+
+// HMAC-SHA-96 of RFC 3414 6.3, the first 12 bytes of an HMAC-SHA1 of the whole message.
+fn hmac_sha1(key: &[u8; 20]) -> Hmac<Sha1> {
+    Hmac::new_from_slice(key).expect("HMAC takes keys of any length")
+}
+
+async fn receive(socket: &UdpSocket) -> Result<Vec<u8>, BoxError> {
+    let mut buffer = vec![0u8; 65536];
+    let len = socket.recv(&mut buffer).await?;
+    buffer.truncate(len);
+    Ok(buffer)
+}
+
+fn random_id() -> i64 {
+    i64::from(rand::random::<u32>() >> 1)
+}
+
+// A GetRequest for the OID, or for nothing as in discovery.
+fn get_request(id: i64, oid: Option<&[u32]>) -> Vec<u8> {
+    let varbinds = match oid {
+        Some(oid) => tlv(
+            SEQUENCE,
+            &[tlv(OBJECT_IDENTIFIER, &encode_oid(oid)), tlv(NULL, &[])].concat(),
+        ),
+        None => Vec::new(),
+    };
+    tlv(
+        GET_REQUEST,
+        &[integer(id), integer(0), integer(0), tlv(SEQUENCE, &varbinds)].concat(),
+    )
+}
+
+fn value(pdu: &Pdu, oid: &[u32]) -> Result<Value, BoxError> {
+    if pdu.kind != RESPONSE {
+        return Err("the agent did not answer with a response".into());
+    }
+    if pdu.error_status != 0 {
+        let name = ERROR_STATUSES
+            .get(pdu.error_status as usize)
+            .copied()
+            .unwrap_or("unknown error");
+        return Err(format!("the agent answered {}", name).into());
+    }
+    let (_, kind, content) = pdu
+        .varbinds
+        .iter()
+        .find(|(varbind, ..)| varbind == oid)
+        .ok_or("the agent answered for another OID")?;
+
+    Ok(match *kind {
+        INTEGER => Value::Number(read_integer(content) as f64),
+        COUNTER32 | GAUGE32 | TIME_TICKS | COUNTER64 => Value::Number(read_unsigned(content) as f64),
+        OCTET_STRING if content.iter().all(|byte| (0x20..0x7f).contains(byte)) => {
+            Value::Text(String::from_utf8_lossy(content).into_owned())
+        }
+        OBJECT_IDENTIFIER => Value::Text(format_oid(&decode_oid(content)?)),
+        IP_ADDRESS if content.len() == 4 => {
+            Value::Text(Ipv4Addr::new(content[0], content[1], content[2], content[3]).to_string())
+        }
+        NULL => return Err("the agent answered without a value".into()),
+        NO_SUCH_OBJECT => return Err("no such object".into()),
+        NO_SUCH_INSTANCE => return Err("no such instance".into()),
+        END_OF_MIB_VIEW => return Err("end of MIB view".into()),
+        // Binary strings and anything else, in hexadecimal like `00:1a:2b`.
+        _ => Value::Text(
+            content
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<Vec<_>>()
+                .join(":"),
+        ),
+    })
+}
+
+fn parse_v2c(message: &[u8]) -> Result<Pdu, BoxError> {
+    let (message, _) = expect(message, SEQUENCE)?;
+    let (version, rest) = expect(message, INTEGER)?;
+    if read_integer(version) != VERSION_2C {
+        return Err("not an SNMPv2c message".into());
+    }
+    let (_, rest) = expect(rest, OCTET_STRING)?;
+    parse_pdu(rest)
+}
+
+fn parse_v3(message: &[u8]) -> Result<V3Response, BoxError> {
+    let start = message.as_ptr() as usize;
+    let (content, _) = expect(message, SEQUENCE)?;
+    let (version, rest) = expect(content, INTEGER)?;
+    if read_integer(version) != VERSION_3 {
+        return Err("not an SNMPv3 message".into());
+    }
+    let (global, rest) = expect(rest, SEQUENCE)?;
+    let (msg_id, global) = expect(global, INTEGER)?;
+    let (_, global) = expect(global, INTEGER)?;
+    let (flags, _) = expect(global, OCTET_STRING)?;
+
+    let (usm, scoped) = expect(rest, OCTET_STRING)?;
+    let (usm, _) = expect(usm, SEQUENCE)?;
+    let (engine_id, usm) = expect(usm, OCTET_STRING)?;
+    let (boots, usm) = expect(usm, INTEGER)?;
+    let (time, usm) = expect(usm, INTEGER)?;
+    let (_, usm) = expect(usm, OCTET_STRING)?;
+    let (auth, _) = expect(usm, OCTET_STRING)?;
+
+    let (scoped, _) = expect(scoped, SEQUENCE)?;
+    let (_, scoped) = expect(scoped, OCTET_STRING)?;
+    let (_, scoped) = expect(scoped, OCTET_STRING)?;
+
+    Ok(V3Response {
+        msg_id: read_integer(msg_id),
+        flags: flags.first().copied().unwrap_or_default(),
+        engine_id: engine_id.to_vec(),
+        boots: read_integer(boots),
+        time: read_integer(time),
+        auth_offset: auth.as_ptr() as usize - start,
+        pdu: parse_pdu(scoped)?,
+    })
+}
+
+fn parse_pdu(data: &[u8]) -> Result<Pdu, BoxError> {
+    let (kind, pdu, _) = read_tlv(data)?;
+    let (request_id, pdu) = expect(pdu, INTEGER)?;
+    let (error_status, pdu) = expect(pdu, INTEGER)?;
+    let (_, pdu) = expect(pdu, INTEGER)?;
+    let (mut list, _) = expect(pdu, SEQUENCE)?;
+
+    let mut varbinds = Vec::new();
+    while !list.is_empty() {
+        let (varbind, rest) = expect(list, SEQUENCE)?;
+        let (oid, varbind) = expect(varbind, OBJECT_IDENTIFIER)?;
+        let (kind, value, _) = read_tlv(varbind)?;
+        varbinds.push((decode_oid(oid)?, kind, value.to_vec()));
+        list = rest;
+    }
+    Ok(Pdu {
+        kind,
+        request_id: read_integer(request_id),
+        error_status: read_integer(error_status),
+        varbinds,
+    })
+}
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if content.len() < 0x80 {
+        out.push(content.len() as u8);
+    } else {
+        let len = (content.len() as u32).to_be_bytes();
+        let len = &len[len.iter().position(|byte| *byte != 0).unwrap_or(3)..];
+        out.push(0x80 | len.len() as u8);
+        out.extend_from_slice(len);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn header_len(len: usize) -> usize {
+    tlv(0, &vec![0; len]).len() - len
+}
+
+fn integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // The shortest two's complement encoding, keeping a leading byte only where it carries the sign.
+    let mut start = 0;
+    while start < 7
+        && ((bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    tlv(INTEGER, &bytes[start..])
+}
+
+// Returns the tag, the contents and whatever follows the element.
+fn read_tlv(data: &[u8]) -> Result<(u8, &[u8], &[u8]), BoxError> {
+    let truncated = || -> BoxError { "the agent's message is truncated".into() };
+    let tag = *data.first().ok_or_else(truncated)?;
+    let first = *data.get(1).ok_or_else(truncated)? as usize;
+    let (len, header) = if first & 0x80 == 0 {
+        (first, 2)
+    } else {
+        let count = first & 0x7f;
+        if count == 0 || count > 4 {
+            return Err("the agent's message is not valid BER".into());
+        }
+        let len = data
+            .get(2..2 + count)
+            .ok_or_else(truncated)?
+            .iter()
+            .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        (len, 2 + count)
+    };
+    let content = data.get(header..header + len).ok_or_else(truncated)?;
+    Ok((tag, content, &data[header + len..]))
+}
+
+fn expect(data: &[u8], tag: u8) -> Result<(&[u8], &[u8]), BoxError> {
+    match read_tlv(data)? {
+        (found, content, rest) if found == tag => Ok((content, rest)),
+        (found, ..) => Err(format!(
+            "expected BER tag {:#04x} in the agent's message, got {:#04x}",
+            tag, found
+        )
+        .into()),
+    }
+}
+
+fn read_integer(content: &[u8]) -> i64 {
+    let negative = content.first().is_some_and(|byte| byte & 0x80 != 0);
+    content
+        .iter()
+        .fold(if negative { -1 } else { 0 }, |value, byte| {
+            (value << 8) | i64::from(*byte)
+        })
+}
+
+fn read_unsigned(content: &[u8]) -> u64 {
+    content
+        .iter()
+        .fold(0, |value, byte| (value << 8) | u64::from(*byte))
+}
+
+pub fn parse_oid(oid: &str) -> Result<Vec<u32>, String> {
+    let invalid = || format!("`{}` is not an OID such as 1.3.6.1.2.1.1.3.0", oid);
+    let arcs = oid
+        .trim_start_matches('.')
+        .split('.')
+        .map(|arc| arc.parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    match arcs[..] {
+        [first, second, ..] if first < 2 && second < 40 => Ok(arcs),
+        [2, second, ..] if second < u32::MAX - 80 => Ok(arcs),
+        _ => Err(invalid()),
+    }
+}
+
+fn format_oid(oid: &[u32]) -> String {
+    oid.iter()
+        .map(|arc| arc.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// The first two arcs share a subidentifier, and every subidentifier is base 128 with the high bit on all but the last
+// byte.
+fn encode_oid(oid: &[u32]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let first = oid[0] * 40 + oid[1];
+    for arc in std::iter::once(first).chain(oid[2..].iter().copied()) {
+        let mut bytes = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            bytes.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        out.extend(bytes.iter().rev());
+    }
+    out
+}
+
+fn decode_oid(content: &[u8]) -> Result<Vec<u32>, BoxError> {
+    let mut subidentifiers = Vec::new();
+    let mut value: u32 = 0;
+    for byte in content {
+        value = value
+            .checked_mul(128)
+            .ok_or("the agent sent an OID that is too long")?
+            | u32::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            subidentifiers.push(value);
+            value = 0;
+        }
+    }
+    let (first, rest) = subidentifiers
+        .split_first()
+        .ok_or("the agent sent an empty OID")?;
+    let mut oid = match *first {
+        first if first < 80 => vec![first / 40, first % 40],
+        first => vec![2, first - 80],
+    };
+    oid.extend_from_slice(rest);
+    Ok(oid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn response(id: i64, error_status: i64, varbinds: &[(&[u32], Vec<u8>)]) -> Vec<u8> {
+        let varbinds: Vec<u8> = varbinds
+            .iter()
+            .flat_map(|(oid, value)| {
+                tlv(
+                    SEQUENCE,
+                    &[tlv(OBJECT_IDENTIFIER, &encode_oid(oid)), value.clone()].concat(),
+                )
+            })
+            .collect();
+        tlv(
+            RESPONSE,
+            &[integer(id), integer(error_status), integer(0), tlv(SEQUENCE, &varbinds)].concat(),
+        )
+    }
+
+    fn read(value: Vec<u8>) -> Result<String, String> {
+        let oid = [1, 3, 6, 1, 2, 1, 1, 3, 0];
+        let pdu = parse_pdu(&response(1, 0, &[(&oid, value)])).unwrap();
+        match super::value(&pdu, &oid) {
+            Ok(Value::Number(number)) => Ok(number.to_string()),
+            Ok(Value::Text(text)) => Ok(text),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    #[test]
+    fn encodes_integers_and_lengths() {
+        assert_eq!(integer(0), [INTEGER, 1, 0]);
+        assert_eq!(integer(127), [INTEGER, 1, 0x7f]);
+        assert_eq!(integer(128), [INTEGER, 2, 0, 0x80]);
+        assert_eq!(integer(256), [INTEGER, 2, 1, 0]);
+        assert_eq!(integer(-1), [INTEGER, 1, 0xff]);
+        assert_eq!(integer(-128), [INTEGER, 1, 0x80]);
+        assert_eq!(integer(-129), [INTEGER, 2, 0xff, 0x7f]);
+        for value in [
+            0,
+            1,
+            -1,
+            127,
+            128,
+            -128,
+            -129,
+            65535,
+            i64::from(i32::MAX),
+            i64::MIN,
+            i64::MAX,
+        ] {
+            assert_eq!(read_integer(expect(&integer(value), INTEGER).unwrap().0), value);
+        }
+
+        assert_eq!(tlv(OCTET_STRING, &[7; 200])[..3], [OCTET_STRING, 0x81, 200]);
+        assert_eq!(tlv(OCTET_STRING, &[7; 300])[..4], [OCTET_STRING, 0x82, 0x01, 0x2c]);
+        assert_eq!(header_len(300), 4);
+        let data = [tlv(OCTET_STRING, &[7; 300]), vec![1]].concat();
+        let (content, rest) = expect(&data, OCTET_STRING).unwrap();
+        assert_eq!((content.len(), rest), (300, &[1][..]));
+    }
+
+    #[test]
+    fn rejects_malformed_ber() {
+        let error = |data: &[u8]| read_tlv(data).unwrap_err().to_string();
+        assert_eq!(error(&[]), "the agent's message is truncated");
+        assert_eq!(error(&[OCTET_STRING, 3, 1]), "the agent's message is truncated");
+        assert_eq!(
+            error(&[OCTET_STRING, 0x85, 0, 0, 0, 0, 1]),
+            "the agent's message is not valid BER"
+        );
+        assert_eq!(
+            expect(&[NULL, 0], INTEGER).unwrap_err().to_string(),
+            "expected BER tag 0x02 in the agent's message, got 0x05"
+        );
+    }
+
+    #[test]
+    fn encodes_and_decodes_oids() {
+        let oid = parse_oid(DEFAULT_OID).unwrap();
+        assert_eq!(encode_oid(&oid), [0x2b, 6, 1, 2, 1, 1, 3, 0]);
+        assert_eq!(decode_oid(&[0x2b, 6, 1, 2, 1, 1, 3, 0]).unwrap(), oid);
+        // Arcs of 128 and more take more than one byte.
+        let enterprise = [1, 3, 6, 1, 4, 1, 2680, 1];
+        assert_eq!(encode_oid(&enterprise), [0x2b, 6, 1, 4, 1, 0x94, 0x78, 1]);
+        assert_eq!(decode_oid(&encode_oid(&enterprise)).unwrap(), enterprise);
+        assert_eq!(decode_oid(&encode_oid(&[2, 999, 3])).unwrap(), [2, 999, 3]);
+        assert_eq!(format_oid(&enterprise), "1.3.6.1.4.1.2680.1");
+
+        assert_eq!(parse_oid(".1.3.6.1"), Ok(vec![1, 3, 6, 1]));
+        assert!(parse_oid("1").is_err());
+        assert!(parse_oid("3.1").is_err());
+        assert!(parse_oid("1.40").is_err());
+        assert!(parse_oid("1.3.six").is_err());
+        assert!(decode_oid(&[]).is_err());
+        assert!(decode_oid(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]).is_err());
+    }
+
+    #[test]
+    fn reads_values() {
+        assert_eq!(read(tlv(TIME_TICKS, &[0x01, 0x02, 0x03])), Ok("66051".to_string()));
+        assert_eq!(read(tlv(COUNTER64, &[0xff; 8])), Ok((u64::MAX as f64).to_string()));
+        assert_eq!(read(integer(-42)), Ok("-42".to_string()));
+        assert_eq!(read(tlv(OCTET_STRING, b"core-sw1")), Ok("core-sw1".to_string()));
+        assert_eq!(read(tlv(OCTET_STRING, &[0x00, 0x1a, 0x2b])), Ok("00:1a:2b".to_string()));
+        assert_eq!(read(tlv(IP_ADDRESS, &[192, 0, 2, 1])), Ok("192.0.2.1".to_string()));
+        assert_eq!(read(tlv(OBJECT_IDENTIFIER, &[0x2b, 6, 1])), Ok("1.3.6.1".to_string()));
+        assert_eq!(read(tlv(NO_SUCH_INSTANCE, &[])), Err("no such instance".to_string()));
+        assert_eq!(
+            read(tlv(NULL, &[])),
+            Err("the agent answered without a value".to_string())
+        );
+
+        let oid = [1, 3, 6, 1];
+        let error = |pdu: &[u8]| match value(&parse_pdu(pdu).unwrap(), &oid) {
+            Err(err) => err.to_string(),
+            Ok(_) => panic!("expected an error"),
+        };
+        assert_eq!(
+            error(&response(1, 2, &[(&oid, tlv(NULL, &[]))])),
+            "the agent answered noSuchName"
+        );
+        assert_eq!(
+            error(&response(1, 0, &[(&[1, 3, 6, 2], integer(1))])),
+            "the agent answered for another OID"
+        );
+    }
+
+    #[test]
+    fn derives_keys_as_rfc_3414_does() {
+        // A.3.2, the password `maplesyrup` for the engine ID 00..02.
+        let key = password_key("maplesyrup");
+        assert_eq!(hex(&key), "9fb5cc0381497b3793528939ff788d5d79145211");
+        let engine_id = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+        assert_eq!(
+            hex(&localize(&key, &engine_id)),
+            "6695febc9288e36282235fc7151f128497b38f3f"
+        );
+
+        // RFC 2202, test case 1, of which the first 12 bytes go into messages.
+        let digest = hmac_sha1(&[0x0b; 20])
+            .chain_update(b"Hi There")
+            .finalize()
+            .into_bytes();
+        assert_eq!(hex(&digest), "b617318655057264e28bc0b6fb378c8ef146be00");
+    }
+
+    #[test]
+    fn authenticates_v3_messages() {
+        let engine_id = b"\x80\x00\x1f\x88\x04engine";
+        let key = localize(&password_key("maplesyrup"), engine_id);
+        let oid = [1, 3, 6, 1, 2, 1, 1, 3, 0];
+        let (id, message) = v3_request(engine_id, 3, 1200, "monitor", Some(&key), Some(&oid));
+
+        let parsed = parse_v3(&message).unwrap();
+        assert_eq!(parsed.msg_id, id);
+        assert_eq!(parsed.flags, AUTH | REPORTABLE);
+        assert_eq!(parsed.engine_id, engine_id);
+        assert_eq!((parsed.boots, parsed.time), (3, 1200));
+        assert_eq!(parsed.pdu.kind, GET_REQUEST);
+        assert_eq!(parsed.pdu.varbinds[0].0, oid);
+        assert!(authentic(&message, parsed.auth_offset, &key));
+
+        let mut tampered = message.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(!authentic(&tampered, parsed.auth_offset, &key));
+        assert!(!authentic(&message, parsed.auth_offset, &[0; 20]));
+
+        // Discovery goes out without authentication or a variable.
+        let (_, discovery) = v3_request(&[], 0, 0, "", None, None);
+        let parsed = parse_v3(&discovery).unwrap();
+        assert_eq!(parsed.flags, REPORTABLE);
+        assert!(parsed.pdu.varbinds.is_empty());
+    }
+
+    #[tokio::test]
+    async fn gets_values_over_v2c() {
+        let agent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = agent.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = [0; 1500];
+            let (len, peer) = agent.recv_from(&mut buffer).await.unwrap();
+            let (message, _) = expect(&buffer[..len], SEQUENCE).unwrap();
+            let (_, rest) = expect(message, INTEGER).unwrap();
+            let (community, rest) = expect(rest, OCTET_STRING).unwrap();
+            assert_eq!(community, b"secret");
+            let request = parse_pdu(rest).unwrap();
+            assert_eq!(request.kind, GET_REQUEST);
+
+            // An answer to some other request comes first and is ignored.
+            for id in [request.request_id + 1, request.request_id] {
+                let ticks = tlv(TIME_TICKS, &[0x12, 0x34]);
+                let pdu = response(id, 0, &[(&request.varbinds[0].0, ticks)]);
+                let reply = tlv(
+                    SEQUENCE,
+                    &[integer(VERSION_2C), tlv(OCTET_STRING, b"secret"), pdu].concat(),
+                );
+                agent.send_to(&reply, peer).await.unwrap();
+            }
+        });
+
+        let oid = parse_oid(DEFAULT_OID).unwrap();
+        let reading = get(address, &oid, Security::Community("secret"), &Source::default())
+            .await
+            .unwrap();
+        assert!(matches!(reading.value, Value::Number(ticks) if ticks == 4660.0));
+    }
+}
@@ -0,0 +1,179 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use socket2::{SockRef, Socket};
+use tokio::net::{TcpSocket, TcpStream, UdpSocket};
+
+use crate::config::{Dscp, Endpoint, IpVersion};
+
+// Where probes are sent from: the local address and the network interface their sockets are bound to, if any, along
+// with the DSCP marking of their packets and whether they may be fragmented.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Source {
+    pub ip: Option<IpAddr>,
+    pub interface: Option<String>,
+    pub dscp: Option<Dscp>,
+    pub dont_fragment: bool,
+}
+
+impl Source {
+    pub fn of(endpoint: &Endpoint) -> Self {
+        Source {
+            ip: endpoint.source_ip,
+            interface: endpoint.source_interface.clone(),
+            dscp: endpoint.dscp.first().copied(),
+            dont_fragment: endpoint.dont_fragment,
+        }
+    }
+
+    // The IP version the source address limits the probe to.
+    pub fn ip_version(&self) -> Option<IpVersion> {
+        self.ip
+            .map(|ip| if ip.is_ipv4() { IpVersion::V4 } else { IpVersion::V6 })
+    }
+
+    // The local address to bind to for reaching `target`.
+    pub(crate) fn local(&self, target: IpAddr) -> io::Result<SocketAddr> {
+        match (self.ip, target) {
+            (Some(ip), _) if ip.is_ipv4() != target.is_ipv4() => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("source_ip {} cannot reach {}", ip, target),
+            )),
+            (Some(ip), _) => Ok((ip, 0).into()),
+            (None, IpAddr::V4(_)) => Ok((Ipv4Addr::UNSPECIFIED, 0).into()),
+            (None, IpAddr::V6(_)) => Ok((Ipv6Addr::UNSPECIFIED, 0).into()),
+        }
+    }
+
+    pub fn bind(&self, socket: &Socket, target: IpAddr) -> io::Result<()> {
+        if let Some(interface) = &self.interface {
+            bind_device(socket.into(), interface)?;
+        }
+        if self.ip.is_some() {
+            socket.bind(&self.local(target)?.into())?;
+        }
+        if let Some(dscp) = self.dscp {
+            mark(socket.into(), target, dscp)?;
+        }
+        if self.dont_fragment {
+            dont_fragment(socket.into(), target)?;
+        }
+        Ok(())
+    }
+
+    pub async fn connect(&self, target: SocketAddr) -> io::Result<TcpStream> {
+        if self.ip.is_none() && self.interface.is_none() && self.dscp.is_none() {
+            return TcpStream::connect(target).await;
+        }
+
+        let socket = match target {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if let Some(interface) = &self.interface {
+            bind_device((&socket).into(), interface)?;
+        }
+        if self.ip.is_some() {
+            socket.bind(self.local(target.ip())?)?;
+        }
+        if let Some(dscp) = self.dscp {
+            mark((&socket).into(), target.ip(), dscp)?;
+        }
+        socket.connect(target).await
+    }
+
+    pub async fn udp(&self, target: SocketAddr) -> io::Result<UdpSocket> {
+        let socket = UdpSocket::bind(self.local(target.ip())?).await?;
+        if let Some(interface) = &self.interface {
+            bind_device((&socket).into(), interface)?;
+        }
+        if let Some(dscp) = self.dscp {
+            mark((&socket).into(), target.ip(), dscp)?;
+        }
+        Ok(socket)
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: SockRef<'_>, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_socket: SockRef<'_>, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "source_interface is only supported on Linux",
+    ))
+}
+
+#[cfg(unix)]
+fn mark(socket: SockRef<'_>, target: IpAddr, dscp: Dscp) -> io::Result<()> {
+    match target {
+        IpAddr::V4(_) => socket.set_tos(u32::from(dscp.tos())),
+        IpAddr::V6(_) => set_option(
+            socket,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TCLASS,
+            libc::c_int::from(dscp.tos()),
+        ),
+    }
+}
+
+#[cfg(not(unix))]
+fn mark(_socket: SockRef<'_>, _target: IpAddr, _dscp: Dscp) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "dscp is not supported on Windows",
+    ))
+}
+
+// Sets the don't fragment bit, and has sending fail with EMSGSIZE for packets larger than the path MTU the kernel
+// knows of, as `ping -M do` does.
+#[cfg(target_os = "linux")]
+fn dont_fragment(socket: SockRef<'_>, target: IpAddr) -> io::Result<()> {
+    match target {
+        IpAddr::V4(_) => set_option(socket, libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_DO),
+        IpAddr::V6(_) => set_option(
+            socket,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        ),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn dont_fragment(_socket: SockRef<'_>, _target: IpAddr) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "dont_fragment is only supported on Linux",
+    ))
+}
+
+// An int socket option that socket2 has no setter for.
+#[cfg(unix)]
+pub(crate) fn set_option(
+    socket: SockRef<'_>,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the option value is a valid int of the given length.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
@@ -0,0 +1,35 @@
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+use crate::source::Source;
+use crate::BoxError;
+
+pub(crate) const SSH_PORT: u16 = 22;
+// Servers may send other lines before their identification string, but not this many.
+const MAX_PREAMBLE: u64 = 8192;
+const IDENTIFICATION: &str = concat!("SSH-2.0-rust-net-stab_", env!("CARGO_PKG_VERSION"), "\r\n");
+
+// Connects and reads the server's identification string, such as `SSH-2.0-OpenSSH_9.6`, as described in RFC 4253.
+// Ours is sent first so the server logs a client that went away rather than one that never spoke SSH.
+pub async fn banner(server: SocketAddr, source: &Source) -> Result<String, BoxError> {
+    let mut stream = source.connect(server).await?;
+    stream.write_all(IDENTIFICATION.as_bytes()).await?;
+
+    let mut reader = BufReader::new(stream.take(MAX_PREAMBLE));
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return Err(if reader.get_ref().limit() == 0 {
+                format!("no SSH banner in the first {} bytes", MAX_PREAMBLE).into()
+            } else {
+                "the server closed the connection without sending an SSH banner".into()
+            });
+        }
+        if line.starts_with(b"SSH-") {
+            let banner = String::from_utf8_lossy(&line);
+            return Ok(banner.trim_end_matches(['\r', '\n']).to_string());
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::io;
+use std::net::SocketAddr;
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+
+use crate::source::Source;
+use crate::status::ProbeEvent;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatsdConfig {
+    // A `host:port` to send to.
+    pub address: String,
+    #[serde(default = "default_udp")]
+    pub protocol: Protocol,
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GraphiteConfig {
+    pub address: String,
+    #[serde(default = "default_tcp")]
+    pub protocol: Protocol,
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Udp,
+    Tcp,
+}
+
+fn default_udp() -> Protocol {
+    Protocol::Udp
+}
+
+fn default_tcp() -> Protocol {
+    Protocol::Tcp
+}
+
+fn default_prefix() -> String {
+    "net_stab".to_string()
+}
+
+impl StatsdConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        validate_address(&self.address)
+    }
+}
+
+impl GraphiteConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        validate_address(&self.address)
+    }
+}
+
+fn validate_address(address: &str) -> Result<(), String> {
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+        _ => Err(format!("invalid address `{}`, expected host:port", address)),
+    }
+}
+
+// Sends a StatsD timing for the round trip, counters for successes and failures and gauges for the state and the loss
+// of every probe result.
+pub async fn run_statsd(config: StatsdConfig, events: broadcast::Receiver<ProbeEvent>, stop: watch::Receiver<bool>) {
+    let mut sink = Sink::new(config.address.clone(), config.protocol);
+    run(
+        events,
+        stop,
+        "StatsD",
+        |event| {
+            let path = format!("{}.{}", config.prefix, path(event));
+            let mut lines = vec![
+                format!(
+                    "{}.{}:1|c",
+                    path,
+                    if event.error.is_none() { "success" } else { "failure" }
+                ),
+                format!("{}.up:{}|g", path, event.up as u8),
+                format!("{}.loss_ratio:{}|g", path, event.loss_ratio),
+            ];
+            if let Some(rtt) = event.rtt_seconds {
+                lines.push(format!("{}.rtt:{}|ms", path, rtt * 1000.0));
+            }
+            lines.join("\n")
+        },
+        &mut sink,
+    )
+    .await
+}
+
+// Sends the same values as plaintext Graphite lines, with the success of the probe as 1 or 0.
+pub async fn run_graphite(
+    config: GraphiteConfig,
+    events: broadcast::Receiver<ProbeEvent>,
+    stop: watch::Receiver<bool>,
+) {
+    let mut sink = Sink::new(config.address.clone(), config.protocol);
+    run(
+        events,
+        stop,
+        "Graphite",
+        |event| {
+            let path = format!("{}.{}", config.prefix, path(event));
+            let timestamp = event.timestamp as u64;
+            let mut lines = vec![
+                format!("{}.success {} {}", path, event.error.is_none() as u8, timestamp),
+                format!("{}.up {} {}", path, event.up as u8, timestamp),
+                format!("{}.loss_ratio {} {}", path, event.loss_ratio, timestamp),
+            ];
+            if let Some(rtt) = event.rtt_seconds {
+                lines.push(format!("{}.rtt_ms {} {}", path, rtt * 1000.0, timestamp));
+            }
+            lines.join("\n") + "\n"
+        },
+        &mut sink,
+    )
+    .await
+}
+
+async fn run(
+    mut events: broadcast::Receiver<ProbeEvent>,
+    mut stop: watch::Receiver<bool>,
+    name: &str,
+    format: impl Fn(&ProbeEvent) -> String,
+    sink: &mut Sink,
+) {
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = stop.changed() => break,
+        };
+        match event {
+            Ok(event) => sink.send(name, format(&event).as_bytes()).await,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("{} output fell behind and skipped {} probe results", name, skipped);
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+
+    // Results published by the last probes still go out before returning.
+    while let Ok(event) = events.try_recv() {
+        sink.send(name, format(&event).as_bytes()).await;
+    }
+}
+
+// The endpoint's name made safe for a dotted metric path, with the IP version appended when there is one.
+fn path(event: &ProbeEvent) -> String {
+    let mut path: String = event
+        .name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    if let Some(ip_version) = event.ip_version {
+        path.push('_');
+        path.push_str(ip_version);
+    }
+    path
+}
+
+// A connection that is opened when first needed and opened again after it failed.
+struct Sink {
+    address: String,
+    protocol: Protocol,
+    udp: Option<UdpSocket>,
+    tcp: Option<TcpStream>,
+}
+
+impl Sink {
+    fn new(address: String, protocol: Protocol) -> Self {
+        Sink {
+            address,
+            protocol,
+            udp: None,
+            tcp: None,
+        }
+    }
+
+    async fn send(&mut self, name: &str, payload: &[u8]) {
+        if let Err(err) = self.try_send(payload).await {
+            error!("Failed to send to {} at {}: {}", name, self.address, err);
+            self.udp = None;
+            self.tcp = None;
+        }
+    }
+
+    async fn try_send(&mut self, payload: &[u8]) -> io::Result<()> {
+        match self.protocol {
+            Protocol::Udp => {
+                if self.udp.is_none() {
+                    let target = resolve(&self.address).await?;
+                    let socket = Source::default().udp(target).await?;
+                    socket.connect(target).await?;
+                    self.udp = Some(socket);
+                }
+                self.udp.as_ref().unwrap().send(payload).await?;
+            }
+            Protocol::Tcp => {
+                if self.tcp.is_none() {
+                    self.tcp = Some(TcpStream::connect(&self.address).await?);
+                }
+                let stream = self.tcp.as_mut().unwrap();
+                stream.write_all(payload).await?;
+                if !payload.ends_with(b"\n") {
+                    stream.write_all(b"\n").await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn resolve(address: &str) -> io::Result<SocketAddr> {
+    tokio::net::lookup_host(address)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", address)))
+}
@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+const HISTORY_LEN: usize = 60;
+const EVENT_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointStatus {
+    pub name: String,
+    pub address: String,
+    pub location: Option<String>,
+    pub probe_type: &'static str,
+    pub ip_version: Option<&'static str>,
+    pub labels: BTreeMap<String, String>,
+    pub up: Option<bool>,
+    pub last_rtt_seconds: Option<f64>,
+    pub loss_ratio: f64,
+    pub last_error: Option<String>,
+    pub last_probe: Option<f64>,
+    pub last_change: Option<f64>,
+    pub maintenance: bool,
+    // Round trip times of the most recent probes, oldest first, with `null` for failed probes.
+    pub history: VecDeque<Option<f64>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    pub endpoints: Vec<EndpointStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeEvent {
+    pub name: String,
+    pub address: String,
+    pub location: Option<String>,
+    pub probe_type: &'static str,
+    pub ip_version: Option<&'static str>,
+    // The state after hysteresis, or the result of this probe while there have not been enough probes to settle on one.
+    pub up: bool,
+    pub rtt_seconds: Option<f64>,
+    pub loss_ratio: f64,
+    pub error: Option<String>,
+    // The `reason` label the failure was counted under in `probe_errors_total`.
+    pub reason: Option<&'static str>,
+    // Probed during a maintenance window in `mark` mode.
+    pub maintenance: bool,
+    pub timestamp: f64,
+    // The trace of the exemplars of its latency, when the OTLP output exports spans.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+}
+
+pub struct ProbeResult {
+    // The state after hysteresis, `None` while there have not been enough probes to settle on one.
+    pub up: Option<bool>,
+    pub rtt: Option<Duration>,
+    pub loss_ratio: f64,
+    pub error: Option<String>,
+    pub reason: Option<&'static str>,
+    pub maintenance: bool,
+    pub trace_id: Option<String>,
+}
+
+// The state of every endpoint as of its latest probe, shared between the probe tasks and the HTTP server.
+#[derive(Clone)]
+pub struct StatusBoard {
+    // Keyed by `key`, since an endpoint probed over both IP versions has two entries.
+    endpoints: Arc<Mutex<BTreeMap<String, EndpointStatus>>>,
+    events: broadcast::Sender<ProbeEvent>,
+}
+
+impl Default for StatusBoard {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        StatusBoard {
+            endpoints: Arc::default(),
+            events,
+        }
+    }
+}
+
+impl StatusBoard {
+    // Keeps the state of an endpoint that is already known, so restarting its probe does not reset it.
+    pub fn insert(&self, status: EndpointStatus) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        match endpoints.get_mut(&key(&status.name, status.ip_version)) {
+            Some(existing) => {
+                existing.address = status.address;
+                existing.location = status.location;
+                existing.probe_type = status.probe_type;
+                existing.labels = status.labels;
+            }
+            None => {
+                endpoints.insert(key(&status.name, status.ip_version), status);
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.endpoints.lock().unwrap().remove(key);
+    }
+
+    pub fn record(&self, key: &str, result: ProbeResult) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let status = match endpoints.get_mut(key) {
+            Some(status) => status,
+            None => return,
+        };
+
+        let now = unix_time();
+        if result.up.is_some() && status.up != result.up {
+            status.last_change = Some(now);
+            status.up = result.up;
+        }
+        status.last_rtt_seconds = result.rtt.map(|rtt| rtt.as_secs_f64());
+        if status.history.len() == HISTORY_LEN {
+            status.history.pop_front();
+        }
+        status.history.push_back(status.last_rtt_seconds);
+        status.loss_ratio = result.loss_ratio;
+        status.last_error = result.error;
+        status.last_probe = Some(now);
+        status.maintenance = result.maintenance;
+
+        // Sending only fails when nobody is subscribed.
+        let _ = self.events.send(ProbeEvent {
+            name: status.name.clone(),
+            address: status.address.clone(),
+            location: status.location.clone(),
+            probe_type: status.probe_type,
+            ip_version: status.ip_version,
+            up: status.up.unwrap_or(status.last_error.is_none()),
+            rtt_seconds: status.last_rtt_seconds,
+            loss_ratio: status.loss_ratio,
+            error: status.last_error.clone(),
+            reason: result.reason,
+            maintenance: result.maintenance,
+            timestamp: now,
+            trace_id: result.trace_id,
+        });
+    }
+
+    // Endpoints whose probes are skipped during maintenance only have the flag set, as there are no results.
+    pub fn set_maintenance(&self, key: &str, maintenance: bool) {
+        if let Some(status) = self.endpoints.lock().unwrap().get_mut(key) {
+            status.maintenance = maintenance;
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ProbeEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            endpoints: self.endpoints.lock().unwrap().values().cloned().collect(),
+        }
+    }
+}
+
+impl ProbeEvent {
+    pub fn key(&self) -> String {
+        key(&self.name, self.ip_version)
+    }
+}
+
+pub fn key(name: &str, ip_version: Option<&str>) -> String {
+    match ip_version {
+        Some(ip_version) => format!("{} ({})", name, ip_version),
+        None => name.to_string(),
+    }
+}
+
+pub fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+// Splits a Unix timestamp into the UTC date and time with the `civil_from_days` algorithm.
+pub(crate) fn civil(timestamp: f64) -> (i64, i64, i64, i64, i64, i64) {
+    let seconds = timestamp as i64;
+    let days = seconds.div_euclid(86400);
+    let time = seconds.rem_euclid(86400);
+
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+// The `days_from_civil` algorithm, the inverse of `civil`: the days between 1970-01-01 and the date.
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
@@ -0,0 +1,344 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::config::{self, Format};
+use crate::source::Source;
+use crate::{arp, expand, icmp, targets, BoxError};
+
+// Echo requests in flight at once, and how long each host has to answer.
+const CONCURRENCY: usize = 256;
+const TIMEOUT: Duration = Duration::from_secs(1);
+// Where the MAC vendor lists of nmap, Wireshark and the IEEE's own, as packaged by ieee-data and hwdata, are installed.
+const VENDOR_FILES: [&str; 5] = [
+    "/usr/share/nmap/nmap-mac-prefixes",
+    "/usr/share/wireshark/manuf",
+    "/usr/share/ieee-data/oui.txt",
+    "/usr/share/hwdata/oui.txt",
+    "/usr/share/misc/oui.txt",
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SweepOptions {
+    // A CIDR block or range, every directly connected network without one.
+    pub subnet: Option<String>,
+    // Add the hosts found to the endpoints of the config.
+    pub add: bool,
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Host {
+    pub address: IpAddr,
+    pub mac: Option<String>,
+    pub vendor: Option<String>,
+    // Which of the requests it answered.
+    pub arp: bool,
+    pub icmp: bool,
+    pub rtt_seconds: f64,
+}
+
+impl Host {
+    // Named after the vendor of its network card where it is known, which says more than the address alone.
+    fn name(&self) -> String {
+        match &self.vendor {
+            Some(vendor) => format!("{} {}", vendor, self.address),
+            None => self.address.to_string(),
+        }
+    }
+}
+
+// Sends an ARP request and an echo request to every address of the networks, and returns the hosts that answered
+// either, in the order of their addresses. ARP finds the hosts that drop pings, with their hardware addresses, but only
+// on a directly connected network on Linux.
+pub async fn sweep(networks: &[String]) -> Result<Vec<Host>, BoxError> {
+    let vendors = vendors();
+    let mut hosts: BTreeMap<IpAddr, Host> = BTreeMap::new();
+    for network in networks {
+        let ips = match expand::addresses(network) {
+            Some(ips) => ips?,
+            None => return Err(format!("`{}` is not a CIDR block or an address range", network).into()),
+        };
+        let ipv4: Vec<Ipv4Addr> = ips
+            .iter()
+            .filter_map(|ip| match ip {
+                IpAddr::V4(ip) => Some(*ip),
+                IpAddr::V6(_) => None,
+            })
+            .collect();
+        if ipv4.len() != ips.len() {
+            return Err(format!(
+                "`{}` is not an IPv4 network, IPv6 networks are too large to sweep",
+                network
+            )
+            .into());
+        }
+
+        let (neighbors, echoes) = tokio::join!(arp::sweep(ipv4, TIMEOUT), ping(&ips));
+        let neighbors = neighbors.unwrap_or_else(|err| {
+            // Networks behind a router have no neighbors to ask, which is no reason to warn about.
+            if err.kind() != std::io::ErrorKind::NetworkUnreachable {
+                warn!("Only pinging {}, the ARP sweep failed: {}", network, err);
+            }
+            Vec::new()
+        });
+
+        for neighbor in neighbors {
+            let vendor = vendors.get(&neighbor.mac[..3]).cloned();
+            let mac = neighbor
+                .mac
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<Vec<_>>()
+                .join(":");
+            hosts.insert(
+                IpAddr::V4(neighbor.ip),
+                Host {
+                    address: IpAddr::V4(neighbor.ip),
+                    mac: Some(mac),
+                    vendor,
+                    arp: true,
+                    icmp: false,
+                    rtt_seconds: neighbor.rtt.as_secs_f64(),
+                },
+            );
+        }
+        for (ip, rtt) in echoes {
+            let host = hosts.entry(ip).or_insert_with(|| Host {
+                address: ip,
+                mac: None,
+                vendor: None,
+                arp: false,
+                icmp: true,
+                rtt_seconds: rtt.as_secs_f64(),
+            });
+            host.icmp = true;
+            host.rtt_seconds = rtt.as_secs_f64();
+        }
+    }
+    Ok(hosts.into_values().collect())
+}
+
+// The addresses that answered an echo request, other than those of this host, with their round trip times.
+async fn ping(ips: &[IpAddr]) -> Vec<(IpAddr, Duration)> {
+    let limit = Arc::new(Semaphore::new(CONCURRENCY));
+    let mut tasks = JoinSet::new();
+    for &ip in ips {
+        let limit = limit.clone();
+        tasks.spawn(async move {
+            let _permit = limit.acquire().await;
+            let echo = icmp::echo(ip, icmp::PAYLOAD.len(), TIMEOUT, &Source::default()).await;
+            echo.ok()
+                .filter(|_| !is_local(ip))
+                .map(|echo| (ip, echo.rtt))
+        });
+    }
+
+    let mut answered = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        if let Ok(Some(answer)) = result {
+            answered.push(answer);
+        }
+    }
+    answered
+}
+
+// Whether the address is one of this host's own, which the system sends from when connecting to it.
+fn is_local(ip: IpAddr) -> bool {
+    let unspecified: IpAddr = match ip {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => std::net::Ipv6Addr::UNSPECIFIED.into(),
+    };
+    UdpSocket::bind((unspecified, 0))
+        .and_then(|socket| {
+            socket.connect((ip, 9))?;
+            socket.local_addr()
+        })
+        .is_ok_and(|local| local.ip() == ip)
+}
+
+// The networks of the routes that do not go through a gateway, such as that of the LAN, other than loopback and
+// networks too large to sweep.
+#[cfg(target_os = "linux")]
+fn connected_networks() -> Result<Vec<String>, String> {
+    const RTF_UP: u32 = 0x1;
+    const RTF_GATEWAY: u32 = 0x2;
+    let routes =
+        fs::read_to_string("/proc/net/route").map_err(|err| format!("failed to read /proc/net/route: {}", err))?;
+
+    // Interface, destination, gateway, flags, and then the rest, with the addresses as little-endian hexadecimal.
+    let mut networks = Vec::new();
+    for line in routes.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let hex = |index: usize| {
+            fields
+                .get(index)
+                .and_then(|field| u32::from_str_radix(field, 16).ok())
+        };
+        if let (Some(destination), Some(0), Some(flags), Some(mask)) = (hex(1), hex(2), hex(3), hex(7)) {
+            let prefix = mask.count_ones();
+            if fields[0] != "lo" && flags & (RTF_UP | RTF_GATEWAY) == RTF_UP && (16..=30).contains(&prefix) {
+                let network = format!("{}/{}", Ipv4Addr::from(destination.to_le_bytes()), prefix);
+                if !networks.contains(&network) {
+                    networks.push(network);
+                }
+            }
+        }
+    }
+    if networks.is_empty() {
+        return Err("there is no directly connected network to sweep, pass --subnet".to_string());
+    }
+    Ok(networks)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connected_networks() -> Result<Vec<String>, String> {
+    Err("the connected networks are only found on Linux, pass --subnet".to_string())
+}
+
+// The vendors of MAC address blocks by their first three bytes, from the first list installed. The lines of all of
+// them start with the block, as `000000`, `00:00:00` or `00-00-00`, and any of `(hex)` and `(base 16)` before the
+// vendor, of which Wireshark's has a short and a long name.
+fn vendors() -> HashMap<[u8; 3], String> {
+    let mut vendors = HashMap::new();
+    let list = match VENDOR_FILES
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+    {
+        Some(list) => list,
+        None => return vendors,
+    };
+    for line in list.lines() {
+        let (block, vendor) = match line.trim_start().split_once(char::is_whitespace) {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let hex: String = block.chars().filter(|c| *c != ':' && *c != '-').collect();
+        let block = match u32::from_str_radix(&hex, 16) {
+            Ok(block) if hex.len() == 6 => block.to_be_bytes(),
+            _ => continue,
+        };
+        let vendor = vendor.trim_start();
+        let vendor = vendor
+            .strip_prefix("(hex)")
+            .or_else(|| vendor.strip_prefix("(base 16)"))
+            .unwrap_or(vendor)
+            .trim()
+            .split('\t')
+            .next()
+            .unwrap_or_default();
+        if !vendor.is_empty() {
+            vendors
+                .entry([block[1], block[2], block[3]])
+                .or_insert_with(|| vendor.to_string());
+        }
+    }
+    vendors
+}
+
+// Lines up the hosts in columns like `check` does.
+pub fn table(hosts: &[Host]) -> String {
+    let rows: Vec<[String; 5]> = hosts
+        .iter()
+        .map(|host| {
+            let answered: Vec<&str> = [(host.arp, "arp"), (host.icmp, "icmp")]
+                .iter()
+                .filter(|(answered, _)| *answered)
+                .map(|(_, name)| *name)
+                .collect();
+            [
+                host.address.to_string(),
+                host.mac.clone().unwrap_or_else(|| "-".to_string()),
+                host.vendor.clone().unwrap_or_else(|| "-".to_string()),
+                answered.join(", "),
+                format!("{:.2} ms", host.rtt_seconds * 1000.0),
+            ]
+        })
+        .collect();
+
+    let header = ["ADDRESS", "MAC", "VENDOR", "ANSWERED", "RTT"].map(String::from);
+    let mut widths = header.clone().map(|column| column.len());
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let mut line = String::new();
+        for (column, width) in row.iter().zip(widths) {
+            line.push_str(&format!("{:<width$}  ", column, width = width));
+        }
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+// The `discover` command. Prints the hosts found, and with `add` writes the ones the config does not probe yet to its
+// endpoints: with the icmp probe when they answered pings, and the arp probe when they only answered ARP.
+pub async fn run(config_path: &str, options: &SweepOptions) -> Result<(), BoxError> {
+    // Loaded first, so that a config that cannot be added to fails before the sweep rather than after it.
+    let config = if options.add {
+        if Format::of(config_path) != Format::Yaml {
+            return Err("--add edits the text of the config, which is only done for YAML".into());
+        }
+        Some(config::load(config_path)?)
+    } else {
+        None
+    };
+    let networks = match &options.subnet {
+        Some(subnet) => vec![subnet.clone()],
+        None => connected_networks()?,
+    };
+    let hosts = sweep(&networks).await?;
+
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&hosts)?);
+    } else {
+        print!("{}", table(&hosts));
+        println!("Found {} hosts in {}", hosts.len(), networks.join(", "));
+    }
+
+    if let Some(config) = config {
+        let entries: Vec<_> = hosts
+            .iter()
+            .filter(|host| {
+                let address = host.address.to_string();
+                let name = host.name();
+                !config
+                    .endpoints
+                    .iter()
+                    .any(|endpoint| endpoint.address == address || endpoint.name == name)
+            })
+            .map(|host| {
+                json!({
+                    "name": host.name(),
+                    "address": host.address.to_string(),
+                    "probe": if host.icmp { "icmp" } else { "arp" },
+                })
+            })
+            .collect();
+        if !entries.is_empty() {
+            targets::append(config_path, &entries)?;
+        }
+        if !options.json {
+            println!(
+                "Added {} endpoints to {}, {} were already in it",
+                entries.len(),
+                config_path,
+                hosts.len() - entries.len()
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,459 @@
+use std::collections::BTreeSet;
+use std::fs;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use warp::http::StatusCode;
+
+use crate::config::{self, Config, Endpoint, Format};
+use crate::discovery::Discovery;
+use crate::expand;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiConfig {
+    pub token_env: Option<String>,
+    pub token_file: Option<String>,
+    // Write the endpoints added and removed through the API to the config file, rather than only keeping them until
+    // the monitor restarts.
+    #[serde(default)]
+    pub persist: bool,
+}
+
+impl ApiConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.token_env.is_some() && self.token_file.is_some() {
+            return Err("set only one of token_env or token_file".to_string());
+        }
+        match self.token()? {
+            None => return Err("set token_env or token_file, the targets API always needs a token".to_string()),
+            Some(token) if token.is_empty() => return Err("the token is empty".to_string()),
+            Some(_) => {}
+        }
+        Ok(())
+    }
+
+    pub(crate) fn token(&self) -> Result<Option<String>, String> {
+        config::secret("token", &self.token_env, &self.token_file)
+    }
+}
+
+// What the server needs to answer the targets API: the token requests have to carry, and where to send them.
+#[derive(Clone)]
+pub struct TargetsApi {
+    pub(crate) token: String,
+    pub(crate) commands: mpsc::Sender<Command>,
+}
+
+// A request of the targets API, answered by whoever owns the config.
+pub(crate) enum Command {
+    List(oneshot::Sender<Vec<Target>>),
+    Add(serde_json::Value, oneshot::Sender<Result<(), Rejection>>),
+    Remove(String, oneshot::Sender<Result<(), Rejection>>),
+}
+
+// The status and message a request is refused with.
+pub(crate) type Rejection = (StatusCode, String);
+
+#[derive(Serialize)]
+pub(crate) struct Target {
+    // `config`, `api` or `discovery`.
+    source: &'static str,
+    #[serde(flatten)]
+    endpoint: Endpoint,
+}
+
+// The endpoints added and removed through the API while they are not persisted, laid over every config that is
+// loaded.
+#[derive(Default)]
+pub(crate) struct Targets {
+    added: Vec<Endpoint>,
+    removed: BTreeSet<String>,
+}
+
+impl Targets {
+    pub(crate) fn apply(&self, config: &Config) -> Config {
+        let mut applied = config.clone();
+        applied
+            .endpoints
+            .retain(|endpoint| !self.removed.contains(&endpoint.name));
+        applied.endpoints.extend(self.added.iter().cloned());
+        applied
+    }
+
+    // Returns whether the config file was changed, in which case it has to be loaded again.
+    pub(crate) fn handle(&mut self, command: Command, config: &Config, discovery: &Discovery, path: &str) -> bool {
+        let persist = config.api.as_ref().is_some_and(|api| api.persist);
+        let applied = self.apply(config);
+        match command {
+            Command::List(reply) => {
+                let targets = discovery
+                    .merge(&applied)
+                    .endpoints
+                    .into_iter()
+                    .map(|endpoint| Target {
+                        source: if self.added.iter().any(|added| added.name == endpoint.name) {
+                            "api"
+                        } else if applied
+                            .endpoints
+                            .iter()
+                            .any(|known| known.name == endpoint.name)
+                        {
+                            "config"
+                        } else {
+                            "discovery"
+                        },
+                        endpoint,
+                    })
+                    .collect();
+                let _ = reply.send(targets);
+                false
+            }
+            Command::Add(body, reply) => {
+                let result = self.add(body, &applied, discovery, path, persist);
+                let changed = persist && result.is_ok();
+                let _ = reply.send(result);
+                changed
+            }
+            Command::Remove(name, reply) => {
+                let result = self.remove(&name, &applied, discovery, path, persist);
+                let changed = persist && result.is_ok();
+                let _ = reply.send(result);
+                changed
+            }
+        }
+    }
+
+    fn add(
+        &mut self,
+        body: serde_json::Value,
+        config: &Config,
+        discovery: &Discovery,
+        path: &str,
+        persist: bool,
+    ) -> Result<(), Rejection> {
+        let mut endpoint: Endpoint =
+            serde_json::from_value(body.clone()).map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+        if discovery
+            .merge(config)
+            .endpoints
+            .iter()
+            .any(|known| known.name == endpoint.name)
+        {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("there already is an endpoint named `{}`", endpoint.name),
+            ));
+        }
+        if expand::addresses(&endpoint.address).is_some() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "address ranges cannot be added through the API, add them to the config".to_string(),
+            ));
+        }
+        endpoint.inherit(&config.defaults);
+        let problems = config::endpoint_problems(&endpoint, config);
+        if !problems.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, problems.join("; ")));
+        }
+
+        if persist {
+            edit(path, |source| add_entry(source, &body))?;
+        } else {
+            self.removed.remove(&endpoint.name);
+            self.added.push(endpoint.clone());
+        }
+        info!("Added endpoint `{}` through the API", endpoint.name);
+        Ok(())
+    }
+
+    fn remove(
+        &mut self,
+        name: &str,
+        config: &Config,
+        discovery: &Discovery,
+        path: &str,
+        persist: bool,
+    ) -> Result<(), Rejection> {
+        if !config
+            .endpoints
+            .iter()
+            .any(|endpoint| endpoint.name == name)
+        {
+            let discovered = discovery
+                .merge(config)
+                .endpoints
+                .iter()
+                .any(|endpoint| endpoint.name == name);
+            return Err(if discovered {
+                (
+                    StatusCode::CONFLICT,
+                    format!("`{}` was discovered, remove it where it was discovered from", name),
+                )
+            } else {
+                (StatusCode::NOT_FOUND, format!("there is no endpoint named `{}`", name))
+            });
+        }
+
+        if persist {
+            edit(path, |source| remove_entry(source, name))?;
+        } else if let Some(index) = self.added.iter().position(|endpoint| endpoint.name == name) {
+            self.added.remove(index);
+        } else {
+            self.removed.insert(name.to_string());
+        }
+        info!("Removed endpoint `{}` through the API", name);
+        Ok(())
+    }
+}
+
+// Persisting edits the text of the file, so that its comments and environment variable references are kept, which is
+// only done for YAML.
+pub(crate) fn check_persist(config: &Config, path: &str) -> Result<(), String> {
+    if config.api.as_ref().is_some_and(|api| api.persist) && Format::of(path) != Format::Yaml {
+        return Err("api: persist needs a YAML config".to_string());
+    }
+    Ok(())
+}
+
+// Adds entries to the endpoints of a YAML config file the way persisting does, for the hosts `discover` found.
+pub(crate) fn append(path: &str, entries: &[serde_json::Value]) -> Result<(), String> {
+    edit(path, |source| {
+        entries
+            .iter()
+            .try_fold(source.to_string(), |source, entry| add_entry(&source, entry))
+    })
+    .map_err(|(_, message)| message)
+}
+
+// Applies the edit to the file, as long as the config it results in is still valid.
+fn edit<F>(path: &str, edit: F) -> Result<(), Rejection>
+where
+    F: FnOnce(&str) -> Result<String, Rejection>,
+{
+    let internal = |err: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}: {}", path, err));
+    let source = fs::read_to_string(path).map_err(internal)?;
+    let edited = edit(&source)?;
+
+    let parsed = config::parse(&edited, Format::Yaml).map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    if let Some(problem) = config::validate(&parsed).into_iter().next() {
+        return Err((StatusCode::BAD_REQUEST, problem.message));
+    }
+
+    // Written next to the file and moved over it, so that a reload never sees it half written.
+    let temporary = format!("{}.tmp", path);
+    fs::write(&temporary, edited).map_err(internal)?;
+    fs::rename(&temporary, path).map_err(internal)
+}
+
+// The lines of the top-level `endpoints` list of a YAML config: its key, where the list ends, and where each of its
+// entries starts and ends. An empty list may be written as `endpoints: []`.
+struct EndpointList {
+    key: usize,
+    indent: usize,
+    end: usize,
+    entries: Vec<(usize, usize)>,
+}
+
+fn endpoint_list(lines: &[&str]) -> Result<Option<EndpointList>, Rejection> {
+    let key = match lines.iter().position(|line| {
+        line.strip_prefix("endpoints:").is_some_and(|rest| {
+            let rest = rest.trim();
+            rest.is_empty() || rest == "[]" || rest.starts_with('#')
+        })
+    }) {
+        Some(key) => key,
+        None if lines.iter().any(|line| line.starts_with("endpoints:")) => {
+            return Err((
+                StatusCode::CONFLICT,
+                "endpoints is not written as a list of entries on their own lines".to_string(),
+            ))
+        }
+        None => return Ok(None),
+    };
+
+    let mut indent = None;
+    let mut end = key + 1;
+    let mut entries: Vec<(usize, usize)> = Vec::new();
+    let empty = lines[key].trim_end() == "endpoints: []";
+    for (number, line) in lines.iter().enumerate().skip(key + 1).filter(|_| !empty) {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let line_indent = line.len() - trimmed.len();
+        if line_indent == 0 && !trimmed.starts_with('-') {
+            break;
+        }
+        let entry_indent = *indent.get_or_insert(line_indent);
+        if line_indent == entry_indent && (trimmed == "-" || trimmed.starts_with("- ")) {
+            entries.push((number, number + 1));
+        }
+        if let Some(entry) = entries.last_mut() {
+            entry.1 = number + 1;
+        }
+        end = number + 1;
+    }
+
+    Ok(Some(EndpointList {
+        key,
+        indent: indent.unwrap_or(2),
+        end,
+        entries,
+    }))
+}
+
+fn add_entry(source: &str, body: &serde_json::Value) -> Result<String, Rejection> {
+    let internal = |err: serde_yaml::Error| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+    // Written with the name first, as entries usually are.
+    let mut entry = serde_yaml::Mapping::new();
+    if let serde_json::Value::Object(fields) = body {
+        let (name, rest): (Vec<_>, Vec<_>) = fields.iter().partition(|(key, _)| *key == "name");
+        for (key, value) in name.into_iter().chain(rest) {
+            entry.insert(key.as_str().into(), serde_yaml::to_value(value).map_err(internal)?);
+        }
+    }
+    let yaml = serde_yaml::to_string(&vec![entry]).map_err(internal)?;
+    let yaml = yaml.strip_prefix("---\n").unwrap_or(&yaml);
+
+    let mut lines: Vec<&str> = source.lines().collect();
+    let (indent, at) = match endpoint_list(&lines)? {
+        Some(list) => {
+            if lines[list.key].trim_end() == "endpoints: []" {
+                lines[list.key] = "endpoints:";
+            }
+            (list.indent, list.end)
+        }
+        None => {
+            lines.push("endpoints:");
+            (2, lines.len())
+        }
+    };
+    let entry = yaml
+        .lines()
+        .map(|line| format!("{}{}", " ".repeat(indent), line));
+
+    let mut edited: Vec<String> = lines[..at].iter().map(|line| line.to_string()).collect();
+    edited.extend(entry);
+    edited.extend(lines[at..].iter().map(|line| line.to_string()));
+    Ok(edited.join("\n") + "\n")
+}
+
+fn remove_entry(source: &str, name: &str) -> Result<String, Rejection> {
+    let lines: Vec<&str> = source.lines().collect();
+    let not_listed = || {
+        (
+            StatusCode::CONFLICT,
+            format!(
+                "`{}` is not an entry of endpoints in the config file, it may come from a group or an address range",
+                name
+            ),
+        )
+    };
+    let list = endpoint_list(&lines)?.ok_or_else(not_listed)?;
+
+    let (start, end) = list
+        .entries
+        .iter()
+        .copied()
+        .find(|(start, end)| {
+            let entry: String = lines[*start..*end]
+                .iter()
+                .map(|line| format!("{}\n", line.get(list.indent..).unwrap_or_default()))
+                .collect();
+            serde_yaml::from_str::<Vec<serde_yaml::Value>>(&entry)
+                .ok()
+                .and_then(|entry| {
+                    entry
+                        .first()?
+                        .get("name")?
+                        .as_str()
+                        .map(|entry| entry == name)
+                })
+                .unwrap_or(false)
+        })
+        .ok_or_else(not_listed)?;
+
+    let mut edited: Vec<&str> = lines[..start].to_vec();
+    // An emptied list has to stay a list.
+    if list.entries.len() == 1 {
+        edited[list.key] = "endpoints: []";
+    }
+    edited.extend(&lines[end..]);
+    Ok(edited.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(source: &str) -> Vec<String> {
+        let config = config::parse(source, Format::Yaml).unwrap();
+        config
+            .endpoints
+            .into_iter()
+            .map(|endpoint| endpoint.name)
+            .collect()
+    }
+
+    fn entry(name: &str) -> serde_json::Value {
+        serde_json::json!({ "address": "192.0.2.10", "name": name })
+    }
+
+    #[test]
+    fn adds_to_an_empty_flow_list() {
+        let edited = add_entry("interval: 5s\nendpoints: []\n", &entry("new")).unwrap();
+        assert_eq!(
+            edited,
+            "interval: 5s\nendpoints:\n  - name: new\n    address: 192.0.2.10\n"
+        );
+        assert_eq!(names(&edited), ["new"]);
+        assert_eq!(names(&add_entry("interval: 5s\n", &entry("new")).unwrap()), ["new"]);
+
+        let error = add_entry("endpoints: [{name: a, address: 192.0.2.1}]\n", &entry("new")).unwrap_err();
+        assert_eq!(error.0, StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn keeps_comments_and_groups() {
+        let source = "# The routers.\nendpoints: # edited by the API\n  - name: a # first\n    address: 192.0.2.1\n\n  \
+                      # between\n  - name: b\n    address: 192.0.2.2\n# Alerting follows.\ngroups:\n  - name: office\n    \
+                      endpoints:\n      - name: c\n        address: 192.0.2.3\n";
+        let edited = add_entry(source, &entry("new")).unwrap();
+        assert!(edited.starts_with("# The routers.\nendpoints: # edited by the API\n  - name: a # first\n"));
+        assert!(edited.contains("  # between\n"));
+        assert!(
+            edited.contains("    address: 192.0.2.2\n  - name: new\n    address: 192.0.2.10\n# Alerting follows.\n")
+        );
+        assert_eq!(names(&edited), ["a", "b", "new", "c"]);
+
+        let edited = remove_entry(&edited, "a").unwrap();
+        assert_eq!(names(&edited), ["b", "new", "c"]);
+        assert!(edited.contains("# between\n"));
+
+        // Endpoints of groups are not entries of the top-level list.
+        let error = remove_entry(&edited, "c").unwrap_err();
+        assert_eq!(error.0, StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn leaves_an_empty_list_when_removing_the_last_entry() {
+        let edited = remove_entry("endpoints:\n  - name: a\n    address: 192.0.2.1\nretries: 1\n", "a").unwrap();
+        assert_eq!(edited, "endpoints: []\nretries: 1\n");
+        assert!(names(&edited).is_empty());
+    }
+
+    #[test]
+    fn only_persists_yaml_configs() {
+        std::env::set_var("NETSTAB_TEST_API_TOKEN", "t0ken");
+        let config = config::parse(
+            "api:\n  token_env: NETSTAB_TEST_API_TOKEN\n  persist: true\n",
+            Format::Yaml,
+        )
+        .unwrap();
+        assert_eq!(check_persist(&config, "config.yaml"), Ok(()));
+        assert!(check_persist(&config, "config.json").is_err());
+        assert!(check_persist(&config, "config.toml").is_err());
+    }
+}
@@ -0,0 +1,292 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, timeout_at, Instant};
+
+use crate::config::Endpoint;
+use crate::cron::Cron;
+use crate::metrics::Metrics;
+use crate::scheduler::Schedule;
+use crate::source::Source;
+use crate::timezone::Timezone;
+use crate::{duration, icmp, BoxError};
+
+pub(crate) const IPERF3_PORT: u16 = 5201;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(3600);
+// On top of the test itself, for connecting and for the exchanges before and after it.
+const GRACE: Duration = Duration::from_secs(10);
+const BLOCK: usize = 128 * 1024;
+const MAX_JSON: usize = 1 << 20;
+
+// The states an iperf3 server moves the test through, sent as a single byte on the control connection.
+const TEST_START: i8 = 1;
+const TEST_RUNNING: i8 = 2;
+const TEST_END: i8 = 4;
+const PARAM_EXCHANGE: i8 = 9;
+const CREATE_STREAMS: i8 = 10;
+const SERVER_TERMINATE: i8 = 11;
+const EXCHANGE_RESULTS: i8 = 13;
+const DISPLAY_RESULTS: i8 = 14;
+const IPERF_DONE: i8 = 16;
+const ACCESS_DENIED: i8 = -1;
+const SERVER_ERROR: i8 = -2;
+
+// A throughput test against an iperf3 server, run every `interval` or whenever `schedule` matches.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Throughput {
+    // The endpoint's address unless set.
+    pub server: Option<String>,
+    pub port: Option<u16>,
+    #[serde(default = "default_duration", with = "duration")]
+    pub duration: Duration,
+    #[serde(default, with = "duration::option")]
+    pub interval: Option<Duration>,
+    pub schedule: Option<String>,
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub direction: Direction,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Both,
+    Upload,
+    Download,
+}
+
+fn default_duration() -> Duration {
+    Duration::from_secs(10)
+}
+
+impl Throughput {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.duration < Duration::from_secs(1) {
+            return Err("duration must be at least 1s".to_string());
+        }
+        if self.interval.is_some() && self.schedule.is_some() {
+            return Err("set only one of interval or schedule".to_string());
+        }
+        if self
+            .interval
+            .is_some_and(|interval| interval <= self.duration)
+        {
+            return Err("interval must be longer than duration".to_string());
+        }
+        match &self.schedule {
+            Some(schedule) => {
+                Cron::parse(schedule).map_err(|err| format!("schedule: {}", err))?;
+            }
+            None if self.timezone.is_some() => return Err("timezone only applies to schedule".to_string()),
+            None => {}
+        }
+        if let Some(timezone) = &self.timezone {
+            Timezone::parse(timezone).map_err(|err| format!("timezone: {}", err))?;
+        }
+        if self.port == Some(0) {
+            return Err("port must be between 1 and 65535".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval.unwrap_or(DEFAULT_INTERVAL)
+    }
+}
+
+// Runs the endpoint's throughput tests until the schedule ends, next to and independent of its probe.
+pub async fn run(endpoint: Endpoint, config: Throughput, metrics: Metrics, mut schedule: Schedule) {
+    let labels = metrics.labels(&endpoint);
+    let host = config
+        .server
+        .clone()
+        .unwrap_or_else(|| endpoint.address.clone());
+    let source = Source::of(&endpoint);
+    let directions: &[bool] = match config.direction {
+        Direction::Both => &[false, true],
+        Direction::Upload => &[false],
+        Direction::Download => &[true],
+    };
+
+    while let Some(_slot) = schedule.next().await {
+        let server = match icmp::resolve(&host, endpoint.ip_version).await {
+            Ok(ip) => SocketAddr::from((ip, config.port.unwrap_or(IPERF3_PORT))),
+            Err(err) => {
+                warn!("Throughput test of {} failed: {}", endpoint.key(), err);
+                metrics.throughput_failures.with_label_values(&labels).inc();
+                continue;
+            }
+        };
+        // An iperf3 server runs one test at a time, so the directions take turns.
+        for &reverse in directions {
+            let direction = if reverse { "download" } else { "upload" };
+            let test = iperf3(server, config.duration.as_secs(), reverse, &source);
+            match timeout(config.duration + GRACE, test).await {
+                Ok(Ok(mbps)) => {
+                    info!("{} of {}: {:.1} Mbps", direction, endpoint.key(), mbps);
+                    let gauge = if reverse {
+                        &metrics.throughput_download
+                    } else {
+                        &metrics.throughput_upload
+                    };
+                    gauge.with_label_values(&labels).set(mbps);
+                }
+                Ok(Err(err)) => {
+                    warn!("Throughput test ({}) of {} failed: {}", direction, endpoint.key(), err);
+                    metrics.throughput_failures.with_label_values(&labels).inc();
+                }
+                Err(_) => {
+                    warn!("Throughput test ({}) of {} timed out", direction, endpoint.key());
+                    metrics.throughput_failures.with_label_values(&labels).inc();
+                }
+            }
+        }
+    }
+}
+
+// Runs a single-stream TCP test in the iperf3 protocol for `seconds`, with the server sending in `reverse`, and returns
+// the throughput in megabits per second. The receiving side's count of bytes is used, the server's for uploads.
+async fn iperf3(server: SocketAddr, seconds: u64, reverse: bool, source: &Source) -> Result<f64, BoxError> {
+    // 36 characters and a NUL, which the server matches the data connection against.
+    let alphabet = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut cookie: Vec<u8> = (0..36)
+        .map(|_| alphabet[rand::random::<usize>() % alphabet.len()])
+        .collect();
+    cookie.push(0);
+
+    let mut control = source.connect(server).await?;
+    control.write_all(&cookie).await?;
+    expect(&mut control, PARAM_EXCHANGE).await?;
+    let parameters = json!({
+        "tcp": true,
+        "omit": 0,
+        "time": seconds,
+        "parallel": 1,
+        "reverse": reverse,
+        "len": BLOCK,
+        "pacing_timer": 1000,
+        "client_version": concat!("rust-net-stab/", env!("CARGO_PKG_VERSION")),
+    });
+    write_json(&mut control, &parameters).await?;
+    expect(&mut control, CREATE_STREAMS).await?;
+    let mut data = source.connect(server).await?;
+    data.write_all(&cookie).await?;
+    expect(&mut control, TEST_START).await?;
+    expect(&mut control, TEST_RUNNING).await?;
+
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(seconds);
+    let mut bytes = 0u64;
+    let mut buffer = vec![0u8; BLOCK];
+    loop {
+        let transferred = if reverse {
+            timeout_at(deadline, data.read(&mut buffer)).await
+        } else {
+            timeout_at(deadline, data.write(&buffer)).await
+        };
+        match transferred {
+            Ok(Ok(0)) => return Err("the iperf3 server closed the data connection".into()),
+            Ok(Ok(len)) => bytes += len as u64,
+            Ok(Err(err)) => return Err(err.into()),
+            Err(_) => break,
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    control.write_all(&[TEST_END as u8]).await?;
+    // The server keeps sending until it sees the end of the test, and has to be read from until then.
+    let drain = tokio::spawn(async move {
+        let mut buffer = vec![0u8; BLOCK];
+        while let Ok(len) = data.read(&mut buffer).await {
+            if len == 0 {
+                break;
+            }
+        }
+    });
+
+    let exchanged = async {
+        expect(&mut control, EXCHANGE_RESULTS).await?;
+        let results = json!({
+            "cpu_util_total": 0,
+            "cpu_util_user": 0,
+            "cpu_util_system": 0,
+            "sender_has_retransmits": if reverse { -1 } else { 0 },
+            "streams": [{
+                "id": 1,
+                "bytes": bytes,
+                "retransmits": -1,
+                "jitter": 0,
+                "errors": 0,
+                "packets": 0,
+                "start_time": 0,
+                "end_time": elapsed,
+            }],
+        });
+        write_json(&mut control, &results).await?;
+        let server_results = read_json(&mut control).await?;
+        expect(&mut control, DISPLAY_RESULTS).await?;
+        let _ = control.write_all(&[IPERF_DONE as u8]).await;
+        Ok::<_, BoxError>(server_results)
+    }
+    .await;
+    drain.abort();
+    let server_results = exchanged?;
+
+    let (bytes, elapsed) = if reverse {
+        (bytes as f64, elapsed)
+    } else {
+        let stream = &server_results["streams"][0];
+        match (stream["bytes"].as_f64(), stream["end_time"].as_f64()) {
+            (Some(bytes), Some(end_time)) if end_time > 0.0 => {
+                (bytes, end_time - stream["start_time"].as_f64().unwrap_or(0.0))
+            }
+            _ => return Err("the iperf3 server did not report the bytes it received".into()),
+        }
+    };
+    Ok(bytes * 8.0 / elapsed / 1e6)
+}
+
+async fn expect(control: &mut TcpStream, state: i8) -> Result<(), BoxError> {
+    let received = control
+        .read_i8()
+        .await
+        .map_err(|_| "the iperf3 server closed the connection")?;
+    match received {
+        _ if received == state => Ok(()),
+        ACCESS_DENIED => Err("the iperf3 server is busy with another test".into()),
+        SERVER_ERROR => {
+            // iperf3's own error number, followed by the errno.
+            let code = control.read_i32().await?;
+            Err(format!("the iperf3 server failed with error {}", code).into())
+        }
+        SERVER_TERMINATE => Err("the iperf3 server ended the test".into()),
+        _ => Err(format!("the iperf3 server sent state {} rather than {}", received, state).into()),
+    }
+}
+
+// JSON goes over the control connection after its length as four bytes.
+async fn write_json(control: &mut TcpStream, value: &Value) -> Result<(), BoxError> {
+    let json = serde_json::to_vec(value)?;
+    control
+        .write_all(&(json.len() as u32).to_be_bytes())
+        .await?;
+    control.write_all(&json).await?;
+    Ok(())
+}
+
+async fn read_json(control: &mut TcpStream) -> Result<Value, BoxError> {
+    let len = control.read_u32().await? as usize;
+    if len > MAX_JSON {
+        return Err("the iperf3 server's results are too long".into());
+    }
+    let mut json = vec![0u8; len];
+    control.read_exact(&mut json).await?;
+    Ok(serde_json::from_slice(&json)?)
+}
@@ -0,0 +1,465 @@
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::status;
+
+// The timezone schedules and maintenance windows are written in: `UTC`, a fixed offset such as `+02:00`, or a name of
+// the tz database such as `Europe/Berlin`, which is read from the zoneinfo files of the system.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Timezone {
+    Fixed(i64),
+    Zone {
+        // From when on each offset applies, in Unix time, with the offset before the first one.
+        transitions: Vec<(i64, i64)>,
+        initial: i64,
+        // Covers the times after the last transition.
+        rule: Option<Rule>,
+    },
+}
+
+// The POSIX TZ rule at the end of a zoneinfo file. Offsets are seconds east of UTC.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Rule {
+    standard: i64,
+    daylight: Option<(i64, Change, Change)>,
+}
+
+// `Mm.w.d/time`: the `week`th `weekday` of the month, the 5th being the last, at `time` local time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Change {
+    month: i64,
+    week: i64,
+    weekday: i64,
+    time: i64,
+}
+
+impl Timezone {
+    pub(crate) fn parse(name: &str) -> Result<Timezone, String> {
+        if matches!(name, "UTC" | "Z" | "GMT") {
+            return Ok(Timezone::Fixed(0));
+        }
+        if name.starts_with(['+', '-']) {
+            return fixed_offset(name)
+                .map(Timezone::Fixed)
+                .ok_or_else(|| format!("invalid UTC offset `{}`, expected one like +02:00", name));
+        }
+        if name.is_empty() || name.starts_with('/') || name.split('/').any(|part| part == "..") {
+            return Err(format!("`{}` is not a timezone", name));
+        }
+
+        let directory = env::var_os("TZDIR").map_or_else(|| PathBuf::from("/usr/share/zoneinfo"), PathBuf::from);
+        let path = directory.join(name);
+        let data =
+            fs::read(&path).map_err(|err| format!("unknown timezone `{}`: {}: {}", name, path.display(), err))?;
+        zoneinfo(&data).ok_or_else(|| format!("{} is not a valid zoneinfo file", path.display()))
+    }
+
+    // Seconds east of UTC at the time.
+    pub(crate) fn offset(&self, utc: i64) -> i64 {
+        match self {
+            Timezone::Fixed(offset) => *offset,
+            Timezone::Zone {
+                transitions,
+                initial,
+                rule,
+            } => match transitions.partition_point(|(at, _)| *at <= utc) {
+                0 if transitions.is_empty() => rule.as_ref().map_or(*initial, |rule| rule.offset(utc)),
+                0 => *initial,
+                index if index == transitions.len() => match rule {
+                    Some(rule) => rule.offset(utc),
+                    None => transitions[index - 1].1,
+                },
+                index => transitions[index - 1].1,
+            },
+        }
+    }
+
+    pub(crate) fn local(&self, utc: i64) -> i64 {
+        utc + self.offset(utc)
+    }
+
+    // Local times repeated by a change back from daylight saving time are taken as the first of the two, and those
+    // skipped by a change to it as if the clocks had not changed yet.
+    pub(crate) fn utc(&self, local: i64) -> i64 {
+        // Offsets change at most once a day, so the ones a day either side are the only candidates.
+        let before = local - self.offset(local - 86400);
+        let after = local - self.offset(local + 86400);
+        [before, after]
+            .iter()
+            .copied()
+            .filter(|utc| self.local(*utc) == local)
+            .min()
+            .unwrap_or(before)
+    }
+}
+
+impl Rule {
+    fn offset(&self, utc: i64) -> i64 {
+        let (daylight, start, end) = match &self.daylight {
+            Some(daylight) => daylight,
+            None => return self.standard,
+        };
+        let (year, ..) = status::civil((utc + self.standard) as f64);
+        let start = start.at(year) - self.standard;
+        let end = end.at(year) - daylight;
+        let in_daylight = if start < end {
+            start <= utc && utc < end
+        } else {
+            // South of the equator daylight saving time spans the turn of the year.
+            !(end <= utc && utc < start)
+        };
+        if in_daylight {
+            *daylight
+        } else {
+            self.standard
+        }
+    }
+}
+
+impl Change {
+    // The local time of the change in the year.
+    fn at(&self, year: i64) -> i64 {
+        let first = status::days_from_civil(year, self.month, 1);
+        let next = if self.month == 12 {
+            status::days_from_civil(year + 1, 1, 1)
+        } else {
+            status::days_from_civil(year, self.month + 1, 1)
+        };
+        let mut day = first + (self.weekday - (first + 4).rem_euclid(7)).rem_euclid(7) + (self.week - 1) * 7;
+        while day >= next {
+            day -= 7;
+        }
+        day * 86400 + self.time
+    }
+}
+
+// `+02:00`, `-0530` or `+2`.
+fn fixed_offset(value: &str) -> Option<i64> {
+    let sign = if value.starts_with('-') { -1 } else { 1 };
+    let digits = value.get(1..)?;
+    let (hours, minutes) = match digits.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if digits.len() == 4 => digits.split_at(2),
+        None => (digits, "0"),
+    };
+    let (hours, minutes): (i64, i64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+// Reads a TZif file as described in RFC 8536, preferring the 64-bit data of version 2 and later.
+fn zoneinfo(data: &[u8]) -> Option<Timezone> {
+    let header = |at: usize| -> Option<[usize; 6]> {
+        if data.get(at..at + 4)? != b"TZif" {
+            return None;
+        }
+        let mut counts = [0; 6];
+        for (index, count) in counts.iter_mut().enumerate() {
+            let start = at + 20 + index * 4;
+            *count = u32::from_be_bytes(data.get(start..start + 4)?.try_into().ok()?) as usize;
+        }
+        Some(counts)
+    };
+
+    let [utc_count, std_count, leap_count, time_count, type_count, char_count] = header(0)?;
+    let (at, time_size, counts) = if *data.get(4)? >= b'2' {
+        let skipped = 44 + time_count * 5 + type_count * 6 + char_count + leap_count * 8 + std_count + utc_count;
+        (skipped, 8, header(skipped)?)
+    } else {
+        (
+            0,
+            4,
+            [utc_count, std_count, leap_count, time_count, type_count, char_count],
+        )
+    };
+    let [utc_count, std_count, leap_count, time_count, type_count, char_count] = counts;
+
+    let times = at + 44;
+    let indices = times + time_count * time_size;
+    let types = indices + time_count;
+    let offset_of = |index: usize| -> Option<i64> {
+        let start = types + index * 6;
+        Some(i32::from_be_bytes(data.get(start..start + 4)?.try_into().ok()?) as i64)
+    };
+
+    let mut transitions = Vec::with_capacity(time_count);
+    for index in 0..time_count {
+        let start = times + index * time_size;
+        let time = if time_size == 8 {
+            i64::from_be_bytes(data.get(start..start + 8)?.try_into().ok()?)
+        } else {
+            i32::from_be_bytes(data.get(start..start + 4)?.try_into().ok()?) as i64
+        };
+        let offset = offset_of(*data.get(indices + index)? as usize)?;
+        transitions.push((time, offset));
+    }
+    let initial = offset_of(0)?;
+
+    let end = types + type_count * 6 + char_count + leap_count * (time_size + 4) + std_count + utc_count;
+    let rule = if time_size == 8 {
+        let footer = std::str::from_utf8(data.get(end..)?).ok()?;
+        let footer = footer.trim_matches('\n');
+        if footer.is_empty() {
+            None
+        } else {
+            Some(rule(footer)?)
+        }
+    } else {
+        None
+    };
+
+    Some(Timezone::Zone {
+        transitions,
+        initial,
+        rule,
+    })
+}
+
+// Such as `CET-1CEST,M3.5.0,M10.5.0/3`, where offsets are west of UTC.
+fn rule(value: &str) -> Option<Rule> {
+    let mut rest = value;
+    let name = |rest: &mut &str| -> Option<()> {
+        let length = if let Some(quoted) = rest.strip_prefix('<') {
+            quoted.find('>')? + 2
+        } else {
+            rest.find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(rest.len())
+        };
+        if length < 3 {
+            return None;
+        }
+        *rest = &rest[length..];
+        Some(())
+    };
+    let time = |rest: &mut &str| -> Option<i64> {
+        let length = rest
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, ':' | '+' | '-')))
+            .unwrap_or(rest.len());
+        let (time, remaining) = rest.split_at(length);
+        *rest = remaining;
+        let (sign, time) = match time.strip_prefix('-') {
+            Some(time) => (-1, time),
+            None => (1, time.trim_start_matches('+')),
+        };
+        let mut parts = time.split(':').map(|part| part.parse::<i64>());
+        let hours = parts.next()?.ok()?;
+        let minutes = parts.next().unwrap_or(Ok(0)).ok()?;
+        let seconds = parts.next().unwrap_or(Ok(0)).ok()?;
+        Some(sign * (hours * 3600 + minutes * 60 + seconds))
+    };
+    let change = |rest: &mut &str| -> Option<Change> {
+        let date = rest.strip_prefix(",M")?;
+        let length = date.find(['/', ',']).unwrap_or(date.len());
+        let mut fields = date[..length].split('.').map(|field| field.parse::<i64>());
+        let (month, week, weekday) = (fields.next()?.ok()?, fields.next()?.ok()?, fields.next()?.ok()?);
+        if !(1..=12).contains(&month) || !(1..=5).contains(&week) || !(0..=6).contains(&weekday) {
+            return None;
+        }
+        *rest = &date[length..];
+        let time = match rest.strip_prefix('/') {
+            Some(remaining) => {
+                *rest = remaining;
+                time(rest)?
+            }
+            None => 7200,
+        };
+        Some(Change {
+            month,
+            week,
+            weekday,
+            time,
+        })
+    };
+
+    name(&mut rest)?;
+    let standard = -time(&mut rest)?;
+    if rest.is_empty() {
+        return Some(Rule {
+            standard,
+            daylight: None,
+        });
+    }
+    name(&mut rest)?;
+    let daylight = if rest.starts_with(',') {
+        standard + 3600
+    } else {
+        -time(&mut rest)?
+    };
+    let start = change(&mut rest)?;
+    let end = change(&mut rest)?;
+    rest.is_empty().then_some(Rule {
+        standard,
+        daylight: Some((daylight, start, end)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The start and end of daylight saving time in Berlin in 2024, in Unix time.
+    const BERLIN_SUMMER: i64 = 1711846800;
+    const BERLIN_WINTER: i64 = 1729990800;
+
+    fn berlin() -> Timezone {
+        Timezone::Zone {
+            transitions: Vec::new(),
+            initial: 3600,
+            rule: rule("CET-1CEST,M3.5.0,M10.5.0/3"),
+        }
+    }
+
+    // A TZif file of version 2 with the same transitions in the 32 and 64-bit blocks and offsets of the types.
+    fn tzif(transitions: &[(i64, u8)], offsets: &[i32], footer: &str) -> Vec<u8> {
+        let block = |time_size: usize| {
+            let mut block = b"TZif2".to_vec();
+            block.extend([0; 15]);
+            for count in [0, 0, 0, transitions.len(), offsets.len(), 4] {
+                block.extend((count as u32).to_be_bytes());
+            }
+            for (time, _) in transitions {
+                match time_size {
+                    4 => block.extend((*time as i32).to_be_bytes()),
+                    _ => block.extend(time.to_be_bytes()),
+                }
+            }
+            block.extend(transitions.iter().map(|(_, index)| index));
+            for (index, offset) in offsets.iter().enumerate() {
+                block.extend(offset.to_be_bytes());
+                block.extend([(index > 0) as u8, 0]);
+            }
+            block.extend(b"CET\0");
+            block
+        };
+        let mut data = block(4);
+        data.extend(block(8));
+        data.extend(format!("\n{}\n", footer).bytes());
+        data
+    }
+
+    #[test]
+    fn parses_fixed_offsets() {
+        assert_eq!(Timezone::parse("UTC"), Ok(Timezone::Fixed(0)));
+        assert_eq!(Timezone::parse("+02:00"), Ok(Timezone::Fixed(7200)));
+        assert_eq!(Timezone::parse("-0530"), Ok(Timezone::Fixed(-19800)));
+        assert_eq!(Timezone::parse("+2"), Ok(Timezone::Fixed(7200)));
+        assert!(Timezone::parse("+15:00").is_err());
+        assert!(Timezone::parse("../etc/passwd").is_err());
+        assert!(Timezone::parse("/etc/localtime").is_err());
+    }
+
+    #[test]
+    fn parses_rules() {
+        let change = |month, week, weekday, time| Change {
+            month,
+            week,
+            weekday,
+            time,
+        };
+        assert_eq!(
+            rule("CET-1CEST,M3.5.0,M10.5.0/3"),
+            Some(Rule {
+                standard: 3600,
+                daylight: Some((7200, change(3, 5, 0, 7200), change(10, 5, 0, 10800))),
+            })
+        );
+        assert_eq!(
+            rule("<+0330>-3:30"),
+            Some(Rule {
+                standard: 12600,
+                daylight: None,
+            })
+        );
+        assert_eq!(
+            rule("IST-1GMT0,M10.5.0,M3.5.0/1").and_then(|rule| rule.daylight),
+            Some((0, change(10, 5, 0, 7200), change(3, 5, 0, 3600)))
+        );
+        assert_eq!(rule("EST"), None);
+        assert_eq!(rule("EST5EDT,M13.1.0,M11.1.0"), None);
+        assert_eq!(rule("EST5EDT,M3.2.0"), None);
+    }
+
+    #[test]
+    fn changes_offsets_with_the_rule() {
+        let berlin = berlin();
+        assert_eq!(berlin.offset(BERLIN_SUMMER - 1), 3600);
+        assert_eq!(berlin.offset(BERLIN_SUMMER), 7200);
+        assert_eq!(berlin.offset(BERLIN_WINTER - 1), 7200);
+        assert_eq!(berlin.offset(BERLIN_WINTER), 3600);
+
+        let new_york = Timezone::Zone {
+            transitions: Vec::new(),
+            initial: -18000,
+            rule: rule("EST5EDT,M3.2.0,M11.1.0"),
+        };
+        assert_eq!(new_york.offset(1710054000 - 1), -18000);
+        assert_eq!(new_york.offset(1710054000), -14400);
+        assert_eq!(new_york.offset(1730613600 - 1), -14400);
+        assert_eq!(new_york.offset(1730613600), -18000);
+
+        // Daylight saving time in Sydney runs from October to April.
+        let sydney = Timezone::Zone {
+            transitions: Vec::new(),
+            initial: 36000,
+            rule: rule("AEST-10AEDT,M10.1.0,M4.1.0/3"),
+        };
+        assert_eq!(sydney.offset(1704067200), 39600);
+        assert_eq!(sydney.offset(1712419200 - 1), 39600);
+        assert_eq!(sydney.offset(1712419200), 36000);
+        assert_eq!(sydney.offset(1728144000 - 1), 36000);
+        assert_eq!(sydney.offset(1728144000), 39600);
+    }
+
+    #[test]
+    fn converts_local_times_around_changes() {
+        let berlin = berlin();
+        // 2024-01-15 11:00 in Berlin.
+        assert_eq!(berlin.utc(1705312800 + 3600), 1705312800);
+        assert_eq!(berlin.local(1705312800), 1705312800 + 3600);
+        // 02:30 on the 31st of March never happens, it is taken as 03:30 summer time.
+        assert_eq!(berlin.utc(BERLIN_SUMMER + 5400), BERLIN_SUMMER + 1800);
+        // 02:30 on the 27th of October happens twice, the first one is taken.
+        assert_eq!(berlin.utc(BERLIN_WINTER + 5400), BERLIN_WINTER - 1800);
+        assert_eq!(berlin.local(BERLIN_WINTER - 1800), BERLIN_WINTER + 5400);
+        assert_eq!(berlin.local(BERLIN_WINTER + 1800), BERLIN_WINTER + 5400);
+    }
+
+    #[test]
+    fn reads_zoneinfo_files() {
+        // Summer time in 2023, then the rule from the footer.
+        let data = tzif(
+            &[(1679792400, 1), (1698541200, 0)],
+            &[3600, 7200],
+            "CET-1CEST,M3.5.0,M10.5.0/3",
+        );
+        let zone = zoneinfo(&data).unwrap();
+        assert_eq!(
+            zone,
+            Timezone::Zone {
+                transitions: vec![(1679792400, 7200), (1698541200, 3600)],
+                initial: 3600,
+                rule: rule("CET-1CEST,M3.5.0,M10.5.0/3"),
+            }
+        );
+        assert_eq!(zone.offset(1679792400 - 1), 3600);
+        assert_eq!(zone.offset(1690000000), 7200);
+        assert_eq!(zone.offset(1700000000), 3600);
+        assert_eq!(zone.offset(BERLIN_SUMMER), 7200);
+        assert_eq!(zone.offset(BERLIN_WINTER), 3600);
+
+        // Without a footer the last offset carries on.
+        let zone = zoneinfo(&tzif(&[(1679792400, 1)], &[3600, 7200], "")).unwrap();
+        assert_eq!(zone.offset(BERLIN_WINTER), 7200);
+
+        assert_eq!(zoneinfo(b"TZif2"), None);
+        assert_eq!(
+            zoneinfo(b"not a zoneinfo file at all, but long enough to have a header"),
+            None
+        );
+        assert_eq!(zoneinfo(&data[..60]), None);
+    }
+}
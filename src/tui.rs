@@ -0,0 +1,389 @@
+use std::io::{self, IsTerminal};
+
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures_util::StreamExt;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Row, Sparkline, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::{oneshot, watch};
+use tokio::time::{Duration, MissedTickBehavior};
+
+use crate::smtp::format_timestamp;
+use crate::status::{self, EndpointStatus, StatusBoard};
+use crate::BoxError;
+
+const SPARKLINE_LEN: usize = 40;
+const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const DETAILS_HEIGHT: u16 = 7;
+
+pub fn check_terminal() -> Result<(), BoxError> {
+    if !io::stdout().is_terminal() {
+        return Err("--tui needs standard output to be a terminal".into());
+    }
+    Ok(())
+}
+
+// Switches the terminal to raw mode on the alternate screen. A panic hook restores it if the process panics.
+pub fn init() -> Result<DefaultTerminal, BoxError> {
+    ratatui::try_init().map_err(|err| {
+        ratatui::restore();
+        format!("failed to set up the terminal for --tui: {}", err).into()
+    })
+}
+
+// Redraws the board every second and on every key until `shutdown` is set, then restores the terminal. Raw mode
+// keeps Ctrl-C from raising a signal, so q, Esc and Ctrl-C send on `quit` for the caller to shut down.
+pub async fn run(
+    mut terminal: DefaultTerminal,
+    board: StatusBoard,
+    mut shutdown: watch::Receiver<bool>,
+    quit: oneshot::Sender<()>,
+) {
+    let mut quit = Some(quit);
+    let mut events = EventStream::new();
+    let mut events_open = true;
+    let mut table = TableState::default().with_selected(Some(0));
+
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.changed() => break,
+            event = events.next(), if events_open => match event {
+                Some(Ok(Event::Key(key))) => {
+                    if handle_key(key, &mut table) {
+                        if let Some(quit) = quit.take() {
+                            let _ = quit.send(());
+                        }
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => events_open = false,
+            },
+        }
+        let endpoints = board.snapshot().endpoints;
+        let _ = terminal.draw(|frame| draw(frame, &endpoints, &mut table));
+    }
+
+    ratatui::restore();
+}
+
+// Moves the selection, returning whether the key asks to quit.
+fn handle_key(key: KeyEvent, table: &mut TableState) -> bool {
+    // Windows also reports key releases.
+    if key.kind != KeyEventKind::Press {
+        return false;
+    }
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => return true,
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return true,
+        KeyCode::Up | KeyCode::Char('k') => table.select_previous(),
+        KeyCode::Down | KeyCode::Char('j') => table.select_next(),
+        KeyCode::Home | KeyCode::Char('g') => table.select_first(),
+        KeyCode::End | KeyCode::Char('G') => table.select_last(),
+        _ => {}
+    }
+    false
+}
+
+fn draw(frame: &mut Frame, endpoints: &[EndpointStatus], table: &mut TableState) {
+    let [header, list, details] = Layout::vertical([
+        Constraint::Length(2),
+        Constraint::Min(3),
+        Constraint::Length(DETAILS_HEIGHT),
+    ])
+    .areas(frame.area());
+
+    let down = endpoints
+        .iter()
+        .filter(|endpoint| endpoint.up == Some(false))
+        .count();
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let dim = Style::default().add_modifier(Modifier::DIM);
+    frame.render_widget(
+        Paragraph::new(Line::from(vec![
+            Span::styled("rust-net-stab", bold),
+            Span::raw(format!(
+                "  {}  {} endpoints, ",
+                format_timestamp(status::unix_time()),
+                endpoints.len()
+            )),
+            Span::styled(
+                format!("{} down", down),
+                if down > 0 {
+                    Style::default().fg(Color::Red)
+                } else {
+                    Style::default()
+                },
+            ),
+            Span::styled("  ↑↓ select  q quit", dim),
+        ])),
+        header,
+    );
+
+    // The selection moves up to the last row when endpoints are removed, and is empty while there are none.
+    let selected = table.selected().unwrap_or_default();
+    table.select(
+        endpoints
+            .len()
+            .checked_sub(1)
+            .map(|last| selected.min(last)),
+    );
+    frame.render_stateful_widget(endpoint_table(endpoints), list, table);
+
+    if let Some(endpoint) = table
+        .selected()
+        .and_then(|selected| endpoints.get(selected))
+    {
+        render_details(frame, endpoint, details);
+    }
+}
+
+fn endpoint_table(endpoints: &[EndpointStatus]) -> Table<'static> {
+    let name = |endpoint: &EndpointStatus| status::key(&endpoint.name, endpoint.ip_version);
+    let name_width = column_width(endpoints.iter().map(name), "ENDPOINT");
+    let address_width = column_width(endpoints.iter().map(|endpoint| endpoint.address.clone()), "ADDRESS");
+
+    let rows = endpoints.iter().map(|endpoint| {
+        let (color, state) = match endpoint.up {
+            Some(true) => (Color::Green, "UP"),
+            Some(false) => (Color::Red, "DOWN"),
+            None => (Color::Yellow, "..."),
+        };
+        let rtt = endpoint
+            .last_rtt_seconds
+            .map(|rtt| format!("{:.2} ms", rtt * 1000.0))
+            .unwrap_or_else(|| "-".to_string());
+
+        Row::new(vec![
+            Line::from(name(endpoint)),
+            Line::from(endpoint.address.clone()),
+            Line::styled(state, Style::default().fg(color)),
+            Line::from(format!("{:.1}%", endpoint.loss_ratio * 100.0)).right_aligned(),
+            Line::from(rtt).right_aligned(),
+            sparkline(endpoint.history.iter().copied()),
+            Line::styled(
+                endpoint.last_error.clone().unwrap_or_default(),
+                Style::default().fg(Color::Red),
+            ),
+        ])
+    });
+    let widths = [
+        Constraint::Length(name_width),
+        Constraint::Length(address_width),
+        Constraint::Length(5),
+        Constraint::Length(6),
+        Constraint::Length(10),
+        Constraint::Length(SPARKLINE_LEN as u16),
+        Constraint::Fill(1),
+    ];
+
+    Table::new(rows, widths)
+        .header(
+            Row::new(["ENDPOINT", "ADDRESS", "STATE", "LOSS", "RTT", "LATENCY", "LAST ERROR"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .column_spacing(2)
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+}
+
+fn column_width(values: impl Iterator<Item = String>, header: &str) -> u16 {
+    values
+        .map(|value| value.chars().count())
+        .chain([header.len()])
+        .max()
+        .unwrap_or_default() as u16
+}
+
+// Everything known about the selected endpoint, with its whole history as a bar chart.
+fn render_details(frame: &mut Frame, endpoint: &EndpointStatus, area: Rect) {
+    let block = Block::bordered().title(format!(" {} ", status::key(&endpoint.name, endpoint.ip_version)));
+    let [info, history] = Layout::vertical([Constraint::Length(2), Constraint::Fill(1)]).areas(block.inner(area));
+    frame.render_widget(block, area);
+
+    let time = |timestamp: Option<f64>| {
+        timestamp
+            .map(format_timestamp)
+            .unwrap_or_else(|| "never".to_string())
+    };
+    let mut facts = vec![format!("{} probe", endpoint.probe_type)];
+    facts.extend(
+        endpoint
+            .location
+            .iter()
+            .map(|location| format!("location {}", location)),
+    );
+    facts.extend(
+        endpoint
+            .labels
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value)),
+    );
+    if endpoint.maintenance {
+        facts.push("in maintenance".to_string());
+    }
+    frame.render_widget(
+        Paragraph::new(vec![
+            Line::from(facts.join(", ")),
+            Line::from(format!(
+                "last probe {}, last change {}",
+                time(endpoint.last_probe),
+                time(endpoint.last_change)
+            )),
+        ]),
+        info,
+    );
+
+    // Only the most recent probes that fit, in microseconds so sub-millisecond round trips still show.
+    let shown = endpoint
+        .history
+        .len()
+        .saturating_sub(history.width as usize);
+    let data: Vec<Option<u64>> = endpoint
+        .history
+        .iter()
+        .skip(shown)
+        .map(|rtt| rtt.map(|rtt| (rtt * 1_000_000.0) as u64))
+        .collect();
+    frame.render_widget(
+        Sparkline::default()
+            .data(&data)
+            .style(Style::default().fg(Color::Green))
+            .absent_value_style(Style::default().fg(Color::Red))
+            .absent_value_symbol("×"),
+        history,
+    );
+}
+
+// The most recent round trip times scaled between the lowest and highest of them, with failed probes in red.
+fn sparkline(history: impl DoubleEndedIterator<Item = Option<f64>> + Clone) -> Line<'static> {
+    let recent: Vec<Option<f64>> = history.rev().take(SPARKLINE_LEN).collect();
+    let rtts = recent.iter().flatten();
+    let min = rtts.clone().copied().fold(f64::INFINITY, f64::min);
+    let max = rtts.copied().fold(0.0, f64::max);
+
+    let mut spans = vec![Span::raw(" ".repeat(SPARKLINE_LEN - recent.len()))];
+    for rtt in recent.iter().rev() {
+        match rtt {
+            Some(rtt) => {
+                let level = if max > min {
+                    ((rtt - min) / (max - min) * (BLOCKS.len() - 1) as f64).round() as usize
+                } else {
+                    0
+                };
+                spans.push(Span::raw(BLOCKS[level].to_string()));
+            }
+            None => spans.push(Span::styled("×", Style::default().fg(Color::Red))),
+        }
+    }
+    Line::from(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use std::collections::{BTreeMap, VecDeque};
+
+    fn endpoint(name: &str, up: Option<bool>, history: &[Option<f64>]) -> EndpointStatus {
+        EndpointStatus {
+            name: name.to_string(),
+            address: "10.0.0.1".to_string(),
+            location: None,
+            probe_type: "icmp",
+            ip_version: None,
+            labels: BTreeMap::new(),
+            up,
+            last_rtt_seconds: history.last().copied().flatten(),
+            loss_ratio: 0.0,
+            last_error: None,
+            last_probe: None,
+            last_change: None,
+            maintenance: false,
+            history: history.iter().copied().collect::<VecDeque<_>>(),
+        }
+    }
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn draws_the_table_and_the_selected_endpoint() {
+        let mut router = endpoint("Router", Some(false), &[Some(0.001), None]);
+        router.last_error = Some("request timed out".to_string());
+        let endpoints = vec![endpoint("Google DNS", Some(true), &[Some(0.012)]), router];
+
+        let mut terminal = Terminal::new(TestBackend::new(140, 16)).unwrap();
+        let mut table = TableState::default().with_selected(Some(5));
+        terminal
+            .draw(|frame| draw(frame, &endpoints, &mut table))
+            .unwrap();
+
+        let buffer = terminal.backend().buffer();
+        let lines: Vec<String> = (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect()
+            })
+            .collect();
+        assert!(lines[0].contains("2 endpoints, 1 down"));
+        assert!(lines[2].starts_with("ENDPOINT"));
+        assert!(lines[3].starts_with("Google DNS  10.0.0.1  UP"));
+        assert!(lines[3].contains("12.00 ms"));
+        assert!(lines[4].contains("DOWN"));
+        assert!(lines[4].contains("▁×  request timed out"));
+        // The selection past the end moves to the last endpoint, whose details show below the table.
+        assert_eq!(table.selected(), Some(1));
+        assert!(lines[9].contains(" Router "));
+        assert!(lines[10].contains("icmp probe"));
+        assert!(lines[11].contains("last probe never"));
+    }
+
+    #[test]
+    fn handles_keys() {
+        let mut table = TableState::default().with_selected(Some(0));
+        assert!(!handle_key(key(KeyCode::Down, KeyModifiers::NONE), &mut table));
+        assert!(!handle_key(key(KeyCode::Char('j'), KeyModifiers::NONE), &mut table));
+        assert_eq!(table.selected(), Some(2));
+        assert!(!handle_key(key(KeyCode::Char('k'), KeyModifiers::NONE), &mut table));
+        assert_eq!(table.selected(), Some(1));
+        assert!(!handle_key(key(KeyCode::Home, KeyModifiers::NONE), &mut table));
+        assert_eq!(table.selected(), Some(0));
+
+        assert!(handle_key(key(KeyCode::Char('q'), KeyModifiers::NONE), &mut table));
+        assert!(handle_key(key(KeyCode::Esc, KeyModifiers::NONE), &mut table));
+        assert!(handle_key(key(KeyCode::Char('c'), KeyModifiers::CONTROL), &mut table));
+        assert!(!handle_key(key(KeyCode::Char('c'), KeyModifiers::NONE), &mut table));
+
+        let mut release = key(KeyCode::Char('q'), KeyModifiers::NONE);
+        release.kind = KeyEventKind::Release;
+        assert!(!handle_key(release, &mut table));
+    }
+
+    #[test]
+    fn scales_the_sparkline() {
+        let line = sparkline(
+            [Some(0.010), Some(0.020), None, Some(0.016)]
+                .iter()
+                .copied(),
+        );
+        let text: String = line
+            .spans
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect();
+        assert_eq!(text, format!("{}▁█×▅", " ".repeat(SPARKLINE_LEN - 4)));
+        assert_eq!(line.spans[3].style.fg, Some(Color::Red));
+
+        assert_eq!(
+            sparkline(std::iter::empty()).spans[0].content,
+            " ".repeat(SPARKLINE_LEN)
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use std::fs;
+
+use crate::config::{self, Format, Problem};
+use crate::BoxError;
+
+// The `validate` command. Prints every problem with the config prefixed by where it is, as compilers do, and returns
+// whether there were none.
+pub fn run(config_path: &str) -> Result<bool, BoxError> {
+    let source = fs::read_to_string(config_path)?;
+    let format = Format::of(config_path);
+    let config = match config::parse(&source, format) {
+        Ok(config) => config,
+        Err(err) => {
+            match (err.line, err.column) {
+                (Some(line), Some(column)) => eprintln!("{}:{}:{}: {}", config_path, line, column, err.message),
+                _ => eprintln!("{}: {}", config_path, err.message),
+            }
+            return Ok(false);
+        }
+    };
+
+    let problems = config::validate(&config);
+    for problem in &problems {
+        match line(&source, format, problem) {
+            Some(line) => eprintln!("{}:{}: {}", config_path, line, problem.message),
+            None => eprintln!("{}: {}", config_path, problem.message),
+        }
+    }
+    if !problems.is_empty() {
+        return Ok(false);
+    }
+
+    let targets: usize = config
+        .endpoints
+        .iter()
+        .map(|endpoint| endpoint.targets().len())
+        .sum();
+    println!(
+        "{} is valid: {} endpoints ({} targets), {} alerts",
+        config_path,
+        config.endpoints.len(),
+        targets,
+        config.alerts.len()
+    );
+    Ok(true)
+}
+
+// The line of the entry a problem is about, or of its section when it is not about an entry. Only finds entries in
+// block style YAML and in TOML arrays of tables, which is how the examples write them, and nothing in JSON.
+fn line(source: &str, format: Format, problem: &Problem) -> Option<usize> {
+    match format {
+        Format::Yaml => yaml_line(source, problem),
+        Format::Toml => toml_line(source, problem),
+        Format::Json => None,
+    }
+}
+
+fn yaml_line(source: &str, problem: &Problem) -> Option<usize> {
+    let mut section = None;
+    let mut section_line = None;
+    let mut indent = None;
+    let mut entries = 0;
+    for (index, text) in source.lines().enumerate() {
+        if !text.starts_with([' ', '\t', '-', '#']) {
+            if let Some((key, _)) = text.split_once(':') {
+                section = Some(key.trim());
+                if key.trim() == problem.section {
+                    section_line = Some(index + 1);
+                }
+            }
+            continue;
+        }
+        if section != Some(problem.section) {
+            continue;
+        }
+
+        let entry = text.trim_start();
+        if !entry.starts_with('-') {
+            continue;
+        }
+        let depth = text.len() - entry.len();
+        if *indent.get_or_insert(depth) != depth {
+            continue;
+        }
+        if problem.index == Some(entries) {
+            return Some(index + 1);
+        }
+        entries += 1;
+    }
+    section_line
+}
+
+fn toml_line(source: &str, problem: &Problem) -> Option<usize> {
+    let entry_header = format!("[[{}]]", problem.section);
+    let mut section_line = None;
+    let mut entries = 0;
+    for (index, text) in source.lines().enumerate() {
+        let text = text.trim();
+        if text == entry_header {
+            if problem.index == Some(entries) {
+                return Some(index + 1);
+            }
+            entries += 1;
+        }
+        let key = text
+            .trim_start_matches('[')
+            .split(['.', ']', '='])
+            .next()
+            .unwrap_or_default()
+            .trim();
+        if key == problem.section {
+            section_line.get_or_insert(index + 1);
+        }
+    }
+    section_line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(section: &'static str, index: Option<usize>) -> Problem {
+        Problem {
+            section,
+            index,
+            message: String::new(),
+        }
+    }
+
+    const YAML: &str = "\
+interval: 5s
+# endpoints:
+#   - name: commented out
+endpoints:
+  - name: a
+    address: 10.0.0.1
+    http:
+      headers:
+        - name: x
+  # - name: not an entry
+  - name: b
+    address: 10.0.0.2
+alerts:
+- name: down
+  endpoints: [a]
+- name: slow
+";
+
+    #[test]
+    fn finds_yaml_entries() {
+        assert_eq!(yaml_line(YAML, &problem("endpoints", Some(0))), Some(5));
+        assert_eq!(yaml_line(YAML, &problem("endpoints", Some(1))), Some(11));
+        // Entries need not be indented.
+        assert_eq!(yaml_line(YAML, &problem("alerts", Some(1))), Some(16));
+        // Without an entry, or past the last one, the section it is in.
+        assert_eq!(yaml_line(YAML, &problem("endpoints", None)), Some(4));
+        assert_eq!(yaml_line(YAML, &problem("endpoints", Some(2))), Some(4));
+        assert_eq!(yaml_line(YAML, &problem("interval", None)), Some(1));
+        assert_eq!(yaml_line(YAML, &problem("history", None)), None);
+    }
+
+    #[test]
+    fn finds_toml_entries() {
+        let toml = "\
+interval = \"5s\"
+
+[metrics]
+namespace = \"netstab\"
+
+[[endpoints]]
+name = \"a\"
+
+[[endpoints]]
+name = \"b\"
+
+[history.retention]
+days = 7
+";
+        assert_eq!(line(toml, Format::Toml, &problem("endpoints", Some(1))), Some(9));
+        assert_eq!(line(toml, Format::Toml, &problem("endpoints", None)), Some(6));
+        assert_eq!(line(toml, Format::Toml, &problem("interval", None)), Some(1));
+        assert_eq!(line(toml, Format::Toml, &problem("metrics", None)), Some(3));
+        assert_eq!(line(toml, Format::Toml, &problem("history", None)), Some(12));
+        assert_eq!(line("{}", Format::Json, &problem("endpoints", None)), None);
+    }
+
+    #[test]
+    fn validates_config_files() {
+        let directory = std::env::temp_dir().join(format!("net-stab-validate-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_string_lossy().into_owned();
+
+        fs::write(path("ok.yaml"), "endpoints:\n  - name: a\n    address: 10.0.0.1\n").unwrap();
+        assert!(run(&path("ok.yaml")).unwrap());
+        fs::write(
+            path("duplicate.yaml"),
+            "endpoints:\n  - name: a\n    address: 10.0.0.1\n  - name: a\n    address: 10.0.0.2\n",
+        )
+        .unwrap();
+        assert!(!run(&path("duplicate.yaml")).unwrap());
+        fs::write(path("invalid.yaml"), "endpoints: [\n").unwrap();
+        assert!(!run(&path("invalid.yaml")).unwrap());
+        assert!(run(&path("missing.yaml")).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use futures_util::{SinkExt, StreamExt};
+use hyper::Uri;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::source::Source;
+use crate::BoxError;
+
+pub struct Exchange {
+    // From connecting up to the server accepting the upgrade.
+    pub handshake: Duration,
+    // From sending the ping to receiving its pong, when one was sent.
+    pub pong: Option<Duration>,
+}
+
+pub fn parse_url(url: &str) -> Result<Uri, BoxError> {
+    let uri: Uri = url.parse()?;
+    match uri.scheme_str() {
+        Some("ws") | Some("wss") if uri.host().is_some() => Ok(uri),
+        _ => Err(format!("`{}` is not a ws or wss url", url).into()),
+    }
+}
+
+// Connects to `ip` rather than resolving the host of the url and upgrades the connection, then with `ping` sends a
+// ping frame and waits for the pong that echoes it.
+pub async fn connect(url: &Uri, ip: IpAddr, ping: bool, verify: bool, source: &Source) -> Result<Exchange, BoxError> {
+    let secure = url.scheme_str() == Some("wss");
+    let port = url.port_u16().unwrap_or(if secure { 443 } else { 80 });
+
+    let start = Instant::now();
+    let stream = source.connect((ip, port).into()).await?;
+    if secure {
+        connect_tls(stream, url, ping, verify, start).await
+    } else {
+        exchange(stream, url, ping, start).await
+    }
+}
+
+#[cfg(feature = "tls")]
+async fn connect_tls(
+    stream: TcpStream,
+    url: &Uri,
+    ping: bool,
+    verify: bool,
+    start: Instant,
+) -> Result<Exchange, BoxError> {
+    let stream = crate::tls::connect(stream, crate::http::host(url), verify).await?;
+    exchange(stream, url, ping, start).await
+}
+
+#[cfg(not(feature = "tls"))]
+async fn connect_tls(
+    _stream: TcpStream,
+    _url: &Uri,
+    _ping: bool,
+    _verify: bool,
+    _start: Instant,
+) -> Result<Exchange, BoxError> {
+    Err("wss urls need rust-net-stab to be built with the `tls` feature".into())
+}
+
+async fn exchange<S>(stream: S, url: &Uri, ping: bool, start: Instant) -> Result<Exchange, BoxError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut request = url.to_string().into_client_request()?;
+    request.headers_mut().insert(
+        "User-Agent",
+        HeaderValue::from_static(concat!("rust-net-stab/", env!("CARGO_PKG_VERSION"))),
+    );
+    let (mut socket, _) = tokio_tungstenite::client_async(request, stream).await?;
+    let handshake = start.elapsed();
+
+    let pong = if ping {
+        let payload = rand::random::<[u8; 8]>().to_vec();
+        let sent = Instant::now();
+        socket.send(Message::Ping(payload.clone())).await?;
+        loop {
+            match socket.next().await {
+                Some(Ok(Message::Pong(data))) if data == payload => break Some(sent.elapsed()),
+                Some(Ok(Message::Close(_))) | None => {
+                    return Err("the server closed the connection before answering the ping".into())
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err.into()),
+            }
+        }
+    } else {
+        None
+    };
+    let _ = socket.close(None).await;
+
+    Ok(Exchange { handshake, pong })
+}